@@ -1,11 +1,14 @@
-use std::{env, net::SocketAddr, time::Duration};
+use std::env;
 
 use anyhow::Result;
-use dnsclient::{r#async::DNSClient, UpstreamServer};
-use instant_acme::{Account, AuthorizationStatus, ChallengeType, Identifier, LetsEncrypt, NewAccount, NewOrder, OrderStatus, RetryPolicy};
+use instant_acme::{Account, Identifier, LetsEncrypt, NewAccount, NewOrder, RetryPolicy};
 use random_string::charsets::ALPHA_LOWER;
 use tracing::{info, level_filters::LevelFilter};
-use zone_update::{Config, Provider, async_impl::AsyncDnsProvider, porkbun::Auth};
+use zone_update::{
+    Config, Provider,
+    async_impl::{AsyncDnsProvider, acme::solver::{self, SolverOptions}},
+    porkbun::Auth,
+};
 
 
 fn dns_client(domain: String, key: String, secret: String) -> Result<Box<dyn AsyncDnsProvider>> {
@@ -34,7 +37,6 @@ async fn get_cert() -> Result<()> {
     //let hostname = "plug-01";
     let fqdn = format!("{hostname}.{domain}");
     let contact = format!("mailto:{}", env::var("EXAMPLE_EMAIL").unwrap());
-    let txt_name = format!("_acme-challenge.{hostname}");
 
 
     let dns_key = env::var("PORKBUN_KEY")?;
@@ -59,54 +61,10 @@ async fn get_cert() -> Result<()> {
 
     let dns_client = dns_client(domain.clone(), dns_key.clone(), dns_secret.clone())?;
 
-    let mut authorizations = order.authorizations();
-    while let Some(result) = authorizations.next().await {
-        let mut authz = result?;
-        info!("Processing {:?}", authz.status);
-        match authz.status {
-            AuthorizationStatus::Pending => {}
-            AuthorizationStatus::Valid => continue,
-            _ => todo!(),
-        }
-
-        info!("Creating challenge");
-        let mut challenge = authz
-            .challenge(ChallengeType::Dns01)
-            .ok_or_else(|| anyhow::anyhow!("no dns01 challenge found"))?;
-
-
-        let token = challenge.key_authorization().dns_value();
-
-        info!("Creating TXT: {txt_name} -> {}", token);
-
-        dns_client.create_txt_record(&txt_name, &token).await?;
-
-        let txt_fqdn = format!("{txt_name}.{domain}");
-
-        //let lookup = DNSClient::new_with_system_resolvers()?;
-        let upstream = UpstreamServer::new(SocketAddr::from(([1,1,1,1], 53)));
-        let lookup = DNSClient::new(vec![upstream]);
-        info!("Waiting for record to go live");
-        for _i in 0..30 {
-            info!("Lookup for {txt_fqdn}");
-            let txts = lookup.query_txt(&txt_fqdn).await?;
-            if txts.len() > 0 {
-                info!("Found {txt_fqdn}");
-                break;
-            }
-            tokio::time::sleep(Duration::from_secs(1)).await;
-        }
-
-        info!("Setting challenge to ready");
-        challenge.set_ready().await?;
-    }
-
-    info!("Polling challenge status");
-    let status = order.poll_ready(&RetryPolicy::default()).await?;
-    if status != OrderStatus::Ready {
-        dns_client.delete_txt_record(&txt_name).await?;
-        return Err(anyhow::anyhow!("unexpected order status: {status:?}"));
-    }
+    // Publishes the challenge records, waits for propagation and for
+    // the order to become ready, then removes the records again.
+    info!("Solving DNS-01 challenges");
+    solver::solve(&*dns_client, &domain, &mut order, &SolverOptions::default()).await?;
 
     let private_key_pem = order.finalize().await?;
     let cert_chain_pem = order.poll_certificate(&RetryPolicy::default()).await?;
@@ -117,10 +75,6 @@ async fn get_cert() -> Result<()> {
     println!("====================== Private =============================\n");
     println!("{}", private_key_pem);
 
-    println!("Deleting acme challenge");
-
-    dns_client.delete_txt_record(&txt_name).await?;
-
     println!("Done");
 
     Ok(())
@@ -0,0 +1,585 @@
+//! ACME DNS-01 challenges.
+//!
+//! [`publish()`] creates the `_acme-challenge` TXT record for an
+//! identifier, waits for it to reach the authoritative nameservers,
+//! and returns a guard that removes the record again when dropped. The
+//! ACME client is then told that the challenge is ready:
+//!
+//! ```no_run
+//! use zone_update::{acme::{self, AcmeOptions}, Config, porkbun};
+//! # fn main() -> zone_update::errors::Result<()> {
+//! let config = Config { domain: "example.com".to_string(), dry_run: false };
+//! let auth = porkbun::Auth { key: "key".to_string(), secret: "secret".to_string() };
+//! let client = porkbun::Porkbun::new(config, auth);
+//!
+//! // The key authorization is provided by the ACME client.
+//! let challenge = acme::publish(&client, "example.com", "*.example.com", "token.thumbprint",
+//!                               &AcmeOptions::default())?;
+//! // ... notify the ACME server and wait for validation ...
+//! challenge.cleanup()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Challenge values are added and removed with
+//! `DnsProvider::add_txt_value()` and `remove_txt_value()`, so other
+//! challenges on the same name (e.g. for `example.com` and
+//! `*.example.com`) are left in place.
+//!
+//! If `_acme-challenge.example.com` is a CNAME to a dedicated
+//! validation zone, [`publish_delegated()`] follows the chain and
+//! publishes the record in that zone instead, using a provider
+//! configured for it.
+//!
+//! Records left behind by crashed runs can be found and removed with
+//! [`sweep()`], which runs in dry-run mode unless told otherwise.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Display, Formatter},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::{
+    errors::{Error, Result},
+    ownership::{parse_created, registry_host, DEFAULT_PREFIX},
+    propagation::{fqdn, resolve_cname, wait_for_propagation, PropagationOptions, PropagationReport},
+    relative_host, strip_quotes, DnsProvider, RecordType, ZoneRecord,
+};
+
+/// The label prefixed to an identifier for its challenge record.
+pub const CHALLENGE_LABEL: &str = "_acme-challenge";
+
+
+/// Options for `publish()`.
+#[derive(Clone, Debug)]
+pub struct AcmeOptions {
+    /// Wait for the record to reach the authoritative nameservers; if
+    /// `None` `publish()` returns as soon as the record is created.
+    pub propagation: Option<PropagationOptions>,
+}
+
+impl Default for AcmeOptions {
+    fn default() -> Self {
+        Self {
+            propagation: Some(PropagationOptions::default()),
+        }
+    }
+}
+
+
+/// The fully-qualified challenge record name for `identifier`.
+/// Wildcard identifiers use the record of their base name.
+pub fn challenge_name(identifier: &str) -> String {
+    let name = identifier.strip_prefix("*.").unwrap_or(identifier).trim_end_matches('.');
+    format!("{CHALLENGE_LABEL}.{name}")
+}
+
+/// The challenge record host for `identifier`, relative to the zone
+/// `domain`. Wildcard identifiers use the record of their base name.
+pub fn challenge_host(domain: &str, identifier: &str) -> Result<String> {
+    zone_host(domain, &challenge_name(identifier))
+        .map_err(|_| Error::ApiError(format!("{identifier} is not in the zone {domain}")))
+}
+
+/// `name` relative to the zone `domain`, or an error if it is outside
+/// the zone.
+pub(crate) fn zone_host(domain: &str, name: &str) -> Result<String> {
+    let name = name.trim_end_matches('.');
+    let host = relative_host(name, domain);
+    if host == name {
+        return Err(Error::ApiError(format!("{name} is not in the zone {domain}")));
+    }
+    Ok(host)
+}
+
+/// The options used to resolve a delegated challenge name.
+pub(crate) fn resolver_options(opts: &AcmeOptions) -> PropagationOptions {
+    opts.propagation.clone().unwrap_or_default()
+}
+
+/// The challenge record value for a key authorization; the base64url
+/// encoded SHA-256 digest (RFC 8555 section 8.4).
+pub fn challenge_value(key_authorization: &str) -> String {
+    base64url(&Sha256::digest(key_authorization.as_bytes()))
+}
+
+fn base64url(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..=chunk.len() {
+            out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+    }
+    out
+}
+
+/// A published DNS-01 challenge record. The record is removed when
+/// this is dropped; use `cleanup()` to handle errors.
+pub struct Dns01Challenge<'a, P: DnsProvider + ?Sized> {
+    client: &'a P,
+    host: String,
+    fqdn: String,
+    value: String,
+    propagation: Option<PropagationReport>,
+    active: bool,
+}
+
+impl<P: DnsProvider + ?Sized> Dns01Challenge<'_, P> {
+    /// The record host, relative to the zone.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The fully-qualified record name.
+    pub fn fqdn(&self) -> &str {
+        &self.fqdn
+    }
+
+    /// The TXT record value.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// The propagation check result, if one was made.
+    pub fn propagation(&self) -> Option<&PropagationReport> {
+        self.propagation.as_ref()
+    }
+
+    /// Remove the challenge value from the zone.
+    pub fn cleanup(mut self) -> Result<()> {
+        self.remove()
+    }
+
+    fn remove(&mut self) -> Result<()> {
+        if !self.active {
+            return Ok(());
+        }
+        self.active = false;
+        info!("Removing challenge {} -> {}", self.fqdn, self.value);
+        self.client.remove_txt_value(&self.host, &self.value)
+    }
+}
+
+impl<P: DnsProvider + ?Sized> Drop for Dns01Challenge<'_, P> {
+    fn drop(&mut self) {
+        if let Err(e) = self.remove() {
+            warn!("Failed to remove challenge {}: {e}", self.fqdn);
+        }
+    }
+}
+
+/// Publish the DNS-01 challenge record for `identifier` in the zone
+/// `domain`, waiting for propagation if configured. If waiting fails
+/// the record is removed again.
+pub fn publish<'a, P>(client: &'a P, domain: &str, identifier: &str, key_authorization: &str,
+                      opts: &AcmeOptions) -> Result<Dns01Challenge<'a, P>>
+where
+    P: DnsProvider + ?Sized
+{
+    let host = challenge_host(domain, identifier)?;
+    publish_at(client, domain, host, challenge_value(key_authorization), opts)
+}
+
+/// Publish the DNS-01 challenge record for `identifier` where its
+/// `_acme-challenge` name is delegated to another zone by CNAME.
+///
+/// The CNAME chain is followed with the resolver from
+/// `opts.propagation` (or the system resolver), and the record is
+/// published at the end of the chain. `client` should manage `zone`,
+/// the zone containing that name; this is usually a separately
+/// configured provider, so the main zone's credentials aren't needed.
+/// The propagation check is made against `zone`'s nameservers. If the
+/// name isn't an alias the record is published in `zone` directly.
+pub fn publish_delegated<'a, P>(client: &'a P, zone: &str, identifier: &str, key_authorization: &str,
+                                opts: &AcmeOptions) -> Result<Dns01Challenge<'a, P>>
+where
+    P: DnsProvider + ?Sized
+{
+    let target = resolve_cname(&challenge_name(identifier), &resolver_options(opts))?;
+    let host = zone_host(zone, &target)?;
+    publish_at(client, zone, host, challenge_value(key_authorization), opts)
+}
+
+fn publish_at<'a, P>(client: &'a P, domain: &str, host: String, value: String,
+                     opts: &AcmeOptions) -> Result<Dns01Challenge<'a, P>>
+where
+    P: DnsProvider + ?Sized
+{
+    info!("Publishing challenge {host} -> {value}");
+    client.add_txt_value(&host, &value)?;
+
+    let mut challenge = Dns01Challenge {
+        client,
+        fqdn: fqdn(domain, &host),
+        host,
+        value,
+        propagation: None,
+        active: true,
+    };
+    if let Some(popts) = &opts.propagation {
+        let report = wait_for_propagation(domain, &challenge.host, RecordType::TXT, Some(&challenge.value), popts)?;
+        challenge.propagation = Some(report);
+    }
+    Ok(challenge)
+}
+
+/// Options for `sweep()`.
+#[derive(Clone, Debug)]
+pub struct SweepOptions {
+    /// Challenge records last changed longer ago than this are
+    /// stale. Defaults to a day.
+    pub max_age: Duration,
+    /// Only report stale records. This defaults to `true`, so that
+    /// the records a sweep would remove can be checked first.
+    pub dry_run: bool,
+    /// The prefix of `ownership` registry records, which date
+    /// challenges where the provider doesn't report timestamps.
+    pub registry_prefix: String,
+}
+
+impl Default for SweepOptions {
+    fn default() -> Self {
+        Self {
+            max_age: Duration::from_secs(24 * 60 * 60),
+            dry_run: true,
+            registry_prefix: DEFAULT_PREFIX.to_string(),
+        }
+    }
+}
+
+/// How the age of a challenge record was found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AgeSource {
+    /// The provider's record timestamp.
+    Provider,
+    /// The creation time in the record set's ownership registry
+    /// record. Only used for the sole value of a set.
+    Marker,
+}
+
+/// A challenge record older than `SweepOptions::max_age`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StaleChallenge {
+    /// The record host, relative to the zone.
+    pub host: String,
+    /// The TXT value, without quotes.
+    pub value: String,
+    /// When the record was last changed.
+    pub modified: DateTime<Utc>,
+    pub source: AgeSource,
+}
+
+/// The outcome of a sweep.
+#[derive(Clone, Debug, Default)]
+pub struct SweepReport {
+    /// Stale challenge records; these were removed unless this was a
+    /// dry run.
+    pub stale: Vec<StaleChallenge>,
+    /// Hosts of registry records released along with the last stale
+    /// challenge on their record set.
+    pub markers: Vec<String>,
+    /// Challenge records with neither a timestamp nor a dated
+    /// registry record. These are never removed.
+    pub undated: Vec<ZoneRecord>,
+    /// Removals that failed, with the error.
+    pub failed: Vec<(String, String)>,
+    pub dry_run: bool,
+}
+
+impl Display for SweepReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let action = if self.dry_run { "would remove" } else { "removed" };
+        for rec in &self.stale {
+            writeln!(f, "- {action} TXT {} \"{}\" (last changed {})", rec.host, rec.value, rec.modified)?;
+        }
+        for host in &self.markers {
+            writeln!(f, "- {action} registry record {host}")?;
+        }
+        for rec in &self.undated {
+            writeln!(f, "! undated TXT {} {}", rec.host, rec.value)?;
+        }
+        for (what, err) in &self.failed {
+            writeln!(f, "! failed to remove {what}: {err}")?;
+        }
+        Ok(())
+    }
+}
+
+fn is_challenge(rec: &ZoneRecord) -> bool {
+    rec.rtype == RecordType::TXT && rec.host.to_lowercase().starts_with(CHALLENGE_LABEL)
+}
+
+/// Find the stale challenge records in a zone listing.
+pub(crate) fn find_stale(records: &[ZoneRecord], opts: &SweepOptions, now: DateTime<Utc>) -> SweepReport {
+    let markers: HashMap<String, DateTime<Utc>> = records.iter()
+        .filter(|rec| rec.rtype == RecordType::TXT)
+        .filter_map(|rec| Some((rec.host.to_lowercase(), parse_created(&rec.value)?)))
+        .collect();
+    let marker_host = |host: &str| registry_host(&opts.registry_prefix, RecordType::TXT, host).to_lowercase();
+
+    // The marker records when the set was claimed, so only dates a
+    // value if it is the only one; others may have been added since.
+    let mut set_sizes: HashMap<String, usize> = HashMap::new();
+    for rec in records.iter().filter(|rec| is_challenge(rec)) {
+        *set_sizes.entry(rec.host.to_lowercase()).or_default() += 1;
+    }
+
+    let mut report = SweepReport { dry_run: opts.dry_run, ..Default::default() };
+    let mut kept = HashSet::new();
+    for rec in records.iter().filter(|rec| is_challenge(rec)) {
+        let dated = rec.modified.map(|t| (t, AgeSource::Provider))
+            .or_else(|| {
+                let sole = set_sizes.get(&rec.host.to_lowercase()) == Some(&1);
+                markers.get(&marker_host(&rec.host))
+                    .filter(|_| sole)
+                    .map(|&t| (t, AgeSource::Marker))
+            });
+        let Some((modified, source)) = dated else {
+            kept.insert(rec.host.to_lowercase());
+            report.undated.push(rec.clone());
+            continue;
+        };
+
+        let stale = (now - modified).to_std()
+            .is_ok_and(|age| age > opts.max_age);
+        if stale {
+            report.stale.push(StaleChallenge {
+                host: rec.host.clone(),
+                value: strip_quotes(&rec.value),
+                modified,
+                source,
+            });
+        } else {
+            kept.insert(rec.host.to_lowercase());
+        }
+    }
+
+    let released: HashSet<String> = report.stale.iter()
+        .filter(|rec| !kept.contains(&rec.host.to_lowercase()))
+        .map(|rec| marker_host(&rec.host))
+        .filter(|host| markers.contains_key(host))
+        .collect();
+    report.markers = records.iter()
+        .filter(|rec| rec.rtype == RecordType::TXT && released.contains(&rec.host.to_lowercase()))
+        .map(|rec| rec.host.clone())
+        .collect();
+    report
+}
+
+/// Remove `_acme-challenge` TXT records that are older than
+/// `opts.max_age`, e.g. those left behind by crashed certificate runs.
+///
+/// Records are dated by the provider's timestamps where the listing
+/// includes them (currently Cloudflare, deSEC, DNSimple and Linode).
+/// Otherwise a record that is the only value of its set is dated by
+/// the set's `ownership` registry record, if it was created through
+/// `ownership::Owned`; `client` must then be the unwrapped provider,
+/// as `Owned` hides registry records. The registry records when the
+/// set was claimed, so can't date values added to it later. Records
+/// that can't be dated are reported but left alone.
+///
+/// `opts.dry_run` is set by default, so only the report is produced.
+/// Failed removals are reported rather than stopping the sweep.
+pub fn sweep<P>(client: &P, opts: &SweepOptions) -> Result<SweepReport>
+where
+    P: DnsProvider + ?Sized
+{
+    let mut report = find_stale(&client.list_records()?, opts, Utc::now());
+    if opts.dry_run {
+        return Ok(report);
+    }
+
+    for rec in &report.stale {
+        info!("Removing stale challenge {} -> {} (last changed {})", rec.host, rec.value, rec.modified);
+        if let Err(e) = client.remove_txt_value(&rec.host, &rec.value) {
+            warn!("Failed to remove challenge {}: {e}", rec.host);
+            report.failed.push((format!("TXT {} {}", rec.host, rec.value), e.to_string()));
+        }
+    }
+    for host in &report.markers {
+        if let Err(e) = client.delete_all_records(RecordType::TXT, host) {
+            warn!("Failed to remove registry record {host}: {e}");
+            report.failed.push((format!("registry record {host}"), e.to_string()));
+        }
+    }
+    Ok(report)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{propagation::tests::Responder, tests::MemoryProvider};
+
+    const NO_WAIT: AcmeOptions = AcmeOptions { propagation: None };
+
+    #[test]
+    fn test_challenge_host() -> Result<()> {
+        assert_eq!("_acme-challenge", challenge_host("example.com", "example.com")?);
+        assert_eq!("_acme-challenge", challenge_host("example.com", "*.example.com")?);
+        assert_eq!("_acme-challenge.www", challenge_host("example.com", "www.example.com.")?);
+        assert_eq!("_acme-challenge.a.b", challenge_host("example.com", "*.a.b.example.com")?);
+        assert!(challenge_host("example.com", "www.example.org").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_challenge_value() {
+        assert_eq!("", base64url(b""));
+        assert_eq!("Zg", base64url(b"f"));
+        assert_eq!("Zm8", base64url(b"fo"));
+        assert_eq!("Zm9v", base64url(b"foo"));
+        assert_eq!("-_8", base64url(&[0xfb, 0xff]));
+        assert_eq!("47DEQpj8HBSa-_TImW-5JCeuQeRkm5NMpJWZG3hSuFU", challenge_value(""));
+    }
+
+    #[test]
+    fn test_guard() -> Result<()> {
+        let client = MemoryProvider::new("example.com");
+        client.create_txt_record("_acme-challenge", "other")?;
+
+        let challenge = publish(&client, "example.com", "example.com", "token.key", &NO_WAIT)?;
+        assert_eq!("_acme-challenge.example.com", challenge.fqdn());
+        let value = challenge.value().to_string();
+        assert_eq!(2, client.get_txt_values("_acme-challenge")?.len());
+
+        drop(challenge);
+        assert_eq!(Some("other".to_string()), client.get_txt_record("_acme-challenge")?);
+
+        let challenge = publish(&client, "example.com", "www.example.com", "token.key", &NO_WAIT)?;
+        assert_eq!(Some(value), client.get_txt_record("_acme-challenge.www")?);
+        challenge.cleanup()?;
+        assert_eq!(None, client.get_txt_record("_acme-challenge.www")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_delegated() -> Result<()> {
+        let client = MemoryProvider::new("validation.org");
+        let server = Responder::start(false);
+        let popts = server.options("validation.org");
+        let opts = AcmeOptions { propagation: Some(popts.clone()) };
+        server.add_cname("_acme-challenge.example.com", "_acme-challenge.example.net");
+        server.add_cname("_acme-challenge.example.net", "example-com.validation.org.");
+        server.add_txt("example-com.validation.org", &challenge_value("token.key"));
+
+        let challenge = publish_delegated(&client, "validation.org", "*.example.com", "token.key", &opts)?;
+        assert_eq!("example-com", challenge.host());
+        assert_eq!("example-com.validation.org", challenge.fqdn());
+        assert!(challenge.propagation().is_some());
+        assert_eq!(Some(challenge_value("token.key")), client.get_txt_record("example-com")?);
+        challenge.cleanup()?;
+        assert_eq!(None, client.get_txt_record("example-com")?);
+
+        // Not delegated
+        let client = MemoryProvider::new("example.com");
+        let opts = AcmeOptions { propagation: Some(server.options("example.com")) };
+        server.add_txt("_acme-challenge.www.example.com", &challenge_value("token.key"));
+        let challenge = publish_delegated(&client, "example.com", "www.example.com", "token.key", &opts)?;
+        assert_eq!("_acme-challenge.www", challenge.host());
+        drop(challenge);
+
+        // The chain leaves the zone
+        let res = publish_delegated(&client, "example.com", "example.com", "token.key", &opts);
+        assert!(matches!(res, Err(Error::ApiError(_))));
+        Ok(())
+    }
+
+    fn txt(host: &str, value: &str, modified: Option<DateTime<Utc>>) -> ZoneRecord {
+        ZoneRecord { rtype: RecordType::TXT, host: host.to_string(), value: format!("\"{value}\""), ttl: None, modified }
+    }
+
+    #[test]
+    fn test_find_stale() {
+        let now = Utc::now();
+        let old = now - chrono::Duration::days(3);
+        let registry = crate::ownership::Registry::new("certbot");
+        let records = vec![
+            txt("_acme-challenge", "old", Some(old)),
+            txt("_acme-challenge", "new", Some(now)),
+            txt("_acme-challenge.www", "marked", None),
+            txt("_owner-txt._acme-challenge.www", &registry.marker_at(old), None),
+            txt("_acme-challenge.api", "unknown", None),
+            txt("www", "not a challenge", Some(old)),
+        ];
+
+        let report = find_stale(&records, &SweepOptions::default(), now);
+        assert!(report.dry_run);
+        assert_eq!(vec![("_acme-challenge", "old", AgeSource::Provider), ("_acme-challenge.www", "marked", AgeSource::Marker)],
+                   report.stale.iter().map(|r| (r.host.as_str(), r.value.as_str(), r.source)).collect::<Vec<_>>());
+        assert_eq!(vec!["_owner-txt._acme-challenge.www"], report.markers);
+        assert_eq!(vec![records[4].clone()], report.undated);
+
+        let opts = SweepOptions { max_age: Duration::from_secs(4 * 24 * 60 * 60), ..Default::default() };
+        assert!(find_stale(&records, &opts, now).stale.is_empty());
+    }
+
+    #[test]
+    fn test_find_stale_shared_marker() {
+        let now = Utc::now();
+        let old = now - chrono::Duration::days(3);
+        let registry = crate::ownership::Registry::new("certbot");
+        // A fresh challenge added to a set claimed long ago
+        let records = vec![
+            txt("_acme-challenge", "left over", None),
+            txt("_acme-challenge", "in flight", None),
+            txt("_owner-txt._acme-challenge", &registry.marker_at(old), None),
+        ];
+
+        let report = find_stale(&records, &SweepOptions::default(), now);
+        assert!(report.stale.is_empty());
+        assert!(report.markers.is_empty());
+        assert_eq!(records[..2].to_vec(), report.undated);
+    }
+
+    #[test]
+    fn test_sweep() -> Result<()> {
+        let client = MemoryProvider::new("example.com");
+        let registry = crate::ownership::Registry::new("certbot");
+        let old = Utc::now() - chrono::Duration::days(2);
+        client.create_txt_record("_acme-challenge", "stale")?;
+        client.create_txt_record("_owner-txt._acme-challenge", &registry.marker_at(old))?;
+        client.create_txt_record("_acme-challenge.www", "unknown")?;
+
+        // Dry-run by default
+        let report = sweep(&client, &SweepOptions::default())?;
+        assert_eq!(1, report.stale.len());
+        assert!(report.to_string().starts_with("- would remove TXT _acme-challenge \"stale\""));
+        assert_eq!(Some("stale".to_string()), client.get_txt_record("_acme-challenge")?);
+
+        let report = sweep(&client, &SweepOptions { dry_run: false, ..Default::default() })?;
+        assert!(report.failed.is_empty());
+        assert_eq!(None, client.get_txt_record("_acme-challenge")?);
+        assert_eq!(None, client.get_txt_record("_owner-txt._acme-challenge")?);
+        assert_eq!(Some("unknown".to_string()), client.get_txt_record("_acme-challenge.www")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_propagation() -> Result<()> {
+        let client = MemoryProvider::new("example.com");
+        let server = Responder::start(false);
+        let popts = server.options("example.com");
+        server.add_txt("_acme-challenge.example.com", &challenge_value("token.key"));
+
+        let opts = AcmeOptions { propagation: Some(popts.clone()) };
+        let challenge = publish(&client, "example.com", "*.example.com", "token.key", &opts)?;
+        assert!(challenge.propagation().is_some());
+        challenge.cleanup()?;
+
+        // The record is removed if it doesn't propagate
+        let opts = AcmeOptions { propagation: Some(PropagationOptions { timeout: std::time::Duration::from_millis(20), ..popts }) };
+        let res = publish(&client, "example.com", "www.example.com", "token.key", &opts);
+        assert!(matches!(res, Err(Error::NotPropagated(_, _))));
+        assert_eq!(None, client.get_txt_record("_acme-challenge.www")?);
+        Ok(())
+    }
+}
@@ -0,0 +1,224 @@
+use chrono::Utc;
+use tracing::{info, warn};
+
+use crate::acme::{find_stale, resolver_options, zone_host};
+use crate::async_impl::{propagation::{resolve_cname, wait_for_propagation}, AsyncDnsProvider};
+use crate::propagation::{fqdn, PropagationReport};
+use crate::{errors::Result, RecordType};
+
+#[cfg(feature = "instant-acme")]
+pub mod solver;
+
+pub use crate::acme::{
+    challenge_host, challenge_name, challenge_value, AcmeOptions, AgeSource, StaleChallenge, SweepOptions, SweepReport,
+    CHALLENGE_LABEL,
+};
+
+
+/// A published DNS-01 challenge record.
+///
+/// Unlike the blocking version the record must be removed with
+/// `cleanup()`; `Drop` can't be async, and blocking an executor
+/// thread on the removal could stall it. A challenge dropped without
+/// cleanup is logged and left in place for `sweep()`.
+///
+/// See `zone_update::acme::Dns01Challenge` for details.
+#[must_use = "the challenge record is only removed by `cleanup()`"]
+pub struct Dns01Challenge<'a, P: AsyncDnsProvider + ?Sized> {
+    client: &'a P,
+    host: String,
+    fqdn: String,
+    value: String,
+    propagation: Option<PropagationReport>,
+    active: bool,
+}
+
+impl<P: AsyncDnsProvider + ?Sized> Dns01Challenge<'_, P> {
+    /// The record host, relative to the zone.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The fully-qualified record name.
+    pub fn fqdn(&self) -> &str {
+        &self.fqdn
+    }
+
+    /// The TXT record value.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// The propagation check result, if one was made.
+    pub fn propagation(&self) -> Option<&PropagationReport> {
+        self.propagation.as_ref()
+    }
+
+    /// Remove the challenge value from the zone.
+    pub async fn cleanup(mut self) -> Result<()> {
+        self.remove().await
+    }
+
+    async fn remove(&mut self) -> Result<()> {
+        if !self.active {
+            return Ok(());
+        }
+        self.active = false;
+        info!("Removing challenge {} -> {}", self.fqdn, self.value);
+        self.client.remove_txt_value(&self.host, &self.value).await
+    }
+}
+
+impl<P: AsyncDnsProvider + ?Sized> Drop for Dns01Challenge<'_, P> {
+    fn drop(&mut self) {
+        if !self.active {
+            return;
+        }
+        warn!("Challenge {} -> {} dropped without cleanup(); leaving it in place", self.fqdn, self.value);
+    }
+}
+
+/// Publish the DNS-01 challenge record for `identifier` in the zone
+/// `domain`, waiting for propagation if configured. If waiting fails
+/// the record is removed again.
+pub async fn publish<'a, P>(client: &'a P, domain: &str, identifier: &str, key_authorization: &str,
+                            opts: &AcmeOptions) -> Result<Dns01Challenge<'a, P>>
+where
+    P: AsyncDnsProvider + ?Sized
+{
+    let host = challenge_host(domain, identifier)?;
+    publish_at(client, domain, host, challenge_value(key_authorization), opts).await
+}
+
+/// Publish the DNS-01 challenge record for `identifier` where its
+/// `_acme-challenge` name is delegated to another zone by CNAME.
+///
+/// See `zone_update::acme::publish_delegated` for details.
+pub async fn publish_delegated<'a, P>(client: &'a P, zone: &str, identifier: &str, key_authorization: &str,
+                                      opts: &AcmeOptions) -> Result<Dns01Challenge<'a, P>>
+where
+    P: AsyncDnsProvider + ?Sized
+{
+    let target = resolve_cname(&challenge_name(identifier), &resolver_options(opts)).await?;
+    let host = zone_host(zone, &target)?;
+    publish_at(client, zone, host, challenge_value(key_authorization), opts).await
+}
+
+async fn publish_at<'a, P>(client: &'a P, domain: &str, host: String, value: String,
+                           opts: &AcmeOptions) -> Result<Dns01Challenge<'a, P>>
+where
+    P: AsyncDnsProvider + ?Sized
+{
+    info!("Publishing challenge {host} -> {value}");
+    client.add_txt_value(&host, &value).await?;
+
+    let mut challenge = Dns01Challenge {
+        client,
+        fqdn: fqdn(domain, &host),
+        host,
+        value,
+        propagation: None,
+        active: true,
+    };
+    if let Some(popts) = &opts.propagation {
+        match wait_for_propagation(domain, &challenge.host, RecordType::TXT, Some(&challenge.value), popts).await {
+            Ok(report) => challenge.propagation = Some(report),
+            Err(e) => {
+                if let Err(ce) = challenge.remove().await {
+                    warn!("Failed to remove challenge {}: {ce}", challenge.fqdn);
+                }
+                return Err(e);
+            }
+        }
+    }
+    Ok(challenge)
+}
+
+/// Remove `_acme-challenge` TXT records that are older than
+/// `opts.max_age`.
+///
+/// See `zone_update::acme::sweep` for details.
+pub async fn sweep<P>(client: &P, opts: &SweepOptions) -> Result<SweepReport>
+where
+    P: AsyncDnsProvider + ?Sized
+{
+    let mut report = find_stale(&client.list_records().await?, opts, Utc::now());
+    if opts.dry_run {
+        return Ok(report);
+    }
+
+    for rec in &report.stale {
+        info!("Removing stale challenge {} -> {} (last changed {})", rec.host, rec.value, rec.modified);
+        if let Err(e) = client.remove_txt_value(&rec.host, &rec.value).await {
+            warn!("Failed to remove challenge {}: {e}", rec.host);
+            report.failed.push((format!("TXT {} {}", rec.host, rec.value), e.to_string()));
+        }
+    }
+    for host in &report.markers {
+        if let Err(e) = client.delete_all_records(RecordType::TXT, host).await {
+            warn!("Failed to remove registry record {host}: {e}");
+            report.failed.push((format!("registry record {host}"), e.to_string()));
+        }
+    }
+    Ok(report)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{async_impl::Unblocked, propagation::tests::Responder, tests::MemoryProvider};
+    use futures::executor::block_on;
+
+    #[test]
+    fn test_async_challenge() -> Result<()> {
+        let client = Unblocked::new(MemoryProvider::new("example.com"));
+        let opts = AcmeOptions { propagation: None };
+        let host = "_acme-challenge".to_string();
+        block_on(client.create_txt_record(&host, &"other".to_string()))?;
+
+        let challenge = block_on(publish(&client, "example.com", "*.example.com", "token.key", &opts))?;
+        assert_eq!(2, block_on(client.get_txt_values(&host))?.len());
+        block_on(challenge.cleanup())?;
+        assert_eq!(Some("other".to_string()), block_on(client.get_txt_record(&host))?);
+
+        // Left in place on drop
+        let challenge = block_on(publish(&client, "example.com", "example.com", "token.key", &opts))?;
+        drop(challenge);
+        assert_eq!(2, block_on(client.get_txt_values(&host))?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_async_sweep() -> Result<()> {
+        let client = Unblocked::new(MemoryProvider::new("example.com"));
+        let marker = crate::ownership::Registry::new("certbot").marker_at(Utc::now() - chrono::Duration::days(2));
+        block_on(client.create_txt_record(&"_acme-challenge".to_string(), &"stale".to_string()))?;
+        block_on(client.create_txt_record(&"_owner-txt._acme-challenge".to_string(), &marker))?;
+
+        let report = block_on(sweep(&client, &SweepOptions::default()))?;
+        assert_eq!(1, report.stale.len());
+        assert!(block_on(client.get_txt_record(&"_acme-challenge".to_string()))?.is_some());
+
+        let report = block_on(sweep(&client, &SweepOptions { dry_run: false, ..Default::default() }))?;
+        assert_eq!(vec!["_owner-txt._acme-challenge"], report.markers);
+        assert!(block_on(client.list_records())?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_async_delegated() -> Result<()> {
+        let client = Unblocked::new(MemoryProvider::new("validation.org"));
+        let server = Responder::start(false);
+        let opts = AcmeOptions { propagation: Some(server.options("validation.org")) };
+        server.add_cname("_acme-challenge.example.com", "example-com.validation.org");
+        server.add_txt("example-com.validation.org", &challenge_value("token.key"));
+
+        let challenge = block_on(publish_delegated(&client, "validation.org", "example.com", "token.key", &opts))?;
+        assert_eq!("example-com.validation.org", challenge.fqdn());
+        assert!(challenge.propagation().is_some());
+        block_on(challenge.cleanup())?;
+        assert_eq!(None, block_on(client.get_txt_record(&"example-com".to_string()))?);
+        Ok(())
+    }
+}
@@ -0,0 +1,388 @@
+//! A DNS-01 solver for [instant-acme](https://docs.rs/instant-acme)
+//! orders.
+//!
+//! [`solve()`] publishes a challenge record for every pending
+//! authorization in an `Order`, tells the ACME server each challenge
+//! is ready once its record has propagated, and polls until the order
+//! is ready to finalize. The records are removed again whether or not
+//! validation succeeds:
+//!
+//! ```no_run
+//! use instant_acme::Order;
+//! use zone_update::{async_impl::{AsyncDnsProvider, acme::solver::{self, SolverOptions}}, errors::Result};
+//!
+//! async fn validate(client: &dyn AsyncDnsProvider, order: &mut Order) -> Result<()> {
+//!     solver::solve(client, "example.com", order, &SolverOptions::default()).await?;
+//!     // ... finalize the order and download the certificate ...
+//!     Ok(())
+//! }
+//! ```
+//!
+//! instant-acme polls the order with tokio's timer, so `solve()` must
+//! run within a tokio runtime.
+
+use instant_acme::{AuthorizationStatus, ChallengeHandle, ChallengeType, Identifier, Order, OrderStatus, RetryPolicy};
+use tracing::{info, warn};
+
+use crate::async_impl::AsyncDnsProvider;
+use crate::errors::{Error, Result};
+use super::{publish, publish_delegated, AcmeOptions, Dns01Challenge};
+
+
+/// Options for `solve()`.
+#[derive(Clone, Debug, Default)]
+pub struct SolverOptions {
+    /// Options used to publish each challenge.
+    pub acme: AcmeOptions,
+    /// Follow `_acme-challenge` CNAMEs to the zone managed by the
+    /// client; see `publish_delegated()`.
+    pub delegated: bool,
+    /// How long to poll the order for.
+    pub retries: RetryPolicy,
+}
+
+/// The DNS name to publish a challenge for. Wildcards are already
+/// stripped by instant-acme, and share the record of their base name.
+fn dns_identifier(identifier: &Identifier) -> Result<String> {
+    match identifier {
+        Identifier::Dns(name) => Ok(name.clone()),
+        other => Err(Error::Unsupported(format!("DNS-01 challenge for identifier {other:?}"))),
+    }
+}
+
+/// Complete the DNS-01 challenges of all pending authorizations in
+/// `order`, publishing the records in the zone `domain` with `client`.
+///
+/// Returns once the order is ready to finalize. The challenge records
+/// are removed before returning, including on error.
+pub async fn solve<P>(client: &P, domain: &str, order: &mut Order, opts: &SolverOptions) -> Result<()>
+where
+    P: AsyncDnsProvider + ?Sized
+{
+    solve_order(client, domain, order, opts).await
+}
+
+async fn solve_order<P>(client: &P, domain: &str, order: &mut dyn AcmeOrder, opts: &SolverOptions) -> Result<()>
+where
+    P: AsyncDnsProvider + ?Sized
+{
+    let mut solver = Solver {
+        client,
+        domain,
+        opts,
+        published: Vec::new(),
+    };
+    let result = solve_with(&mut solver, order, opts).await;
+
+    let mut cleanup = Ok(());
+    for challenge in solver.published {
+        let fqdn = challenge.fqdn().to_string();
+        if let Err(e) = challenge.cleanup().await {
+            warn!("Failed to remove challenge {fqdn}: {e}");
+            cleanup = cleanup.and(Err(e));
+        }
+    }
+    result.and(cleanup)
+}
+
+async fn solve_with(solver: &mut dyn SolveAuthorization, order: &mut dyn AcmeOrder, opts: &SolverOptions) -> Result<()> {
+    order.each_authorization(solver).await?;
+
+    let status = order.poll_ready(&opts.retries).await?;
+    if status != OrderStatus::Ready {
+        return Err(Error::ApiError(format!("Order is {status:?} after validation")));
+    }
+    Ok(())
+}
+
+
+/// The parts of an ACME order driven by the solver. This is
+/// implemented for instant-acme's `Order`, and by a stand-in in the
+/// tests.
+#[async_trait::async_trait]
+trait AcmeOrder: Send {
+    /// Pass each authorization of the order to `solver` in turn,
+    /// stopping at the first error.
+    async fn each_authorization(&mut self, solver: &mut dyn SolveAuthorization) -> Result<()>;
+
+    /// Wait for the order to be validated.
+    async fn poll_ready(&mut self, retries: &RetryPolicy) -> Result<OrderStatus>;
+}
+
+/// An authorization of an `AcmeOrder`.
+#[async_trait::async_trait]
+trait Authorization: Send {
+    fn status(&self) -> AuthorizationStatus;
+
+    /// The authorized identifier, for messages.
+    fn name(&self) -> &str;
+
+    /// The DNS name and key authorization of the DNS-01 challenge.
+    fn dns01(&self) -> Result<(String, String)>;
+
+    /// Tell the server the DNS-01 challenge is ready to be validated.
+    async fn set_ready(&mut self) -> Result<()>;
+}
+
+#[async_trait::async_trait]
+trait SolveAuthorization: Send {
+    async fn solve(&mut self, authz: &mut dyn Authorization) -> Result<()>;
+}
+
+/// Publishes the challenges of pending authorizations, keeping the
+/// records for cleanup.
+struct Solver<'a, 'o, P: AsyncDnsProvider + ?Sized> {
+    client: &'a P,
+    domain: &'o str,
+    opts: &'o SolverOptions,
+    published: Vec<Dns01Challenge<'a, P>>,
+}
+
+#[async_trait::async_trait]
+impl<P> SolveAuthorization for Solver<'_, '_, P>
+where
+    P: AsyncDnsProvider + ?Sized
+{
+    async fn solve(&mut self, authz: &mut dyn Authorization) -> Result<()> {
+        match authz.status() {
+            AuthorizationStatus::Pending => {}
+            AuthorizationStatus::Valid => return Ok(()),
+            status => {
+                return Err(Error::ApiError(format!("Authorization for {} is {status:?}", authz.name())));
+            }
+        }
+
+        let (identifier, key_auth) = authz.dns01()?;
+        let (client, domain, opts) = (self.client, self.domain, self.opts);
+        let record = if opts.delegated {
+            publish_delegated(client, domain, &identifier, &key_auth, &opts.acme).await?
+        } else {
+            publish(client, domain, &identifier, &key_auth, &opts.acme).await?
+        };
+        self.published.push(record);
+
+        info!("Challenge for {identifier} is ready");
+        authz.set_ready().await
+    }
+}
+
+#[async_trait::async_trait]
+impl AcmeOrder for Order {
+    async fn each_authorization(&mut self, solver: &mut dyn SolveAuthorization) -> Result<()> {
+        let mut authorizations = self.authorizations();
+        while let Some(authz) = authorizations.next().await {
+            let mut authz = authz?;
+            let mut current = OrderAuthorization {
+                status: authz.status,
+                name: authz.identifier().to_string(),
+                challenge: authz.challenge(ChallengeType::Dns01),
+            };
+            solver.solve(&mut current).await?;
+        }
+        Ok(())
+    }
+
+    async fn poll_ready(&mut self, retries: &RetryPolicy) -> Result<OrderStatus> {
+        Ok(Order::poll_ready(self, retries).await?)
+    }
+}
+
+struct OrderAuthorization<'a> {
+    status: AuthorizationStatus,
+    name: String,
+    challenge: Option<ChallengeHandle<'a>>,
+}
+
+fn no_challenge() -> Error {
+    Error::Unsupported("No DNS-01 challenge offered".to_string())
+}
+
+#[async_trait::async_trait]
+impl Authorization for OrderAuthorization<'_> {
+    fn status(&self) -> AuthorizationStatus {
+        self.status
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn dns01(&self) -> Result<(String, String)> {
+        let challenge = self.challenge.as_ref().ok_or_else(no_challenge)?;
+        let identifier = dns_identifier(challenge.identifier().identifier)?;
+        Ok((identifier, challenge.key_authorization().as_str().to_string()))
+    }
+
+    async fn set_ready(&mut self) -> Result<()> {
+        let challenge = self.challenge.as_mut().ok_or_else(no_challenge)?;
+        Ok(challenge.set_ready().await?)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{acme::{challenge_host, challenge_value}, async_impl::Unblocked, tests::MemoryProvider, DnsProvider};
+    use futures::executor::block_on;
+    use std::sync::Arc;
+
+    /// An order whose authorizations are solved in turn, recording
+    /// which challenge records were published when each was set
+    /// ready.
+    struct StandInOrder {
+        provider: Arc<MemoryProvider>,
+        authorizations: Vec<(&'static str, AuthorizationStatus)>,
+        fail_ready: Option<&'static str>,
+        status: OrderStatus,
+        events: Vec<String>,
+    }
+
+    impl StandInOrder {
+        fn new(provider: &Arc<MemoryProvider>, authorizations: &[(&'static str, AuthorizationStatus)]) -> Self {
+            Self {
+                provider: provider.clone(),
+                authorizations: authorizations.to_vec(),
+                fail_ready: None,
+                status: OrderStatus::Ready,
+                events: Vec::new(),
+            }
+        }
+    }
+
+    struct StandInAuthorization<'a> {
+        name: &'static str,
+        status: AuthorizationStatus,
+        provider: &'a MemoryProvider,
+        fail: bool,
+        events: &'a mut Vec<String>,
+    }
+
+    #[async_trait::async_trait]
+    impl AcmeOrder for StandInOrder {
+        async fn each_authorization(&mut self, solver: &mut dyn SolveAuthorization) -> Result<()> {
+            for (name, status) in self.authorizations.clone() {
+                let mut authz = StandInAuthorization {
+                    name,
+                    status,
+                    provider: &self.provider,
+                    fail: self.fail_ready == Some(name),
+                    events: &mut self.events,
+                };
+                solver.solve(&mut authz).await?;
+            }
+            Ok(())
+        }
+
+        async fn poll_ready(&mut self, _retries: &RetryPolicy) -> Result<OrderStatus> {
+            self.events.push("poll".to_string());
+            Ok(self.status)
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Authorization for StandInAuthorization<'_> {
+        fn status(&self) -> AuthorizationStatus {
+            self.status
+        }
+
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn dns01(&self) -> Result<(String, String)> {
+            Ok((self.name.to_string(), format!("{}.key", self.name)))
+        }
+
+        async fn set_ready(&mut self) -> Result<()> {
+            let host = challenge_host("example.com", self.name)?;
+            let values = self.provider.get_txt_values(&host)?;
+            let published = values.contains(&challenge_value(&format!("{}.key", self.name)));
+            self.events.push(format!("ready {} {published}", self.name));
+            if self.fail {
+                return Err(Error::ApiError("Challenge rejected".to_string()));
+            }
+            Ok(())
+        }
+    }
+
+    fn solve_stand_in(provider: &Arc<MemoryProvider>, order: &mut StandInOrder) -> Result<()> {
+        let client = Unblocked::from_arc(provider.clone());
+        let opts = SolverOptions {
+            acme: AcmeOptions { propagation: None },
+            ..Default::default()
+        };
+        block_on(solve_order(&client, "example.com", order, &opts))
+    }
+
+    #[test]
+    fn test_solve_order() -> Result<()> {
+        let provider = Arc::new(MemoryProvider::new("example.com"));
+        let mut order = StandInOrder::new(&provider, &[
+            ("example.com", AuthorizationStatus::Pending),
+            ("www.example.com", AuthorizationStatus::Valid),
+            ("mail.example.com", AuthorizationStatus::Pending),
+        ]);
+
+        solve_stand_in(&provider, &mut order)?;
+
+        // Each record is published before its challenge is set ready,
+        // valid authorizations are skipped, and all records are
+        // removed once the order is ready.
+        assert_eq!(vec!["ready example.com true", "ready mail.example.com true", "poll"], order.events);
+        assert!(provider.list_records()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_solve_failure_midway() -> Result<()> {
+        let provider = Arc::new(MemoryProvider::new("example.com"));
+        let mut order = StandInOrder::new(&provider, &[
+            ("example.com", AuthorizationStatus::Pending),
+            ("www.example.com", AuthorizationStatus::Pending),
+            ("mail.example.com", AuthorizationStatus::Pending),
+        ]);
+        order.fail_ready = Some("www.example.com");
+
+        let res = solve_stand_in(&provider, &mut order);
+        assert!(matches!(res, Err(Error::ApiError(_))));
+        assert_eq!(vec!["ready example.com true", "ready www.example.com true"], order.events);
+        assert!(provider.list_records()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_solve_invalid() -> Result<()> {
+        let provider = Arc::new(MemoryProvider::new("example.com"));
+        let mut order = StandInOrder::new(&provider, &[
+            ("example.com", AuthorizationStatus::Pending),
+            ("www.example.com", AuthorizationStatus::Invalid),
+        ]);
+
+        let res = solve_stand_in(&provider, &mut order);
+        assert!(matches!(res, Err(Error::ApiError(msg)) if msg.contains("www.example.com")));
+        assert_eq!(vec!["ready example.com true"], order.events);
+        assert!(provider.list_records()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_solve_not_ready() -> Result<()> {
+        let provider = Arc::new(MemoryProvider::new("example.com"));
+        let mut order = StandInOrder::new(&provider, &[("example.com", AuthorizationStatus::Pending)]);
+        order.status = OrderStatus::Invalid;
+
+        let res = solve_stand_in(&provider, &mut order);
+        assert!(matches!(res, Err(Error::ApiError(msg)) if msg.contains("Invalid")));
+        assert!(provider.list_records()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dns_identifier() -> Result<()> {
+        assert_eq!("example.com", dns_identifier(&Identifier::Dns("example.com".to_string()))?);
+        let res = dns_identifier(&Identifier::Ip([192, 0, 2, 1].into()));
+        assert!(matches!(res, Err(Error::Unsupported(_))));
+        Ok(())
+    }
+}
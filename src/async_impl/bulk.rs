@@ -0,0 +1,355 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use async_io::Timer;
+use futures_util::{stream, StreamExt};
+use tracing::{error, info, warn};
+
+use crate::{
+    async_impl::AsyncDnsProvider,
+    change::{group_by_name, prepare_sequential, rollback_changes, sequential_failure, Change, ChangeSet},
+    errors::{Error, Result},
+};
+
+
+/// Options for `apply_bulk()`.
+#[derive(Clone, Debug)]
+pub struct BulkOptions {
+    /// Maximum number of names being changed at once.
+    pub concurrency: usize,
+    /// Minimum time between starting requests, across all names.
+    /// Use this to stay under provider rate limits.
+    pub min_interval: Option<Duration>,
+}
+
+impl Default for BulkOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 8,
+            min_interval: None,
+        }
+    }
+}
+
+
+/// Apply a single change to an async provider.
+///
+/// Unlike `apply_record_change()` this accepts unsized providers,
+/// e.g. the `Box<dyn AsyncDnsProvider>` from `Provider::async_impl()`.
+pub async fn apply_change<P>(provider: &P, change: &Change) -> Result<()>
+where
+    P: AsyncDnsProvider + ?Sized
+{
+    provider.apply_change(change).await
+}
+
+/// Apply a single change by calling the matching record method.
+///
+/// This is the async equivalent of `Change::apply()`, for use in
+/// implementations of `AsyncDnsProvider::apply_change()`.
+pub async fn apply_record_change<P>(provider: &P, change: &Change) -> Result<()>
+where
+    P: AsyncDnsProvider
+{
+    match change {
+        Change::Create { rtype, host, value } => provider.create_record(*rtype, host, value).await,
+        Change::Update { rtype, host, value } => provider.update_record(*rtype, host, value).await,
+        Change::Delete { rtype, host } => provider.delete_record(*rtype, host).await,
+        Change::DeleteAll { rtype, host } => provider.delete_all_records(*rtype, host).await,
+    }
+}
+
+/// Apply a change set one change at a time, rolling back on failure.
+///
+/// This is the async equivalent of `change::apply_sequential()`, and
+/// the fallback for `AsyncDnsProvider::apply_changes()`.
+pub async fn apply_sequential<P>(provider: &P, changes: &ChangeSet) -> Result<()>
+where
+    P: AsyncDnsProvider + ?Sized
+{
+    let sets = prepare_sequential(changes, provider.list_records().await)?;
+
+    for (i, change) in changes.changes.iter().enumerate() {
+        info!("Applying {change}");
+        let Err(e) = provider.apply_change(change).await else {
+            continue;
+        };
+        warn!("Failed to {change}: {e}");
+
+        let Some(sets) = &sets else {
+            return Err(sequential_failure(e, false));
+        };
+        // The failed change may have been partially applied
+        let rollback = rollback_changes(sets, &changes.changes[..=i]);
+        let mut rolled_back = true;
+        for undo in &rollback {
+            info!("Rolling back: {undo}");
+            if let Err(e) = provider.apply_change(undo).await {
+                error!("Failed to roll back with {undo}: {e}");
+                rolled_back = false;
+            }
+        }
+        return Err(sequential_failure(e, rolled_back));
+    }
+
+    Ok(())
+}
+
+/// Apply many changes with bounded concurrency.
+///
+/// Changes to different names are applied concurrently, up to
+/// `opts.concurrency` names at a time. Changes to the same name are
+/// applied sequentially, with deletes first; see
+/// `change::group_by_name()`. A failed change does not stop the
+/// others.
+///
+/// Returns the result of each change, in the same order as
+/// `changes`.
+pub async fn apply_bulk<P>(provider: &P, changes: &[Change], opts: &BulkOptions) -> Vec<Result<()>>
+where
+    P: AsyncDnsProvider + ?Sized
+{
+    let limiter = opts.min_interval.map(RateLimiter::new);
+    let groups = group_by_name(changes);
+
+    let done: Vec<Vec<(usize, Result<()>)>> = stream::iter(groups)
+        .map(|group| {
+            let limiter = limiter.as_ref();
+            async move {
+                let mut results = Vec::with_capacity(group.len());
+                for i in group {
+                    if let Some(limiter) = limiter {
+                        limiter.wait().await;
+                    }
+                    let change = &changes[i];
+                    info!("Applying {change}");
+                    let result = provider.apply_change(change).await;
+                    if let Err(e) = &result {
+                        warn!("Failed to {change}: {e}");
+                    }
+                    results.push((i, result));
+                }
+                results
+            }
+        })
+        .buffer_unordered(opts.concurrency.max(1))
+        .collect()
+        .await;
+
+    let mut results: Vec<Option<Result<()>>> = changes.iter().map(|_| None).collect();
+    for (i, result) in done.into_iter().flatten() {
+        results[i] = Some(result);
+    }
+    results.into_iter()
+        .map(|r| r.unwrap_or_else(|| Err(Error::ApiError("Change was not applied".to_string()))))
+        .collect()
+}
+
+
+/// Spaces out request starts by a fixed interval.
+struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    async fn wait(&self) {
+        let now = Instant::now();
+        let at = {
+            // Poisoning can only come from a panic in this block, so
+            // the value is still usable.
+            let mut next = self.next.lock()
+                .unwrap_or_else(|e| e.into_inner());
+            let at = (*next).max(now);
+            *next = at + self.interval;
+            at
+        };
+        if at > now {
+            Timer::at(at).await;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{async_impl::Unblocked, errors::MutationState, tests::MemoryProvider, DnsProvider, RecordType};
+    use std::{
+        fmt::Display,
+        net::Ipv4Addr,
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+    };
+    use serde::{de::DeserializeOwned, Serialize};
+
+    /// Memory provider that tracks the number of concurrent writes.
+    struct Tracking {
+        inner: MemoryProvider,
+        current: AtomicUsize,
+        max: AtomicUsize,
+    }
+
+    impl Tracking {
+        fn track<R>(&self, f: impl FnOnce() -> R) -> R {
+            let n = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max.fetch_max(n, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(20));
+            let r = f();
+            self.current.fetch_sub(1, Ordering::SeqCst);
+            r
+        }
+    }
+
+    impl DnsProvider for Tracking {
+        fn get_record<T>(&self, rtype: RecordType, host: &str) -> Result<Option<T>>
+        where
+            T: DeserializeOwned
+        {
+            self.inner.get_record(rtype, host)
+        }
+
+        fn create_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+        where
+            T: Serialize + DeserializeOwned + Display + Clone
+        {
+            self.track(|| self.inner.create_record(rtype, host, record))
+        }
+
+        fn update_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+        where
+            T: Serialize + DeserializeOwned + Display + Clone
+        {
+            self.track(|| self.inner.update_record(rtype, host, record))
+        }
+
+        fn delete_record(&self, rtype: RecordType, host: &str) -> Result<()> {
+            self.track(|| self.inner.delete_record(rtype, host))
+        }
+
+        fn delete_all_records(&self, rtype: RecordType, host: &str) -> Result<()> {
+            self.track(|| self.inner.delete_all_records(rtype, host))
+        }
+
+        crate::generate_helpers!();
+    }
+
+    fn create(host: &str, value: &str) -> Change {
+        Change::Create { rtype: RecordType::A, host: host.to_string(), value: value.to_string() }
+    }
+
+    #[test]
+    fn test_bulk_concurrency() -> Result<()> {
+        let client = Unblocked::new(Tracking {
+            inner: MemoryProvider::new("example.com"),
+            current: AtomicUsize::new(0),
+            max: AtomicUsize::new(0),
+        });
+        let changes: Vec<Change> = (0..20)
+            .map(|i| create(&format!("host{i}"), &format!("10.0.0.{i}")))
+            .collect();
+        let opts = BulkOptions { concurrency: 4, ..Default::default() };
+
+        let results = futures::executor::block_on(apply_bulk(&client, &changes, &opts));
+        assert_eq!(20, results.len());
+        assert!(results.iter().all(|r| r.is_ok()));
+
+        let max = client.inner().max.load(Ordering::SeqCst);
+        assert!(max > 1 && max <= 4, "max concurrency was {max}");
+        assert_eq!(20, client.inner().inner.records.lock().unwrap().len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bulk_ordering_and_results() -> Result<()> {
+        let memory = MemoryProvider::new("example.com");
+        memory.create_a_record("www", &Ipv4Addr::new(10, 0, 0, 1))?;
+        memory.create_a_record("mail", &Ipv4Addr::new(10, 0, 0, 2))?;
+        memory.create_a_record("mail", &Ipv4Addr::new(10, 0, 0, 3))?;
+        let client = Unblocked::new(memory);
+
+        let changes = vec![
+            create("www", "10.0.0.3"),
+            // Fails, as there are multiple records
+            Change::Delete { rtype: RecordType::A, host: "mail".to_string() },
+            Change::DeleteAll { rtype: RecordType::A, host: "www".to_string() },
+        ];
+        let results = futures::executor::block_on(apply_bulk(&client, &changes, &BulkOptions::default()));
+
+        assert!(results[0].is_ok());
+        assert!(matches!(results[1], Err(Error::UnexpectedRecord(_))));
+        assert!(results[2].is_ok());
+        // The delete ran before the create
+        let www: Option<Ipv4Addr> = client.inner().get_a_record("www")?;
+        assert_eq!(Some(Ipv4Addr::new(10, 0, 0, 3)), www);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_boxed_provider() -> Result<()> {
+        let provider: Box<dyn AsyncDnsProvider> = Box::new(Unblocked::new(MemoryProvider::new("example.com")));
+
+        futures::executor::block_on(apply_change(&*provider, &create("www", "10.0.0.1")))?;
+        let changes = vec![
+            create("mail", "10.0.0.2"),
+            Change::Update { rtype: RecordType::A, host: "www".to_string(), value: "10.0.0.3".to_string() },
+        ];
+        let results = futures::executor::block_on(apply_bulk(&*provider, &changes, &BulkOptions::default()));
+        assert!(results.iter().all(|r| r.is_ok()));
+
+        let records = futures::executor::block_on(provider.list_records())?;
+        assert_eq!(2, records.len());
+        assert!(records.iter().any(|r| r.host == "www" && r.value == "10.0.0.3"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bulk_rate_limit() {
+        let client = Unblocked::new(MemoryProvider::new("example.com"));
+        let changes: Vec<Change> = (0..5)
+            .map(|i| create(&format!("host{i}"), "10.0.0.1"))
+            .collect();
+        let opts = BulkOptions {
+            concurrency: 5,
+            min_interval: Some(Duration::from_millis(20)),
+        };
+
+        let start = Instant::now();
+        let results = futures::executor::block_on(apply_bulk(&client, &changes, &opts));
+        assert!(results.iter().all(|r| r.is_ok()));
+        assert!(start.elapsed() >= Duration::from_millis(80));
+    }
+
+    #[test]
+    fn test_apply_sequential_rollback() -> Result<()> {
+        let mut memory = MemoryProvider::new("example.com");
+        memory.types = &[RecordType::A];
+        memory.create_a_record("www", &Ipv4Addr::new(10, 0, 0, 1))?;
+        let client = Unblocked::new(memory);
+
+        let changes: ChangeSet = vec![
+            Change::DeleteAll { rtype: RecordType::A, host: "www".to_string() },
+            create("www", "10.0.0.2"),
+            Change::Create { rtype: RecordType::TXT, host: "www".to_string(), value: "\"abc\"".to_string() },
+        ].into();
+        let result = futures::executor::block_on(apply_sequential(&client, &changes));
+
+        assert!(matches!(result, Err(Error::ChangeSetFailed(_, MutationState::NotApplied))));
+        let www: Option<Ipv4Addr> = client.inner().get_a_record("www")?;
+        assert_eq!(Some(Ipv4Addr::new(10, 0, 0, 1)), www);
+
+        Ok(())
+    }
+}
@@ -1,34 +1,20 @@
-
-use std::sync::Arc;
-use std::fmt::Display;
-
-use blocking::unblock;
-use serde::{de::DeserializeOwned, Serialize};
-
+use crate::async_impl::Native;
 use crate::bunny as sync;
-use crate::{async_provider_impl, Config, DnsProvider};
-use crate::{errors::Result, RecordType};
-
-use crate::async_impl::AsyncDnsProvider;
+use crate::Config;
 
 
 pub use crate::bunny::Auth;
 
-pub struct Bunny {
-    inner: Arc<sync::Bunny>,
-}
+/// Native async `Bunny` provider.
+pub type Bunny = Native<sync::Bunny>;
 
 impl Bunny {
+    /// Create a new async `Bunny` provider.
     pub fn new(config: Config, auth: Auth) -> Self {
-        let inner = sync::Bunny::new(config, auth);
-        Self {
-            inner: Arc::new(inner)
-        }
+        Native::wrap(sync::Bunny::new(config, auth))
     }
 }
 
-async_provider_impl!(Bunny);
-
 
 #[cfg(test)]
 mod tests {
@@ -1,35 +1,20 @@
-use std::sync::Arc;
-use std::fmt::Display;
-
-use blocking::unblock;
-use serde::{de::DeserializeOwned, Serialize};
-
+use crate::async_impl::Native;
 use crate::cloudflare as sync;
-use crate::{async_provider_impl, Config, DnsProvider};
-use crate::{errors::Result, RecordType};
-
-use crate::async_impl::AsyncDnsProvider;
+use crate::Config;
 
 
 pub use crate::cloudflare::Auth;
 
-/// Async wrapper around the synchronous `Cloudflare` provider.
-pub struct Cloudflare {
-    inner: Arc<sync::Cloudflare>,
-}
+/// Native async `Cloudflare` provider.
+pub type Cloudflare = Native<sync::Cloudflare>;
 
 impl Cloudflare {
-    /// Create a new async `Cloudflare` wrapper.
+    /// Create a new async `Cloudflare` provider.
     pub fn new(config: Config, auth: Auth) -> Self {
-        let inner = sync::Cloudflare::new(config, auth);
-        Self {
-            inner: Arc::new(inner)
-        }
+        Native::wrap(sync::Cloudflare::new(config, auth))
     }
 }
 
-async_provider_impl!(Cloudflare);
-
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,182 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use async_io::Timer;
+use blocking::unblock;
+use chrono::Utc;
+use tracing::{debug, info, warn};
+
+use crate::{
+    async_impl::AsyncDnsProvider,
+    ddns::{publish_changes, Core, SHUTDOWN_POLL},
+    discovery::AddressSource,
+    errors::Result,
+};
+
+pub use crate::ddns::{Addresses, Published, State, UpdateReport, UpdaterOptions};
+
+
+/// Keeps the address records of a set of hosts up to date.
+///
+/// See `zone_update::ddns::Updater` for details. Address sources are
+/// blocking, so are queried on the `blocking` thread pool.
+pub struct Updater<P, S> {
+    provider: P,
+    source: Arc<S>,
+    core: Core,
+}
+
+impl<P: AsyncDnsProvider, S: AddressSource + 'static> Updater<P, S> {
+    /// Create an updater for `hosts`, loading the state file if
+    /// configured. An unreadable state file is ignored.
+    pub fn new(provider: P, source: S, hosts: &[&str], opts: UpdaterOptions) -> Self {
+        Self {
+            provider,
+            source: Arc::new(source),
+            core: Core::new(hosts, opts),
+        }
+    }
+
+    /// The last published addresses.
+    pub fn state(&self) -> &State {
+        &self.core.state
+    }
+
+    pub fn provider(&self) -> &P {
+        &self.provider
+    }
+
+    /// Discover the current addresses, and update any hosts that
+    /// have changed or are due a refresh. See
+    /// `zone_update::ddns::Updater::check()`.
+    pub async fn check(&mut self) -> Result<UpdateReport> {
+        let mut results = Vec::new();
+        for &family in &self.core.opts.families {
+            let source = self.source.clone();
+            results.push((family, unblock(move || source.address(family)).await));
+        }
+        let (addrs, unresolved) = match Core::addresses(results) {
+            Ok(found) => found,
+            Err(e) => {
+                self.core.record(false);
+                return Err(e);
+            }
+        };
+
+        let now = Utc::now();
+        let mut results = Vec::new();
+        for host in self.core.pending(&addrs, now) {
+            let result = self.publish(&host, &addrs).await;
+            results.push((host, result));
+        }
+        Ok(self.core.finish(addrs, unresolved, results, now))
+    }
+
+    async fn publish(&self, host: &String, addrs: &Addresses) -> Result<()> {
+        let current = Addresses {
+            v4: if addrs.v4.is_some() { self.provider.get_a_record(host).await? } else { None },
+            v6: if addrs.v6.is_some() { self.provider.get_aaaa_record(host).await? } else { None },
+        };
+        let changes = publish_changes(host, addrs, current);
+        if changes.is_empty() {
+            debug!("{host} is up to date");
+            return Ok(());
+        }
+        info!("Updating {host} to {addrs:?}");
+        self.provider.apply_changes(&changes).await
+    }
+
+    /// The delay before the next check; the interval, or the backoff
+    /// after a failure.
+    pub fn next_delay(&self) -> Duration {
+        self.core.delay()
+    }
+
+    /// Check for changes until `shutdown` is set.
+    pub async fn run(&mut self, shutdown: &AtomicBool) {
+        while !shutdown.load(Ordering::Relaxed) {
+            match self.check().await {
+                Ok(report) if report.failed.is_empty() => {}
+                Ok(report) => warn!("{} hosts failed to update", report.failed.len()),
+                Err(e) => warn!("Address discovery failed: {e}"),
+            }
+            let wake = Instant::now() + self.next_delay();
+            while !shutdown.load(Ordering::Relaxed) {
+                let remaining = wake.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    break;
+                }
+                Timer::after(remaining.min(SHUTDOWN_POLL)).await;
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        async_impl::Unblocked,
+        ddns::tests::{state_file, Settable},
+        discovery::Family,
+        tests::MemoryProvider,
+        DnsProvider,
+    };
+    use std::{fs, sync::Mutex};
+
+    #[test]
+    fn test_updater() -> Result<()> {
+        let path = state_file("async");
+        let opts = UpdaterOptions {
+            families: vec![Family::V4, Family::V6],
+            state_file: Some(path.clone()),
+            ..Default::default()
+        };
+        let source = Settable(Mutex::new(Addresses { v4: Some("192.0.2.1".parse()?), v6: Some("2001:db8::1".parse()?) }));
+        let provider = Unblocked::new(MemoryProvider::new("example.com"));
+        let mut updater = Updater::new(provider, source, &["www"], opts);
+
+        futures_lite::future::block_on(async {
+            assert_eq!(vec!["www"], updater.check().await?.updated);
+            assert!(updater.check().await?.updated.is_empty());
+
+            updater.source.0.lock().unwrap().v6 = Some("2001:db8::2".parse()?);
+            assert_eq!(vec!["www"], updater.check().await?.updated);
+            Ok::<_, crate::errors::Error>(())
+        })?;
+
+        let inner = updater.provider().inner();
+        assert_eq!(Some("192.0.2.1".parse()?), inner.get_a_record("www")?);
+        assert_eq!(Some("2001:db8::2".parse()?), inner.get_aaaa_record("www")?);
+        assert_eq!(updater.state(), &State::load(&path)?);
+
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_partial_families() -> Result<()> {
+        let opts = UpdaterOptions {
+            families: vec![Family::V4, Family::V6],
+            ..Default::default()
+        };
+        let source = Settable(Mutex::new(Addresses { v4: Some("192.0.2.1".parse()?), v6: None }));
+        let provider = Unblocked::new(MemoryProvider::new("example.com"));
+        let mut updater = Updater::new(provider, source, &["www"], opts);
+
+        let report = futures_lite::future::block_on(updater.check())?;
+        assert_eq!(vec!["www"], report.updated);
+        assert_eq!(Family::V6, report.unresolved[0].0);
+
+        let inner = updater.provider().inner();
+        assert_eq!(Some("192.0.2.1".parse()?), inner.get_a_record("www")?);
+        assert_eq!(None, inner.get_aaaa_record("www")?);
+        Ok(())
+    }
+}
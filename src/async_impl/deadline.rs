@@ -0,0 +1,357 @@
+use std::{
+    fmt::Display,
+    future::Future,
+    net::{Ipv4Addr, Ipv6Addr},
+    ops::Deref,
+    sync::{atomic::{AtomicBool, Ordering}, Arc},
+    time::{Duration, Instant},
+};
+
+use async_io::Timer;
+use futures_lite::future;
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::warn;
+use ureq::http::Method;
+
+use crate::async_impl::{
+    http::{AsyncHttpClient, Request, Response},
+    AsyncDnsProvider, Native,
+};
+use crate::{
+    change::{Change, ChangeSet},
+    errors::{Error, MutationState, Result},
+    RecordType, ZoneListing,
+};
+
+
+/// Async provider wrapper that bounds every operation by a deadline.
+///
+/// If the deadline passes before an operation completes, the
+/// operation is dropped and `Error::DeadlineExceeded` is returned,
+/// reporting whether the change may have been applied. Read-only
+/// operations always report `MutationState::NotApplied`.
+///
+/// When created with `Native::with_deadline()` in-flight HTTP
+/// requests are aborted, and a change is only reported as
+/// `MaybeApplied` if a mutating request had been started. Wrapping
+/// other providers (e.g. `Unblocked`) cannot abort the operation, so
+/// any interrupted change is reported as `MaybeApplied`.
+pub struct WithDeadline<A> {
+    inner: A,
+    deadline: Instant,
+    /// Builds a copy of `inner` that sets the flag when it starts a
+    /// mutating request, if the provider supports it.
+    tracked: Option<Box<dyn Fn(Arc<AtomicBool>) -> A + Send + Sync>>,
+}
+
+/// The provider used for a single operation.
+enum Call<'a, A> {
+    Shared(&'a A),
+    Tracked(A),
+}
+
+impl<A> Deref for Call<'_, A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        match self {
+            Call::Shared(inner) => inner,
+            Call::Tracked(inner) => inner,
+        }
+    }
+}
+
+impl<A: AsyncDnsProvider> WithDeadline<A> {
+    /// Bound the operations of `inner` by `deadline`.
+    pub fn new(inner: A, deadline: Instant) -> Self {
+        Self {
+            inner,
+            deadline,
+            tracked: None,
+        }
+    }
+
+    /// The deadline for operations.
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// The provider for a mutating operation, and the flag tracking
+    /// whether it has sent a mutating request. Each operation has its
+    /// own flag, so concurrent and earlier operations don't affect the
+    /// reported state.
+    fn call(&self) -> (Call<'_, A>, Option<Arc<AtomicBool>>) {
+        match &self.tracked {
+            Some(tracked) => {
+                let sent = Arc::new(AtomicBool::new(false));
+                (Call::Tracked(tracked(sent.clone())), Some(sent))
+            }
+            None => (Call::Shared(&self.inner), None),
+        }
+    }
+
+    async fn run<T>(&self, mutating: bool, sent: Option<Arc<AtomicBool>>,
+                    op: impl Future<Output = Result<T>>) -> Result<T>
+    {
+        let timeout = async {
+            Timer::at(self.deadline).await;
+            let state = match &sent {
+                _ if !mutating => MutationState::NotApplied,
+                Some(sent) if !sent.load(Ordering::SeqCst) => MutationState::NotApplied,
+                _ => MutationState::MaybeApplied,
+            };
+            warn!("Deadline exceeded; change {state}");
+            Err(Error::DeadlineExceeded(state))
+        };
+        future::or(op, timeout).await
+    }
+}
+
+impl<P> Native<P>
+where
+    Native<P>: AsyncDnsProvider,
+    P: Send + Sync + 'static,
+{
+    /// Return a view of this provider with operations bounded by
+    /// `deadline`. See `WithDeadline`.
+    pub fn with_deadline(&self, deadline: Instant) -> WithDeadline<Native<P>> {
+        let (inner, client) = (self.inner.clone(), self.client.clone());
+        let tracked = move |sent| Native {
+            inner: inner.clone(),
+            client: Arc::new(Tracking { inner: client.clone(), sent }) as Arc<dyn AsyncHttpClient>,
+        };
+
+        WithDeadline {
+            inner: Native {
+                inner: self.inner.clone(),
+                client: self.client.clone(),
+            },
+            deadline,
+            tracked: Some(Box::new(tracked)),
+        }
+    }
+
+    /// Return a view of this provider with operations bounded by
+    /// `timeout` from now. See `WithDeadline`.
+    pub fn with_timeout(&self, timeout: Duration) -> WithDeadline<Native<P>> {
+        self.with_deadline(Instant::now() + timeout)
+    }
+}
+
+
+/// Records whether a request that may change the zone was started.
+struct Tracking {
+    inner: Arc<dyn AsyncHttpClient>,
+    sent: Arc<AtomicBool>,
+}
+
+#[async_trait::async_trait]
+impl AsyncHttpClient for Tracking {
+    async fn execute(&self, req: Request<Option<String>>) -> Result<Response<String>> {
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            self.sent.store(true, Ordering::SeqCst);
+        }
+        self.inner.execute(req).await
+    }
+}
+
+
+#[async_trait::async_trait]
+impl<A: AsyncDnsProvider> AsyncDnsProvider for WithDeadline<A> {
+
+    async fn get_record<T>(&self, rtype: RecordType, host: &String) -> Result<Option<T>>
+    where
+        T: DeserializeOwned + Send + Sync + 'static
+    {
+        self.run(false, None, self.inner.get_record(rtype, host)).await
+    }
+
+    async fn create_record<T>(&self, rtype: RecordType, host: &String, record: &T) -> Result<()>
+    where
+        T: Serialize + DeserializeOwned + Display + Clone + Send + Sync + 'static
+    {
+        let (inner, sent) = self.call();
+        self.run(true, sent, inner.create_record(rtype, host, record)).await
+    }
+
+    async fn update_record<T>(&self, rtype: RecordType, host: &String, record: &T) -> Result<()>
+    where
+        T: Serialize + DeserializeOwned + Display + Clone + Send + Sync + 'static
+    {
+        let (inner, sent) = self.call();
+        self.run(true, sent, inner.update_record(rtype, host, record)).await
+    }
+
+    async fn delete_record(&self, rtype: RecordType, host: &String) -> Result<()> {
+        let (inner, sent) = self.call();
+        self.run(true, sent, inner.delete_record(rtype, host)).await
+    }
+
+    async fn delete_all_records(&self, rtype: RecordType, host: &String) -> Result<()> {
+        let (inner, sent) = self.call();
+        self.run(true, sent, inner.delete_all_records(rtype, host)).await
+    }
+
+    async fn list_zone(&self) -> Result<ZoneListing> {
+        self.run(false, None, self.inner.list_zone()).await
+    }
+
+    fn record_types(&self) -> &'static [RecordType] {
+        self.inner.record_types()
+    }
+
+    fn check_changes(&self, changes: &[Change]) -> Result<()> {
+        self.inner.check_changes(changes)
+    }
+
+    async fn apply_changes(&self, changes: &ChangeSet) -> Result<()> {
+        let (inner, sent) = self.call();
+        self.run(true, sent, inner.apply_changes(changes)).await
+    }
+
+    async fn apply_change(&self, change: &Change) -> Result<()> {
+        let (inner, sent) = self.call();
+        self.run(true, sent, inner.apply_change(change)).await
+    }
+
+    async fn add_txt_value(&self, host: &String, value: &str) -> Result<()> {
+        let (inner, sent) = self.call();
+        self.run(true, sent, inner.add_txt_value(host, value)).await
+    }
+
+    async fn remove_txt_value(&self, host: &String, value: &str) -> Result<()> {
+        let (inner, sent) = self.call();
+        self.run(true, sent, inner.remove_txt_value(host, value)).await
+    }
+
+    async fn get_txt_record(&self, host: &String) -> Result<Option<String>> {
+        self.run(false, None, self.inner.get_txt_record(host)).await
+    }
+
+    async fn create_txt_record(&self, host: &String, record: &String) -> Result<()> {
+        let (inner, sent) = self.call();
+        self.run(true, sent, inner.create_txt_record(host, record)).await
+    }
+
+    async fn update_txt_record(&self, host: &String, record: &String) -> Result<()> {
+        let (inner, sent) = self.call();
+        self.run(true, sent, inner.update_txt_record(host, record)).await
+    }
+
+    async fn delete_txt_record(&self, host: &String) -> Result<()> {
+        let (inner, sent) = self.call();
+        self.run(true, sent, inner.delete_txt_record(host)).await
+    }
+
+    async fn get_a_record(&self, host: &String) -> Result<Option<Ipv4Addr>> {
+        self.run(false, None, self.inner.get_a_record(host)).await
+    }
+
+    async fn create_a_record(&self, host: &String, record: &Ipv4Addr) -> Result<()> {
+        let (inner, sent) = self.call();
+        self.run(true, sent, inner.create_a_record(host, record)).await
+    }
+
+    async fn update_a_record(&self, host: &String, record: &Ipv4Addr) -> Result<()> {
+        let (inner, sent) = self.call();
+        self.run(true, sent, inner.update_a_record(host, record)).await
+    }
+
+    async fn delete_a_record(&self, host: &String) -> Result<()> {
+        let (inner, sent) = self.call();
+        self.run(true, sent, inner.delete_a_record(host)).await
+    }
+
+    async fn get_aaaa_record(&self, host: &String) -> Result<Option<Ipv6Addr>> {
+        self.run(false, None, self.inner.get_aaaa_record(host)).await
+    }
+
+    async fn create_aaaa_record(&self, host: &String, record: &Ipv6Addr) -> Result<()> {
+        let (inner, sent) = self.call();
+        self.run(true, sent, inner.create_aaaa_record(host, record)).await
+    }
+
+    async fn update_aaaa_record(&self, host: &String, record: &Ipv6Addr) -> Result<()> {
+        let (inner, sent) = self.call();
+        self.run(true, sent, inner.update_aaaa_record(host, record)).await
+    }
+
+    async fn delete_aaaa_record(&self, host: &String) -> Result<()> {
+        let (inner, sent) = self.call();
+        self.run(true, sent, inner.delete_aaaa_record(host)).await
+    }
+}
+
+
+#[cfg(all(test, feature = "dnsimple"))]
+mod tests {
+    use super::*;
+    use crate::{async_impl::http::tests::serve_delayed, dnsimple, Config};
+
+    fn get_client(url: String) -> Native<dnsimple::Dnsimple> {
+        let config = Config {
+            domain: "example.com".to_string(),
+            dry_run: false,
+        };
+        let auth = dnsimple::Auth { key: "token".to_string() };
+        Native::wrap(dnsimple::Dnsimple::new_with_endpoint(config, auth, Some(1010), url.leak()))
+    }
+
+    #[test]
+    fn test_deadline_during_mutation() {
+        let (url, _server) = serve_delayed(Duration::from_millis(500), vec![
+            "HTTP/1.1 201 Created\r\nContent-Length: 2\r\n\r\n{}".to_string(),
+        ]);
+        let client = get_client(url);
+
+        let start = Instant::now();
+        let result = future::block_on(client.with_timeout(Duration::from_millis(100))
+                                      .create_a_record(&"www".to_string(), &Ipv4Addr::new(10, 0, 0, 1)));
+        assert!(matches!(result, Err(Error::DeadlineExceeded(MutationState::MaybeApplied))));
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_deadline_during_lookup() {
+        // update_record() looks up the record before changing it.
+        let (url, _server) = serve_delayed(Duration::from_millis(500), vec![
+            "HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\n{\"data\":[]}".to_string(),
+        ]);
+        let client = get_client(url);
+
+        let result = future::block_on(client.with_timeout(Duration::from_millis(100))
+                                      .update_a_record(&"www".to_string(), &Ipv4Addr::new(10, 0, 0, 1)));
+        assert!(matches!(result, Err(Error::DeadlineExceeded(MutationState::NotApplied))));
+    }
+
+    #[test]
+    fn test_state_per_operation() -> Result<()> {
+        // The first change completes; the second times out during its
+        // lookup, so wasn't applied regardless of the first.
+        let (url, _server) = serve_delayed(Duration::from_millis(250), vec![
+            "HTTP/1.1 201 Created\r\nContent-Length: 2\r\n\r\n{}".to_string(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\n{\"data\":[]}".to_string(),
+        ]);
+        let client = get_client(url).with_timeout(Duration::from_millis(400));
+
+        future::block_on(client.create_a_record(&"www".to_string(), &Ipv4Addr::new(10, 0, 0, 1)))?;
+        let result = future::block_on(client.update_a_record(&"www".to_string(), &Ipv4Addr::new(10, 0, 0, 2)));
+        assert!(matches!(result, Err(Error::DeadlineExceeded(MutationState::NotApplied))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_within_deadline() -> Result<()> {
+        let (url, server) = serve_delayed(Duration::ZERO, vec![
+            "HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\n{\"data\":[]}".to_string(),
+        ]);
+        let client = get_client(url);
+
+        let ip = future::block_on(client.with_timeout(Duration::from_secs(10))
+                                  .get_a_record(&"www".to_string()))?;
+        assert_eq!(None, ip);
+        server.join().unwrap();
+
+        Ok(())
+    }
+}
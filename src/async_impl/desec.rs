@@ -1,33 +1,20 @@
-use std::sync::Arc;
-use std::fmt::Display;
-
-use blocking::unblock;
-use serde::{de::DeserializeOwned, Serialize};
-
+use crate::async_impl::Native;
 use crate::desec as sync;
-use crate::{async_provider_impl, Config, DnsProvider};
-use crate::{errors::Result, RecordType};
-
-use crate::async_impl::AsyncDnsProvider;
+use crate::Config;
 
 
 pub use crate::desec::Auth;
 
-pub struct DeSec {
-    inner: Arc<sync::DeSec>,
-}
+/// Native async `DeSec` provider.
+pub type DeSec = Native<sync::DeSec>;
 
 impl DeSec {
+    /// Create a new async `DeSec` provider.
     pub fn new(config: Config, auth: Auth) -> Self {
-        let inner = sync::DeSec::new(config, auth);
-        Self {
-            inner: Arc::new(inner)
-        }
+        Native::wrap(sync::DeSec::new(config, auth))
     }
 }
 
-async_provider_impl!(DeSec);
-
 
 #[cfg(test)]
 mod tests {
@@ -1,34 +1,20 @@
-
-use std::sync::Arc;
-use std::fmt::Display;
-
-use blocking::unblock;
-use serde::{de::DeserializeOwned, Serialize};
-
+use crate::async_impl::Native;
 use crate::digitalocean as sync;
-use crate::{async_provider_impl, Config, DnsProvider};
-use crate::{errors::Result, RecordType};
-
-use crate::async_impl::AsyncDnsProvider;
+use crate::Config;
 
 
 pub use crate::digitalocean::Auth;
 
-pub struct DigitalOcean {
-    inner: Arc<sync::DigitalOcean>,
-}
+/// Native async `DigitalOcean` provider.
+pub type DigitalOcean = Native<sync::DigitalOcean>;
 
 impl DigitalOcean {
+    /// Create a new async `DigitalOcean` provider.
     pub fn new(config: Config, auth: Auth) -> Self {
-        let inner = sync::DigitalOcean::new(config, auth);
-        Self {
-            inner: Arc::new(inner)
-        }
+        Native::wrap(sync::DigitalOcean::new(config, auth))
     }
 }
 
-async_provider_impl!(DigitalOcean);
-
 
 #[cfg(test)]
 mod tests {
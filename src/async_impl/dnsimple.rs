@@ -1,45 +1,30 @@
-use std::sync::Arc;
-use std::fmt::Display;
-
-use blocking::unblock;
-use serde::{de::DeserializeOwned, Serialize};
-
+use crate::async_impl::Native;
 use crate::dnsimple::{self as sync, API_BASE};
-use crate::{async_provider_impl, Config, DnsProvider};
-use crate::{errors::Result, RecordType};
-
-use crate::async_impl::AsyncDnsProvider;
+use crate::Config;
 
 
 pub use crate::dnsimple::Auth;
 
-/// Async wrapper around the synchronous `Dnsimple` provider.
-pub struct Dnsimple {
-    inner: Arc<sync::Dnsimple>,
-}
+/// Native async `Dnsimple` provider.
+pub type Dnsimple = Native<sync::Dnsimple>;
 
 impl Dnsimple {
-    /// Create a new async `Dnsimple` wrapper using the default endpoint.
+    /// Create a new async `Dnsimple` provider using the default endpoint.
     pub fn new(config: Config, auth: Auth, acc: Option<u32>) -> Self {
         Self::new_with_endpoint(config, auth, acc, API_BASE)
     }
 
     fn new_with_endpoint(config: Config, auth: Auth, acc: Option<u32>, endpoint: &'static str) -> Self {
-        let inner = sync::Dnsimple::new_with_endpoint(config, auth, acc, endpoint);
-        Self {
-            inner: Arc::new(inner)
-        }
+        Native::wrap(sync::Dnsimple::new_with_endpoint(config, auth, acc, endpoint))
     }
 }
 
-async_provider_impl!(Dnsimple);
-
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::generate_async_tests;
-    use std::env;
+    use crate::{async_impl::{http::tests::serve, AsyncDnsProvider}, errors::Result, generate_async_tests};
+    use std::{env, net::Ipv4Addr};
 
     const TEST_API: &str = "https://api.sandbox.dnsimple.com/v2";
 
@@ -55,4 +40,29 @@ mod tests {
 
     generate_async_tests!("test_dnsimple");
 
+    #[test]
+    fn test_native_local() -> Result<()> {
+        let body = r#"{"data": [{"id": 1, "zone_id": "example.com", "name": "www", "content": "10.9.8.7", "ttl": 60,
+                                 "type": "A", "created_at": "2025-09-20T01:10:32Z", "updated_at": "2025-09-20T01:10:32Z"}]}"#;
+        let (url, server) = serve(vec![
+            format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{body}", body.len()),
+        ]);
+
+        let config = Config {
+            domain: "example.com".to_string(),
+            dry_run: false,
+        };
+        let auth = Auth { key: "token".to_string() };
+        let client = Dnsimple::new_with_endpoint(config, auth, Some(1010), url.leak());
+
+        let ip = futures::executor::block_on(client.get_a_record(&"www".to_string()))?;
+        assert_eq!(Some(Ipv4Addr::new(10, 9, 8, 7)), ip);
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("GET /1010/zones/example.com/records?name=www&type=A HTTP/1.1\r\n"));
+        assert!(requests[0].contains("authorization: Bearer token\r\n"));
+
+        Ok(())
+    }
+
 }
@@ -1,39 +1,24 @@
-use std::sync::Arc;
-use std::fmt::Display;
-
-use blocking::unblock;
-use serde::{de::DeserializeOwned, Serialize};
-
+use crate::async_impl::Native;
 use crate::dnsmadeeasy::{self as sync, API_BASE};
-use crate::{async_provider_impl, Config, DnsProvider};
-use crate::{errors::Result, RecordType};
-
-use crate::async_impl::AsyncDnsProvider;
+use crate::Config;
 
 
 pub use crate::dnsmadeeasy::Auth;
 
-/// Async wrapper around the synchronous `DnsMadeEasy` provider.
-pub struct DnsMadeEasy {
-    inner: Arc<sync::DnsMadeEasy>,
-}
+/// Native async `DnsMadeEasy` provider.
+pub type DnsMadeEasy = Native<sync::DnsMadeEasy>;
 
 impl DnsMadeEasy {
-    /// Create a new async `DnsMadeEasy` wrapper using the default endpoint.
+    /// Create a new async `DnsMadeEasy` provider using the default endpoint.
     pub fn new(config: Config, auth: Auth) -> Self {
         Self::new_with_endpoint(config, auth, API_BASE)
     }
 
     fn new_with_endpoint(config: Config, auth: Auth, endpoint: &'static str) -> Self {
-        let inner = sync::DnsMadeEasy::new_with_endpoint(config, auth, endpoint);
-        Self {
-            inner: Arc::new(inner)
-        }
+        Native::wrap(sync::DnsMadeEasy::new_with_endpoint(config, auth, endpoint))
     }
 }
 
-async_provider_impl!(DnsMadeEasy);
-
 
 #[cfg(test)]
 mod tests {
@@ -1,35 +1,20 @@
-use std::sync::Arc;
-use std::fmt::Display;
-
-use blocking::unblock;
-use serde::{de::DeserializeOwned, Serialize};
-
+use crate::async_impl::Native;
 use crate::gandi as sync;
-use crate::{async_provider_impl, Config, DnsProvider};
-use crate::{errors::Result, RecordType};
+use crate::Config;
 
-use crate::async_impl::AsyncDnsProvider;
 
 pub use crate::gandi::Auth;
 
-/// Async wrapper around the synchronous `Gandi` provider.
-pub struct Gandi {
-    inner: Arc<sync::Gandi>,
-}
+/// Native async `Gandi` provider.
+pub type Gandi = Native<sync::Gandi>;
 
 impl Gandi {
-    /// Create a new async `Gandi` wrapper.
+    /// Create a new async `Gandi` provider.
     pub fn new(config: Config, auth: Auth) -> Self {
-        let inner = sync::Gandi::new(config, auth);
-        Self {
-            inner: Arc::new(inner)
-        }
+        Native::wrap(sync::Gandi::new(config, auth))
     }
-
 }
 
-async_provider_impl!(Gandi);
-
 
 #[cfg(test)]
 mod tests {
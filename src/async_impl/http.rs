@@ -0,0 +1,394 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use async_io::Timer;
+use async_net::TcpStream;
+use futures_lite::{future, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use futures_rustls::{
+    rustls::{crypto::ring, pki_types::ServerName, ClientConfig, RootCertStore},
+    TlsConnector,
+};
+use tracing::debug;
+use ureq::http::{header::{CONNECTION, CONTENT_LENGTH, HOST, TRANSFER_ENCODING}, HeaderName, HeaderValue};
+
+use crate::{errors::{Error, Result}, http::HttpClient};
+
+pub use ureq::http::{Request, Response};
+
+
+/// A runtime-agnostic asynchronous HTTP transport.
+///
+/// The native async providers perform all their requests through
+/// this trait, so any async HTTP stack (hyper, reqwest, or a
+/// runtime-specific client on thread-per-core runtimes) can be
+/// plugged in. Requests without a body (e.g. `GET`) have a `None`
+/// body; the response body must be fully read before returning.
+///
+/// A default implementation is provided by [`Client`].
+#[async_trait::async_trait]
+pub trait AsyncHttpClient: Send + Sync {
+    async fn execute(&self, req: Request<Option<String>>) -> Result<Response<String>>;
+}
+
+impl<'a> HttpClient for dyn AsyncHttpClient + 'a {
+    fn execute(&self, req: Request<Option<String>>) -> impl Future<Output = Result<Response<String>>> + Send {
+        AsyncHttpClient::execute(self, req)
+    }
+}
+
+
+/// The default time allowed to connect, including the TLS handshake.
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// The default time allowed between reads of the response.
+pub const READ_TIMEOUT: Duration = Duration::from_secs(30);
+/// The default maximum size of a response, including headers.
+pub const MAX_RESPONSE_SIZE: usize = 16 * 1024 * 1024;
+
+/// The default `AsyncHttpClient`.
+///
+/// A minimal HTTP/1.1 client over `async-net` and rustls. It is not
+/// tied to any particular runtime, and opens a new connection per
+/// request. Connecting and each read of the response are bounded by
+/// timeouts, and responses larger than a maximum size are rejected;
+/// see the `with_*()` methods.
+#[derive(Clone)]
+pub struct Client {
+    tls: TlsConnector,
+    connect_timeout: Duration,
+    read_timeout: Duration,
+    max_response_size: usize,
+}
+
+impl Client {
+    /// Create a new client using the bundled webpki root certificates.
+    pub fn new() -> Self {
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        // Select the provider explicitly, as other crates in the
+        // tree may enable a different default.
+        let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .expect("Default TLS protocol versions should be supported")
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        Self {
+            tls: TlsConnector::from(Arc::new(config)),
+            connect_timeout: CONNECT_TIMEOUT,
+            read_timeout: READ_TIMEOUT,
+            max_response_size: MAX_RESPONSE_SIZE,
+        }
+    }
+
+    /// Set the time allowed to connect, including the TLS handshake.
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Set the time allowed between reads of the response.
+    pub fn with_read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Set the maximum size of a response, including headers.
+    pub fn with_max_response_size(mut self, size: usize) -> Self {
+        self.max_response_size = size;
+        self
+    }
+
+    async fn exchange<S>(&self, mut stream: S, request: &[u8]) -> Result<Vec<u8>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin
+    {
+        within(self.read_timeout, "Sending request", async {
+            stream.write_all(request).await?;
+            stream.flush().await?;
+            Ok(())
+        }).await?;
+
+        // We request `Connection: close`, so the response is complete at EOF.
+        let mut raw = Vec::new();
+        let mut buf = [0; 8192];
+        loop {
+            let n = within(self.read_timeout, "Reading response", async {
+                Ok(stream.read(&mut buf).await?)
+            }).await?;
+            if n == 0 {
+                return Ok(raw);
+            }
+            if raw.len() + n > self.max_response_size {
+                return Err(Error::HttpError(format!("Response exceeds {} bytes", self.max_response_size)));
+            }
+            raw.extend_from_slice(&buf[..n]);
+        }
+    }
+}
+
+/// Run `op`, failing if it takes longer than `timeout`.
+async fn within<T>(timeout: Duration, what: &str, op: impl Future<Output = Result<T>>) -> Result<T> {
+    let expired = async {
+        Timer::after(timeout).await;
+        Err(Error::HttpError(format!("{what} timed out after {timeout:?}")))
+    };
+    future::or(op, expired).await
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait::async_trait]
+impl AsyncHttpClient for Client {
+    async fn execute(&self, req: Request<Option<String>>) -> Result<Response<String>> {
+        let uri = req.uri();
+        let tls = match uri.scheme_str() {
+            Some("https") => true,
+            Some("http") => false,
+            _ => return Err(Error::UrlError(format!("Unsupported URL: {uri}"))),
+        };
+        let host = uri.host()
+            .ok_or(Error::UrlError(format!("No host in URL: {uri}")))?
+            .to_string();
+        let port = uri.port_u16()
+            .unwrap_or(if tls { 443 } else { 80 });
+
+        debug!("{} {uri}", req.method());
+        let request = serialise_request(&req, &host)?;
+
+        let connecting = format!("Connecting to {host}:{port}");
+        let stream = within(self.connect_timeout, &connecting, async {
+            Ok(TcpStream::connect((host.as_str(), port)).await?)
+        }).await?;
+        let raw = if tls {
+            let name = ServerName::try_from(host)
+                .map_err(|e| Error::UrlError(e.to_string()))?;
+            let stream = within(self.connect_timeout, &connecting, async {
+                Ok(self.tls.connect(name, stream).await?)
+            }).await?;
+            self.exchange(stream, &request).await?
+        } else {
+            self.exchange(stream, &request).await?
+        };
+
+        parse_response(&raw)
+    }
+}
+
+
+fn serialise_request(req: &Request<Option<String>>, host: &str) -> Result<Vec<u8>> {
+    let path = req.uri().path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or("/");
+
+    let mut out = format!("{} {path} HTTP/1.1\r\n", req.method());
+    if !req.headers().contains_key(HOST) {
+        out.push_str(&format!("{HOST}: {host}\r\n"));
+    }
+    for (k, v) in req.headers() {
+        let v = v.to_str()
+            .map_err(|e| Error::HttpError(format!("Invalid header value for {k}: {e}")))?;
+        out.push_str(&format!("{k}: {v}\r\n"));
+    }
+    let body = req.body().as_deref().unwrap_or("");
+    if req.body().is_some() {
+        out.push_str(&format!("{CONTENT_LENGTH}: {}\r\n", body.len()));
+    }
+    out.push_str(&format!("{CONNECTION}: close\r\n\r\n"));
+    out.push_str(body);
+
+    Ok(out.into_bytes())
+}
+
+fn parse_response(raw: &[u8]) -> Result<Response<String>> {
+    let mut headers = [httparse::EMPTY_HEADER; 64];
+    let mut resp = httparse::Response::new(&mut headers);
+    let offset = match resp.parse(raw) {
+        Ok(httparse::Status::Complete(offset)) => offset,
+        Ok(httparse::Status::Partial) => return Err(Error::HttpError("Truncated HTTP response".to_string())),
+        Err(e) => return Err(Error::HttpError(format!("Invalid HTTP response: {e}"))),
+    };
+
+    let mut builder = Response::builder()
+        .status(resp.code.unwrap_or_default());
+    let mut chunked = false;
+    let mut length = None;
+    for h in resp.headers.iter() {
+        let name = HeaderName::from_bytes(h.name.as_bytes())?;
+        let value = HeaderValue::from_bytes(h.value)?;
+        if name == TRANSFER_ENCODING {
+            chunked = value.as_bytes().eq_ignore_ascii_case(b"chunked");
+        } else if name == CONTENT_LENGTH {
+            length = value.to_str().ok()
+                .and_then(|l| l.parse::<usize>().ok());
+        }
+        builder = builder.header(name, value);
+    }
+
+    let body = &raw[offset..];
+    let body = if chunked {
+        decode_chunked(body)?
+    } else if let Some(len) = length {
+        body.get(..len)
+            .ok_or(Error::HttpError("Truncated HTTP response body".to_string()))?
+            .to_vec()
+    } else {
+        body.to_vec()
+    };
+    let body = String::from_utf8(body)
+        .map_err(|e| Error::HttpError(format!("Response body is not UTF-8: {e}")))?;
+
+    builder.body(body)
+        .map_err(|e| Error::HttpError(format!("Invalid HTTP response: {e}")))
+}
+
+fn decode_chunked(mut body: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    loop {
+        let (start, len) = match httparse::parse_chunk_size(body) {
+            Ok(httparse::Status::Complete(chunk)) => chunk,
+            _ => return Err(Error::HttpError("Invalid chunked response body".to_string())),
+        };
+        let len = usize::try_from(len)
+            .map_err(|_| Error::HttpError("Chunk too large".to_string()))?;
+        if len == 0 {
+            return Ok(out);
+        }
+        let end = start.checked_add(len)
+            .ok_or(Error::HttpError("Invalid chunked response body".to_string()))?;
+        let chunk = body.get(start..end)
+            .ok_or(Error::HttpError("Truncated chunked response body".to_string()))?;
+        out.extend_from_slice(chunk);
+        // Skip the chunk and its trailing CRLF
+        body = body.get(end + 2..)
+            .ok_or(Error::HttpError("Truncated chunked response body".to_string()))?;
+    }
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::{io::{Read, Write}, net::TcpListener, thread, time::Duration};
+    use ureq::http::StatusCode;
+
+    /// Serve canned HTTP responses on a local port, one per
+    /// connection, returning the base URL and the received requests.
+    pub(crate) fn serve(responses: Vec<String>) -> (String, thread::JoinHandle<Vec<String>>) {
+        serve_delayed(Duration::ZERO, responses)
+    }
+
+    /// As `serve()`, but wait `delay` before each response.
+    pub(crate) fn serve_delayed(delay: Duration, responses: Vec<String>) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for resp in responses {
+                let (mut sock, _) = listener.accept().unwrap();
+                let mut buf = [0; 8192];
+                let n = sock.read(&mut buf).unwrap();
+                requests.push(String::from_utf8_lossy(&buf[..n]).to_string());
+                thread::sleep(delay);
+                // The client may have given up by now
+                let _ = sock.write_all(resp.as_bytes());
+            }
+            requests
+        });
+
+        (url, handle)
+    }
+
+    /// A `200 OK` response carrying `body` as JSON.
+    pub(crate) fn json_ok(body: &str) -> String {
+        format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}", body.len())
+    }
+
+    /// The JSON body of a request received by `serve()`.
+    pub(crate) fn json_body(request: &str) -> serde_json::Value {
+        let (_, body) = request.split_once("\r\n\r\n").unwrap();
+        serde_json::from_str(body).unwrap()
+    }
+
+    #[test]
+    fn test_content_length() -> Result<()> {
+        let (url, server) = serve(vec![
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 11\r\n\r\n{\"a\": true}".to_string(),
+        ]);
+
+        let req = Request::post(format!("{url}/path?q=1"))
+            .header("Authorization", "Bearer xyz")
+            .body(Some("{}".to_string()))
+            .unwrap();
+        let resp = futures::executor::block_on(Client::new().execute(req))?;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!("{\"a\": true}", resp.body());
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("POST /path?q=1 HTTP/1.1\r\n"));
+        assert!(requests[0].contains("authorization: Bearer xyz\r\n"));
+        assert!(requests[0].contains("content-length: 2\r\n"));
+        assert!(requests[0].ends_with("\r\n\r\n{}"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_chunked() -> Result<()> {
+        let (url, server) = serve(vec![
+            "HTTP/1.1 404 Not Found\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nnot \r\n5\r\nfound\r\n0\r\n\r\n".to_string(),
+        ]);
+
+        let req = Request::get(format!("{url}/")).body(None).unwrap();
+        let resp = futures::executor::block_on(Client::new().execute(req))?;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+        assert_eq!("not found", resp.body());
+
+        server.join().unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_chunk_size_overflow() {
+        let resp = decode_chunked(b"ffffffffffffffff\r\nnot found\r\n0\r\n\r\n");
+        assert!(matches!(resp, Err(Error::HttpError(msg)) if msg.contains("Invalid")));
+    }
+
+    #[test]
+    fn test_bad_scheme() {
+        let req = Request::get("ftp://example.com/").body(None).unwrap();
+        let resp = futures::executor::block_on(Client::new().execute(req));
+        assert!(matches!(resp, Err(Error::UrlError(_))));
+    }
+
+    #[test]
+    fn test_read_timeout() {
+        let (url, _server) = serve_delayed(Duration::from_millis(500), vec![json_ok("{}")]);
+        let client = Client::new().with_read_timeout(Duration::from_millis(100));
+
+        let req = Request::get(url).body(None).unwrap();
+        let resp = futures::executor::block_on(client.execute(req));
+        assert!(matches!(resp, Err(Error::HttpError(msg)) if msg.contains("timed out")));
+    }
+
+    #[test]
+    fn test_max_response_size() -> Result<()> {
+        let body = "x".repeat(1000);
+        let (url, server) = serve(vec![json_ok(&body), json_ok(&body)]);
+
+        let req = Request::get(url.clone()).body(None).unwrap();
+        let resp = futures::executor::block_on(Client::new().with_max_response_size(500).execute(req));
+        assert!(matches!(resp, Err(Error::HttpError(msg)) if msg.contains("exceeds")));
+
+        let req = Request::get(url).body(None).unwrap();
+        let resp = futures::executor::block_on(Client::new().with_max_response_size(2000).execute(req))?;
+        assert_eq!(body, *resp.body());
+
+        server.join().unwrap();
+        Ok(())
+    }
+}
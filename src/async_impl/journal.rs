@@ -0,0 +1,260 @@
+use std::{fmt::Display, net::{Ipv4Addr, Ipv6Addr}};
+
+use blocking::unblock;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::async_impl::{bulk::apply_record_change, AsyncDnsProvider};
+use crate::journal::{completed, old_value, record_after, Journal};
+use crate::{change::{Change, ChangeSet}, errors::Result, RecordType, ZoneListing};
+
+pub use crate::journal::{JsonLinesJournal, MutationEvent, MutationHook, Operation, Outcome, Phase};
+
+
+/// Async provider wrapper that reports all changes to a `MutationHook`.
+///
+/// See `zone_update::journal::Journaled` for details. Hooks are run
+/// on the `blocking` thread pool, so may block on file I/O.
+pub struct Journaled<P> {
+    inner: P,
+    journal: Journal,
+}
+
+impl<P: AsyncDnsProvider> Journaled<P> {
+    /// Wrap `inner`; `provider` and `zone` are recorded in each event.
+    pub fn new(inner: P, provider: &str, zone: &str, hook: impl MutationHook + 'static) -> Self {
+        Self {
+            inner,
+            journal: Journal::new(provider, zone, hook),
+        }
+    }
+
+    /// Return the wrapped provider.
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+
+    async fn before(&self, operation: Operation, rtype: RecordType, host: &str,
+                    old: Option<String>, new: Option<String>) -> Result<MutationEvent>
+    {
+        let event = self.journal.event(operation, rtype, host, old, new);
+        let hook = self.journal.hook();
+        unblock(move || hook.on_mutation(&event).map(|()| event)).await
+    }
+
+    async fn after(&self, event: MutationEvent, result: &Result<()>) {
+        let event = completed(event, result);
+        let hook = self.journal.hook();
+        unblock(move || record_after(&*hook, &event)).await
+    }
+}
+
+#[async_trait::async_trait]
+impl<P: AsyncDnsProvider> AsyncDnsProvider for Journaled<P> {
+
+    async fn get_record<T>(&self, rtype: RecordType, host: &String) -> Result<Option<T>>
+    where
+        T: DeserializeOwned + Send + Sync + 'static
+    {
+        self.inner.get_record(rtype, host).await
+    }
+
+    async fn create_record<T>(&self, rtype: RecordType, host: &String, record: &T) -> Result<()>
+    where
+        T: Serialize + DeserializeOwned + Display + Clone + Send + Sync + 'static
+    {
+        let old = old_value(self.inner.get_record::<String>(rtype, host).await, host);
+        let event = self.before(Operation::Create, rtype, host, old, Some(record.to_string())).await?;
+        let result = self.inner.create_record(rtype, host, record).await;
+        self.after(event, &result).await;
+        result
+    }
+
+    async fn update_record<T>(&self, rtype: RecordType, host: &String, record: &T) -> Result<()>
+    where
+        T: Serialize + DeserializeOwned + Display + Clone + Send + Sync + 'static
+    {
+        let old = old_value(self.inner.get_record::<String>(rtype, host).await, host);
+        let event = self.before(Operation::Update, rtype, host, old, Some(record.to_string())).await?;
+        let result = self.inner.update_record(rtype, host, record).await;
+        self.after(event, &result).await;
+        result
+    }
+
+    async fn delete_record(&self, rtype: RecordType, host: &String) -> Result<()> {
+        let old = old_value(self.inner.get_record::<String>(rtype, host).await, host);
+        let event = self.before(Operation::Delete, rtype, host, old, None).await?;
+        let result = self.inner.delete_record(rtype, host).await;
+        self.after(event, &result).await;
+        result
+    }
+
+    async fn delete_all_records(&self, rtype: RecordType, host: &String) -> Result<()> {
+        let old = old_value(self.inner.get_record::<String>(rtype, host).await, host);
+        let event = self.before(Operation::DeleteAll, rtype, host, old, None).await?;
+        let result = self.inner.delete_all_records(rtype, host).await;
+        self.after(event, &result).await;
+        result
+    }
+
+    async fn list_zone(&self) -> Result<ZoneListing> {
+        self.inner.list_zone().await
+    }
+
+    fn record_types(&self) -> &'static [RecordType] {
+        self.inner.record_types()
+    }
+
+    // Each change is journaled, with the outcome of the set as a
+    // whole.
+    async fn apply_changes(&self, changes: &ChangeSet) -> Result<()> {
+        let mut events = Vec::with_capacity(changes.changes.len());
+        for change in &changes.changes {
+            let (rtype, host) = (change.rtype(), change.host().to_string());
+            let old = old_value(self.inner.get_record::<String>(rtype, &host).await, &host);
+            match self.before(Operation::of(change), rtype, &host, old, change.value().map(str::to_string)).await {
+                Ok(event) => events.push(event),
+                Err(e) => {
+                    let result = Err(e);
+                    for event in events {
+                        self.after(event, &result).await;
+                    }
+                    return result;
+                }
+            }
+        }
+        let result = self.inner.apply_changes(changes).await;
+        for event in events {
+            self.after(event, &result).await;
+        }
+        result
+    }
+
+    fn check_changes(&self, changes: &[Change]) -> Result<()> {
+        self.inner.check_changes(changes)
+    }
+
+    async fn apply_change(&self, change: &Change) -> Result<()> {
+        apply_record_change(self, change).await
+    }
+
+    async fn get_txt_record(&self, host: &String) -> Result<Option<String>> {
+        self.inner.get_txt_record(host).await
+    }
+
+    async fn create_txt_record(&self, host: &String, record: &String) -> Result<()> {
+        self.create_record(RecordType::TXT, host, &crate::ensure_quotes(record)).await
+    }
+
+    async fn update_txt_record(&self, host: &String, record: &String) -> Result<()> {
+        self.update_record(RecordType::TXT, host, &crate::ensure_quotes(record)).await
+    }
+
+    async fn delete_txt_record(&self, host: &String) -> Result<()> {
+        self.delete_record(RecordType::TXT, host).await
+    }
+
+    async fn add_txt_value(&self, host: &String, value: &str) -> Result<()> {
+        let event = self.before(Operation::Create, RecordType::TXT, host, None, Some(crate::ensure_quotes(value))).await?;
+        let result = self.inner.add_txt_value(host, value).await;
+        self.after(event, &result).await;
+        result
+    }
+
+    async fn remove_txt_value(&self, host: &String, value: &str) -> Result<()> {
+        let event = self.before(Operation::Delete, RecordType::TXT, host, Some(crate::ensure_quotes(value)), None).await?;
+        let result = self.inner.remove_txt_value(host, value).await;
+        self.after(event, &result).await;
+        result
+    }
+
+    async fn get_a_record(&self, host: &String) -> Result<Option<Ipv4Addr>> {
+        self.inner.get_a_record(host).await
+    }
+
+    async fn create_a_record(&self, host: &String, record: &Ipv4Addr) -> Result<()> {
+        self.create_record(RecordType::A, host, record).await
+    }
+
+    async fn update_a_record(&self, host: &String, record: &Ipv4Addr) -> Result<()> {
+        self.update_record(RecordType::A, host, record).await
+    }
+
+    async fn delete_a_record(&self, host: &String) -> Result<()> {
+        self.delete_record(RecordType::A, host).await
+    }
+
+    async fn get_aaaa_record(&self, host: &String) -> Result<Option<Ipv6Addr>> {
+        self.inner.get_aaaa_record(host).await
+    }
+
+    async fn create_aaaa_record(&self, host: &String, record: &Ipv6Addr) -> Result<()> {
+        self.create_record(RecordType::AAAA, host, record).await
+    }
+
+    async fn update_aaaa_record(&self, host: &String, record: &Ipv6Addr) -> Result<()> {
+        self.update_record(RecordType::AAAA, host, record).await
+    }
+
+    async fn delete_aaaa_record(&self, host: &String) -> Result<()> {
+        self.delete_record(RecordType::AAAA, host).await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{async_impl::Unblocked, tests::{Batching, MemoryProvider}};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_async_events_recorded() -> Result<()> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let hook = move |ev: &MutationEvent| {
+            sink.lock().unwrap().push(ev.clone());
+            Ok(())
+        };
+        let inner = Unblocked::new(MemoryProvider::new("example.com"));
+        let client = Journaled::new(inner, "memory", "example.com", hook);
+
+        let host = "www".to_string();
+        futures::executor::block_on(async {
+            client.create_a_record(&host, &Ipv4Addr::new(10, 0, 0, 1)).await?;
+            client.update_a_record(&host, &Ipv4Addr::new(10, 0, 0, 2)).await?;
+            client.delete_a_record(&host).await
+        })?;
+
+        let events = events.lock().unwrap();
+        assert_eq!(6, events.len());
+        assert_eq!(Some("10.0.0.1".to_string()), events[2].old);
+        assert_eq!(Some("10.0.0.2".to_string()), events[4].old);
+        assert!(events.iter().skip(1).step_by(2).all(|e| e.outcome == Outcome::Success));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_async_apply_changes_batched() -> Result<()> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let hook = move |ev: &MutationEvent| {
+            sink.lock().unwrap().push(ev.clone());
+            Ok(())
+        };
+        let client = Journaled::new(Unblocked::new(Batching::new("example.com")), "memory", "example.com", hook);
+
+        let changes: ChangeSet = vec![
+            Change::Create { rtype: RecordType::A, host: "www".to_string(), value: "10.0.0.1".to_string() },
+            Change::Create { rtype: RecordType::A, host: "mail".to_string(), value: "10.0.0.2".to_string() },
+        ].into();
+        futures::executor::block_on(client.apply_changes(&changes))?;
+
+        assert_eq!(vec![changes], *client.into_inner().inner().batches.lock().unwrap());
+        let events = events.lock().unwrap();
+        assert_eq!(4, events.len());
+        assert!(events[2..].iter().all(|e| e.phase == Phase::After && e.outcome == Outcome::Success));
+
+        Ok(())
+    }
+}
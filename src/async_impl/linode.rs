@@ -1,34 +1,20 @@
-
-use std::sync::Arc;
-use std::fmt::Display;
-
-use blocking::unblock;
-use serde::{de::DeserializeOwned, Serialize};
-
+use crate::async_impl::Native;
 use crate::linode as sync;
-use crate::{async_provider_impl, Config, DnsProvider};
-use crate::{errors::Result, RecordType};
-
-use crate::async_impl::AsyncDnsProvider;
+use crate::Config;
 
 
 pub use crate::linode::Auth;
 
-pub struct Linode {
-    inner: Arc<sync::Linode>,
-}
+/// Native async `Linode` provider.
+pub type Linode = Native<sync::Linode>;
 
 impl Linode {
+    /// Create a new async `Linode` provider.
     pub fn new(config: Config, auth: Auth) -> Self {
-        let inner = sync::Linode::new(config, auth);
-        Self {
-            inner: Arc::new(inner)
-        }
+        Native::wrap(sync::Linode::new(config, auth))
     }
 }
 
-async_provider_impl!(Linode);
-
 
 #[cfg(test)]
 mod tests {
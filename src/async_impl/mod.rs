@@ -1,8 +1,15 @@
-use std::{fmt::Display, net::Ipv4Addr};
+use std::{fmt::Display, net::{IpAddr, Ipv4Addr, Ipv6Addr}, sync::Arc};
 
 use serde::{de::DeserializeOwned, Serialize};
 
-use crate::{errors::Result, RecordType};
+use crate::{
+    async_impl::http::AsyncHttpClient,
+    change::{Change, ChangeSet},
+    errors::{Error, Result},
+    address_changes, check_current, ensure_quotes, has_txt_value, strip_quotes, txt_removal,
+    verify::set_values,
+    RecordType, ZoneListing, ZoneRecord,
+};
 
 
 #[cfg(feature = "bunny")]
@@ -24,11 +31,48 @@ pub mod dnsimple;
 #[cfg(feature = "porkbun")]
 pub mod porkbun;
 
+#[cfg(feature = "acme")]
+pub mod acme;
+pub mod bulk;
+pub mod ddns;
+pub mod deadline;
+pub mod http;
+pub mod journal;
+pub mod ownership;
+pub mod prefix;
+pub mod propagation;
+pub mod safety;
+pub mod verify;
+mod unblocked;
+
+pub use unblocked::Unblocked;
+
 
 /// Asynchronous DNS provider trait.
 ///
 /// Mirrors `DnsProvider` with async methods that can be implemented by
 /// async wrappers around synchronous providers or native async clients.
+///
+/// # Cancellation
+///
+/// Dropping an operation's future before it completes (e.g. via a
+/// runtime timeout) has different effects depending on the
+/// implementation:
+///
+/// * `Native` providers stop at the next suspension point and any
+///   in-flight HTTP request is aborted. A change that was already
+///   sent may or may not have been applied by the provider. Note that
+///   some operations are several requests (e.g. `update_record()`
+///   looks up the record before changing it), so may be interrupted
+///   part-way through.
+/// * `Unblocked` providers cannot be interrupted; the operation will
+///   continue on its thread and may apply the change after the future
+///   has been dropped.
+///
+/// In both cases it is unknown whether the change was applied. Use
+/// `deadline::WithDeadline` to bound operations instead; it reports
+/// whether a change may have been applied in
+/// `Error::DeadlineExceeded`.
 #[async_trait::async_trait]
 pub trait AsyncDnsProvider: Send + Sync {
 
@@ -51,6 +95,63 @@ pub trait AsyncDnsProvider: Send + Sync {
 
     async fn delete_all_records(&self, rtype: RecordType, host: &String) -> Result<()>;
 
+    /// Update a DNS record only if its current value is `expected`.
+    /// See `DnsProvider::update_record_if()`.
+    async fn update_record_if<T>(&self, rtype: RecordType, host: &String, expected: &T, record: &T) -> Result<()>
+    where
+        T: Serialize + DeserializeOwned + Display + Clone + Send + Sync + 'static,
+        Self: Sized
+    {
+        let current = self.get_record::<String>(rtype, host).await?;
+        check_current(rtype, host, &expected.to_string(), current)?;
+        self.update_record(rtype, host, record).await
+    }
+
+    /// Delete a DNS record only if its current value is `expected`.
+    /// See `DnsProvider::update_record_if()`.
+    async fn delete_record_if<T>(&self, rtype: RecordType, host: &String, expected: &T) -> Result<()>
+    where
+        T: Serialize + DeserializeOwned + Display + Clone + Send + Sync + 'static,
+        Self: Sized
+    {
+        let current = self.get_record::<String>(rtype, host).await?;
+        check_current(rtype, host, &expected.to_string(), current)?;
+        self.delete_record(rtype, host).await
+    }
+
+    /// List all records in the zone, including those that cannot be
+    /// represented. See `DnsProvider::list_zone()`.
+    async fn list_zone(&self) -> Result<ZoneListing> {
+        Err(Error::Unsupported("listing records".to_string()))
+    }
+
+    /// List all records in the zone. See `DnsProvider::list_records()`.
+    async fn list_records(&self) -> Result<Vec<ZoneRecord>> {
+        self.list_zone().await
+            .map(|zone| zone.records)
+    }
+
+    /// The record types this provider can manage. See
+    /// `DnsProvider::record_types()`.
+    fn record_types(&self) -> &'static [RecordType] {
+        RecordType::ALL
+    }
+
+    /// Apply a set of changes as a unit. See
+    /// `DnsProvider::apply_changes()` and `bulk::apply_sequential()`.
+    async fn apply_changes(&self, changes: &ChangeSet) -> Result<()> {
+        bulk::apply_sequential(self, changes).await
+    }
+
+    /// Check a set of changes without applying them. See
+    /// `DnsProvider::check_changes()`.
+    fn check_changes(&self, _changes: &[Change]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Apply a single change.
+    async fn apply_change(&self, change: &Change) -> Result<()>;
+
     async fn get_txt_record(&self, host: &String) -> Result<Option<String>>;
 
     async fn create_txt_record(&self, host: &String, record: &String) -> Result<()>;
@@ -59,6 +160,41 @@ pub trait AsyncDnsProvider: Send + Sync {
 
     async fn delete_txt_record(&self, host: &String) -> Result<()>;
 
+    /// Get all values of the TXT record set at `host`. See
+    /// `DnsProvider::get_txt_values()`.
+    async fn get_txt_values(&self, host: &String) -> Result<Vec<String>> {
+        match self.get_txt_record(host).await {
+            Ok(value) => Ok(value.into_iter().collect()),
+            Err(Error::UnexpectedRecord(_)) => {
+                let values = set_values(self.list_records().await?, RecordType::TXT, host);
+                Ok(values.iter().map(|v| strip_quotes(v)).collect())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Add a value to the TXT record set at `host`, leaving any other
+    /// values in place. See `DnsProvider::add_txt_value()`.
+    async fn add_txt_value(&self, host: &String, value: &str) -> Result<()> {
+        if has_txt_value(&self.get_txt_values(host).await?, value) {
+            return Ok(())
+        }
+        self.apply_changes(&vec![Change::Create {
+            rtype: RecordType::TXT,
+            host: host.clone(),
+            value: ensure_quotes(value),
+        }].into()).await
+    }
+
+    /// Remove a value from the TXT record set at `host`, leaving any
+    /// other values in place. See `DnsProvider::remove_txt_value()`.
+    async fn remove_txt_value(&self, host: &String, value: &str) -> Result<()> {
+        match txt_removal(host, value, self.get_txt_values(host).await?) {
+            Some(changes) => self.apply_changes(&changes).await,
+            None => Ok(()),
+        }
+    }
+
     async fn get_a_record(&self, host: &String) -> Result<Option<Ipv4Addr>>;
 
     async fn create_a_record(&self, host: &String, record: &Ipv4Addr) -> Result<()>;
@@ -66,103 +202,73 @@ pub trait AsyncDnsProvider: Send + Sync {
     async fn update_a_record(&self, host: &String, record: &Ipv4Addr) -> Result<()>;
 
     async fn delete_a_record(&self, host: &String) -> Result<()>;
-}
 
-#[macro_export]
-macro_rules! async_provider_impl {
-    ($i:ident) => {
-        #[async_trait::async_trait]
-        impl AsyncDnsProvider for $i {
-
-            async fn get_record<T>(&self, rtype: RecordType, host: &String) -> Result<Option<T>>
-            where
-                T: DeserializeOwned + Send + Sync + 'static
-            {
-                let provider = self.inner.clone();
-                let host = host.clone();
-                unblock(move || provider.get_record(rtype, &host)).await
-            }
+    async fn get_aaaa_record(&self, host: &String) -> Result<Option<Ipv6Addr>>;
 
-            async fn create_record<T>(&self, rtype: RecordType, host: &String, record: &T) -> Result<()>
-            where
-                T: Serialize + DeserializeOwned + Display + Clone + Send + Sync + 'static
-            {
-                let provider = self.inner.clone();
-                let host = host.clone();
-                let record = record.clone();
-                unblock(move || provider.create_record(rtype, &host, &record)).await
-            }
+    async fn create_aaaa_record(&self, host: &String, record: &Ipv6Addr) -> Result<()>;
 
-            async fn update_record<T>(&self, rtype: RecordType, host: &String, record: &T) -> Result<()>
-            where
-                T: Serialize + DeserializeOwned + Display + Clone + Send + Sync + 'static
-            {
-                let provider = self.inner.clone();
-                let host = host.clone();
-                let record = record.clone();
-                unblock(move || provider.update_record(rtype, &host, &record)).await
-            }
+    async fn update_aaaa_record(&self, host: &String, record: &Ipv6Addr) -> Result<()>;
 
-            async fn delete_record(&self, rtype: RecordType, host: &String) -> Result<()>
-            {
-                let provider = self.inner.clone();
-                let host = host.clone();
-                unblock(move || provider.delete_record(rtype, &host)).await
-            }
+    async fn delete_aaaa_record(&self, host: &String) -> Result<()>;
 
-            async fn delete_all_records(&self, rtype: RecordType, host: &String) -> Result<()>
-            {
-                let provider = self.inner.clone();
-                let host = host.clone();
-                unblock(move || provider.delete_all_records(rtype, &host)).await
-            }
-
-            async fn get_txt_record(&self, host: &String) -> Result<Option<String>>
-            {
-                self.get_record::<String>(RecordType::TXT, host).await
-                    .map(|opt| opt.map(|s| $crate::strip_quotes(&s)))
-            }
-
-            async fn create_txt_record(&self, host: &String, record: &String) -> Result<()>
-            {
-                self.create_record(RecordType::TXT, host, &$crate::ensure_quotes(record)).await
-            }
-
-            async fn update_txt_record(&self, host: &String, record: &String) -> Result<()>
-            {
-                self.update_record(RecordType::TXT, host, &$crate::ensure_quotes(record)).await
-            }
-
-            async fn delete_txt_record(&self, host: &String) -> Result<()>
-            {
-                self.delete_record(RecordType::TXT, host).await
-            }
-
-            async fn get_a_record(&self, host: &String) -> Result<Option<std::net::Ipv4Addr>>
-            {
-                self.get_record(RecordType::A, host).await
-            }
+    /// Set the A and AAAA records of a dual-stack host. See
+    /// `DnsProvider::set_host_addresses()`.
+    async fn set_host_addresses(&self, host: &String, v4: Option<Ipv4Addr>, v6: Option<Ipv6Addr>) -> Result<()> {
+        let current = [
+            self.get_a_record(host).await?.map(IpAddr::V4),
+            self.get_aaaa_record(host).await?.map(IpAddr::V6),
+        ];
+        let changes = address_changes(host, current, [v4.map(IpAddr::V4), v6.map(IpAddr::V6)]);
+        if changes.is_empty() {
+            return Ok(());
+        }
+        self.apply_changes(&changes).await
+    }
+}
 
-            async fn create_a_record(&self, host: &String, record: &std::net::Ipv4Addr) -> Result<()>
-            {
-                self.create_record(RecordType::A, host, record).await
-            }
 
-            async fn update_a_record(&self, host: &String, record: &std::net::Ipv4Addr) -> Result<()>
-            {
-                self.update_record(RecordType::A, host, record).await
-            }
+/// Native async implementation of a provider.
+///
+/// This drives the provider's operations directly over an
+/// `AsyncHttpClient`, without borrowing a thread from a blocking
+/// pool per call, so is suitable for thread-per-core runtimes. The
+/// provider-specific modules (e.g. `async_impl::cloudflare`) are
+/// aliases of this type with the usual constructors. By default the
+/// built-in `http::Client` is used; an alternative transport can be
+/// supplied with `with_client()`.
+///
+/// For providers that only implement the blocking `DnsProvider`
+/// trait see `Unblocked`.
+pub struct Native<P> {
+    pub(crate) inner: Arc<P>,
+    pub(crate) client: Arc<dyn AsyncHttpClient>,
+}
 
-            async fn delete_a_record(&self, host: &String) -> Result<()>
-            {
-                self.delete_record(RecordType::A, host).await
-            }
+impl<P> Native<P> {
+    /// Wrap a provider using the default HTTP client.
+    pub fn wrap(inner: P) -> Self {
+        Self::wrap_with_client(inner, http::Client::new())
+    }
 
+    /// Wrap a provider using the supplied HTTP client.
+    pub fn wrap_with_client(inner: P, client: impl AsyncHttpClient + 'static) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            client: Arc::new(client),
         }
+    }
+
+    /// Replace the HTTP client used by this provider.
+    pub fn with_client(mut self, client: impl AsyncHttpClient + 'static) -> Self {
+        self.client = Arc::new(client);
+        self
+    }
 
-    };
+    /// Return the wrapped provider.
+    pub fn inner(&self) -> &Arc<P> {
+        &self.inner
+    }
 }
-pub use async_provider_impl;
 
 
 #[cfg(test)]
@@ -313,6 +419,7 @@ mod tests {
             mod smol_tests {
                 use super::*;
                 use $crate::async_impl::tests::*;
+                use $crate::errors::Result;
                 use macro_rules_attribute::apply;
                 use smol_macros::test;
 
@@ -356,6 +463,7 @@ mod tests {
             mod tokio_tests {
                 use super::*;
                 use $crate::async_impl::tests::*;
+                use $crate::errors::Result;
 
                 #[tokio::test]
                 #[test_log::test]
@@ -398,6 +506,7 @@ mod tests {
             mod compio_tests {
                 use super::*;
                 use $crate::async_impl::tests::*;
+                use $crate::errors::Result;
 
                 #[compio::test]
                 #[serial_test::serial]
@@ -436,6 +545,7 @@ mod tests {
             mod monoio_tests {
                 use super::*;
                 use $crate::async_impl::tests::*;
+                use $crate::errors::Result;
 
                 #[monoio::test]
                 #[serial_test::serial]
@@ -474,6 +584,7 @@ mod tests {
             mod glommio_tests {
                 use super::*;
                 use $crate::async_impl::tests::*;
+                use $crate::errors::Result;
 
                 #[test]
                 #[serial_test::serial]
@@ -0,0 +1,263 @@
+use std::{collections::{hash_map::Entry, HashMap}, fmt::Display, net::{Ipv4Addr, Ipv6Addr}};
+
+use chrono::Utc;
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::info;
+
+use crate::async_impl::{bulk::apply_record_change, AsyncDnsProvider};
+use crate::ownership::{exists, Claim};
+use crate::{change::{Change, ChangeSet}, errors::Result, RecordType, ZoneListing};
+
+pub use crate::ownership::{Registry, DEFAULT_PREFIX};
+
+
+/// Async provider wrapper that tracks record ownership in a TXT
+/// registry.
+///
+/// See `zone_update::ownership::Owned` for details.
+pub struct Owned<P> {
+    inner: P,
+    registry: Registry,
+}
+
+impl<P: AsyncDnsProvider> Owned<P> {
+    /// Wrap `inner`, tracking ownership in `registry`.
+    pub fn new(inner: P, registry: Registry) -> Self {
+        Self {
+            inner,
+            registry,
+        }
+    }
+
+    /// The ownership settings.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Return the wrapped provider.
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+
+    async fn claim(&self, rtype: RecordType, host: &String) -> Result<Claim> {
+        let reg_host = self.registry.registry_host(rtype, host);
+        let marker = self.inner.get_record::<String>(RecordType::TXT, &reg_host).await?;
+        let exists = marker.is_none() && exists(self.inner.get_record::<String>(rtype, host).await)?;
+        Ok(self.registry.claim(marker, exists))
+    }
+
+    /// The claims on the record sets touched by `changes`.
+    async fn claims(&self, changes: &[Change]) -> Result<HashMap<(RecordType, String), Claim>> {
+        let mut claims = HashMap::new();
+        for change in changes {
+            if let Entry::Vacant(entry) = claims.entry((change.rtype(), change.host().to_string())) {
+                entry.insert(self.claim(change.rtype(), &change.host().to_string()).await?);
+            }
+        }
+        Ok(claims)
+    }
+
+    async fn checked(&self, rtype: RecordType, host: &String) -> Result<Claim> {
+        let claim = self.claim(rtype, host).await?;
+        self.registry.allow(&claim, rtype, host)?;
+        Ok(claim)
+    }
+
+    async fn release(&self, rtype: RecordType, host: &String) -> Result<()> {
+        info!("Releasing ownership of {rtype} {host}");
+        self.inner.delete_all_records(RecordType::TXT, &self.registry.registry_host(rtype, host)).await
+    }
+}
+
+#[async_trait::async_trait]
+impl<P: AsyncDnsProvider> AsyncDnsProvider for Owned<P> {
+
+    async fn get_record<T>(&self, rtype: RecordType, host: &String) -> Result<Option<T>>
+    where
+        T: DeserializeOwned + Send + Sync + 'static
+    {
+        self.inner.get_record(rtype, host).await
+    }
+
+    async fn create_record<T>(&self, rtype: RecordType, host: &String, record: &T) -> Result<()>
+    where
+        T: Serialize + DeserializeOwned + Display + Clone + Send + Sync + 'static
+    {
+        let claim = self.checked(rtype, host).await?;
+        self.inner.create_record(rtype, host, record).await?;
+        if claim == Claim::Free {
+            info!("Claiming ownership of {rtype} {host}");
+            self.inner.create_txt_record(&self.registry.registry_host(rtype, host), &self.registry.marker_at(Utc::now())).await?;
+        }
+        Ok(())
+    }
+
+    async fn update_record<T>(&self, rtype: RecordType, host: &String, record: &T) -> Result<()>
+    where
+        T: Serialize + DeserializeOwned + Display + Clone + Send + Sync + 'static
+    {
+        self.checked(rtype, host).await?;
+        self.inner.update_record(rtype, host, record).await
+    }
+
+    async fn delete_record(&self, rtype: RecordType, host: &String) -> Result<()> {
+        let claim = self.checked(rtype, host).await?;
+        self.inner.delete_record(rtype, host).await?;
+        if claim == Claim::Owned && !exists(self.inner.get_record::<String>(rtype, host).await)? {
+            self.release(rtype, host).await?;
+        }
+        Ok(())
+    }
+
+    async fn delete_all_records(&self, rtype: RecordType, host: &String) -> Result<()> {
+        let claim = self.checked(rtype, host).await?;
+        self.inner.delete_all_records(rtype, host).await?;
+        if claim == Claim::Owned {
+            self.release(rtype, host).await?;
+        }
+        Ok(())
+    }
+
+    async fn list_zone(&self) -> Result<ZoneListing> {
+        self.inner.list_zone().await
+            .map(|listing| self.registry.owned_listing(listing))
+    }
+
+    fn record_types(&self) -> &'static [RecordType] {
+        self.inner.record_types()
+    }
+
+    // Registry changes are submitted in the same set, so batch
+    // providers apply them atomically with the records.
+    async fn apply_changes(&self, changes: &ChangeSet) -> Result<()> {
+        let claims = self.claims(&changes.changes).await?;
+        let (changes, deleted) = self.registry.claimed_changes(&changes.changes, claims, Utc::now())?;
+        self.inner.apply_changes(&changes).await?;
+        for (rtype, host) in deleted {
+            if !exists(self.inner.get_record::<String>(rtype, &host).await)? {
+                self.release(rtype, &host).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_changes(&self, changes: &[Change]) -> Result<()> {
+        self.inner.check_changes(changes)
+    }
+
+    async fn apply_change(&self, change: &Change) -> Result<()> {
+        apply_record_change(self, change).await
+    }
+
+    async fn get_txt_record(&self, host: &String) -> Result<Option<String>> {
+        self.inner.get_txt_record(host).await
+    }
+
+    async fn create_txt_record(&self, host: &String, record: &String) -> Result<()> {
+        self.create_record(RecordType::TXT, host, &crate::ensure_quotes(record)).await
+    }
+
+    async fn update_txt_record(&self, host: &String, record: &String) -> Result<()> {
+        self.update_record(RecordType::TXT, host, &crate::ensure_quotes(record)).await
+    }
+
+    async fn delete_txt_record(&self, host: &String) -> Result<()> {
+        self.delete_record(RecordType::TXT, host).await
+    }
+
+    async fn add_txt_value(&self, host: &String, value: &str) -> Result<()> {
+        let claim = self.checked(RecordType::TXT, host).await?;
+        self.inner.add_txt_value(host, value).await?;
+        if claim == Claim::Free {
+            info!("Claiming ownership of TXT {host}");
+            self.inner.create_txt_record(&self.registry.registry_host(RecordType::TXT, host), &self.registry.marker_at(Utc::now())).await?;
+        }
+        Ok(())
+    }
+
+    async fn remove_txt_value(&self, host: &String, value: &str) -> Result<()> {
+        let claim = self.checked(RecordType::TXT, host).await?;
+        self.inner.remove_txt_value(host, value).await?;
+        if claim == Claim::Owned && self.inner.get_txt_values(host).await?.is_empty() {
+            self.release(RecordType::TXT, host).await?;
+        }
+        Ok(())
+    }
+
+    async fn get_a_record(&self, host: &String) -> Result<Option<Ipv4Addr>> {
+        self.inner.get_a_record(host).await
+    }
+
+    async fn create_a_record(&self, host: &String, record: &Ipv4Addr) -> Result<()> {
+        self.create_record(RecordType::A, host, record).await
+    }
+
+    async fn update_a_record(&self, host: &String, record: &Ipv4Addr) -> Result<()> {
+        self.update_record(RecordType::A, host, record).await
+    }
+
+    async fn delete_a_record(&self, host: &String) -> Result<()> {
+        self.delete_record(RecordType::A, host).await
+    }
+
+    async fn get_aaaa_record(&self, host: &String) -> Result<Option<Ipv6Addr>> {
+        self.inner.get_aaaa_record(host).await
+    }
+
+    async fn create_aaaa_record(&self, host: &String, record: &Ipv6Addr) -> Result<()> {
+        self.create_record(RecordType::AAAA, host, record).await
+    }
+
+    async fn update_aaaa_record(&self, host: &String, record: &Ipv6Addr) -> Result<()> {
+        self.update_record(RecordType::AAAA, host, record).await
+    }
+
+    async fn delete_aaaa_record(&self, host: &String) -> Result<()> {
+        self.delete_record(RecordType::AAAA, host).await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{async_impl::Unblocked, errors::Error, tests::{Batching, MemoryProvider}, DnsProvider};
+
+    #[test]
+    fn test_async_ownership() -> Result<()> {
+        let inner = MemoryProvider::new("example.com");
+        inner.create_a_record("manual", &Ipv4Addr::new(10, 0, 0, 9))?;
+        let client = Owned::new(Unblocked::new(inner), Registry::new("team-a"));
+
+        let (www, manual) = ("www".to_string(), "manual".to_string());
+        futures::executor::block_on(async {
+            client.create_a_record(&www, &Ipv4Addr::new(10, 0, 0, 1)).await?;
+            assert!(client.get_txt_record(&"_owner-a.www".to_string()).await?.is_some());
+
+            let res = client.delete_a_record(&manual).await;
+            assert!(matches!(res, Err(Error::NotOwned(_))));
+
+            assert_eq!(1, client.list_records().await?.len());
+            client.delete_a_record(&www).await?;
+            assert_eq!(None, client.get_txt_record(&"_owner-a.www".to_string()).await?);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_async_apply_changes_batched() -> Result<()> {
+        let client = Owned::new(Unblocked::new(Batching::new("example.com")), Registry::new("team-a"));
+
+        let changes = vec![
+            Change::Create { rtype: RecordType::A, host: "www".to_string(), value: "10.0.0.1".to_string() },
+        ].into();
+        futures::executor::block_on(client.apply_changes(&changes))?;
+
+        let inner = client.into_inner();
+        let batches = inner.inner().batches.lock().unwrap();
+        assert_eq!(1, batches.len());
+        assert_eq!("_owner-a.www", batches[0].changes[1].host());
+
+        Ok(())
+    }
+}
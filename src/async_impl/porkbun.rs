@@ -1,35 +1,20 @@
-use std::sync::Arc;
-use std::fmt::Display;
-
-use blocking::unblock;
-use serde::{de::DeserializeOwned, Serialize};
-
+use crate::async_impl::Native;
 use crate::porkbun as sync;
-use crate::{async_provider_impl, Config, DnsProvider};
-use crate::{errors::Result, RecordType};
-
-use crate::async_impl::AsyncDnsProvider;
+use crate::Config;
 
 
 pub use crate::porkbun::Auth;
 
-/// Async wrapper around the synchronous `Porkbun` provider.
-pub struct Porkbun {
-    inner: Arc<sync::Porkbun>,
-}
+/// Native async `Porkbun` provider.
+pub type Porkbun = Native<sync::Porkbun>;
 
 impl Porkbun {
-    /// Create a new async `Porkbun` wrapper.
+    /// Create a new async `Porkbun` provider.
     pub fn new(config: Config, auth: Auth) -> Self {
-        let inner = sync::Porkbun::new(config, auth);
-        Self {
-            inner: Arc::new(inner)
-        }
+        Native::wrap(sync::Porkbun::new(config, auth))
     }
 }
 
-async_provider_impl!(Porkbun);
-
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,59 @@
+use crate::{
+    async_impl::AsyncDnsProvider,
+    errors::Result,
+    prefix::{derive, record},
+};
+
+pub use crate::prefix::{detect, HostSuffix, Prefix, PrefixReport};
+
+
+/// Set the AAAA record of each host to its address under `prefix`.
+///
+/// See `zone_update::prefix::update_hosts` for details.
+pub async fn update_hosts<P>(client: &P, prefix: &Prefix, hosts: &[HostSuffix]) -> Result<PrefixReport>
+where
+    P: AsyncDnsProvider + ?Sized
+{
+    let mut report = PrefixReport::default();
+    for (host, addr) in derive(prefix, hosts)? {
+        let result = match client.get_aaaa_record(&host).await {
+            Ok(Some(cur)) if cur == addr => Ok(false),
+            Ok(Some(_)) => client.update_aaaa_record(&host, &addr).await.map(|_| true),
+            Ok(None) => client.create_aaaa_record(&host, &addr).await.map(|_| true),
+            Err(e) => Err(e),
+        };
+        record(&mut report, host, addr, result);
+    }
+    Ok(report)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{async_impl::Unblocked, prefix::tests::hosts, tests::MemoryProvider, DnsProvider};
+
+    #[test]
+    fn test_update_hosts() -> Result<()> {
+        let client = Unblocked::new(MemoryProvider::new("example.com"));
+        let prefix = "2001:db8:bbbb::/48".parse()?;
+
+        let report = futures_lite::future::block_on(update_hosts(&client, &prefix, &hosts()))?;
+        assert_eq!(2, report.updated.len());
+        assert_eq!(Some("2001:db8:bbbb:0:10::2".parse()?), client.inner().get_aaaa_record("nas")?);
+
+        let report = futures_lite::future::block_on(update_hosts(&client, &prefix, &hosts()))?;
+        assert_eq!(vec!["nas", "printer"], report.unchanged);
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_hosts_dyn() -> Result<()> {
+        let client: Box<dyn AsyncDnsProvider> = Box::new(Unblocked::new(MemoryProvider::new("example.com")));
+        let prefix = "2001:db8:bbbb::/48".parse()?;
+
+        let report = futures_lite::future::block_on(update_hosts(&*client, &prefix, &hosts()))?;
+        assert_eq!(2, report.updated.len());
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use async_io::Timer;
+use async_net::{TcpStream, UdpSocket};
+use futures_lite::{future, AsyncReadExt, AsyncWriteExt};
+use tracing::{debug, info, warn};
+
+use crate::propagation::{
+    address_types, answer_values, follow_cname, fqdn, mismatched_id, nameserver_addrs, not_propagated,
+    query_timeout,
+    wire::{self, Query, Response},
+};
+use crate::{errors::Result, verify::is_visible, RecordType};
+
+pub use crate::propagation::{system_resolver, PropagationOptions, PropagationReport};
+
+
+async fn with_timeout<T>(server: SocketAddr, timeout: Duration, fut: impl Future<Output = Result<T>>) -> Result<T> {
+    future::or(fut, async {
+        Timer::after(timeout).await;
+        Err(query_timeout(server))
+    }).await
+}
+
+async fn udp_exchange(server: SocketAddr, query: &Query) -> Result<Response> {
+    let bind: SocketAddr = match server {
+        SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+        SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(server).await?;
+    socket.send(&query.encode()?).await?;
+
+    let mut buf = [0; 4096];
+    loop {
+        let len = socket.recv(&mut buf).await?;
+        // Ignore stray or malformed datagrams
+        match wire::parse(&buf[..len]) {
+            Ok(resp) if resp.id == query.id => return Ok(resp),
+            _ => debug!("Ignoring unexpected datagram from {server}"),
+        }
+    }
+}
+
+async fn tcp_exchange(server: SocketAddr, query: &Query) -> Result<Response> {
+    let mut stream = TcpStream::connect(server).await?;
+
+    let msg = query.encode()?;
+    let mut out = (msg.len() as u16).to_be_bytes().to_vec();
+    out.extend(msg);
+    stream.write_all(&out).await?;
+
+    let mut len = [0; 2];
+    stream.read_exact(&mut len).await?;
+    let mut buf = vec![0; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut buf).await?;
+
+    let resp = wire::parse(&buf)?;
+    if resp.id != query.id {
+        return Err(mismatched_id(server));
+    }
+    Ok(resp)
+}
+
+async fn exchange(server: SocketAddr, query: &Query, timeout: Duration) -> Result<Response> {
+    let resp = with_timeout(server, timeout, udp_exchange(server, query)).await?;
+    if resp.truncated {
+        debug!("Truncated response from {server}, retrying over TCP");
+        return with_timeout(server, timeout, tcp_exchange(server, query)).await;
+    }
+    Ok(resp)
+}
+
+
+/// Look up the addresses of the authoritative nameservers for
+/// `domain`, which should be the zone apex. Returns
+/// `opts.nameservers` if set.
+pub async fn authoritative_nameservers(domain: &str, opts: &PropagationOptions) -> Result<Vec<SocketAddr>> {
+    if !opts.nameservers.is_empty() {
+        return Ok(opts.nameservers.clone());
+    }
+
+    let query = Query::new(domain, wire::TYPE_NS, true);
+    let names = answer_values(&exchange(opts.resolver, &query, opts.query_timeout).await?, wire::TYPE_NS)?;
+    debug!("Nameservers for {domain}: {names:?}");
+
+    let mut addrs = Vec::new();
+    for name in names {
+        for &qtype in address_types(opts) {
+            let query = Query::new(&name, qtype, true);
+            let ips = answer_values(&exchange(opts.resolver, &query, opts.query_timeout).await?, qtype)?;
+            addrs.extend(ips.iter()
+                         .filter_map(|ip| ip.parse::<IpAddr>().ok())
+                         .map(|ip| SocketAddr::new(ip, opts.port)));
+        }
+    }
+
+    nameserver_addrs(domain, addrs)
+}
+
+/// Follow the CNAME chain from `name` with the resolver, returning
+/// the final name; this is `name` itself if it isn't an alias.
+pub async fn resolve_cname(name: &str, opts: &PropagationOptions) -> Result<String> {
+    let mut chain = vec![name.trim_end_matches('.').to_string()];
+    loop {
+        let current = chain.last().unwrap();
+        let query = Query::new(current, wire::TYPE_CNAME, true);
+        let resp = exchange(opts.resolver, &query, opts.query_timeout).await?;
+        match answer_values(&resp, wire::TYPE_CNAME)?.pop() {
+            Some(target) => follow_cname(&mut chain, target)?,
+            None => return Ok(chain.pop().unwrap()),
+        }
+    }
+}
+
+/// Query a nameserver directly, without recursion, for the values of
+/// a record set. A missing name returns no values.
+pub async fn lookup(server: SocketAddr, fqdn: &str, rtype: RecordType, timeout: Duration) -> Result<Vec<String>> {
+    let qtype = wire::type_code(rtype)?;
+    let query = Query::new(fqdn, qtype, false);
+    answer_values(&exchange(server, &query, timeout).await?, qtype)
+}
+
+/// Wait until every authoritative nameserver for `domain` returns
+/// `expected` for the record.
+///
+/// See `zone_update::propagation::wait_for_propagation` for details.
+pub async fn wait_for_propagation(domain: &str, host: &str, rtype: RecordType, expected: Option<&str>,
+                                  opts: &PropagationOptions) -> Result<PropagationReport>
+{
+    wire::type_code(rtype)?;
+    let fqdn = fqdn(domain, host);
+    let nameservers = authoritative_nameservers(domain, opts).await?;
+    let start = Instant::now();
+
+    loop {
+        let mut pending = Vec::new();
+        for &ns in &nameservers {
+            let agrees = match lookup(ns, &fqdn, rtype, opts.query_timeout).await {
+                Ok(values) => is_visible(rtype, expected, &values),
+                Err(e) => {
+                    warn!("Failed to query {ns} for {fqdn}: {e}");
+                    false
+                }
+            };
+            if !agrees {
+                pending.push(ns);
+            }
+        }
+
+        let elapsed = start.elapsed();
+        if pending.is_empty() {
+            info!("{rtype} {fqdn} propagated to {} nameservers after {elapsed:?}", nameservers.len());
+            return Ok(PropagationReport { elapsed, nameservers });
+        }
+        if elapsed >= opts.timeout {
+            return Err(not_propagated(rtype, &fqdn, expected, &pending, elapsed));
+        }
+        debug!("{rtype} {fqdn} pending on {pending:?}");
+        Timer::after(opts.interval.min(opts.timeout - elapsed)).await;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{errors::Error, propagation::tests::Responder};
+    use std::{net::UdpSocket as StdUdpSocket, sync::Arc, thread};
+
+    #[test]
+    fn test_async_lookup() -> Result<()> {
+        let server = Responder::start(true);
+        server.add_txt("_acme-challenge.example.com", "token");
+        let timeout = Duration::from_secs(1);
+
+        let values = futures::executor::block_on(lookup(server.addr, "_acme-challenge.example.com", RecordType::TXT, timeout))?;
+        assert_eq!(vec!["token"], values);
+
+        let silent = StdUdpSocket::bind("127.0.0.1:0").unwrap();
+        let res = futures::executor::block_on(lookup(silent.local_addr().unwrap(), "example.com", RecordType::A,
+                                                     Duration::from_millis(50)));
+        assert!(matches!(res, Err(Error::DnsError(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_async_wait_for_propagation() -> Result<()> {
+        let server = Arc::new(Responder::start(false));
+        let opts = server.options("example.com");
+
+        let delayed = server.clone();
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            delayed.add_txt("_acme-challenge.example.com", "token");
+        });
+        let report = futures::executor::block_on(
+            wait_for_propagation("example.com", "_acme-challenge", RecordType::TXT, Some("token"), &opts))?;
+        writer.join().unwrap();
+        assert_eq!(1, report.nameservers.len());
+
+        let opts = PropagationOptions { timeout: Duration::from_millis(30), ..opts };
+        let res = futures::executor::block_on(
+            wait_for_propagation("example.com", "_acme-challenge", RecordType::TXT, None, &opts));
+        assert!(matches!(res, Err(Error::NotPropagated(_, _))));
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+use std::{
+    fmt::Display,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::async_impl::{bulk::apply_record_change, AsyncDnsProvider};
+use crate::{change::{Change, ChangeSet}, errors::Result, RecordType, ZoneListing};
+
+pub use crate::safety::{ProtectedRecord, SafetyPolicy};
+
+
+/// Async provider wrapper that refuses changes not allowed by a
+/// `SafetyPolicy`.
+///
+/// See `zone_update::safety::Guarded` for details.
+pub struct Guarded<P> {
+    inner: P,
+    domain: String,
+    policy: SafetyPolicy,
+}
+
+impl<P: AsyncDnsProvider> Guarded<P> {
+    /// Wrap `inner`, which manages `domain`, enforcing `policy`.
+    pub fn new(inner: P, domain: &str, policy: SafetyPolicy) -> Self {
+        Self {
+            inner,
+            domain: domain.to_string(),
+            policy,
+        }
+    }
+
+    pub fn policy(&self) -> &SafetyPolicy {
+        &self.policy
+    }
+
+    /// Return the wrapped provider.
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+
+    async fn check(&self, changes: &[Change]) -> Result<()> {
+        let records = if self.policy.needs_listing(changes) {
+            self.inner.list_records().await?
+        } else {
+            Vec::new()
+        };
+        self.policy.check_listed(&self.domain, changes, &records)
+    }
+}
+
+#[async_trait::async_trait]
+impl<P: AsyncDnsProvider> AsyncDnsProvider for Guarded<P> {
+
+    async fn get_record<T>(&self, rtype: RecordType, host: &String) -> Result<Option<T>>
+    where
+        T: DeserializeOwned + Send + Sync + 'static
+    {
+        self.inner.get_record(rtype, host).await
+    }
+
+    async fn create_record<T>(&self, rtype: RecordType, host: &String, record: &T) -> Result<()>
+    where
+        T: Serialize + DeserializeOwned + Display + Clone + Send + Sync + 'static
+    {
+        self.inner.create_record(rtype, host, record).await
+    }
+
+    async fn update_record<T>(&self, rtype: RecordType, host: &String, record: &T) -> Result<()>
+    where
+        T: Serialize + DeserializeOwned + Display + Clone + Send + Sync + 'static
+    {
+        self.policy.check_record(&self.domain, rtype, host)?;
+        self.inner.update_record(rtype, host, record).await
+    }
+
+    async fn delete_record(&self, rtype: RecordType, host: &String) -> Result<()> {
+        self.check(&[Change::Delete { rtype, host: host.clone() }]).await?;
+        self.inner.delete_record(rtype, host).await
+    }
+
+    async fn delete_all_records(&self, rtype: RecordType, host: &String) -> Result<()> {
+        self.check(&[Change::DeleteAll { rtype, host: host.clone() }]).await?;
+        self.inner.delete_all_records(rtype, host).await
+    }
+
+    async fn list_zone(&self) -> Result<ZoneListing> {
+        self.inner.list_zone().await
+    }
+
+    fn record_types(&self) -> &'static [RecordType] {
+        self.inner.record_types()
+    }
+
+    async fn apply_changes(&self, changes: &ChangeSet) -> Result<()> {
+        self.check(&changes.changes).await?;
+        self.inner.apply_changes(changes).await
+    }
+
+    /// Checks protected records only, as the deletion count of
+    /// `DeleteAll` changes needs the zone listing. `apply_changes()`
+    /// and the delete methods check the limit as well.
+    fn check_changes(&self, changes: &[Change]) -> Result<()> {
+        self.policy.check_changes(&self.domain, changes)?;
+        self.inner.check_changes(changes)
+    }
+
+    async fn apply_change(&self, change: &Change) -> Result<()> {
+        apply_record_change(self, change).await
+    }
+
+    async fn get_txt_record(&self, host: &String) -> Result<Option<String>> {
+        self.inner.get_txt_record(host).await
+    }
+
+    async fn create_txt_record(&self, host: &String, record: &String) -> Result<()> {
+        self.create_record(RecordType::TXT, host, &crate::ensure_quotes(record)).await
+    }
+
+    async fn update_txt_record(&self, host: &String, record: &String) -> Result<()> {
+        self.update_record(RecordType::TXT, host, &crate::ensure_quotes(record)).await
+    }
+
+    async fn delete_txt_record(&self, host: &String) -> Result<()> {
+        self.delete_record(RecordType::TXT, host).await
+    }
+
+    async fn add_txt_value(&self, host: &String, value: &str) -> Result<()> {
+        self.inner.add_txt_value(host, value).await
+    }
+
+    async fn remove_txt_value(&self, host: &String, value: &str) -> Result<()> {
+        self.check(&[Change::Delete { rtype: RecordType::TXT, host: host.clone() }]).await?;
+        self.inner.remove_txt_value(host, value).await
+    }
+
+    async fn get_a_record(&self, host: &String) -> Result<Option<Ipv4Addr>> {
+        self.inner.get_a_record(host).await
+    }
+
+    async fn create_a_record(&self, host: &String, record: &Ipv4Addr) -> Result<()> {
+        self.create_record(RecordType::A, host, record).await
+    }
+
+    async fn update_a_record(&self, host: &String, record: &Ipv4Addr) -> Result<()> {
+        self.update_record(RecordType::A, host, record).await
+    }
+
+    async fn delete_a_record(&self, host: &String) -> Result<()> {
+        self.delete_record(RecordType::A, host).await
+    }
+
+    async fn get_aaaa_record(&self, host: &String) -> Result<Option<Ipv6Addr>> {
+        self.inner.get_aaaa_record(host).await
+    }
+
+    async fn create_aaaa_record(&self, host: &String, record: &Ipv6Addr) -> Result<()> {
+        self.create_record(RecordType::AAAA, host, record).await
+    }
+
+    async fn update_aaaa_record(&self, host: &String, record: &Ipv6Addr) -> Result<()> {
+        self.update_record(RecordType::AAAA, host, record).await
+    }
+
+    async fn delete_aaaa_record(&self, host: &String) -> Result<()> {
+        self.delete_record(RecordType::AAAA, host).await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{async_impl::Unblocked, errors::Error, tests::MemoryProvider, DnsProvider};
+
+    #[test]
+    fn test_async_guarded() -> Result<()> {
+        let inner = MemoryProvider::new("example.com");
+        for i in 1..=3 {
+            inner.create_a_record("www", &Ipv4Addr::new(192, 0, 2, i))?;
+        }
+        let policy = SafetyPolicy::default().max_deletions(2);
+        let client = Guarded::new(Unblocked::new(inner), "example.com", policy);
+
+        futures::executor::block_on(async {
+            let res = client.delete_all_records(RecordType::NS, &"".to_string()).await;
+            assert!(matches!(res, Err(Error::SafetyViolation(_))));
+            let res = client.delete_all_records(RecordType::A, &"www".to_string()).await;
+            assert!(matches!(res, Err(Error::SafetyViolation(_))));
+            assert_eq!(3, client.list_records().await?.len());
+            Ok(())
+        })
+    }
+}
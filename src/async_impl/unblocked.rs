@@ -0,0 +1,436 @@
+use std::{fmt::Display, net::{Ipv4Addr, Ipv6Addr}, sync::Arc};
+
+use blocking::unblock;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::async_impl::AsyncDnsProvider;
+use crate::{change::{Change, ChangeSet}, errors::Result, DnsProvider, RecordType, ZoneListing};
+
+
+/// Async adapter for any blocking `DnsProvider`.
+///
+/// Each call is run on the `blocking` thread pool. This is the
+/// fallback for providers without a native async implementation,
+/// including third-party `DnsProvider` implementations:
+///
+/// ```ignore
+/// let client = Unblocked::new(MyProvider::new(config));
+/// client.create_txt_record(&host, &value).await?;
+/// ```
+///
+/// Note that the blocking task is not cancelled if the returned
+/// future is dropped; the operation will run to completion.
+pub struct Unblocked<P> {
+    inner: Arc<P>,
+}
+
+impl<P> Unblocked<P>
+where
+    P: DnsProvider + Send + Sync + 'static
+{
+    /// Wrap a blocking provider.
+    pub fn new(inner: P) -> Self {
+        Self::from_arc(Arc::new(inner))
+    }
+
+    /// Wrap a blocking provider that is shared elsewhere.
+    pub fn from_arc(inner: Arc<P>) -> Self {
+        Self { inner }
+    }
+
+    /// Return the wrapped provider.
+    pub fn inner(&self) -> &Arc<P> {
+        &self.inner
+    }
+}
+
+#[async_trait::async_trait]
+impl<P> AsyncDnsProvider for Unblocked<P>
+where
+    P: DnsProvider + Send + Sync + 'static
+{
+
+    async fn get_record<T>(&self, rtype: RecordType, host: &String) -> Result<Option<T>>
+    where
+        T: DeserializeOwned + Send + Sync + 'static
+    {
+        let provider = self.inner.clone();
+        let host = host.clone();
+        unblock(move || provider.get_record(rtype, &host)).await
+    }
+
+    async fn create_record<T>(&self, rtype: RecordType, host: &String, record: &T) -> Result<()>
+    where
+        T: Serialize + DeserializeOwned + Display + Clone + Send + Sync + 'static
+    {
+        let provider = self.inner.clone();
+        let host = host.clone();
+        let record = record.clone();
+        unblock(move || provider.create_record(rtype, &host, &record)).await
+    }
+
+    async fn update_record<T>(&self, rtype: RecordType, host: &String, record: &T) -> Result<()>
+    where
+        T: Serialize + DeserializeOwned + Display + Clone + Send + Sync + 'static
+    {
+        let provider = self.inner.clone();
+        let host = host.clone();
+        let record = record.clone();
+        unblock(move || provider.update_record(rtype, &host, &record)).await
+    }
+
+    async fn delete_record(&self, rtype: RecordType, host: &String) -> Result<()> {
+        let provider = self.inner.clone();
+        let host = host.clone();
+        unblock(move || provider.delete_record(rtype, &host)).await
+    }
+
+    async fn delete_all_records(&self, rtype: RecordType, host: &String) -> Result<()> {
+        let provider = self.inner.clone();
+        let host = host.clone();
+        unblock(move || provider.delete_all_records(rtype, &host)).await
+    }
+
+    async fn list_zone(&self) -> Result<ZoneListing> {
+        let provider = self.inner.clone();
+        unblock(move || provider.list_zone()).await
+    }
+
+    fn record_types(&self) -> &'static [RecordType] {
+        self.inner.record_types()
+    }
+
+    fn check_changes(&self, changes: &[Change]) -> Result<()> {
+        self.inner.check_changes(changes)
+    }
+
+    async fn apply_changes(&self, changes: &ChangeSet) -> Result<()> {
+        let provider = self.inner.clone();
+        let changes = changes.clone();
+        unblock(move || provider.apply_changes(&changes)).await
+    }
+
+    async fn apply_change(&self, change: &Change) -> Result<()> {
+        let provider = self.inner.clone();
+        let change = change.clone();
+        unblock(move || provider.apply_change(&change)).await
+    }
+
+    async fn get_txt_record(&self, host: &String) -> Result<Option<String>> {
+        let provider = self.inner.clone();
+        let host = host.clone();
+        unblock(move || provider.get_txt_record(&host)).await
+    }
+
+    async fn create_txt_record(&self, host: &String, record: &String) -> Result<()> {
+        let provider = self.inner.clone();
+        let host = host.clone();
+        let record = record.clone();
+        unblock(move || provider.create_txt_record(&host, &record)).await
+    }
+
+    async fn update_txt_record(&self, host: &String, record: &String) -> Result<()> {
+        let provider = self.inner.clone();
+        let host = host.clone();
+        let record = record.clone();
+        unblock(move || provider.update_txt_record(&host, &record)).await
+    }
+
+    async fn delete_txt_record(&self, host: &String) -> Result<()> {
+        let provider = self.inner.clone();
+        let host = host.clone();
+        unblock(move || provider.delete_txt_record(&host)).await
+    }
+
+    async fn get_txt_values(&self, host: &String) -> Result<Vec<String>> {
+        let provider = self.inner.clone();
+        let host = host.clone();
+        unblock(move || provider.get_txt_values(&host)).await
+    }
+
+    async fn add_txt_value(&self, host: &String, value: &str) -> Result<()> {
+        let provider = self.inner.clone();
+        let host = host.clone();
+        let value = value.to_string();
+        unblock(move || provider.add_txt_value(&host, &value)).await
+    }
+
+    async fn remove_txt_value(&self, host: &String, value: &str) -> Result<()> {
+        let provider = self.inner.clone();
+        let host = host.clone();
+        let value = value.to_string();
+        unblock(move || provider.remove_txt_value(&host, &value)).await
+    }
+
+    async fn get_a_record(&self, host: &String) -> Result<Option<Ipv4Addr>> {
+        let provider = self.inner.clone();
+        let host = host.clone();
+        unblock(move || provider.get_a_record(&host)).await
+    }
+
+    async fn create_a_record(&self, host: &String, record: &Ipv4Addr) -> Result<()> {
+        let provider = self.inner.clone();
+        let host = host.clone();
+        let record = *record;
+        unblock(move || provider.create_a_record(&host, &record)).await
+    }
+
+    async fn update_a_record(&self, host: &String, record: &Ipv4Addr) -> Result<()> {
+        let provider = self.inner.clone();
+        let host = host.clone();
+        let record = *record;
+        unblock(move || provider.update_a_record(&host, &record)).await
+    }
+
+    async fn delete_a_record(&self, host: &String) -> Result<()> {
+        let provider = self.inner.clone();
+        let host = host.clone();
+        unblock(move || provider.delete_a_record(&host)).await
+    }
+
+    async fn get_aaaa_record(&self, host: &String) -> Result<Option<Ipv6Addr>> {
+        let provider = self.inner.clone();
+        let host = host.clone();
+        unblock(move || provider.get_aaaa_record(&host)).await
+    }
+
+    async fn create_aaaa_record(&self, host: &String, record: &Ipv6Addr) -> Result<()> {
+        let provider = self.inner.clone();
+        let host = host.clone();
+        let record = *record;
+        unblock(move || provider.create_aaaa_record(&host, &record)).await
+    }
+
+    async fn update_aaaa_record(&self, host: &String, record: &Ipv6Addr) -> Result<()> {
+        let provider = self.inner.clone();
+        let host = host.clone();
+        let record = *record;
+        unblock(move || provider.update_aaaa_record(&host, &record)).await
+    }
+
+    async fn delete_aaaa_record(&self, host: &String) -> Result<()> {
+        let provider = self.inner.clone();
+        let host = host.clone();
+        unblock(move || provider.delete_aaaa_record(&host)).await
+    }
+
+    async fn set_host_addresses(&self, host: &String, v4: Option<Ipv4Addr>, v6: Option<Ipv6Addr>) -> Result<()> {
+        let provider = self.inner.clone();
+        let host = host.clone();
+        unblock(move || provider.set_host_addresses(&host, v4, v6)).await
+    }
+}
+
+
+/// Implement `AsyncDnsProvider` for a wrapper around a synchronous
+/// provider, running each call on the `blocking` thread pool.
+///
+/// The wrapper must have an `inner: Arc<Provider>` field. Each call
+/// is forwarded to an `Unblocked` sharing that provider.
+#[deprecated(note = "use `Unblocked` instead")]
+#[macro_export]
+macro_rules! async_provider_impl {
+    ($i:ident) => {
+        const _: () = {
+            use std::{fmt::Display, net::{Ipv4Addr, Ipv6Addr}};
+            use ::serde::{de::DeserializeOwned, Serialize};
+            use $crate::{async_impl::{AsyncDnsProvider, Unblocked}, change::{Change, ChangeSet}, errors::Result, RecordType, ZoneListing};
+
+            #[async_trait::async_trait]
+            impl AsyncDnsProvider for $i {
+
+                async fn get_record<T>(&self, rtype: RecordType, host: &String) -> Result<Option<T>>
+                where
+                    T: DeserializeOwned + Send + Sync + 'static
+                {
+                    Unblocked::from_arc(self.inner.clone()).get_record(rtype, host).await
+                }
+
+                async fn create_record<T>(&self, rtype: RecordType, host: &String, record: &T) -> Result<()>
+                where
+                    T: Serialize + DeserializeOwned + Display + Clone + Send + Sync + 'static
+                {
+                    Unblocked::from_arc(self.inner.clone()).create_record(rtype, host, record).await
+                }
+
+                async fn update_record<T>(&self, rtype: RecordType, host: &String, record: &T) -> Result<()>
+                where
+                    T: Serialize + DeserializeOwned + Display + Clone + Send + Sync + 'static
+                {
+                    Unblocked::from_arc(self.inner.clone()).update_record(rtype, host, record).await
+                }
+
+                async fn delete_record(&self, rtype: RecordType, host: &String) -> Result<()> {
+                    Unblocked::from_arc(self.inner.clone()).delete_record(rtype, host).await
+                }
+
+                async fn delete_all_records(&self, rtype: RecordType, host: &String) -> Result<()> {
+                    Unblocked::from_arc(self.inner.clone()).delete_all_records(rtype, host).await
+                }
+
+                async fn list_zone(&self) -> Result<ZoneListing> {
+                    Unblocked::from_arc(self.inner.clone()).list_zone().await
+                }
+
+                fn record_types(&self) -> &'static [RecordType] {
+                    Unblocked::from_arc(self.inner.clone()).record_types()
+                }
+
+                fn check_changes(&self, changes: &[Change]) -> Result<()> {
+                    Unblocked::from_arc(self.inner.clone()).check_changes(changes)
+                }
+
+                async fn apply_changes(&self, changes: &ChangeSet) -> Result<()> {
+                    Unblocked::from_arc(self.inner.clone()).apply_changes(changes).await
+                }
+
+                async fn apply_change(&self, change: &Change) -> Result<()> {
+                    Unblocked::from_arc(self.inner.clone()).apply_change(change).await
+                }
+
+                async fn get_txt_record(&self, host: &String) -> Result<Option<String>> {
+                    Unblocked::from_arc(self.inner.clone()).get_txt_record(host).await
+                }
+
+                async fn create_txt_record(&self, host: &String, record: &String) -> Result<()> {
+                    Unblocked::from_arc(self.inner.clone()).create_txt_record(host, record).await
+                }
+
+                async fn update_txt_record(&self, host: &String, record: &String) -> Result<()> {
+                    Unblocked::from_arc(self.inner.clone()).update_txt_record(host, record).await
+                }
+
+                async fn delete_txt_record(&self, host: &String) -> Result<()> {
+                    Unblocked::from_arc(self.inner.clone()).delete_txt_record(host).await
+                }
+
+                async fn get_txt_values(&self, host: &String) -> Result<Vec<String>> {
+                    Unblocked::from_arc(self.inner.clone()).get_txt_values(host).await
+                }
+
+                async fn add_txt_value(&self, host: &String, value: &str) -> Result<()> {
+                    Unblocked::from_arc(self.inner.clone()).add_txt_value(host, value).await
+                }
+
+                async fn remove_txt_value(&self, host: &String, value: &str) -> Result<()> {
+                    Unblocked::from_arc(self.inner.clone()).remove_txt_value(host, value).await
+                }
+
+                async fn get_a_record(&self, host: &String) -> Result<Option<Ipv4Addr>> {
+                    Unblocked::from_arc(self.inner.clone()).get_a_record(host).await
+                }
+
+                async fn create_a_record(&self, host: &String, record: &Ipv4Addr) -> Result<()> {
+                    Unblocked::from_arc(self.inner.clone()).create_a_record(host, record).await
+                }
+
+                async fn update_a_record(&self, host: &String, record: &Ipv4Addr) -> Result<()> {
+                    Unblocked::from_arc(self.inner.clone()).update_a_record(host, record).await
+                }
+
+                async fn delete_a_record(&self, host: &String) -> Result<()> {
+                    Unblocked::from_arc(self.inner.clone()).delete_a_record(host).await
+                }
+
+                async fn get_aaaa_record(&self, host: &String) -> Result<Option<Ipv6Addr>> {
+                    Unblocked::from_arc(self.inner.clone()).get_aaaa_record(host).await
+                }
+
+                async fn create_aaaa_record(&self, host: &String, record: &Ipv6Addr) -> Result<()> {
+                    Unblocked::from_arc(self.inner.clone()).create_aaaa_record(host, record).await
+                }
+
+                async fn update_aaaa_record(&self, host: &String, record: &Ipv6Addr) -> Result<()> {
+                    Unblocked::from_arc(self.inner.clone()).update_aaaa_record(host, record).await
+                }
+
+                async fn delete_aaaa_record(&self, host: &String) -> Result<()> {
+                    Unblocked::from_arc(self.inner.clone()).delete_aaaa_record(host).await
+                }
+
+                async fn set_host_addresses(&self, host: &String, v4: Option<Ipv4Addr>, v6: Option<Ipv6Addr>) -> Result<()> {
+                    Unblocked::from_arc(self.inner.clone()).set_host_addresses(host, v4, v6).await
+                }
+            }
+        };
+    };
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{errors::Error, tests::MemoryProvider};
+
+    #[test]
+    fn test_unblocked() -> Result<()> {
+        let client = Unblocked::new(MemoryProvider::new("example.com"));
+        let host = "www".to_string();
+
+        futures::executor::block_on(async {
+            client.create_a_record(&host, &Ipv4Addr::new(10, 0, 0, 1)).await?;
+            assert_eq!(Some(Ipv4Addr::new(10, 0, 0, 1)), client.get_a_record(&host).await?);
+
+            client.create_txt_record(&host, &"a value".to_string()).await?;
+            assert_eq!(Some("a value".to_string()), client.get_txt_record(&host).await?);
+
+            client.delete_all_records(RecordType::A, &host).await?;
+            assert_eq!(None, client.get_a_record(&host).await?);
+
+            let v6: Ipv6Addr = "2001:db8::1".parse()?;
+            client.set_host_addresses(&host, Some(Ipv4Addr::new(10, 0, 0, 2)), Some(v6)).await?;
+            assert_eq!(Some(v6), client.get_aaaa_record(&host).await?);
+            client.set_host_addresses(&host, None, None).await?;
+            Ok::<_, Error>(())
+        })?;
+
+        // Changes are made to the shared inner provider
+        assert_eq!(1, client.inner().records.lock().unwrap().len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unblocked_conditional() -> Result<()> {
+        let client = Unblocked::new(MemoryProvider::new("example.com"));
+        let host = "www".to_string();
+        let (ip1, ip2) = (Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2));
+
+        futures::executor::block_on(async {
+            client.create_a_record(&host, &ip1).await?;
+            client.update_record_if(RecordType::A, &host, &ip1, &ip2).await?;
+            let res = client.delete_record_if(RecordType::A, &host, &ip1).await;
+            assert!(matches!(res, Err(Error::Conflict(_, Some(_)))));
+            client.delete_record_if(RecordType::A, &host, &ip2).await
+        })?;
+
+        assert!(client.inner().records.lock().unwrap().is_empty());
+        Ok(())
+    }
+
+    struct AsyncMemory {
+        inner: Arc<MemoryProvider>,
+    }
+
+    #[allow(deprecated)]
+    mod legacy {
+        use super::AsyncMemory;
+        crate::async_provider_impl!(AsyncMemory);
+    }
+
+    #[test]
+    fn test_async_provider_impl() -> Result<()> {
+        let client = AsyncMemory { inner: Arc::new(MemoryProvider::new("example.com")) };
+        let host = "www".to_string();
+
+        futures::executor::block_on(async {
+            client.create_txt_record(&host, &"a value".to_string()).await?;
+            client.add_txt_value(&host, "another").await?;
+            assert_eq!(2, client.get_txt_values(&host).await?.len());
+            Ok::<_, Error>(())
+        })?;
+
+        assert_eq!(2, client.inner.records.lock().unwrap().len());
+        Ok(())
+    }
+}
@@ -0,0 +1,246 @@
+use std::{
+    fmt::Display,
+    net::{Ipv4Addr, Ipv6Addr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use async_io::Timer;
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::{debug, info};
+
+use crate::async_impl::{bulk::apply_record_change, AsyncDnsProvider};
+use crate::verify::{expected_states, is_visible, not_visible, set_values};
+use crate::{change::{Change, ChangeSet}, errors::{Error, Result}, has_txt_value, RecordType, ZoneListing};
+
+pub use crate::verify::VerifyOptions;
+
+
+/// Async provider wrapper that waits for each change to be visible
+/// in the provider's API before returning.
+///
+/// See `zone_update::verify::Verified` for details.
+pub struct Verified<P> {
+    inner: P,
+    opts: VerifyOptions,
+    last: Mutex<Option<Duration>>,
+}
+
+impl<P: AsyncDnsProvider> Verified<P> {
+    /// Wrap `inner`, verifying with `opts`.
+    pub fn new(inner: P, opts: VerifyOptions) -> Self {
+        Self {
+            inner,
+            opts,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// The time the last verified change took to become visible.
+    pub fn last_elapsed(&self) -> Option<Duration> {
+        *self.last.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Return the wrapped provider.
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+
+    async fn current(&self, rtype: RecordType, host: &String) -> Result<Vec<String>> {
+        match self.inner.get_record::<String>(rtype, host).await {
+            Ok(value) => Ok(value.into_iter().collect()),
+            Err(Error::UnexpectedRecord(_)) => Ok(set_values(self.inner.list_records().await?, rtype, host)),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn verify(&self, rtype: RecordType, host: &String, expected: Option<&str>) -> Result<()> {
+        self.verify_with(rtype, host, expected, |current| is_visible(rtype, expected, current)).await
+    }
+
+    async fn verify_with<F>(&self, rtype: RecordType, host: &String, expected: Option<&str>, visible: F) -> Result<()>
+    where
+        F: Fn(&[String]) -> bool
+    {
+        let start = Instant::now();
+        loop {
+            if visible(&self.current(rtype, host).await?) {
+                let elapsed = start.elapsed();
+                info!("Change to {rtype} {host} visible after {elapsed:?}");
+                *self.last.lock().unwrap_or_else(|e| e.into_inner()) = Some(elapsed);
+                return Ok(())
+            }
+
+            let elapsed = start.elapsed();
+            if elapsed >= self.opts.timeout {
+                return Err(not_visible(rtype, host, expected, elapsed));
+            }
+            debug!("Change to {rtype} {host} not yet visible");
+            Timer::after(self.opts.interval.min(self.opts.timeout - elapsed)).await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<P: AsyncDnsProvider> AsyncDnsProvider for Verified<P> {
+
+    async fn get_record<T>(&self, rtype: RecordType, host: &String) -> Result<Option<T>>
+    where
+        T: DeserializeOwned + Send + Sync + 'static
+    {
+        self.inner.get_record(rtype, host).await
+    }
+
+    async fn create_record<T>(&self, rtype: RecordType, host: &String, record: &T) -> Result<()>
+    where
+        T: Serialize + DeserializeOwned + Display + Clone + Send + Sync + 'static
+    {
+        self.inner.create_record(rtype, host, record).await?;
+        self.verify(rtype, host, Some(&record.to_string())).await
+    }
+
+    async fn update_record<T>(&self, rtype: RecordType, host: &String, record: &T) -> Result<()>
+    where
+        T: Serialize + DeserializeOwned + Display + Clone + Send + Sync + 'static
+    {
+        self.inner.update_record(rtype, host, record).await?;
+        self.verify(rtype, host, Some(&record.to_string())).await
+    }
+
+    async fn delete_record(&self, rtype: RecordType, host: &String) -> Result<()> {
+        self.inner.delete_record(rtype, host).await?;
+        self.verify(rtype, host, None).await
+    }
+
+    async fn delete_all_records(&self, rtype: RecordType, host: &String) -> Result<()> {
+        self.inner.delete_all_records(rtype, host).await?;
+        self.verify(rtype, host, None).await
+    }
+
+    async fn list_zone(&self) -> Result<ZoneListing> {
+        self.inner.list_zone().await
+    }
+
+    fn record_types(&self) -> &'static [RecordType] {
+        self.inner.record_types()
+    }
+
+    async fn apply_changes(&self, changes: &ChangeSet) -> Result<()> {
+        self.inner.apply_changes(changes).await?;
+        for (rtype, host, expected) in expected_states(&changes.changes) {
+            self.verify(rtype, &host, expected.as_deref()).await?;
+        }
+        Ok(())
+    }
+
+    fn check_changes(&self, changes: &[Change]) -> Result<()> {
+        self.inner.check_changes(changes)
+    }
+
+    async fn apply_change(&self, change: &Change) -> Result<()> {
+        apply_record_change(self, change).await
+    }
+
+    async fn get_txt_record(&self, host: &String) -> Result<Option<String>> {
+        self.inner.get_txt_record(host).await
+    }
+
+    async fn create_txt_record(&self, host: &String, record: &String) -> Result<()> {
+        self.create_record(RecordType::TXT, host, &crate::ensure_quotes(record)).await
+    }
+
+    async fn update_txt_record(&self, host: &String, record: &String) -> Result<()> {
+        self.update_record(RecordType::TXT, host, &crate::ensure_quotes(record)).await
+    }
+
+    async fn delete_txt_record(&self, host: &String) -> Result<()> {
+        self.delete_record(RecordType::TXT, host).await
+    }
+
+    async fn add_txt_value(&self, host: &String, value: &str) -> Result<()> {
+        self.inner.add_txt_value(host, value).await?;
+        self.verify(RecordType::TXT, host, Some(value)).await
+    }
+
+    async fn remove_txt_value(&self, host: &String, value: &str) -> Result<()> {
+        self.inner.remove_txt_value(host, value).await?;
+        self.verify_with(RecordType::TXT, host, None, |current| !has_txt_value(current, value)).await
+    }
+
+    async fn get_a_record(&self, host: &String) -> Result<Option<Ipv4Addr>> {
+        self.inner.get_a_record(host).await
+    }
+
+    async fn create_a_record(&self, host: &String, record: &Ipv4Addr) -> Result<()> {
+        self.create_record(RecordType::A, host, record).await
+    }
+
+    async fn update_a_record(&self, host: &String, record: &Ipv4Addr) -> Result<()> {
+        self.update_record(RecordType::A, host, record).await
+    }
+
+    async fn delete_a_record(&self, host: &String) -> Result<()> {
+        self.delete_record(RecordType::A, host).await
+    }
+
+    async fn get_aaaa_record(&self, host: &String) -> Result<Option<Ipv6Addr>> {
+        self.inner.get_aaaa_record(host).await
+    }
+
+    async fn create_aaaa_record(&self, host: &String, record: &Ipv6Addr) -> Result<()> {
+        self.create_record(RecordType::AAAA, host, record).await
+    }
+
+    async fn update_aaaa_record(&self, host: &String, record: &Ipv6Addr) -> Result<()> {
+        self.update_record(RecordType::AAAA, host, record).await
+    }
+
+    async fn delete_aaaa_record(&self, host: &String) -> Result<()> {
+        self.delete_record(RecordType::AAAA, host).await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{async_impl::Unblocked, tests::Batching, verify::tests::Lagging};
+
+    #[test]
+    fn test_async_verify() -> Result<()> {
+        let opts = VerifyOptions {
+            timeout: Duration::from_secs(5),
+            interval: Duration::from_millis(1),
+        };
+        let client = Verified::new(Unblocked::new(Lagging::new(3)), opts.clone());
+        let host = "www".to_string();
+
+        futures::executor::block_on(client.create_a_record(&host, &Ipv4Addr::new(10, 0, 0, 1)))?;
+        assert!(client.last_elapsed().is_some());
+
+        let opts = VerifyOptions { timeout: Duration::from_millis(20), ..opts };
+        let client = Verified::new(Unblocked::new(Lagging::new(usize::MAX)), opts);
+        let res = futures::executor::block_on(client.create_a_record(&host, &Ipv4Addr::new(10, 0, 0, 1)));
+        assert!(matches!(res, Err(Error::NotVisible(_, _))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_async_apply_changes_batched() -> Result<()> {
+        let opts = VerifyOptions {
+            timeout: Duration::from_secs(5),
+            interval: Duration::from_millis(1),
+        };
+        let client = Verified::new(Unblocked::new(Batching::new("example.com")), opts);
+
+        let changes = vec![
+            Change::Create { rtype: RecordType::A, host: "www".to_string(), value: "10.0.0.1".to_string() },
+        ].into();
+        futures::executor::block_on(client.apply_changes(&changes))?;
+        assert!(client.last_elapsed().is_some());
+        assert_eq!(1, client.into_inner().inner().batches.lock().unwrap().len());
+
+        Ok(())
+    }
+}
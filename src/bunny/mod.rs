@@ -2,15 +2,16 @@ mod types;
 
 use std::{fmt::{Debug, Display}, sync::Mutex};
 
-use serde::{de::DeserializeOwned, Deserialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tracing::{error, info, warn};
 
 use crate::{
-    Config, DnsProvider, RecordType,
-    bunny::types::{CreateUpdate, Record, ZoneInfo, ZoneList},
+    Config, RecordType, ZoneListing,
+    bunny::types::{CreateUpdate, Record, ZoneInfo, ZoneList, ZoneRecords},
     errors::{Error, Result},
-    generate_helpers,
-    http::{self, ResponseToOption, WithHeaders},
+    find_zone,
+    verify::same_value,
+    http::{http_provider_impl, HttpClient, ResponseToOption, WithHeaders},
 };
 
 const API_BASE: &str = "https://api.bunny.net/dnszone";
@@ -34,6 +35,9 @@ impl Auth {
 /// Synchronous Bunny DNS provider implementation.
 ///
 /// Holds configuration and authentication state for performing API calls.
+///
+/// Bunny's zone update endpoint only covers zone settings, not
+/// records, so change sets are applied sequentially.
 pub struct Bunny {
     config: Config,
     auth: Auth,
@@ -52,59 +56,67 @@ impl Bunny {
     }
 
 
-    fn get_zone_id(&self) -> Result<u64> {
-        let mut id_p = self.zone_id.lock()
-            .map_err(|e| Error::LockingError(e.to_string()))?;
-
-        if let Some(id) = id_p.as_ref() {
-            return Ok(*id);
+    async fn get_zone_id<C>(&self, client: &C) -> Result<u64>
+    where
+        C: HttpClient + ?Sized,
+    {
+        // The lock is not held across the lookup, as it may
+        // suspend; concurrent first calls may both fetch the ID.
+        if let Some(id) = *self.zone_id.lock()
+            .map_err(|e| Error::LockingError(e.to_string()))?
+        {
+            return Ok(id);
         }
 
-        let zone = self.get_zone_info()?;
+        let zone = self.get_zone_info(client).await?;
         let id = zone.id;
-        *id_p = Some(id);
+        *self.zone_id.lock()
+            .map_err(|e| Error::LockingError(e.to_string()))? = Some(id);
 
         Ok(id)
     }
 
-    fn get_zone_info(&self) -> Result<ZoneInfo> {
+    async fn get_zone_info<C>(&self, client: &C) -> Result<ZoneInfo>
+    where
+        C: HttpClient + ?Sized,
+    {
         let uri = format!("{API_BASE}?search={}", self.config.domain);
-        let zones = http::client()
+        let zones = client
             .get(uri)
             .with_json_headers()
             .header("AccessKey", self.auth.get_header())
-            .call()?
+            .call().await?
             .to_option::<ZoneList>()?
-            .ok_or(Error::RecordNotFound(format!("Couldn't fetch zone info for {}", self.config.domain)))?
+            .ok_or(Error::ZoneNotFound(self.config.domain.clone()))?
             .items;
-        let zone = zones.into_iter()
-            .find(|z| z.domain == self.config.domain)
-            .ok_or(Error::RecordNotFound(format!("Couldn't fetch zone info for {}", self.config.domain)))?;
 
-        Ok(zone)
+        // The search is a substring match, so may return
+        // e.g. `sub.example.com` and `example.com.au` as well.
+        find_zone(zones, &self.config.domain, |z| &z.domain)
     }
 
-    fn get_upstream_records<T>(&self, rtype: RecordType, host: &str) -> Result<Vec<Record<T>>>
+    async fn get_upstream_records<T, C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<Vec<Record<T>>>
     where
-        T: DeserializeOwned
+        T: DeserializeOwned,
+        C: HttpClient + ?Sized,
     {
         println!("GET UPSTREAM {rtype}, {host}");
-        let zone_id = self.get_zone_id()?;
+        let zone_id = self.get_zone_id(client).await?;
         let url = format!("{API_BASE}/{zone_id}");
 
-        let mut response = http::client().get(url)
+        let response = client.get(url)
             .header("AccessKey", self.auth.get_header())
             .with_json_headers()
-            .call()?;
+            .call().await?;
 
         // Bunny returns *all* records, with no ability to filter by
         // type, resulting in a mixed-type array. To work around this
         // we filter on the raw json values before deserialising
         // properly.
-        let body = response.body_mut().read_to_string()?;
+        let body = response.body();
         let u64rtype = u64::from(rtype);
 
-        let values: serde_json::Value = serde_json::from_str(&body)?;
+        let values: serde_json::Value = serde_json::from_str(body)?;
         let data = values["Records"].as_array()
             .ok_or(Error::ApiError("Data field not found".to_string()))?;
         let records = data.iter()
@@ -120,11 +132,12 @@ impl Bunny {
         Ok(records)
     }
 
-    fn get_upstream_record<T>(&self, rtype: RecordType, host: &str) -> Result<Option<Record<T>>>
+    async fn get_upstream_record<T, C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<Option<Record<T>>>
     where
-        T: DeserializeOwned
+        T: DeserializeOwned,
+        C: HttpClient + ?Sized,
     {
-        let mut recs = self.get_upstream_records(rtype, host)?;
+        let mut recs = self.get_upstream_records(client, rtype, host).await?;
 
         // FIXME: Assumes no or single address (which probably makes
         // sense for DDNS and DNS-01, but may cause issues with
@@ -141,8 +154,11 @@ impl Bunny {
         Ok(Some(recs.remove(0)))
     }
 
-    fn do_delete(&self, rec: Record<String>) -> Result<()> {
-        let zone_id = self.get_zone_id()?;
+    async fn do_delete<C>(&self, client: &C, rec: Record<String>) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let zone_id = self.get_zone_id(client).await?;
         let url = format!("{API_BASE}/{zone_id}/records/{}", rec.id);
         if self.config.dry_run {
             info!("DRY-RUN: Would have sent DELETE to {url}");
@@ -150,24 +166,20 @@ impl Bunny {
         }
 
         info!("Deleting DNS {} record {}", rec.rtype, rec.name);
-        http::client().delete(url)
+        client.delete(url)
             .with_json_headers()
             .header("AccessKey", self.auth.get_header())
-            .call()?;
+            .call().await?;
 
         Ok(())
     }
 
-}
-
-
-impl DnsProvider for Bunny {
-
-    fn get_record<T>(&self, rtype: RecordType, host: &str) -> Result<Option<T>>
+    async fn get_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<Option<T>>
     where
-        T: DeserializeOwned
+        T: DeserializeOwned,
+        C: HttpClient + ?Sized,
     {
-        let resp = self.get_upstream_record(rtype, host)?;
+        let resp = self.get_upstream_record(client, rtype, host).await?;
         let rec: Record<T> = match resp {
             Some(recs) => recs,
             None => return Ok(None)
@@ -175,11 +187,12 @@ impl DnsProvider for Bunny {
         Ok(Some(rec.value))
     }
 
-    fn create_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+    async fn create_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str, record: &T) -> Result<()>
     where
         T: Display,
+        C: HttpClient + ?Sized,
     {
-        let zone_id = self.get_zone_id()?;
+        let zone_id = self.get_zone_id(client).await?;
         let url = format!("{API_BASE}/{zone_id}/records");
 
         let rec = CreateUpdate {
@@ -196,19 +209,20 @@ impl DnsProvider for Bunny {
             return Ok(())
         }
 
-        let _response = http::client().put(url)
+        let _response = client.put(url)
             .with_json_headers()
             .header("AccessKey", self.auth.get_header())
-            .send(body)?;
+            .send(body).await?;
 
         Ok(())
     }
 
-    fn update_record<T>(&self, rtype: RecordType, host: &str, urec: &T) -> Result<()>
+    async fn update_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str, urec: &T) -> Result<()>
     where
         T: DeserializeOwned + Display,
+        C: HttpClient + ?Sized,
     {
-        let rec: Record<T> = match self.get_upstream_record(rtype, host)? {
+        let rec: Record<T> = match self.get_upstream_record(client, rtype, host).await? {
             Some(rec) => rec,
             None => {
                 warn!("UPDATE: Record {host} doesn't exist");
@@ -217,7 +231,7 @@ impl DnsProvider for Bunny {
         };
 
         let rec_id = rec.id;
-        let zone_id = self.get_zone_id()?;
+        let zone_id = self.get_zone_id(client).await?;
         let url = format!("{API_BASE}/{zone_id}/records/{rec_id}");
 
         let record = CreateUpdate {
@@ -233,17 +247,19 @@ impl DnsProvider for Bunny {
         }
 
         let body = serde_json::to_string(&record)?;
-        http::client().post(url)
+        client.post(url)
             .with_json_headers()
             .header("AccessKey", self.auth.get_header())
-            .send(body)?;
+            .send(body).await?;
 
         Ok(())
     }
 
-    fn delete_record(&self, rtype: RecordType, host: &str) -> Result<()>
+    async fn delete_record_with<C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
     {
-        let rec = match self.get_upstream_record(rtype, host)? {
+        let rec = match self.get_upstream_record(client, rtype, host).await? {
             Some(rec) => rec,
             None => {
                 warn!("DELETE: Record {host} doesn't exist");
@@ -251,23 +267,57 @@ impl DnsProvider for Bunny {
             }
         };
 
-        self.do_delete(rec)
+        self.do_delete(client, rec).await
     }
 
-    fn delete_all_records(&self, rtype: RecordType, host: &str) -> Result<()>
+    async fn delete_all_records_with<C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
     {
-        let recs: Vec<Record<String>> = self.get_upstream_records(rtype, host)?;
+        let recs: Vec<Record<String>> = self.get_upstream_records(client, rtype, host).await?;
         for rec in recs {
-            self.do_delete(rec)?;
+            self.do_delete(client, rec).await?;
         }
 
         Ok(())
     }
 
-    generate_helpers!();
+    async fn delete_value_with<C>(&self, client: &C, rtype: RecordType, host: &str, value: &str) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let recs: Vec<Record<String>> = self.get_upstream_records(client, rtype, host).await?;
+        for rec in recs.into_iter().filter(|rec| same_value(rtype, &rec.value, value)) {
+            self.do_delete(client, rec).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn list_zone_with<C>(&self, client: &C) -> Result<ZoneListing>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let zone_id = self.get_zone_id(client).await?;
+        let url = format!("{API_BASE}/{zone_id}");
+
+        let zone = client.get(url)
+            .header("AccessKey", self.auth.get_header())
+            .with_json_headers()
+            .call().await?
+            .to_option::<ZoneRecords>()?
+            .ok_or(Error::ZoneNotFound(self.config.domain.clone()))?;
+
+        let listing = ZoneListing::collect(zone.records.into_iter()
+            .map(|rec| rec.into_zone_record(&self.config.domain)));
+
+        Ok(listing)
+    }
 
 }
 
+http_provider_impl!(Bunny);
+
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
@@ -1,6 +1,6 @@
 use serde::{Deserialize, Deserializer, Serialize};
 
-use crate::{errors::Error, RecordType};
+use crate::{errors::Error, relative_host, to_rdata, RdataParts, RecordType, UnsupportedRecord, ZoneRecord};
 
 
 #[derive(Deserialize, Debug)]
@@ -62,6 +62,64 @@ pub(crate) struct Record<T> {
     pub ttl: u64,
 }
 
+// As `Record`, but for zone listings, which may contain
+// Bunny-specific types (redirects, pull zones, scripts).
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct ListRecord {
+    #[serde(rename = "Type")]
+    pub rtype: u64,
+    pub value: String,
+    pub name: String,
+    pub ttl: u64,
+    #[serde(default)]
+    pub priority: u64,
+    #[serde(default)]
+    pub weight: u64,
+    #[serde(default)]
+    pub port: u64,
+    #[serde(default)]
+    pub flags: u64,
+    #[serde(default)]
+    pub tag: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct ZoneRecords {
+    pub records: Vec<ListRecord>,
+}
+
+impl ListRecord {
+    pub fn into_zone_record(self, domain: &str) -> Result<ZoneRecord, UnsupportedRecord> {
+        let Ok(rtype) = RecordType::try_from(self.rtype) else {
+            let name = match self.rtype {
+                5 => "Redirect".to_string(),
+                6 => "Flatten".to_string(),
+                7 => "PullZone".to_string(),
+                11 => "Script".to_string(),
+                id => format!("type {id}"),
+            };
+            return Err(UnsupportedRecord::unknown_type(name, relative_host(&self.name, domain), self.value));
+        };
+        let parts = RdataParts {
+            priority: Some(self.priority),
+            weight: Some(self.weight),
+            port: Some(self.port),
+            flags: Some(self.flags),
+            tag: Some(&self.tag),
+        };
+
+        Ok(ZoneRecord {
+            rtype,
+            host: relative_host(&self.name, domain),
+            value: to_rdata(rtype, &self.value, parts),
+            ttl: u32::try_from(self.ttl).ok(),
+            modified: None,
+        })
+    }
+}
+
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "PascalCase")]
 pub(crate) struct CreateUpdate<T> {
@@ -149,3 +207,66 @@ where
 {
     u64::from(*rt).serialize(serializer)
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tests::check_malformed_replies, ZoneListing};
+
+    #[test]
+    fn test_malformed_zone_list() {
+        check_malformed_replies::<ZoneList>(r#"{
+            "Items": [{"Id": 12345, "Domain": "example.com"}]
+        }"#);
+    }
+
+    #[test]
+    fn test_malformed_record() {
+        check_malformed_replies::<Record<String>>(r#"{
+            "Id": 12681448,
+            "Type": 0,
+            "Ttl": 300,
+            "Value": "170.64.213.116",
+            "Name": "www",
+            "Weight": 100
+        }"#);
+    }
+
+    #[test]
+    fn test_malformed_zone_records() {
+        check_malformed_replies::<ZoneRecords>(r#"{
+            "Records": [{
+                "Id": 12681448,
+                "Type": 4,
+                "Ttl": 300,
+                "Value": "mail.example.com",
+                "Name": "",
+                "Priority": 10
+            }]
+        }"#);
+    }
+
+    #[test]
+    fn test_into_zone_record() {
+        let zone: ZoneRecords = serde_json::from_str(r#"{
+            "Records": [
+                {"Type": 4, "Ttl": 300, "Value": "mail.example.com", "Name": "", "Priority": 10},
+                {"Type": 9, "Ttl": 300, "Value": "letsencrypt.org", "Name": "", "Flags": 0, "Tag": "issue"},
+                {"Type": 5, "Ttl": 300, "Value": "https://example.org", "Name": "old"}
+            ]
+        }"#).unwrap();
+        let zone = ZoneListing::collect(zone.records.into_iter()
+            .map(|r| r.into_zone_record("example.com")));
+        let recs = zone.records;
+
+        assert_eq!(2, recs.len());
+        assert_eq!("10 mail.example.com", recs[0].value);
+        assert_eq!("0 issue \"letsencrypt.org\"", recs[1].value);
+        assert_eq!("", recs[1].host);
+
+        assert_eq!(1, zone.skipped.len());
+        assert_eq!("Redirect", zone.skipped[0].rtype);
+        assert_eq!("old", zone.skipped[0].host);
+    }
+}
@@ -0,0 +1,442 @@
+use std::{collections::HashMap, fmt::{self, Display, Formatter}};
+
+use serde::{Deserialize, Serialize};
+
+use tracing::{error, info, warn};
+
+use crate::{
+    errors::{Error, MutationState, Result},
+    DnsProvider, RecordType, ZoneRecord,
+};
+
+
+/// A single change to a zone.
+///
+/// Values are passed to the provider as-is, so TXT values should be
+/// quoted as they would be for `create_record()`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "op")]
+pub enum Change {
+    Create {
+        rtype: RecordType,
+        host: String,
+        value: String,
+    },
+    Update {
+        rtype: RecordType,
+        host: String,
+        value: String,
+    },
+    Delete {
+        rtype: RecordType,
+        host: String,
+    },
+    DeleteAll {
+        rtype: RecordType,
+        host: String,
+    },
+}
+
+impl Change {
+    /// The host this change applies to.
+    pub fn host(&self) -> &str {
+        match self {
+            Change::Create { host, .. }
+            | Change::Update { host, .. }
+            | Change::Delete { host, .. }
+            | Change::DeleteAll { host, .. } => host,
+        }
+    }
+
+    /// The record type this change applies to.
+    pub fn rtype(&self) -> RecordType {
+        match self {
+            Change::Create { rtype, .. }
+            | Change::Update { rtype, .. }
+            | Change::Delete { rtype, .. }
+            | Change::DeleteAll { rtype, .. } => *rtype,
+        }
+    }
+
+    /// The new value, if any.
+    pub fn value(&self) -> Option<&str> {
+        match self {
+            Change::Create { value, .. } | Change::Update { value, .. } => Some(value),
+            Change::Delete { .. } | Change::DeleteAll { .. } => None,
+        }
+    }
+
+    /// Whether this change removes records.
+    pub fn is_delete(&self) -> bool {
+        matches!(self, Change::Delete { .. } | Change::DeleteAll { .. })
+    }
+
+    /// Apply this change to a blocking provider.
+    pub fn apply(&self, provider: &impl DnsProvider) -> Result<()> {
+        match self {
+            Change::Create { rtype, host, value } => provider.create_record(*rtype, host, value),
+            Change::Update { rtype, host, value } => provider.update_record(*rtype, host, value),
+            Change::Delete { rtype, host } => provider.delete_record(*rtype, host),
+            Change::DeleteAll { rtype, host } => provider.delete_all_records(*rtype, host),
+        }
+    }
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Create { rtype, host, value } => write!(f, "create {rtype} {host} -> {value}"),
+            Change::Update { rtype, host, value } => write!(f, "update {rtype} {host} -> {value}"),
+            Change::Delete { rtype, host } => write!(f, "delete {rtype} {host}"),
+            Change::DeleteAll { rtype, host } => write!(f, "delete all {rtype} {host}"),
+        }
+    }
+}
+
+
+/// A set of changes to be applied together.
+///
+/// Submit with `DnsProvider::apply_changes()`, which uses the
+/// provider's atomic batch endpoint where there is one. Changes are
+/// otherwise applied in order, as for `Change::apply()`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ChangeSet {
+    pub changes: Vec<Change>,
+}
+
+impl ChangeSet {
+    /// Create an empty change set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a change to the set.
+    pub fn push(&mut self, change: Change) -> &mut Self {
+        self.changes.push(change);
+        self
+    }
+
+    /// Whether there are no changes in the set.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl From<Vec<Change>> for ChangeSet {
+    fn from(changes: Vec<Change>) -> Self {
+        Self { changes }
+    }
+}
+
+impl FromIterator<Change> for ChangeSet {
+    fn from_iter<I: IntoIterator<Item = Change>>(iter: I) -> Self {
+        Self { changes: iter.into_iter().collect() }
+    }
+}
+
+
+/// The values of a record set before and after a change set.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct SetChange {
+    pub rtype: RecordType,
+    /// The host as first given in the changes.
+    pub host: String,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+}
+
+impl SetChange {
+    pub fn is_changed(&self) -> bool {
+        self.before != self.after
+    }
+
+    fn matches(&self, change: &Change) -> bool {
+        self.rtype == change.rtype() && self.host.eq_ignore_ascii_case(change.host())
+    }
+}
+
+/// Apply changes to the current values of the record sets they
+/// touch, without contacting the provider. `current` returns the
+/// values of a record set as the provider stores them.
+///
+/// This follows the usual single-record semantics; updating or
+/// deleting a single record fails if the set has more than one
+/// value, and updating a missing record is a no-op. Returns the
+/// touched sets in order of first change; hosts are compared
+/// case-insensitively.
+pub(crate) fn resolve<F>(changes: &[Change], current: F) -> Result<Vec<SetChange>>
+where
+    F: Fn(RecordType, &str) -> Vec<String>
+{
+    let mut sets: Vec<SetChange> = Vec::new();
+
+    for change in changes {
+        let (rtype, host) = (change.rtype(), change.host());
+        let i = match sets.iter().position(|s| s.matches(change)) {
+            Some(i) => i,
+            None => {
+                let before = current(rtype, host);
+                sets.push(SetChange { rtype, host: host.to_string(), after: before.clone(), before });
+                sets.len() - 1
+            }
+        };
+        let set = &mut sets[i];
+
+        let nr = set.after.len();
+        let single = || if nr > 1 {
+            Err(Error::UnexpectedRecord(format!("Returned number of records is {nr}, should be 1")))
+        } else {
+            Ok(())
+        };
+        match change {
+            Change::Create { value, .. } => set.after.push(value.clone()),
+            Change::Update { value, .. } => {
+                single()?;
+                if let Some(v) = set.after.first_mut() {
+                    *v = value.clone();
+                }
+            }
+            Change::Delete { .. } => {
+                single()?;
+                set.after.clear();
+            }
+            Change::DeleteAll { .. } => set.after.clear(),
+        }
+    }
+
+    Ok(sets)
+}
+
+/// The changes that restore the record sets touched by `applied` to
+/// their previous values, in reverse order.
+pub(crate) fn rollback_changes(sets: &[SetChange], applied: &[Change]) -> Vec<Change> {
+    sets.iter().rev()
+        .filter(|set| applied.iter().any(|c| set.matches(c)))
+        .flat_map(|set| {
+            let delete = Change::DeleteAll { rtype: set.rtype, host: set.host.clone() };
+            let creates = set.before.iter()
+                .map(|value| Change::Create { rtype: set.rtype, host: set.host.clone(), value: value.clone() });
+            std::iter::once(delete).chain(creates)
+        })
+        .collect()
+}
+
+/// The touched record sets of a change set, from a zone snapshot.
+fn snapshot_sets(changes: &[Change], snapshot: &[ZoneRecord]) -> Result<Vec<SetChange>> {
+    resolve(changes, |rtype, host| {
+        snapshot.iter()
+            .filter(|r| r.rtype == rtype && r.host.eq_ignore_ascii_case(host))
+            .map(|r| r.value.clone())
+            .collect()
+    })
+}
+
+/// Plan the fallback application of a change set. Returns the
+/// touched record sets if the zone could be listed for rollback.
+pub(crate) fn prepare_sequential(changes: &ChangeSet, listed: Result<Vec<ZoneRecord>>) -> Result<Option<Vec<SetChange>>> {
+    match listed {
+        Ok(snapshot) => snapshot_sets(&changes.changes, &snapshot).map(Some),
+        Err(Error::Unsupported(_)) => {
+            warn!("Zone cannot be listed; changes will not be rolled back on failure");
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// The error for a failed fallback change set, after any rollback.
+pub(crate) fn sequential_failure(error: Error, rolled_back: bool) -> Error {
+    let state = if rolled_back {
+        MutationState::NotApplied
+    } else {
+        MutationState::MaybeApplied
+    };
+    Error::ChangeSetFailed(Box::new(error), state)
+}
+
+/// Apply a change set one change at a time, rolling back on failure.
+///
+/// This is the fallback for providers without an atomic batch
+/// endpoint. The zone is listed first so that the record sets touched
+/// by the changes can be restored if a change fails; restoring
+/// recreates the records, so record IDs and TTLs may not be
+/// preserved. Rollback is best-effort, and is skipped if the zone
+/// cannot be listed.
+///
+/// On failure returns `Error::ChangeSetFailed`, which reports
+/// whether the rollback succeeded.
+pub fn apply_sequential<P>(provider: &P, changes: &ChangeSet) -> Result<()>
+where
+    P: DnsProvider + ?Sized
+{
+    let sets = prepare_sequential(changes, provider.list_records())?;
+
+    for (i, change) in changes.changes.iter().enumerate() {
+        info!("Applying {change}");
+        let Err(e) = provider.apply_change(change) else {
+            continue;
+        };
+        warn!("Failed to {change}: {e}");
+
+        let Some(sets) = &sets else {
+            return Err(sequential_failure(e, false));
+        };
+        // The failed change may have been partially applied
+        let rollback = rollback_changes(sets, &changes.changes[..=i]);
+        let mut rolled_back = true;
+        for undo in &rollback {
+            info!("Rolling back: {undo}");
+            if let Err(e) = provider.apply_change(undo) {
+                error!("Failed to roll back with {undo}: {e}");
+                rolled_back = false;
+            }
+        }
+        return Err(sequential_failure(e, rolled_back));
+    }
+
+    Ok(())
+}
+
+
+/// Group changes so that no two changes to the same name run
+/// concurrently. This is the ordering used by the bulk operations.
+///
+/// Returns the indices of `changes` by name, in the order each name
+/// first appears. Within a name deletes are ordered before creates
+/// and updates, otherwise the original order is kept. Names are
+/// compared case-insensitively.
+pub fn group_by_name(changes: &[Change]) -> Vec<Vec<usize>> {
+    let mut names: HashMap<String, usize> = HashMap::new();
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+
+    for (i, change) in changes.iter().enumerate() {
+        let g = *names.entry(change.host().to_lowercase())
+            .or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+        groups[g].push(i);
+    }
+
+    for group in groups.iter_mut() {
+        // Stable, so the original order is otherwise preserved.
+        group.sort_by_key(|i| !changes[*i].is_delete());
+    }
+
+    groups
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::MemoryProvider;
+
+    fn create(host: &str, value: &str) -> Change {
+        Change::Create { rtype: RecordType::A, host: host.to_string(), value: value.to_string() }
+    }
+
+    fn delete(host: &str) -> Change {
+        Change::DeleteAll { rtype: RecordType::A, host: host.to_string() }
+    }
+
+    #[test]
+    fn test_group_by_name() {
+        let changes = vec![
+            create("www", "10.0.0.1"),
+            create("mail", "10.0.0.2"),
+            delete("WWW"),
+            delete("mail"),
+            create("ftp", "10.0.0.3"),
+        ];
+        let groups = group_by_name(&changes);
+        assert_eq!(vec![vec![2, 0], vec![3, 1], vec![4]], groups);
+    }
+
+    #[test]
+    fn test_apply() -> Result<()> {
+        let provider = MemoryProvider::new("example.com");
+        create("www", "10.0.0.1").apply(&provider)?;
+        Change::Update { rtype: RecordType::A, host: "www".to_string(), value: "10.0.0.2".to_string() }
+            .apply(&provider)?;
+        assert_eq!(Some("10.0.0.2".parse()?), provider.get_a_record("www")?);
+
+        delete("www").apply(&provider)?;
+        assert_eq!(None, provider.get_a_record("www")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve() -> Result<()> {
+        let current = |_: RecordType, host: &str| match host {
+            "www" => vec!["10.0.0.1".to_string()],
+            "multi" => vec!["10.0.0.1".to_string(), "10.0.0.2".to_string()],
+            _ => vec![],
+        };
+
+        let sets = resolve(&[
+            create("www", "10.0.0.2"),
+            delete("MAIL"),
+            create("mail", "10.0.0.3"),
+            delete("ftp"),
+        ], current)?;
+        assert_eq!(3, sets.len());
+        assert_eq!(vec!["10.0.0.1", "10.0.0.2"], sets[0].after);
+        assert_eq!(vec!["10.0.0.3"], sets[1].after);
+        assert!(!sets[2].is_changed());
+
+        let update = Change::Update { rtype: RecordType::A, host: "multi".to_string(), value: "10.0.0.3".to_string() };
+        assert!(matches!(resolve(&[update], current), Err(Error::UnexpectedRecord(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_sequential() -> Result<()> {
+        let provider = MemoryProvider::new("example.com");
+        provider.create_a_record("www", &"10.0.0.1".parse()?)?;
+
+        let changes: ChangeSet = vec![
+            delete("www"),
+            create("www", "10.0.0.2"),
+            create("mail", "10.0.0.3"),
+        ].into();
+        provider.apply_changes(&changes)?;
+        assert_eq!(Some("10.0.0.2".parse()?), provider.get_a_record("www")?);
+        assert_eq!(Some("10.0.0.3".parse()?), provider.get_a_record("mail")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_sequential_rollback() -> Result<()> {
+        let mut provider = MemoryProvider::new("example.com");
+        provider.types = &[RecordType::A];
+        provider.create_a_record("www", &"10.0.0.1".parse()?)?;
+
+        // The TXT create fails after the A record has been replaced
+        let mut changes = ChangeSet::new();
+        changes.push(delete("www"))
+            .push(create("www", "10.0.0.2"))
+            .push(Change::Create { rtype: RecordType::TXT, host: "www".to_string(), value: "\"abc\"".to_string() });
+
+        let err = provider.apply_changes(&changes).unwrap_err();
+        assert!(matches!(err, Error::ChangeSetFailed(_, MutationState::NotApplied)));
+        assert_eq!(Some("10.0.0.1".parse()?), provider.get_a_record("www")?);
+        assert_eq!(1, provider.list_records()?.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_serde() -> Result<()> {
+        let change: Change = serde_json::from_str(r#"{"op": "create", "rtype": "TXT", "host": "_acme", "value": "\"abc\""}"#)?;
+        assert_eq!(Change::Create { rtype: RecordType::TXT, host: "_acme".to_string(), value: "\"abc\"".to_string() }, change);
+
+        let changes: ChangeSet = serde_json::from_str(r#"[{"op": "delete_all", "rtype": "A", "host": "www"}]"#)?;
+        assert_eq!(ChangeSet::from(vec![delete("www")]), changes);
+        Ok(())
+    }
+}
@@ -2,16 +2,19 @@ mod types;
 
 use std::{fmt::{Debug, Display}, sync::Mutex};
 
-use serde::{de::DeserializeOwned, Deserialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tracing::{error, info, warn};
 
 use crate::{
-    cloudflare::types::{CreateRecord, GetRecord, GetRecords, Response, ZoneInfo}, errors::{Error, Result}, generate_helpers,
-    http::{self, ResponseToOption, WithHeaders}, Config, DnsProvider, RecordType
+    change::{resolve, ChangeSet},
+    cloudflare::types::{BatchDelete, BatchPatch, BatchRequest, CreateRecord, GetRecord, GetRecords, ListRecord, Response, ZoneInfo},
+    errors::{Error, Result}, find_zone,
+    http::{http_provider_impl, HttpClient, ResponseToOption, WithHeaders}, verify::same_value, Config, RecordType, ZoneListing
 };
 
 
 const API_BASE: &str = "https://api.cloudflare.com/client/v4";
+const LIST_PAGE_SIZE: usize = 1000;
 
 
 /// Authentication credentials for the Cloudflare API.
@@ -34,6 +37,7 @@ impl Auth {
 /// Holds configuration and authentication state for performing API calls.
 pub struct Cloudflare {
     config: Config,
+    endpoint: &'static str,
     auth: Auth,
     zone_id: Mutex<Option<String>>,
 }
@@ -42,35 +46,43 @@ impl Cloudflare {
 
     /// Create a new `Cloudflare` provider instance.
     pub fn new(config: Config, auth: Auth) -> Self {
+        Self::new_with_endpoint(config, auth, API_BASE)
+    }
+
+    /// Create a new `Cloudflare` provider with a custom API endpoint.
+    pub fn new_with_endpoint(config: Config, auth: Auth, endpoint: &'static str) -> Self {
         Self {
             config,
+            endpoint,
             auth,
             zone_id: Mutex::new(None),
         }
     }
 
-    fn get_upstream_records<T>(&self, _rtype: &RecordType, host: &str) -> Result<Vec<GetRecord<T>>>
+    async fn get_upstream_records<T, C>(&self, client: &C, _rtype: &RecordType, host: &str) -> Result<Vec<GetRecord<T>>>
     where
-        T: DeserializeOwned
+        T: DeserializeOwned,
+        C: HttpClient + ?Sized,
     {
-        let zone_id = self.get_zone_id()?;
-        let url = format!("{API_BASE}/zones/{zone_id}/dns_records?name={host}.{}", self.config.domain);
+        let zone_id = self.get_zone_id(client).await?;
+        let url = format!("{}/zones/{zone_id}/dns_records?name={host}.{}", self.endpoint, self.config.domain);
 
-        let response = http::client().get(url)
+        let response = client.get(url)
             .with_json_headers()
             .with_auth(self.auth.get_header())
-            .call()?
+            .call().await?
             .to_option::<Response<GetRecords<T>>>()?;
         let recs = check_response(response)?;
 
         Ok(recs)
     }
 
-    fn get_upstream_record<T>(&self, _rtype: &RecordType, host: &str) -> Result<Option<GetRecord<T>>>
+    async fn get_upstream_record<T, C>(&self, client: &C, _rtype: &RecordType, host: &str) -> Result<Option<GetRecord<T>>>
     where
-        T: DeserializeOwned
+        T: DeserializeOwned,
+        C: HttpClient + ?Sized,
     {
-        let mut recs = self.get_upstream_records(_rtype, host)?;
+        let mut recs = self.get_upstream_records(client, _rtype, host).await?;
 
         // FIXME: Assumes no or single address (which probably makes
         // sense for DDNS and DNS-01, but may cause issues with
@@ -87,36 +99,51 @@ impl Cloudflare {
         Ok(Some(recs.remove(0)))
     }
 
-    fn get_zone_id(&self) -> Result<String> {
-        let mut id_p = self.zone_id.lock()
-            .map_err(|e| Error::LockingError(e.to_string()))?;
-
-        if let Some(id) = id_p.as_ref() {
+    async fn get_zone_id<C>(&self, client: &C) -> Result<String>
+    where
+        C: HttpClient + ?Sized,
+    {
+        // The lock is not held across the lookup, as it may
+        // suspend; concurrent first calls may both fetch the ID.
+        if let Some(id) = self.zone_id.lock()
+            .map_err(|e| Error::LockingError(e.to_string()))?
+            .as_ref()
+        {
             return Ok(id.clone());
         }
 
-        let zone = self.get_zone_info()?;
+        let zone = self.get_zone_info(client).await?;
         let id = zone.id;
-        *id_p = Some(id.clone());
+        *self.zone_id.lock()
+            .map_err(|e| Error::LockingError(e.to_string()))? = Some(id.clone());
 
         Ok(id)
     }
 
-    fn get_zone_info(&self) -> Result<ZoneInfo> {
-        let uri = format!("{API_BASE}/zones?name={}", self.config.domain);
-        let resp = http::client()
+    async fn get_zone_info<C>(&self, client: &C) -> Result<ZoneInfo>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let uri = format!("{}/zones?name={}", self.endpoint, self.config.domain);
+        let resp = client
             .get(uri)
             .with_json_headers()
             .with_auth(self.auth.get_header())
-            .call()?
-            .to_option::<Response<Vec<ZoneInfo>>>()?;
-        let mut zones = check_response(resp)?;
-
-        Ok(zones.remove(0))
+            .call().await?
+            .to_option::<Response<Vec<ZoneInfo>>>()?
+            .ok_or(Error::ZoneNotFound(self.config.domain.clone()))?;
+        let zones = check_response(Some(resp))?;
+
+        // The token may not have visibility of the zone, in which
+        // case Cloudflare returns success with an empty list.
+        find_zone(zones, &self.config.domain, |z| &z.name)
     }
 
-    fn do_delete(&self, rec: GetRecord<String>) -> Result<()> {
-        let url = format!("{API_BASE}/zones/{}/dns_records/{}", self.get_zone_id()?, rec.id);
+    async fn do_delete<C>(&self, client: &C, rec: GetRecord<String>) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let url = format!("{}/zones/{}/dns_records/{}", self.endpoint, self.get_zone_id(client).await?, rec.id);
 
         if self.config.dry_run {
             info!("DRY-RUN: Would have sent DELETE to {url}");
@@ -124,34 +151,19 @@ impl Cloudflare {
         }
 
         info!("Deleting DNS {} record {}", rec.rtype, rec.name);
-        http::client().delete(url)
+        client.delete(url)
             .with_json_headers()
             .with_auth(self.auth.get_header())
-            .call()?;
+            .call().await?;
         Ok(())
     }
 
-}
-
-fn check_response<T>(response: Option<Response<T>>) -> Result<T> {
-    let response = match response {
-        Some(r) => r,
-        None => return Err(Error::RecordNotFound("Record not found".to_string())),
-    };
-    if !response.success {
-        return Err(Error::ApiError("Failed to find record".to_string()))
-    }
-    Ok(response.result)
-}
-
-
-impl DnsProvider for Cloudflare {
-
-    fn get_record<T>(&self, rtype: RecordType, host: &str) -> Result<Option<T>>
+    async fn get_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<Option<T>>
     where
-        T: DeserializeOwned
+        T: DeserializeOwned,
+        C: HttpClient + ?Sized,
     {
-        let resp = self.get_upstream_record(&rtype, host)?;
+        let resp = self.get_upstream_record(client, &rtype, host).await?;
         let rec: GetRecord<T> = match resp {
             Some(recs) => recs,
             None => return Ok(None)
@@ -159,12 +171,13 @@ impl DnsProvider for Cloudflare {
         Ok(Some(rec.content))
     }
 
-    fn create_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+    async fn create_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str, record: &T) -> Result<()>
     where
         T: Display,
+        C: HttpClient + ?Sized,
     {
-        let zone_id = self.get_zone_id()?;
-        let url = format!("{API_BASE}/zones/{zone_id}/dns_records");
+        let zone_id = self.get_zone_id(client).await?;
+        let url = format!("{}/zones/{zone_id}/dns_records", self.endpoint);
 
         let rec = CreateRecord {
             name: format!("{host}.{}", self.config.domain),
@@ -179,19 +192,20 @@ impl DnsProvider for Cloudflare {
         }
 
         let body = serde_json::to_string(&rec)?;
-        let _response = http::client().post(url)
+        let _response = client.post(url)
             .with_json_headers()
             .with_auth(self.auth.get_header())
-            .send(body)?;
+            .send(body).await?;
 
         Ok(())
     }
 
-    fn update_record<T>(&self, rtype: RecordType, host: &str, urec: &T) -> Result<()>
+    async fn update_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str, urec: &T) -> Result<()>
     where
         T: DeserializeOwned + Display,
+        C: HttpClient + ?Sized,
     {
-        let rec: GetRecord<T> = match self.get_upstream_record(&rtype, host)? {
+        let rec: GetRecord<T> = match self.get_upstream_record(client, &rtype, host).await? {
             Some(rec) => rec,
             None => {
                 warn!("UPDATE: Record {host} doesn't exist");
@@ -200,8 +214,8 @@ impl DnsProvider for Cloudflare {
         };
 
         let rec_id = rec.id;
-        let zone_id = self.get_zone_id()?;
-        let url = format!("{API_BASE}/zones/{zone_id}/dns_records/{rec_id}");
+        let zone_id = self.get_zone_id(client).await?;
+        let url = format!("{}/zones/{zone_id}/dns_records/{rec_id}", self.endpoint);
 
         let record = CreateRecord {
             name: host.to_string(),
@@ -216,17 +230,19 @@ impl DnsProvider for Cloudflare {
         }
 
         let body = serde_json::to_string(&record)?;
-        http::client().put(url)
+        client.put(url)
             .with_json_headers()
             .with_auth(self.auth.get_header())
-            .send(body)?;
+            .send(body).await?;
 
         Ok(())
     }
 
-    fn delete_record(&self, rtype: RecordType, host: &str) -> Result<()>
+    async fn delete_record_with<C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
     {
-        let rec: GetRecord<String> = match self.get_upstream_record(&rtype, host)? {
+        let rec: GetRecord<String> = match self.get_upstream_record(client, &rtype, host).await? {
             Some(rec) => rec,
             None => {
                 warn!("DELETE: Record {host} doesn't exist");
@@ -234,25 +250,180 @@ impl DnsProvider for Cloudflare {
             }
         };
 
-        self.do_delete(rec)?;
+        self.do_delete(client, rec).await?;
 
         Ok(())
 
     }
 
-    fn delete_all_records(&self, rtype: RecordType, host: &str) -> Result<()>
+    async fn delete_all_records_with<C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
     {
-        let recs: Vec<GetRecord<String>> = self.get_upstream_records(&rtype, host)?;
+        let recs: Vec<GetRecord<String>> = self.get_upstream_records(client, &rtype, host).await?;
         for rec in recs {
-            self.do_delete(rec)?;
+            self.do_delete(client, rec).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn delete_value_with<C>(&self, client: &C, rtype: RecordType, host: &str, value: &str) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let zone_id = self.get_zone_id(client).await?;
+        let recs = self.get_set_records(client, &zone_id, rtype, host).await?;
+        for rec in recs.into_iter().filter(|rec| same_value(rtype, &rec.content, value)) {
+            self.do_delete(client, rec).await?;
         }
 
         Ok(())
     }
 
-    generate_helpers!();
+    async fn list_zone_with<C>(&self, client: &C) -> Result<ZoneListing>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let zone_id = self.get_zone_id(client).await?;
+
+        let mut listed = Vec::new();
+        let mut lossy = Vec::new();
+        for page in 1.. {
+            let url = format!("{}/zones/{zone_id}/dns_records?page={page}&per_page={LIST_PAGE_SIZE}", self.endpoint);
+            let response = client.get(url)
+                .with_json_headers()
+                .with_auth(self.auth.get_header())
+                .call().await?
+                .to_option::<Response<Vec<ListRecord>>>()?;
+            let recs = check_response(response)?;
+
+            let last = recs.len() < LIST_PAGE_SIZE;
+            for rec in recs {
+                lossy.extend(rec.proxied_record(&self.config.domain));
+                listed.push(rec.into_zone_record(&self.config.domain));
+            }
+            if last {
+                break;
+            }
+        }
+
+        let mut listing = ZoneListing::collect(listed);
+        listing.lossy = lossy;
+        Ok(listing)
+    }
+
+    async fn get_set_records<C>(&self, client: &C, zone_id: &str, rtype: RecordType, host: &str) -> Result<Vec<GetRecord<String>>>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let url = format!("{}/zones/{zone_id}/dns_records?type={rtype}&name={}", self.endpoint, self.fqdn(host));
+        let response = client.get(url)
+            .with_json_headers()
+            .with_auth(self.auth.get_header())
+            .call().await?
+            .to_option::<Response<GetRecords<String>>>()?;
+        check_response(response)
+    }
+
+    fn fqdn(&self, host: &str) -> String {
+        match host {
+            "" => self.config.domain.clone(),
+            host => format!("{host}.{}", self.config.domain),
+        }
+    }
+
+    async fn apply_changes_with<C>(&self, client: &C, changes: &ChangeSet) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let zone_id = self.get_zone_id(client).await?;
+
+        // Fetch the records of each touched set up front, as
+        // resolution is synchronous.
+        let mut current: Vec<(RecordType, String, Vec<GetRecord<String>>)> = Vec::new();
+        for change in &changes.changes {
+            let (rtype, host) = (change.rtype(), change.host());
+            if current.iter().any(|(t, h, _)| *t == rtype && h.eq_ignore_ascii_case(host)) {
+                continue;
+            }
+            let recs = self.get_set_records(client, &zone_id, rtype, host).await?;
+            current.push((rtype, host.to_string(), recs));
+        }
+        let records_of = |rtype: RecordType, host: &str| current.iter()
+            .find(|(t, h, _)| *t == rtype && h.eq_ignore_ascii_case(host))
+            .map(|(_, _, recs)| recs.as_slice())
+            .unwrap_or_default();
+
+        let sets = resolve(&changes.changes, |rtype, host| {
+            records_of(rtype, host).iter()
+                .map(|r| r.content.clone())
+                .collect()
+        })?;
+
+        // Keep records whose value is still wanted, reuse the others
+        // for new values, and delete or add the remainder.
+        let mut batch = BatchRequest::default();
+        for set in sets.iter().filter(|s| s.is_changed()) {
+            let mut wanted = set.after.clone();
+            let mut spare = Vec::new();
+            for rec in records_of(set.rtype, &set.host) {
+                match wanted.iter().position(|v| *v == rec.content) {
+                    Some(i) => { wanted.remove(i); }
+                    None => spare.push(rec.id.clone()),
+                }
+            }
+
+            let mut wanted = wanted.into_iter();
+            for id in spare {
+                match wanted.next() {
+                    Some(content) => batch.patches.push(BatchPatch { id, content }),
+                    None => batch.deletes.push(BatchDelete { id }),
+                }
+            }
+            batch.posts.extend(wanted.map(|content| CreateRecord {
+                name: self.fqdn(&set.host),
+                rtype: set.rtype,
+                content,
+                ttl: 300,
+            }));
+        }
+        if batch.is_empty() {
+            return Ok(())
+        }
+
+        let url = format!("{}/zones/{zone_id}/dns_records/batch", self.endpoint);
+        if self.config.dry_run {
+            info!("DRY-RUN: Would have sent {batch:?} to {url}");
+            return Ok(())
+        }
+
+        let body = serde_json::to_string(&batch)?;
+        let response = client.post(url)
+            .with_json_headers()
+            .with_auth(self.auth.get_header())
+            .send(body).await?
+            .to_option::<Response<serde_json::Value>>()?;
+        check_response(response)?;
+
+        Ok(())
+    }
+
+}
+
+fn check_response<T>(response: Option<Response<T>>) -> Result<T> {
+    let response = match response {
+        Some(r) => r,
+        None => return Err(Error::RecordNotFound("Record not found".to_string())),
+    };
+    if !response.success {
+        return Err(Error::ApiError("Failed to find record".to_string()))
+    }
+    Ok(response.result)
 }
 
+http_provider_impl!(Cloudflare, batch, values);
+
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
@@ -271,4 +442,58 @@ pub(crate) mod tests {
     }
 
     generate_tests!("test_cloudflare");
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn test_apply_changes_batch() -> Result<()> {
+        use crate::async_impl::{http::tests::{json_body, json_ok, serve}, AsyncDnsProvider, Native};
+        use crate::change::Change;
+        use serde_json::{json, Value};
+
+        let rec = |id: &str, name: &str, rtype: &str, content: &str| json!(
+            {"id": id, "name": name, "ttl": 300, "type": rtype, "content": content});
+        let ok = |result: Value| json_ok(&json!({"success": true, "result": result}).to_string());
+        let (url, server) = serve(vec![
+            ok(json!([{"id": "z1", "name": "example.com"}])),
+            ok(json!([rec("r1", "www.example.com", "A", "192.0.2.1")])),
+            ok(json!([
+                rec("r2", "old.example.com", "TXT", "\"a\""),
+                rec("r3", "old.example.com", "TXT", "\"b\""),
+            ])),
+            ok(json!([
+                rec("r4", "pool.example.com", "A", "192.0.2.10"),
+                rec("r5", "pool.example.com", "A", "192.0.2.11"),
+            ])),
+            ok(json!([])),
+            ok(json!({})),
+        ]);
+        let config = Config {
+            domain: "example.com".to_string(),
+            dry_run: false,
+        };
+        let auth = Auth { key: "xyz".to_string() };
+        let client = Native::wrap(Cloudflare::new_with_endpoint(config, auth, url.leak()));
+
+        let changes = ChangeSet { changes: vec![
+            Change::Update { rtype: RecordType::A, host: "www".to_string(), value: "192.0.2.2".to_string() },
+            Change::DeleteAll { rtype: RecordType::TXT, host: "old".to_string() },
+            Change::DeleteAll { rtype: RecordType::A, host: "pool".to_string() },
+            Change::Create { rtype: RecordType::A, host: "pool".to_string(), value: "192.0.2.11".to_string() },
+            Change::Create { rtype: RecordType::A, host: "pool".to_string(), value: "192.0.2.12".to_string() },
+            Change::Create { rtype: RecordType::AAAA, host: "".to_string(), value: "2001:db8::1".to_string() },
+        ]};
+        futures::executor::block_on(client.apply_changes(&changes))?;
+
+        // Records keeping their value (r5) are left alone, and spare
+        // records are reused before posting new ones.
+        let requests = server.join().unwrap();
+        assert!(requests[5].starts_with("POST /zones/z1/dns_records/batch HTTP/1.1\r\n"));
+        assert_eq!(json!({
+            "deletes": [{"id": "r2"}, {"id": "r3"}],
+            "patches": [{"id": "r1", "content": "192.0.2.2"}, {"id": "r4", "content": "192.0.2.12"}],
+            "posts": [{"name": "example.com", "ttl": 300, "type": "AAAA", "content": "2001:db8::1"}],
+        }), json_body(&requests[5]));
+
+        Ok(())
+    }
 }
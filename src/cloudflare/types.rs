@@ -1,8 +1,9 @@
 #![allow(unused)]
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-use crate::RecordType;
+use crate::{parse_rtype, relative_host, to_rdata, RdataParts, RecordType, UnsupportedRecord, ZoneRecord};
 
 #[derive(Debug, Deserialize)]
 pub(crate) struct Response<T> {
@@ -52,6 +53,75 @@ pub struct GetRecord<T>
 
 pub type GetRecords<T> = Vec<GetRecord<T>>;
 
+// As `GetRecord`, but for zone listings, which may contain types we
+// don't support. Structured types also have a `data` field, e.g.
+//
+// "data": {
+//     "priority": 10,
+//     "weight": 5,
+//     "port": 443,
+//     "target": "sip.example.com"
+// }
+#[derive(Deserialize, Debug, Clone)]
+pub struct ListRecord {
+    pub name: String,
+    pub ttl: u32,
+    #[serde(rename = "type")]
+    pub rtype: String,
+    pub content: String,
+    pub priority: Option<u64>,
+    pub data: Option<RecordData>,
+    #[serde(default)]
+    pub proxied: bool,
+    pub modified_on: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RecordData {
+    pub weight: Option<u64>,
+    pub port: Option<u64>,
+    pub target: Option<String>,
+}
+
+impl ListRecord {
+    /// The record as listed, if it uses Cloudflare proxying. The
+    /// proxy setting has no equivalent at other providers.
+    pub fn proxied_record(&self, domain: &str) -> Option<UnsupportedRecord> {
+        self.proxied.then(|| UnsupportedRecord {
+            rtype: self.rtype.clone(),
+            host: relative_host(&self.name, domain),
+            value: self.content.clone(),
+            reason: "record is proxied by Cloudflare".to_string(),
+        })
+    }
+
+    pub fn into_zone_record(self, domain: &str) -> Result<ZoneRecord, UnsupportedRecord> {
+        let Some(rtype) = parse_rtype(&self.rtype) else {
+            return Err(UnsupportedRecord::unknown_type(self.rtype, relative_host(&self.name, domain), self.content));
+        };
+
+        let (value, weight, port) = match (rtype, self.data) {
+            (RecordType::SRV, Some(RecordData { weight, port, target: Some(target) })) => (target, weight, port),
+            _ => (self.content, None, None),
+        };
+        let parts = RdataParts {
+            priority: self.priority,
+            weight,
+            port,
+            ..Default::default()
+        };
+
+        Ok(ZoneRecord {
+            rtype,
+            host: relative_host(&self.name, domain),
+            value: to_rdata(rtype, &value, parts),
+            // A TTL of 1 is 'automatic'
+            ttl: (self.ttl != 1).then_some(self.ttl),
+            modified: self.modified_on,
+        })
+    }
+}
+
 
 // {
 //     "name": "example.com",
@@ -69,3 +139,144 @@ pub struct CreateRecord<T> {
     pub rtype: RecordType,
     pub content: T,
 }
+
+// {
+//     "deletes": [{"id": "023e105f4ecef8ad9ca31a8372d0c353"}],
+//     "patches": [{"id": "023e105f4ecef8ad9ca31a8372d0c353", "content": "198.51.100.4"}],
+//     "posts": [{"name": "example.com", "ttl": 3600, "type": "A", "content": "198.51.100.4"}]
+// }
+/// Payload for the batch endpoint; all operations succeed or fail
+/// together.
+#[derive(Serialize, Debug, Default)]
+pub struct BatchRequest {
+    pub deletes: Vec<BatchDelete>,
+    pub patches: Vec<BatchPatch>,
+    pub posts: Vec<CreateRecord<String>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BatchDelete {
+    pub id: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BatchPatch {
+    pub id: String,
+    pub content: String,
+}
+
+impl BatchRequest {
+    pub fn is_empty(&self) -> bool {
+        self.deletes.is_empty() && self.patches.is_empty() && self.posts.is_empty()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::check_malformed_replies;
+
+    #[test]
+    fn test_malformed_zone_info() {
+        check_malformed_replies::<Response<Vec<ZoneInfo>>>(r#"{
+            "success": true,
+            "result": [{"id": "023e105f4ecef8ad9ca31a8372d0c353", "name": "example.com"}]
+        }"#);
+    }
+
+    #[test]
+    fn test_malformed_records() {
+        check_malformed_replies::<Response<GetRecords<String>>>(r#"{
+            "success": true,
+            "result": [{
+                "id": "023e105f4ecef8ad9ca31a8372d0c353",
+                "name": "www.example.com",
+                "ttl": 3600,
+                "type": "A",
+                "content": "198.51.100.4"
+            }]
+        }"#);
+    }
+
+    #[test]
+    fn test_malformed_list() {
+        check_malformed_replies::<Response<Vec<ListRecord>>>(r#"{
+            "success": true,
+            "result": [{
+                "name": "_sip._tcp.example.com",
+                "ttl": 1,
+                "type": "SRV",
+                "content": "5 443 sip.example.com",
+                "priority": 10,
+                "data": {"priority": 10, "weight": 5, "port": 443, "target": "sip.example.com"}
+            }]
+        }"#);
+    }
+
+    #[test]
+    fn test_into_zone_record() {
+        let recs: Vec<ListRecord> = serde_json::from_str(r#"[
+            {"name": "example.com", "ttl": 300, "type": "MX", "content": "mail.example.com", "priority": 10},
+            {"name": "_sip._tcp.example.com", "ttl": 1, "type": "SRV", "content": "5 443 sip.example.com",
+             "priority": 10, "data": {"priority": 10, "weight": 5, "port": 443, "target": "sip.example.com"},
+             "modified_on": "2014-01-01T05:20:00.12345Z"},
+            {"name": "example.com", "ttl": 3600, "type": "SOA", "content": "ns.example.com"}
+        ]"#).unwrap();
+        let recs: Vec<ZoneRecord> = recs.into_iter()
+            .filter_map(|r| r.into_zone_record("example.com").ok())
+            .collect();
+
+        assert_eq!(2, recs.len());
+        assert_eq!(ZoneRecord {
+            rtype: RecordType::MX,
+            host: "".to_string(),
+            value: "10 mail.example.com".to_string(),
+            ttl: Some(300),
+            modified: None,
+        }, recs[0]);
+        assert_eq!("_sip._tcp", recs[1].host);
+        assert_eq!("10 5 443 sip.example.com", recs[1].value);
+        assert_eq!(None, recs[1].ttl);
+        assert_eq!("2014-01-01T05:20:00.123450+00:00", recs[1].modified.unwrap().to_rfc3339());
+    }
+
+    #[test]
+    fn test_proxied_record() {
+        let rec: ListRecord = serde_json::from_str(r#"
+            {"name": "www.example.com", "ttl": 1, "type": "A", "content": "198.51.100.4", "proxied": true}
+        "#).unwrap();
+        let proxied = rec.proxied_record("example.com").unwrap();
+        assert_eq!("www", proxied.host);
+        assert_eq!("198.51.100.4", proxied.value);
+        // Still listed, with the origin address
+        assert_eq!("198.51.100.4", rec.into_zone_record("example.com").unwrap().value);
+
+        let rec: ListRecord = serde_json::from_str(r#"
+            {"name": "www.example.com", "ttl": 1, "type": "A", "content": "198.51.100.4"}
+        "#).unwrap();
+        assert!(rec.proxied_record("example.com").is_none());
+    }
+
+    #[test]
+    fn test_batch_request() {
+        let mut batch = BatchRequest::default();
+        assert!(batch.is_empty());
+        batch.deletes.push(BatchDelete { id: "1".to_string() });
+        batch.patches.push(BatchPatch { id: "2".to_string(), content: "198.51.100.4".to_string() });
+        batch.posts.push(CreateRecord {
+            name: "www.example.com".to_string(),
+            ttl: 300,
+            rtype: RecordType::A,
+            content: "198.51.100.5".to_string(),
+        });
+        assert!(!batch.is_empty());
+
+        let json: serde_json::Value = serde_json::to_value(&batch).unwrap();
+        assert_eq!(serde_json::json!({
+            "deletes": [{"id": "1"}],
+            "patches": [{"id": "2", "content": "198.51.100.4"}],
+            "posts": [{"name": "www.example.com", "ttl": 300, "type": "A", "content": "198.51.100.5"}]
+        }), json);
+    }
+}
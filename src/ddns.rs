@@ -0,0 +1,545 @@
+//! Dynamic DNS updates.
+//!
+//! An [`Updater`] keeps the A and/or AAAA records of a set of hosts
+//! pointing at the current public address, as reported by an
+//! `AddressSource` from the `discovery` module:
+//!
+//! ```no_run
+//! use std::sync::atomic::AtomicBool;
+//! use zone_update::{Config, porkbun, ddns::{Updater, UpdaterOptions}, discovery::echo::HttpEcho};
+//! # fn main() -> zone_update::errors::Result<()> {
+//! let config = Config { domain: "example.com".to_string(), dry_run: false };
+//! let auth = porkbun::Auth { key: "key".to_string(), secret: "secret".to_string() };
+//! let provider = porkbun::Porkbun::new(config, auth);
+//!
+//! let opts = UpdaterOptions {
+//!     state_file: Some("/var/lib/ddns/state.json".into()),
+//!     ..Default::default()
+//! };
+//! let mut updater = Updater::new(provider, HttpEcho::default(), &["home", "vpn"], opts);
+//! updater.run(&AtomicBool::new(false));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The last published addresses are cached, and optionally persisted
+//! to a state file, so the provider is only contacted when the
+//! address changes. Every `refresh` interval the records are checked
+//! against the provider regardless, in case they were changed
+//! elsewhere. Failures are retried with exponential backoff.
+//!
+//! If only some families can be discovered (e.g. IPv6 connectivity
+//! is down) those that were are still published; the records of the
+//! others are left alone, and the failures are listed in the
+//! `UpdateReport`.
+//!
+//! An async version is in `async_impl::ddns`.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::{
+    address_changes,
+    change::ChangeSet,
+    discovery::{AddressSource, Family},
+    errors::Result,
+    DnsProvider,
+};
+
+/// The longest `run()` sleeps before checking for shutdown.
+pub(crate) const SHUTDOWN_POLL: Duration = Duration::from_secs(1);
+
+
+/// Options for an `Updater`.
+#[derive(Clone, Debug)]
+pub struct UpdaterOptions {
+    /// The address families to publish; A records for IPv4 and AAAA
+    /// for IPv6. Records of other families are left alone. Defaults
+    /// to IPv4 only.
+    pub families: Vec<Family>,
+    /// How often to check the current address.
+    pub interval: Duration,
+    /// How often to check the records against the provider, even if
+    /// the address hasn't changed.
+    pub refresh: Duration,
+    /// The delay after the first failure. This doubles with each
+    /// consecutive failure, up to `max_backoff`.
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    /// Where to persist the last published addresses. Without this
+    /// all hosts are refreshed on startup.
+    pub state_file: Option<PathBuf>,
+}
+
+impl Default for UpdaterOptions {
+    fn default() -> Self {
+        Self {
+            families: vec![Family::V4],
+            interval: Duration::from_secs(5 * 60),
+            refresh: Duration::from_secs(24 * 60 * 60),
+            backoff: Duration::from_secs(30),
+            max_backoff: Duration::from_secs(60 * 60),
+            state_file: None,
+        }
+    }
+}
+
+/// The addresses of a host. `None` means the family isn't managed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Addresses {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub v4: Option<Ipv4Addr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub v6: Option<Ipv6Addr>,
+}
+
+impl Addresses {
+    /// The address of `family`, if any.
+    pub fn get(&self, family: Family) -> Option<IpAddr> {
+        match family {
+            Family::V4 => self.v4.map(IpAddr::V4),
+            Family::V6 => self.v6.map(IpAddr::V6),
+        }
+    }
+
+    fn set(&mut self, addr: IpAddr) {
+        match addr {
+            IpAddr::V4(ip) => self.v4 = Some(ip),
+            IpAddr::V6(ip) => self.v6 = Some(ip),
+        }
+    }
+
+    /// Replace the addresses of the families set in `other`.
+    fn merge(&mut self, other: &Addresses) {
+        self.v4 = other.v4.or(self.v4);
+        self.v6 = other.v6.or(self.v6);
+    }
+}
+
+/// The addresses last published for a host.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Published {
+    #[serde(flatten)]
+    pub addresses: Addresses,
+    pub at: DateTime<Utc>,
+}
+
+/// The last published addresses of each host, as persisted to the
+/// state file.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct State {
+    pub hosts: BTreeMap<String, Published>,
+}
+
+impl State {
+    /// Load the state from `path`. A missing file is an empty state.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(json) => Ok(serde_json::from_str(&json)?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Save the state to `path`. The file is replaced atomically, so
+    /// an interrupted save leaves the previous state in place.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// The result of a single update pass.
+#[derive(Clone, Debug, Default)]
+pub struct UpdateReport {
+    /// The current addresses of the families that were discovered.
+    pub addresses: Addresses,
+    /// Families whose address couldn't be discovered, with the
+    /// error. Their records were left alone.
+    pub unresolved: Vec<(Family, String)>,
+    /// Hosts whose records were checked against the provider, because
+    /// the address changed or a refresh was due.
+    pub updated: Vec<String>,
+    /// Hosts that failed to update, with the error.
+    pub failed: Vec<(String, String)>,
+}
+
+
+/// Scheduling and change detection shared by the sync and async
+/// updaters.
+pub(crate) struct Core {
+    pub(crate) hosts: Vec<String>,
+    pub(crate) opts: UpdaterOptions,
+    pub(crate) state: State,
+    failures: u32,
+}
+
+impl Core {
+    pub(crate) fn new(hosts: &[&str], opts: UpdaterOptions) -> Self {
+        let state = match &opts.state_file {
+            Some(path) => State::load(path).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable DDNS state {}: {e}", path.display());
+                State::default()
+            }),
+            None => State::default(),
+        };
+        Self {
+            hosts: hosts.iter().map(|h| h.to_string()).collect(),
+            opts,
+            state,
+            failures: 0,
+        }
+    }
+
+    /// Combine the addresses discovered for each family, and the
+    /// families that couldn't be discovered. Fails only if no family
+    /// could be discovered.
+    pub(crate) fn addresses(results: Vec<(Family, Result<IpAddr>)>) -> Result<(Addresses, Vec<(Family, String)>)> {
+        let mut addrs = Addresses::default();
+        let mut unresolved = Vec::new();
+        let mut first_err = None;
+        for (family, result) in results {
+            match result {
+                Ok(addr) => addrs.set(addr),
+                Err(e) => {
+                    warn!("Failed to discover {family} address: {e}");
+                    unresolved.push((family, e.to_string()));
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+        match first_err {
+            Some(e) if addrs == Addresses::default() => Err(e),
+            _ => Ok((addrs, unresolved)),
+        }
+    }
+
+    /// The hosts that need to be checked against the provider.
+    /// Families missing from `addrs` aren't compared.
+    pub(crate) fn pending(&self, addrs: &Addresses, now: DateTime<Utc>) -> Vec<String> {
+        let refresh = TimeDelta::from_std(self.opts.refresh).unwrap_or(TimeDelta::MAX);
+        let changed = |published: &Addresses| [Family::V4, Family::V6].into_iter()
+            .any(|family| addrs.get(family).is_some() && addrs.get(family) != published.get(family));
+        self.hosts.iter()
+            .filter(|host| match self.state.hosts.get(*host) {
+                Some(published) => changed(&published.addresses) || now - published.at >= refresh,
+                None => true,
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Record the outcome of a pass. The published addresses of
+    /// families missing from `addrs` are kept.
+    pub(crate) fn finish(&mut self, addrs: Addresses, unresolved: Vec<(Family, String)>,
+                         results: Vec<(String, Result<()>)>, now: DateTime<Utc>) -> UpdateReport
+    {
+        let mut report = UpdateReport { addresses: addrs, unresolved, ..Default::default() };
+        for (host, result) in results {
+            match result {
+                Ok(()) => {
+                    let mut addresses = self.state.hosts.get(&host)
+                        .map(|p| p.addresses)
+                        .unwrap_or_default();
+                    addresses.merge(&addrs);
+                    self.state.hosts.insert(host.clone(), Published { addresses, at: now });
+                    report.updated.push(host);
+                }
+                Err(e) => {
+                    warn!("Failed to update {host}: {e}");
+                    report.failed.push((host, e.to_string()));
+                }
+            }
+        }
+        if !report.updated.is_empty()
+            && let Some(path) = &self.opts.state_file
+            && let Err(e) = self.state.save(path)
+        {
+            warn!("Failed to save DDNS state to {}: {e}", path.display());
+        }
+        self.record(report.failed.is_empty());
+        report
+    }
+
+    pub(crate) fn record(&mut self, success: bool) {
+        self.failures = if success { 0 } else { self.failures.saturating_add(1) };
+    }
+
+    /// The delay before the next pass.
+    pub(crate) fn delay(&self) -> Duration {
+        match self.failures {
+            0 => self.opts.interval,
+            n => {
+                let factor = 2u32.saturating_pow(n - 1);
+                self.opts.backoff.saturating_mul(factor).min(self.opts.max_backoff)
+            }
+        }
+    }
+}
+
+/// The changes that publish `addrs` to a host with the `current`
+/// records. Families not in `addrs` are left alone.
+pub(crate) fn publish_changes(host: &str, addrs: &Addresses, current: Addresses) -> ChangeSet {
+    let current = [Family::V4, Family::V6]
+        .map(|family| addrs.get(family).and(current.get(family)));
+    address_changes(host, current, [addrs.get(Family::V4), addrs.get(Family::V6)])
+}
+
+
+/// Keeps the address records of a set of hosts up to date.
+pub struct Updater<P, S> {
+    provider: P,
+    source: S,
+    core: Core,
+}
+
+impl<P: DnsProvider, S: AddressSource> Updater<P, S> {
+    /// Create an updater for `hosts`, loading the state file if
+    /// configured. An unreadable state file is ignored.
+    pub fn new(provider: P, source: S, hosts: &[&str], opts: UpdaterOptions) -> Self {
+        Self {
+            provider,
+            source,
+            core: Core::new(hosts, opts),
+        }
+    }
+
+    /// The last published addresses.
+    pub fn state(&self) -> &State {
+        &self.core.state
+    }
+
+    pub fn provider(&self) -> &P {
+        &self.provider
+    }
+
+    /// Discover the current addresses, and update any hosts that
+    /// have changed or are due a refresh. Fails if no address could
+    /// be discovered; families that couldn't be discovered and
+    /// failures of individual hosts are returned in the report.
+    pub fn check(&mut self) -> Result<UpdateReport> {
+        let results = self.core.opts.families.iter()
+            .map(|&family| (family, self.source.address(family)))
+            .collect();
+        let (addrs, unresolved) = match Core::addresses(results) {
+            Ok(found) => found,
+            Err(e) => {
+                self.core.record(false);
+                return Err(e);
+            }
+        };
+
+        let now = Utc::now();
+        let results = self.core.pending(&addrs, now).into_iter()
+            .map(|host| {
+                let result = self.publish(&host, &addrs);
+                (host, result)
+            })
+            .collect();
+        Ok(self.core.finish(addrs, unresolved, results, now))
+    }
+
+    fn publish(&self, host: &str, addrs: &Addresses) -> Result<()> {
+        let current = Addresses {
+            v4: if addrs.v4.is_some() { self.provider.get_a_record(host)? } else { None },
+            v6: if addrs.v6.is_some() { self.provider.get_aaaa_record(host)? } else { None },
+        };
+        let changes = publish_changes(host, addrs, current);
+        if changes.is_empty() {
+            debug!("{host} is up to date");
+            return Ok(());
+        }
+        info!("Updating {host} to {addrs:?}");
+        self.provider.apply_changes(&changes)
+    }
+
+    /// The delay before the next check; the interval, or the backoff
+    /// after a failure.
+    pub fn next_delay(&self) -> Duration {
+        self.core.delay()
+    }
+
+    /// Check for changes until `shutdown` is set.
+    pub fn run(&mut self, shutdown: &AtomicBool) {
+        while !shutdown.load(Ordering::Relaxed) {
+            match self.check() {
+                Ok(report) if report.failed.is_empty() => {}
+                Ok(report) => warn!("{} hosts failed to update", report.failed.len()),
+                Err(e) => warn!("Address discovery failed: {e}"),
+            }
+            let wake = Instant::now() + self.next_delay();
+            while !shutdown.load(Ordering::Relaxed) {
+                let remaining = wake.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    break;
+                }
+                thread::sleep(remaining.min(SHUTDOWN_POLL));
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{errors::Error, tests::MemoryProvider};
+    use std::sync::Mutex;
+
+    /// An address source that reports whatever it is set to.
+    #[derive(Default)]
+    pub(crate) struct Settable(pub(crate) Mutex<Addresses>);
+
+    impl AddressSource for Settable {
+        fn name(&self) -> String {
+            "settable".to_string()
+        }
+
+        fn address(&self, family: Family) -> Result<IpAddr> {
+            self.0.lock().unwrap().get(family)
+                .ok_or(Error::DiscoveryError(format!("no {family} address")))
+        }
+    }
+
+    pub(crate) fn state_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir()
+            .join(format!("zone-update-ddns-{name}-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_updater() -> Result<()> {
+        let path = state_file("sync");
+        let opts = UpdaterOptions {
+            families: vec![Family::V4, Family::V6],
+            state_file: Some(path.clone()),
+            ..Default::default()
+        };
+        let source = Settable::default();
+        *source.0.lock().unwrap() = Addresses { v4: Some("192.0.2.1".parse()?), v6: Some("2001:db8::1".parse()?) };
+
+        let mut updater = Updater::new(MemoryProvider::new("example.com"), source, &["www", "vpn"], opts.clone());
+        let report = updater.check()?;
+        assert_eq!(vec!["www", "vpn"], report.updated);
+        assert_eq!(Some("2001:db8::1".parse()?), updater.provider().get_aaaa_record("vpn")?);
+
+        // Unchanged; the provider isn't contacted, so changes made
+        // elsewhere are left until the next refresh.
+        updater.provider().update_a_record("www", &"192.0.2.9".parse()?)?;
+        assert!(updater.check()?.updated.is_empty());
+        assert_eq!(Some("192.0.2.9".parse()?), updater.provider().get_a_record("www")?);
+
+        *updater.source.0.lock().unwrap() = Addresses { v4: Some("192.0.2.2".parse()?), v6: Some("2001:db8::1".parse()?) };
+        assert_eq!(2, updater.check()?.updated.len());
+        assert_eq!(Some("192.0.2.2".parse()?), updater.provider().get_a_record("www")?);
+
+        // The state is picked up by a new updater
+        let state = State::load(&path)?;
+        assert_eq!(updater.state(), &state);
+        let source = Settable(Mutex::new(state.hosts["www"].addresses));
+        let mut updater = Updater::new(MemoryProvider::new("example.com"), source, &["www", "vpn"], opts.clone());
+        assert!(updater.check()?.updated.is_empty());
+
+        // Forced refresh
+        let source = Settable(Mutex::new(state.hosts["www"].addresses));
+        let opts = UpdaterOptions { refresh: Duration::ZERO, ..opts };
+        let mut updater = Updater::new(MemoryProvider::new("example.com"), source, &["www"], opts);
+        assert_eq!(vec!["www"], updater.check()?.updated);
+
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_backoff() -> Result<()> {
+        let opts = UpdaterOptions {
+            families: vec![Family::V6],
+            backoff: Duration::from_secs(10),
+            max_backoff: Duration::from_secs(25),
+            ..Default::default()
+        };
+        let source = Settable::default();
+        *source.0.lock().unwrap() = Addresses { v4: Some("192.0.2.1".parse()?), v6: None };
+        let mut updater = Updater::new(MemoryProvider::new("example.com"), source, &["www"], opts.clone());
+        assert_eq!(opts.interval, updater.next_delay());
+
+        assert!(updater.check().is_err());
+        assert_eq!(Duration::from_secs(10), updater.next_delay());
+        assert!(updater.check().is_err());
+        assert_eq!(Duration::from_secs(20), updater.next_delay());
+        assert!(updater.check().is_err());
+        assert_eq!(Duration::from_secs(25), updater.next_delay());
+
+        // Only IPv6 is managed, so the A record is untouched
+        updater.source.0.lock().unwrap().v6 = Some("2001:db8::1".parse()?);
+        updater.provider().create_a_record("www", &"192.0.2.9".parse()?)?;
+        updater.check()?;
+        assert_eq!(opts.interval, updater.next_delay());
+        assert_eq!(Some("192.0.2.9".parse()?), updater.provider().get_a_record("www")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_partial_families() -> Result<()> {
+        let opts = UpdaterOptions {
+            families: vec![Family::V4, Family::V6],
+            ..Default::default()
+        };
+        let source = Settable(Mutex::new(Addresses { v4: Some("192.0.2.1".parse()?), v6: Some("2001:db8::1".parse()?) }));
+        let mut updater = Updater::new(MemoryProvider::new("example.com"), source, &["www"], opts.clone());
+        updater.check()?;
+
+        // IPv6 discovery fails; IPv4 is still published, and the AAAA
+        // record and its state are left alone.
+        *updater.source.0.lock().unwrap() = Addresses { v4: Some("192.0.2.2".parse()?), v6: None };
+        let report = updater.check()?;
+        assert_eq!(vec!["www"], report.updated);
+        assert_eq!(vec![Family::V6], report.unresolved.iter().map(|(f, _)| *f).collect::<Vec<_>>());
+        assert_eq!(opts.interval, updater.next_delay());
+        assert_eq!(Some("192.0.2.2".parse()?), updater.provider().get_a_record("www")?);
+        assert_eq!(Some("2001:db8::1".parse()?), updater.provider().get_aaaa_record("www")?);
+        assert_eq!(Some("2001:db8::1".parse()?), updater.state().hosts["www"].addresses.v6);
+
+        // Unchanged once it is back
+        updater.source.0.lock().unwrap().v6 = Some("2001:db8::1".parse()?);
+        let report = updater.check()?;
+        assert!(report.updated.is_empty());
+        assert!(report.unresolved.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_run() -> Result<()> {
+        let shutdown = AtomicBool::new(false);
+        let source = Settable(Mutex::new(Addresses { v4: Some("192.0.2.1".parse()?), v6: None }));
+        let opts = UpdaterOptions { interval: Duration::from_millis(10), ..Default::default() };
+        let mut updater = Updater::new(MemoryProvider::new("example.com"), source, &["www"], opts);
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(50));
+                shutdown.store(true, Ordering::Relaxed);
+            });
+            updater.run(&shutdown);
+        });
+        assert_eq!(Some("192.0.2.1".parse()?), updater.provider().get_a_record("www")?);
+        Ok(())
+    }
+}
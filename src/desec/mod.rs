@@ -6,11 +6,11 @@ use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tracing::{error, info, warn};
 
 use crate::{
-    Config, DnsProvider, RecordType,
-    desec::types::{CreateUpdateRRSet, RRSet},
+    Config, RecordType, ZoneListing,
+    change::{resolve, ChangeSet},
+    desec::types::{CreateUpdateRRSet, ListRRSet, RRSet},
     errors::{Error, Result},
-    generate_helpers,
-    http::{self, ResponseToOption, WithHeaders},
+    http::{http_provider_impl, HttpClient, ResponseToOption, WithHeaders},
 };
 
 const API_BASE: &str = "https://desec.io/api/v1";
@@ -34,32 +34,40 @@ impl Auth {
 /// Holds configuration and authentication state for performing API calls.
 pub struct DeSec {
     config: Config,
+    endpoint: &'static str,
     auth: Auth,
 }
 
 impl DeSec {
     /// Create a new `deSEC` provider instance.
     pub fn new(config: Config, auth: Auth) -> Self {
+        Self::new_with_endpoint(config, auth, API_BASE)
+    }
+
+    /// Create a new `deSEC` provider with a custom API endpoint.
+    pub fn new_with_endpoint(config: Config, auth: Auth, endpoint: &'static str) -> Self {
         Self {
             config,
+            endpoint,
             auth,
         }
     }
 
 }
 
-impl DnsProvider for DeSec {
+impl DeSec {
 
-    fn get_record<T>(&self, rtype: RecordType, host: &str) -> Result<Option<T>>
+    async fn get_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<Option<T>>
     where
-        T: DeserializeOwned
+        T: DeserializeOwned,
+        C: HttpClient + ?Sized,
     {
 
-        let url = format!("{API_BASE}/domains/{}/rrsets/{host}/{rtype}/", self.config.domain);
-        let response = http::client().get(url)
+        let url = format!("{}/domains/{}/rrsets/{host}/{rtype}/", self.endpoint, self.config.domain);
+        let response = client.get(url)
             .with_json_headers()
             .with_auth(self.auth.get_header())
-            .call()?
+            .call().await?
             .to_option::<RRSet<T>>()?;
 
         let mut rec: RRSet<T> = match response {
@@ -83,11 +91,12 @@ impl DnsProvider for DeSec {
 
     }
 
-    fn create_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+    async fn create_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str, record: &T) -> Result<()>
     where
-        T: Serialize + DeserializeOwned + Display + Clone
+        T: Serialize + DeserializeOwned + Display + Clone,
+        C: HttpClient + ?Sized,
     {
-        let url = format!("{API_BASE}/domains/{}/rrsets/", self.config.domain);
+        let url = format!("{}/domains/{}/rrsets/", self.endpoint, self.config.domain);
 
         let record = CreateUpdateRRSet {
             subname: host.to_string(),
@@ -101,20 +110,21 @@ impl DnsProvider for DeSec {
         }
 
         let body = serde_json::to_string(&record)?;
-        let _response = http::client().post(url)
+        let _response = client.post(url)
             .with_json_headers()
             .with_auth(self.auth.get_header())
-            .send(body)?
+            .send(body).await?
             .check_error()?;
 
         Ok(())
     }
 
-    fn update_record<T>(&self, rtype: RecordType, host: &str, urec: &T) -> Result<()>
+    async fn update_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str, urec: &T) -> Result<()>
     where
-        T: Serialize + DeserializeOwned + Display + Clone
+        T: Serialize + DeserializeOwned + Display + Clone,
+        C: HttpClient + ?Sized,
     {
-        let url = format!("{API_BASE}/domains/{}/rrsets/{host}/{rtype}/", self.config.domain);
+        let url = format!("{}/domains/{}/rrsets/{host}/{rtype}/", self.endpoint, self.config.domain);
 
         let record = CreateUpdateRRSet {
             subname: host.to_string(),
@@ -129,42 +139,113 @@ impl DnsProvider for DeSec {
         }
 
         let body = serde_json::to_string(&record)?;
-        let _response = http::client().put(url)
+        let _response = client.put(url)
             .with_json_headers()
             .with_auth(self.auth.get_header())
-            .send(body)?
+            .send(body).await?
             .check_error()?;
 
         Ok(())
     }
 
-    fn delete_record(&self, rtype: RecordType, host: &str) -> Result<()>
+    async fn delete_record_with<C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
     {
-        let url = format!("{API_BASE}/domains/{}/rrsets/{host}/{rtype}/", self.config.domain);
+        let url = format!("{}/domains/{}/rrsets/{host}/{rtype}/", self.endpoint, self.config.domain);
         if self.config.dry_run {
             info!("DRY-RUN: Would have sent DELETE to {url}");
             return Ok(())
         }
 
-        http::client().delete(url)
+        client.delete(url)
             .with_json_headers()
             .with_auth(self.auth.get_header())
-            .call()?;
+            .call().await?;
 
         Ok(())
     }
 
-    fn delete_all_records(&self, rtype: RecordType, host: &str) -> Result<()>
-    where Self: Sized
+    async fn delete_all_records_with<C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
     {
         // Desec deletes all members of an RRset by default
-        self.delete_record(rtype, host)
+        self.delete_record_with(client, rtype, host).await
     }
 
-    generate_helpers!();
+    async fn list_rrsets<C>(&self, client: &C) -> Result<Vec<ListRRSet>>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let url = format!("{}/domains/{}/rrsets/", self.endpoint, self.config.domain);
+        client.get(url)
+            .with_json_headers()
+            .with_auth(self.auth.get_header())
+            .call().await?
+            .to_option::<Vec<ListRRSet>>()?
+            .ok_or(Error::ZoneNotFound(self.config.domain.clone()))
+    }
+
+    async fn list_zone_with<C>(&self, client: &C) -> Result<ZoneListing>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let rrsets = self.list_rrsets(client).await?;
+
+        let listing = ZoneListing::collect(rrsets.into_iter()
+            .flat_map(|rrset| rrset.into_zone_records()));
+
+        Ok(listing)
+    }
+
+    async fn apply_changes_with<C>(&self, client: &C, changes: &ChangeSet) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let rrsets = self.list_rrsets(client).await?;
+        let find = |rtype: RecordType, host: &str| rrsets.iter()
+            .find(|r| r.rtype == rtype.to_string() && r.subname.eq_ignore_ascii_case(host));
+
+        // The bulk endpoint replaces whole RRsets, and deletes those
+        // with no records, in a single transaction.
+        let patch = resolve(&changes.changes, |rtype, host| {
+            find(rtype, host)
+                .map(|r| r.records.clone())
+                .unwrap_or_default()
+        })?
+            .into_iter()
+            .filter(|set| set.is_changed())
+            .map(|set| CreateUpdateRRSet {
+                ttl: find(set.rtype, &set.host).map_or(3600, |r| r.ttl),
+                subname: set.host,
+                rtype: set.rtype,
+                records: set.after,
+            })
+            .collect::<Vec<_>>();
+
+        if patch.is_empty() {
+            return Ok(())
+        }
+        let url = format!("{}/domains/{}/rrsets/", self.endpoint, self.config.domain);
+        if self.config.dry_run {
+            info!("DRY-RUN: Would have sent {patch:?} to {url}");
+            return Ok(())
+        }
+
+        let body = serde_json::to_string(&patch)?;
+        client.patch(url)
+            .with_json_headers()
+            .with_auth(self.auth.get_header())
+            .send(body).await?
+            .check_error()?;
+
+        Ok(())
+    }
 
 }
 
+http_provider_impl!(DeSec, batch);
 
 #[cfg(test)]
 pub(crate) mod tests {
@@ -184,4 +265,46 @@ pub(crate) mod tests {
     }
 
     generate_tests!("test_desec");
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn test_apply_changes_patch() -> Result<()> {
+        use crate::async_impl::{http::tests::{json_body, json_ok, serve}, AsyncDnsProvider, Native};
+        use crate::change::Change;
+        use serde_json::json;
+
+        let (url, server) = serve(vec![
+            json_ok(&json!([
+                {"subname": "www", "type": "A", "ttl": 600, "records": ["192.0.2.1"]},
+                {"subname": "old", "type": "TXT", "ttl": 3600, "records": ["\"a\"", "\"b\""]},
+                {"subname": "", "type": "MX", "ttl": 3600, "records": ["10 mail.example.com."]},
+            ]).to_string()),
+            json_ok("[]"),
+        ]);
+        let config = Config {
+            domain: "example.com".to_string(),
+            dry_run: false,
+        };
+        let auth = Auth { key: "xyz".to_string() };
+        let client = Native::wrap(DeSec::new_with_endpoint(config, auth, url.leak()));
+
+        let changes = ChangeSet { changes: vec![
+            Change::Update { rtype: RecordType::A, host: "www".to_string(), value: "192.0.2.2".to_string() },
+            Change::DeleteAll { rtype: RecordType::TXT, host: "old".to_string() },
+            Change::Create { rtype: RecordType::AAAA, host: "new".to_string(), value: "2001:db8::1".to_string() },
+            // Unchanged, so not sent
+            Change::Update { rtype: RecordType::MX, host: "".to_string(), value: "10 mail.example.com.".to_string() },
+        ]};
+        futures::executor::block_on(client.apply_changes(&changes))?;
+
+        let requests = server.join().unwrap();
+        assert!(requests[1].starts_with("PATCH /domains/example.com/rrsets/ HTTP/1.1\r\n"));
+        assert_eq!(json!([
+            {"subname": "www", "type": "A", "ttl": 600, "records": ["192.0.2.2"]},
+            {"subname": "old", "type": "TXT", "ttl": 3600, "records": []},
+            {"subname": "new", "type": "AAAA", "ttl": 3600, "records": ["2001:db8::1"]},
+        ]), json_body(&requests[1]));
+
+        Ok(())
+    }
 }
@@ -1,6 +1,7 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-use crate::RecordType;
+use crate::{parse_rtype, RecordType, UnsupportedRecord, ZoneRecord};
 
 
 // {
@@ -30,6 +31,40 @@ pub(crate) struct RRSet<T>
     pub records: Vec<T>,
 }
 
+// As `RRSet`, but for zone listings, which may contain types we
+// don't support. Records are already in presentation format.
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct ListRRSet {
+    pub subname: String,
+    #[serde(rename = "type")]
+    pub rtype: String,
+    pub ttl: u32,
+    pub records: Vec<String>,
+    /// When the set was last written. This dates the set rather than
+    /// the individual records, so is no older than its newest record.
+    pub touched: Option<DateTime<Utc>>,
+}
+
+impl ListRRSet {
+    pub fn into_zone_records(self) -> Vec<Result<ZoneRecord, UnsupportedRecord>> {
+        let Some(rtype) = parse_rtype(&self.rtype) else {
+            return self.records.into_iter()
+                .map(|value| Err(UnsupportedRecord::unknown_type(&self.rtype, self.subname.clone(), value)))
+                .collect();
+        };
+
+        self.records.into_iter()
+            .map(|value| Ok(ZoneRecord {
+                rtype,
+                host: self.subname.clone(),
+                value,
+                ttl: Some(self.ttl),
+                modified: self.touched,
+            }))
+            .collect()
+    }
+}
+
 // Just a subset of RRSet
 #[derive(Serialize, Debug, Clone)]
 pub(crate) struct CreateUpdateRRSet<T> {
@@ -39,3 +74,53 @@ pub(crate) struct CreateUpdateRRSet<T> {
     pub ttl: u32,
     pub records: Vec<T>,
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::check_malformed_replies;
+
+    #[test]
+    fn test_malformed_rrset() {
+        check_malformed_replies::<RRSet<String>>(r#"{
+            "domain": "example.dedyn.io",
+            "subname": "",
+            "name": "example.dedyn.io.",
+            "records": ["170.64.213.116"],
+            "ttl": 3600,
+            "type": "A"
+        }"#);
+    }
+
+    #[test]
+    fn test_malformed_list() {
+        check_malformed_replies::<Vec<ListRRSet>>(r#"[{
+            "subname": "",
+            "records": ["10 mail.example.dedyn.io."],
+            "ttl": 3600,
+            "type": "MX",
+            "touched": "2026-01-10T00:08:04.265704Z"
+        }]"#);
+    }
+
+    #[test]
+    fn test_into_zone_records() {
+        let rrsets: Vec<ListRRSet> = serde_json::from_str(r#"[
+            {"subname": "", "records": ["ns1.desec.io.", "ns2.desec.org."], "ttl": 3600, "type": "NS",
+             "touched": "2026-01-10T00:08:04.265704Z"},
+            {"subname": "", "records": ["ns1.desec.io. get.desec.io. 1 86400 3600 2419200 3600"], "ttl": 3600, "type": "SOA"},
+            {"subname": "www", "records": ["192.0.2.1"], "ttl": 3600, "type": "A"}
+        ]"#).unwrap();
+        let recs: Vec<ZoneRecord> = rrsets.into_iter()
+            .flat_map(|r| r.into_zone_records())
+            .filter_map(|r| r.ok())
+            .collect();
+
+        assert_eq!(3, recs.len());
+        assert_eq!(RecordType::NS, recs[1].rtype);
+        assert_eq!("ns2.desec.org.", recs[1].value);
+        assert_eq!("2026-01-10T00:08:04.265704+00:00", recs[1].modified.unwrap().to_rfc3339());
+        assert_eq!(None, recs[2].modified);
+    }
+}
@@ -6,14 +6,15 @@ use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tracing::{error, info, warn};
 
 use crate::{
-    Config, DnsProvider, RecordType,
-    digitalocean::types::{CreateUpdate, Record, Records},
+    Config, RecordType, ZoneListing,
+    digitalocean::types::{CreateUpdate, ListRecords, Record, Records},
     errors::{Error, Result},
-    generate_helpers,
-    http::{self, ResponseToOption, WithHeaders},
+    http::{http_provider_impl, HttpClient, ResponseToOption, WithHeaders},
+    verify::same_value,
 };
 
 const API_BASE: &str = "https://api.digitalocean.com/v2/domains";
+const LIST_PAGE_SIZE: usize = 200;
 
 /// Authentication credentials for the Digital Ocean API.
 ///
@@ -46,16 +47,17 @@ impl DigitalOcean {
         }
     }
 
-    fn get_upstream_records<T>(&self, rtype: &RecordType, host: &str) -> Result<Vec<Record<T>>>
+    async fn get_upstream_records<T, C>(&self, client: &C, rtype: &RecordType, host: &str) -> Result<Vec<Record<T>>>
     where
-        T: DeserializeOwned
+        T: DeserializeOwned,
+        C: HttpClient + ?Sized,
     {
         let url = format!("{API_BASE}/{}/records?type={rtype}&name={host}.{}", self.config.domain, self.config.domain);
 
-        let response = http::client().get(url)
+        let response = client.get(url)
             .with_json_headers()
             .with_auth(self.auth.get_header())
-            .call()?
+            .call().await?
             .to_option()?;
 
         // FIXME: Similar to other impls, can dedup?
@@ -67,11 +69,12 @@ impl DigitalOcean {
         Ok(recs.domain_records)
     }
 
-    fn get_upstream_record<T>(&self, rtype: &RecordType, host: &str) -> Result<Option<Record<T>>>
+    async fn get_upstream_record<T, C>(&self, client: &C, rtype: &RecordType, host: &str) -> Result<Option<Record<T>>>
     where
-        T: DeserializeOwned
+        T: DeserializeOwned,
+        C: HttpClient + ?Sized,
     {
-        let mut recs = self.get_upstream_records(rtype, host)?;
+        let mut recs = self.get_upstream_records(client, rtype, host).await?;
 
         // FIXME: Assumes no or single address (which probably makes
         // sense for DDNS and DNS-01, but may cause issues with
@@ -88,7 +91,10 @@ impl DigitalOcean {
         Ok(Some(recs.remove(0)))
     }
 
-    fn do_delete(&self, rec: Record<String>) -> Result<()> {
+    async fn do_delete<C>(&self, client: &C, rec: Record<String>) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
+    {
 
         let url = format!("{API_BASE}/{}/records/{}", self.config.domain, rec.id);
         if self.config.dry_run {
@@ -97,23 +103,24 @@ impl DigitalOcean {
         }
 
         info!("Deleting DNS {} record {}", rec.rtype, rec.name);
-        http::client().delete(url)
+        client.delete(url)
             .with_auth(self.auth.get_header())
             .with_json_headers()
-            .call()?;
+            .call().await?;
 
         Ok(())
     }
 
 }
 
-impl DnsProvider for DigitalOcean {
+impl DigitalOcean {
 
-    fn get_record<T>(&self, rtype: RecordType, host: &str) -> Result<Option<T> >
+    async fn get_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<Option<T> >
     where
-        T: DeserializeOwned
+        T: DeserializeOwned,
+        C: HttpClient + ?Sized,
     {
-         let rec: Record<T> = match self.get_upstream_record(&rtype, host)? {
+         let rec: Record<T> = match self.get_upstream_record(client, &rtype, host).await? {
             Some(rec) => rec,
             None => return Ok(None)
         };
@@ -121,9 +128,10 @@ impl DnsProvider for DigitalOcean {
         Ok(Some(rec.data))
     }
 
-    fn create_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+    async fn create_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str, record: &T) -> Result<()>
     where
-        T: Serialize + DeserializeOwned + Display + Clone
+        T: Serialize + DeserializeOwned + Display + Clone,
+        C: HttpClient + ?Sized,
     {
         let url = format!("{API_BASE}/{}/records", self.config.domain);
 
@@ -139,20 +147,21 @@ impl DnsProvider for DigitalOcean {
         }
 
         let body = serde_json::to_string(&record)?;
-        let _response = http::client().post(url)
+        let _response = client.post(url)
             .with_auth(self.auth.get_header())
             .with_json_headers()
-            .send(body)?
+            .send(body).await?
             .check_error()?;
 
         Ok(())
     }
 
-    fn update_record<T>(&self, rtype: RecordType, host: &str, urec: &T) -> Result<()>
+    async fn update_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str, urec: &T) -> Result<()>
     where
-        T: Serialize + DeserializeOwned + Display + Clone
+        T: Serialize + DeserializeOwned + Display + Clone,
+        C: HttpClient + ?Sized,
     {
-        let rec: Record<T> = self.get_upstream_record(&rtype, host)?
+        let rec: Record<T> = self.get_upstream_record(client, &rtype, host).await?
             .ok_or(Error::RecordNotFound(host.to_string()))?;
         let url = format!("{API_BASE}/{}/records/{}", self.config.domain, rec.id);
 
@@ -169,18 +178,20 @@ impl DnsProvider for DigitalOcean {
         }
 
         let body = serde_json::to_string(&record)?;
-        let _response = http::client().put(url)
+        let _response = client.put(url)
             .with_auth(self.auth.get_header())
             .with_json_headers()
-            .send(body)?
+            .send(body).await?
             .check_error()?;
 
         Ok(())
     }
 
-    fn delete_record(&self, rtype: RecordType, host: &str) -> Result<()>
+    async fn delete_record_with<C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
     {
-        let rec = match self.get_upstream_record(&rtype, host)? {
+        let rec = match self.get_upstream_record(client, &rtype, host).await? {
             Some(rec) => rec,
             None => {
                 warn!("No {rtype} record to delete for {host}");
@@ -188,24 +199,74 @@ impl DnsProvider for DigitalOcean {
             }
         };
 
-        self.do_delete(rec)
+        self.do_delete(client, rec).await
     }
 
-    fn delete_all_records(&self, rtype: RecordType, host: &str) -> Result<()>
-    where Self: Sized
+    async fn delete_all_records_with<C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
     {
-        let recs: Vec<Record<String>> = self.get_upstream_records(&rtype, host)?;
+        let recs: Vec<Record<String>> = self.get_upstream_records(client, &rtype, host).await?;
         for rec in recs {
-            self.do_delete(rec)?;
+            self.do_delete(client, rec).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn delete_value_with<C>(&self, client: &C, rtype: RecordType, host: &str, value: &str) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let recs: Vec<Record<String>> = self.get_upstream_records(client, &rtype, host).await?;
+        for rec in recs.into_iter().filter(|rec| same_value(rtype, &rec.data, value)) {
+            self.do_delete(client, rec).await?;
         }
 
         Ok(())
     }
 
-    generate_helpers!();
+    async fn list_zone_with<C>(&self, client: &C) -> Result<ZoneListing>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let mut listed = Vec::new();
+        for page in 1.. {
+            let url = format!("{API_BASE}/{}/records?page={page}&per_page={LIST_PAGE_SIZE}", self.config.domain);
+            let recs = client.get(url)
+                .with_json_headers()
+                .with_auth(self.auth.get_header())
+                .call().await?
+                .to_option::<ListRecords>()?
+                .ok_or(Error::ZoneNotFound(self.config.domain.clone()))?
+                .domain_records;
+
+            let last = recs.len() < LIST_PAGE_SIZE;
+            listed.extend(recs.into_iter()
+                          .map(|rec| rec.into_zone_record(&self.config.domain)));
+            if last {
+                break;
+            }
+        }
+
+        Ok(ZoneListing::collect(listed))
+    }
 
 }
 
+// DigitalOcean has no PTR, SVCB or HTTPS records.
+const RECORD_TYPES: &[RecordType] = &[
+    RecordType::A,
+    RecordType::AAAA,
+    RecordType::CAA,
+    RecordType::CNAME,
+    RecordType::MX,
+    RecordType::NS,
+    RecordType::SRV,
+    RecordType::TXT,
+];
+
+http_provider_impl!(DigitalOcean, RECORD_TYPES);
 
 #[cfg(test)]
 pub(crate) mod tests {
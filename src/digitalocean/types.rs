@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use crate::RecordType;
+use crate::{parse_rtype, relative_host, to_rdata, RdataParts, RecordType, UnsupportedRecord, ZoneRecord};
 
 
 // {
@@ -63,3 +63,103 @@ pub(crate) struct Record<T> {
     pub data: T,
 
 }
+
+// As `Record`, but for zone listings, which may contain types we
+// don't support (e.g. SOA).
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct ListRecord {
+    #[serde(rename = "type")]
+    pub rtype: String,
+    pub name: String,
+    pub ttl: u32,
+    pub data: String,
+    pub priority: Option<u64>,
+    pub port: Option<u64>,
+    pub weight: Option<u64>,
+    pub flags: Option<u64>,
+    pub tag: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct ListRecords {
+    pub domain_records: Vec<ListRecord>,
+}
+
+impl ListRecord {
+    pub fn into_zone_record(self, domain: &str) -> Result<ZoneRecord, UnsupportedRecord> {
+        let Some(rtype) = parse_rtype(&self.rtype) else {
+            return Err(UnsupportedRecord::unknown_type(self.rtype, relative_host(&self.name, domain), self.data));
+        };
+        let parts = RdataParts {
+            priority: self.priority,
+            weight: self.weight,
+            port: self.port,
+            flags: self.flags,
+            tag: self.tag.as_deref(),
+        };
+
+        Ok(ZoneRecord {
+            rtype,
+            host: relative_host(&self.name, domain),
+            value: to_rdata(rtype, &self.data, parts),
+            ttl: Some(self.ttl),
+            modified: None,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::check_malformed_replies;
+
+    #[test]
+    fn test_malformed_records() {
+        check_malformed_replies::<Records<String>>(r#"{
+            "domain_records": [{
+                "id": 28448432,
+                "type": "A",
+                "name": "@",
+                "data": "1.2.3.4",
+                "ttl": 1800
+            }],
+            "meta": {"total": 1}
+        }"#);
+    }
+
+    #[test]
+    fn test_malformed_list() {
+        check_malformed_replies::<ListRecords>(r#"{
+            "domain_records": [{
+                "id": 28448432,
+                "type": "MX",
+                "name": "@",
+                "data": "mail.example.com",
+                "priority": 10,
+                "ttl": 1800,
+                "tag": null
+            }]
+        }"#);
+    }
+
+    #[test]
+    fn test_into_zone_record() {
+        let recs: ListRecords = serde_json::from_str(r#"{
+            "domain_records": [
+                {"type": "SOA", "name": "@", "data": "1800", "ttl": 1800},
+                {"type": "SRV", "name": "_sip._tcp", "data": "sip.example.com", "priority": 1,
+                 "port": 5060, "weight": 10, "ttl": 1800},
+                {"type": "CAA", "name": "@", "data": "letsencrypt.org", "flags": 0, "tag": "issue", "ttl": 3600}
+            ]
+        }"#).unwrap();
+        let recs: Vec<ZoneRecord> = recs.domain_records.into_iter()
+            .filter_map(|r| r.into_zone_record("example.com").ok())
+            .collect();
+
+        assert_eq!(2, recs.len());
+        assert_eq!("1 10 5060 sip.example.com", recs[0].value);
+        assert_eq!("0 issue \"letsencrypt.org\"", recs[1].value);
+        assert_eq!("", recs[1].host);
+    }
+}
@@ -0,0 +1,84 @@
+//! HTTP address echo services.
+
+use std::net::IpAddr;
+
+use crate::{
+    errors::{Error, Result},
+    http::{block_on, Blocking, HttpClient, ResponseToOption},
+};
+use super::{AddressSource, Family};
+
+
+/// An HTTP service that returns the client's address as plain text.
+///
+/// Separate URLs are used for each family, as the address returned
+/// depends on how the service is reached. The defaults use ipify.
+#[derive(Clone, Debug)]
+pub struct HttpEcho {
+    /// A URL that is only reachable over IPv4.
+    pub v4_url: String,
+    /// A URL that is only reachable over IPv6.
+    pub v6_url: String,
+}
+
+impl Default for HttpEcho {
+    fn default() -> Self {
+        Self {
+            v4_url: "https://api.ipify.org".to_string(),
+            v6_url: "https://api6.ipify.org".to_string(),
+        }
+    }
+}
+
+impl HttpEcho {
+    fn url(&self, family: Family) -> &str {
+        match family {
+            Family::V4 => &self.v4_url,
+            Family::V6 => &self.v6_url,
+        }
+    }
+}
+
+impl AddressSource for HttpEcho {
+    fn name(&self) -> String {
+        format!("HTTP echo {} / {}", self.v4_url, self.v6_url)
+    }
+
+    fn address(&self, family: Family) -> Result<IpAddr> {
+        let url = self.url(family);
+        let resp = block_on(Blocking.get(url).call())?
+            .check_error()?;
+        let body = resp.body().trim();
+        let addr = body.parse::<IpAddr>()
+            .map_err(|_| Error::DiscoveryError(format!("{url} returned {body:?}, not an address")))?;
+        family.check(addr, url)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::tests::serve_http;
+
+    #[test]
+    fn test_echo() -> Result<()> {
+        let server = serve_http(|request, _| match request {
+            r if r.starts_with("GET /v4 ") => (200, "203.0.113.7\n".to_string()),
+            r if r.starts_with("GET /v6 ") => (200, "2001:db8::7".to_string()),
+            _ => (200, "<html>".to_string()),
+        });
+        let source = HttpEcho {
+            v4_url: format!("http://{server}/v4"),
+            v6_url: format!("http://{server}/v6"),
+        };
+        assert_eq!(IpAddr::from([203, 0, 113, 7]), source.address(Family::V4)?);
+        assert_eq!("2001:db8::7".parse::<IpAddr>()?, source.address(Family::V6)?);
+
+        let source = HttpEcho { v4_url: format!("http://{server}/v6"), ..source };
+        assert!(matches!(source.address(Family::V4), Err(Error::DiscoveryError(_))));
+        let source = HttpEcho { v4_url: format!("http://{server}/other"), ..source };
+        assert!(matches!(source.address(Family::V4), Err(Error::DiscoveryError(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+//! Addresses of local network interfaces.
+
+use std::net::IpAddr;
+
+use crate::errors::{Error, Result};
+use super::{AddressSource, Family, Scope};
+
+
+/// An address assigned to a network interface.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterfaceAddr {
+    /// The interface name, e.g. `eth0`.
+    pub name: String,
+    pub addr: IpAddr,
+}
+
+/// Addresses assigned to local network interfaces.
+///
+/// This works where the host has a public address itself, which is
+/// common for IPv6. Behind NAT the interface addresses are private;
+/// use one of the other sources for IPv4.
+#[derive(Clone, Debug)]
+pub struct Interfaces {
+    /// Only use addresses of this interface.
+    pub interface: Option<String>,
+    /// The acceptable scopes, in order of preference. Defaults to
+    /// `Scope::Global` only.
+    pub scopes: Vec<Scope>,
+}
+
+impl Default for Interfaces {
+    fn default() -> Self {
+        Self {
+            interface: None,
+            scopes: vec![Scope::Global],
+        }
+    }
+}
+
+impl Interfaces {
+    /// The matching addresses of `family`, most preferred first.
+    pub fn addresses(&self, family: Family) -> Result<Vec<IpAddr>> {
+        let listed = list()?;
+        let mut addrs = Vec::new();
+        for scope in &self.scopes {
+            addrs.extend(listed.iter()
+                         .filter(|ia| self.interface.as_ref().is_none_or(|name| *name == ia.name))
+                         .map(|ia| ia.addr)
+                         .filter(|addr| Family::of(addr) == family && Scope::of(addr) == *scope));
+        }
+        Ok(addrs)
+    }
+}
+
+impl AddressSource for Interfaces {
+    fn name(&self) -> String {
+        match &self.interface {
+            Some(name) => format!("interface {name}"),
+            None => "interfaces".to_string(),
+        }
+    }
+
+    fn address(&self, family: Family) -> Result<IpAddr> {
+        self.addresses(family)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::DiscoveryError(format!("no {family} address with scope {:?} on {}",
+                                                         self.scopes, self.name())))
+    }
+}
+
+
+/// List the addresses of all network interfaces.
+#[cfg(unix)]
+pub fn list() -> Result<Vec<InterfaceAddr>> {
+    use std::{ffi::CStr, io, net::{Ipv4Addr, Ipv6Addr}, ptr};
+
+    let mut head: *mut libc::ifaddrs = ptr::null_mut();
+    // SAFETY: `head` is a valid out-pointer, and the list is freed below.
+    if unsafe { libc::getifaddrs(&mut head) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    let mut addrs = Vec::new();
+    let mut cur = head;
+    while !cur.is_null() {
+        // SAFETY: `cur` is a non-null entry of the list returned by
+        // `getifaddrs()`. The address is only read according to its
+        // family, and the name is a NUL-terminated string.
+        let (addr, name, next) = unsafe {
+            let ifa = &*cur;
+            let addr = match ifa.ifa_addr.as_ref().map(|sa| sa.sa_family as i32) {
+                Some(libc::AF_INET) => {
+                    let sin = &*(ifa.ifa_addr as *const libc::sockaddr_in);
+                    Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr))))
+                }
+                Some(libc::AF_INET6) => {
+                    let sin6 = &*(ifa.ifa_addr as *const libc::sockaddr_in6);
+                    Some(IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr)))
+                }
+                _ => None,
+            };
+            (addr, CStr::from_ptr(ifa.ifa_name).to_string_lossy().into_owned(), ifa.ifa_next)
+        };
+        if let Some(addr) = addr {
+            addrs.push(InterfaceAddr { name, addr });
+        }
+        cur = next;
+    }
+
+    // SAFETY: `head` was returned by `getifaddrs()`, and is not used
+    // after this.
+    unsafe { libc::freeifaddrs(head) };
+    Ok(addrs)
+}
+
+/// List the addresses of all network interfaces.
+#[cfg(not(unix))]
+pub fn list() -> Result<Vec<InterfaceAddr>> {
+    Err(Error::Unsupported("listing interfaces on this platform".to_string()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn test_loopback() -> Result<()> {
+        let lo = list()?.into_iter()
+            .find(|ia| ia.addr == IpAddr::from([127, 0, 0, 1]))
+            .expect("no loopback interface");
+
+        let source = Interfaces { interface: Some(lo.name.clone()), scopes: vec![Scope::Global, Scope::Loopback] };
+        assert_eq!(lo.addr, source.address(Family::V4)?);
+
+        let source = Interfaces { interface: Some(lo.name), ..Default::default() };
+        assert!(matches!(source.address(Family::V4), Err(Error::DiscoveryError(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,391 @@
+//! Public address discovery for dynamic DNS.
+//!
+//! Before a DDNS client can update a record it needs to know the
+//! host's current public address. This module provides a number of
+//! [`AddressSource`]s:
+//!
+//! * [`interfaces::Interfaces`]: addresses assigned to local network
+//!   interfaces, filtered by [`Scope`]. This is usually the simplest
+//!   option for IPv6.
+//! * [`echo::HttpEcho`]: an HTTP service that returns the address the
+//!   request came from, e.g. `https://api.ipify.org`.
+//! * [`stun::Stun`]: a STUN binding request (RFC 8489).
+//! * [`upnp::Upnp`] and [`natpmp::NatPmp`]: ask the local NAT gateway
+//!   for its external IPv4 address.
+//!
+//! Sources can be combined with a [`Discovery`], which queries them
+//! according to a consensus [`Policy`]:
+//!
+//! ```no_run
+//! use zone_update::discovery::{echo::HttpEcho, stun::Stun, Discovery, Family, Policy};
+//! # fn main() -> zone_update::errors::Result<()> {
+//! let discovery = Discovery::new(vec![
+//!     Box::new(HttpEcho::default()),
+//!     Box::new(Stun::default()),
+//! ], Policy::Unanimous);
+//!
+//! let addr = discovery.address(Family::V4)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Addresses that aren't globally reachable (see [`Scope`]), such as
+//! the upstream address of a gateway behind carrier-grade NAT, are
+//! rejected by `Discovery` and the gateway sources unless explicitly
+//! allowed.
+//!
+//! All sources are blocking.
+
+pub mod echo;
+pub mod interfaces;
+pub mod natpmp;
+pub mod stun;
+pub mod upnp;
+
+use std::{
+    fmt::{self, Display, Formatter},
+    net::IpAddr,
+};
+
+use tracing::{debug, warn};
+
+use crate::errors::{Error, Result};
+
+
+/// An IP address family.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Family {
+    V4,
+    V6,
+}
+
+impl Family {
+    /// The family of `addr`.
+    pub fn of(addr: &IpAddr) -> Self {
+        match addr {
+            IpAddr::V4(_) => Family::V4,
+            IpAddr::V6(_) => Family::V6,
+        }
+    }
+
+    /// Check that an address reported by `source` is of this family.
+    pub(crate) fn check(self, addr: IpAddr, source: &str) -> Result<IpAddr> {
+        if Family::of(&addr) != self {
+            return Err(Error::DiscoveryError(format!("{source} returned {addr}, expected an {self} address")));
+        }
+        Ok(addr)
+    }
+}
+
+impl Display for Family {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Family::V4 => write!(f, "IPv4"),
+            Family::V6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+/// The reachability of an address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Scope {
+    /// `127.0.0.0/8` and `::1`.
+    Loopback,
+    /// `169.254.0.0/16` and `fe80::/10`.
+    LinkLocal,
+    /// Private and shared ranges; `10.0.0.0/8`, `172.16.0.0/12`,
+    /// `192.168.0.0/16`, `100.64.0.0/10` (carrier-grade NAT) and
+    /// `fc00::/7` (unique local).
+    Private,
+    /// Everything else.
+    Global,
+}
+
+impl Scope {
+    /// The scope of `addr`. Unspecified addresses are treated as
+    /// private.
+    pub fn of(addr: &IpAddr) -> Self {
+        match addr {
+            IpAddr::V4(ip) if ip.is_loopback() => Scope::Loopback,
+            IpAddr::V4(ip) if ip.is_link_local() => Scope::LinkLocal,
+            IpAddr::V4(ip) if ip.is_private() || ip.is_unspecified() => Scope::Private,
+            // Shared address space, RFC 6598
+            IpAddr::V4(ip) if ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64 => Scope::Private,
+            IpAddr::V6(ip) if ip.is_loopback() => Scope::Loopback,
+            IpAddr::V6(ip) if ip.is_unicast_link_local() => Scope::LinkLocal,
+            IpAddr::V6(ip) if ip.is_unique_local() || ip.is_unspecified() => Scope::Private,
+            _ => Scope::Global,
+        }
+    }
+}
+
+/// Check that an address reported by `source` is globally reachable.
+/// Gateways behind another NAT (e.g. carrier-grade NAT) report
+/// their private upstream address, which is no use in DNS.
+pub(crate) fn check_global(addr: IpAddr, source: &str) -> Result<IpAddr> {
+    match Scope::of(&addr) {
+        Scope::Global => Ok(addr),
+        scope => Err(Error::DiscoveryError(format!("{source} returned {addr}, which is not a global address ({scope:?})"))),
+    }
+}
+
+
+/// A source of the host's public address.
+pub trait AddressSource: Send + Sync {
+    /// A short description of the source for logs and errors.
+    fn name(&self) -> String;
+
+    /// Discover the current address of `family`. Sources that can't
+    /// provide an address of that family (e.g. NAT-PMP for IPv6)
+    /// return `Error::Unsupported`.
+    fn address(&self, family: Family) -> Result<IpAddr>;
+}
+
+/// How a `Discovery` combines the addresses reported by its sources.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// Query the sources in order, and use the first address found.
+    First,
+    /// Query all sources, and use the address reported by at least
+    /// this many of them. If several addresses qualify the one with
+    /// the most reports wins, then the earliest source.
+    Quorum(usize),
+    /// Use the address reported by more than half of the sources,
+    /// counting those that failed.
+    Majority,
+    /// All sources must report the same address.
+    Unanimous,
+}
+
+/// A set of address sources and a policy for combining them.
+///
+/// Addresses that aren't `Scope::Global` are treated as failures of
+/// the reporting source, unless allowed with `allow_non_global()`.
+pub struct Discovery {
+    sources: Vec<Box<dyn AddressSource>>,
+    policy: Policy,
+    allow_non_global: bool,
+}
+
+impl Discovery {
+    pub fn new(sources: Vec<Box<dyn AddressSource>>, policy: Policy) -> Self {
+        Self {
+            sources,
+            policy,
+            allow_non_global: false,
+        }
+    }
+
+    /// Accept addresses of any scope, e.g. for a DDNS zone on a
+    /// private network.
+    pub fn allow_non_global(mut self, allow: bool) -> Self {
+        self.allow_non_global = allow;
+        self
+    }
+
+    /// The configured sources.
+    pub fn sources(&self) -> &[Box<dyn AddressSource>] {
+        &self.sources
+    }
+
+    /// The current address of `family`, according to the policy.
+    pub fn address(&self, family: Family) -> Result<IpAddr> {
+        if self.sources.is_empty() {
+            return Err(Error::DiscoveryError("no address sources configured".to_string()));
+        }
+
+        let mut results = Vec::with_capacity(self.sources.len());
+        for source in &self.sources {
+            let result = source.address(family)
+                .and_then(|addr| match self.allow_non_global {
+                    true => Ok(addr),
+                    false => check_global(addr, &source.name()),
+                });
+            match &result {
+                Ok(addr) => debug!("{} reports {addr}", source.name()),
+                Err(e) => warn!("{} failed: {e}", source.name()),
+            }
+            let found = result.is_ok();
+            results.push((source.name(), result));
+            if found && self.policy == Policy::First {
+                break;
+            }
+        }
+        consensus(self.policy, self.sources.len(), family, results)
+    }
+}
+
+impl AddressSource for Discovery {
+    fn name(&self) -> String {
+        let names = self.sources.iter()
+            .map(|s| s.name())
+            .collect::<Vec<_>>();
+        format!("{:?} of [{}]", self.policy, names.join(", "))
+    }
+
+    fn address(&self, family: Family) -> Result<IpAddr> {
+        Discovery::address(self, family)
+    }
+}
+
+/// Apply `policy` to the results of querying `total` sources.
+pub(crate) fn consensus(policy: Policy, total: usize, family: Family,
+                        results: Vec<(String, Result<IpAddr>)>) -> Result<IpAddr>
+{
+    let mut errors = Vec::new();
+    // Addresses in order of first report, with their count
+    let mut votes: Vec<(IpAddr, usize)> = Vec::new();
+    for (name, result) in results {
+        match result {
+            Ok(addr) => match votes.iter_mut().find(|(a, _)| *a == addr) {
+                Some((_, n)) => *n += 1,
+                None => votes.push((addr, 1)),
+            },
+            Err(e) => errors.push(format!("{name}: {e}")),
+        }
+    }
+
+    let required = match policy {
+        Policy::First => 1,
+        Policy::Quorum(n) => n.max(1),
+        Policy::Majority => total / 2 + 1,
+        Policy::Unanimous => total,
+    };
+    let best = votes.iter()
+        .enumerate()
+        .max_by_key(|&(i, &(_, n))| (n, std::cmp::Reverse(i)))
+        .map(|(_, &vote)| vote);
+
+    match best {
+        Some((addr, n)) if n >= required => Ok(addr),
+        _ => {
+            let reports = votes.iter()
+                .map(|(addr, n)| format!("{addr} x{n}"))
+                .chain(errors)
+                .collect::<Vec<_>>();
+            Err(Error::DiscoveryError(format!("no {family} address reported by {required} of {total} sources ({})",
+                                              reports.join("; "))))
+        }
+    }
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::{SocketAddr, TcpListener},
+        thread,
+    };
+
+    /// A minimal HTTP server for tests. The handler is passed the
+    /// request line and body, and returns the status and body of the
+    /// response.
+    pub(crate) fn serve_http<F>(handler: F) -> SocketAddr
+    where
+        F: Fn(&str, &str) -> (u16, String) + Send + 'static
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+
+                let mut len = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((key, value)) = line.split_once(':')
+                        && key.eq_ignore_ascii_case("content-length")
+                    {
+                        len = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; len];
+                reader.read_exact(&mut body).unwrap();
+
+                let (status, body) = handler(request.trim(), &String::from_utf8_lossy(&body));
+                let resp = format!("HTTP/1.1 {status} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                                   body.len());
+                stream.write_all(resp.as_bytes()).unwrap();
+            }
+        });
+        addr
+    }
+
+    struct Fixed(&'static str, Option<IpAddr>);
+
+    impl AddressSource for Fixed {
+        fn name(&self) -> String {
+            self.0.to_string()
+        }
+
+        fn address(&self, family: Family) -> Result<IpAddr> {
+            let addr = self.1.ok_or(Error::DiscoveryError("unavailable".to_string()))?;
+            family.check(addr, self.0)
+        }
+    }
+
+    fn discovery(policy: Policy, addrs: &[Option<&str>]) -> Discovery {
+        let sources = addrs.iter()
+            .map(|a| Box::new(Fixed("fixed", a.map(|a| a.parse().unwrap()))) as Box<dyn AddressSource>)
+            .collect();
+        Discovery::new(sources, policy)
+    }
+
+    #[test]
+    fn test_scope() {
+        let scope = |addr: &str| Scope::of(&addr.parse().unwrap());
+        assert_eq!(Scope::Loopback, scope("127.0.0.1"));
+        assert_eq!(Scope::LinkLocal, scope("169.254.1.1"));
+        assert_eq!(Scope::Private, scope("192.168.1.1"));
+        assert_eq!(Scope::Private, scope("100.64.0.1"));
+        assert_eq!(Scope::Global, scope("100.128.0.1"));
+        assert_eq!(Scope::Global, scope("203.0.113.1"));
+        assert_eq!(Scope::Loopback, scope("::1"));
+        assert_eq!(Scope::LinkLocal, scope("fe80::1"));
+        assert_eq!(Scope::Private, scope("fd00::1"));
+        assert_eq!(Scope::Global, scope("2001:db8::1"));
+    }
+
+    #[test]
+    fn test_policies() -> Result<()> {
+        let a: IpAddr = "203.0.113.1".parse()?;
+        let split = [Some("203.0.113.1"), None, Some("203.0.113.2"), Some("203.0.113.1")];
+
+        assert_eq!(a, discovery(Policy::First, &[None, Some("203.0.113.1")]).address(Family::V4)?);
+        assert_eq!(a, discovery(Policy::Quorum(2), &split).address(Family::V4)?);
+        assert!(discovery(Policy::Quorum(3), &split).address(Family::V4).is_err());
+        assert!(discovery(Policy::Majority, &split).address(Family::V4).is_err());
+        assert_eq!(a, discovery(Policy::Majority, &[split[0], split[1], split[3]]).address(Family::V4)?);
+        assert!(discovery(Policy::Unanimous, &[split[0], split[3], None]).address(Family::V4).is_err());
+        assert_eq!(a, discovery(Policy::Unanimous, &[split[0], split[3]]).address(Family::V4)?);
+
+        // Sources of the wrong family count as failures
+        let err = discovery(Policy::First, &[Some("2001:db8::1")]).address(Family::V4).unwrap_err();
+        assert!(err.to_string().contains("expected an IPv4 address"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_non_global() -> Result<()> {
+        let a: IpAddr = "203.0.113.1".parse()?;
+        for addr in ["0.0.0.0", "192.168.1.1", "100.64.0.1"] {
+            let err = discovery(Policy::Unanimous, &[Some(addr)]).address(Family::V4).unwrap_err();
+            assert!(err.to_string().contains("not a global address"), "{addr}");
+        }
+        // Skipped in favour of a later source
+        assert_eq!(a, discovery(Policy::First, &[Some("10.0.0.1"), Some("203.0.113.1")]).address(Family::V4)?);
+
+        let private = discovery(Policy::First, &[Some("192.168.1.1")]).allow_non_global(true);
+        assert_eq!(IpAddr::from([192, 168, 1, 1]), private.address(Family::V4)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,189 @@
+//! NAT-PMP external address queries (RFC 6886).
+
+use std::{
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use tracing::debug;
+
+use crate::errors::{Error, Result};
+use super::{check_global, AddressSource, Family};
+
+/// The port NAT-PMP gateways listen on.
+pub const NATPMP_PORT: u16 = 5351;
+
+const VERSION: u8 = 0;
+const OP_EXTERNAL_ADDRESS: u8 = 0;
+const RESPONSE_LEN: usize = 12;
+
+/// The initial retransmission timeout.
+const INITIAL_RTO: Duration = Duration::from_millis(250);
+
+
+/// Ask a NAT-PMP gateway for its external IPv4 address.
+///
+/// NAT-PMP has no discovery mechanism; the gateway is normally the
+/// default router. Only IPv4 is supported.
+#[derive(Clone, Debug)]
+pub struct NatPmp {
+    /// The gateway address, usually the default router on port
+    /// `NATPMP_PORT`.
+    pub gateway: SocketAddr,
+    /// How long to wait for a response, including retransmissions.
+    pub timeout: Duration,
+    /// Accept an external address that isn't `Scope::Global`, e.g.
+    /// behind carrier-grade NAT.
+    pub allow_non_global: bool,
+}
+
+impl NatPmp {
+    /// Query the gateway at `gateway` on the standard port.
+    pub fn new(gateway: Ipv4Addr) -> Self {
+        Self {
+            gateway: (gateway, NATPMP_PORT).into(),
+            timeout: Duration::from_secs(2),
+            allow_non_global: false,
+        }
+    }
+}
+
+impl AddressSource for NatPmp {
+    fn name(&self) -> String {
+        format!("NAT-PMP {}", self.gateway)
+    }
+
+    fn address(&self, family: Family) -> Result<IpAddr> {
+        if family != Family::V4 {
+            return Err(Error::Unsupported("NAT-PMP only reports IPv4 addresses".to_string()));
+        }
+        let addr = family.check(IpAddr::V4(external_address(self.gateway, self.timeout)?), &self.name())?;
+        match self.allow_non_global {
+            true => Ok(addr),
+            false => check_global(addr, &self.name()),
+        }
+    }
+}
+
+/// Send an external address request to `gateway`.
+pub fn external_address(gateway: SocketAddr, timeout: Duration) -> Result<Ipv4Addr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.connect(gateway)?;
+
+    let request = [VERSION, OP_EXTERNAL_ADDRESS];
+    let deadline = Instant::now() + timeout;
+    let mut rto = INITIAL_RTO;
+    let mut buf = [0; 64];
+
+    socket.send(&request)?;
+    let mut resend = Instant::now() + rto;
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(Error::DiscoveryError(format!("no NAT-PMP response from {gateway} after {timeout:?}")));
+        }
+        if now >= resend {
+            debug!("Retransmitting NAT-PMP request to {gateway}");
+            socket.send(&request)?;
+            rto *= 2;
+            resend = now + rto;
+        }
+
+        socket.set_read_timeout(Some(resend.min(deadline) - now))?;
+        let len = match socket.recv(&mut buf) {
+            Ok(len) => len,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(addr) = parse_response(&buf[..len])? {
+            return Ok(addr);
+        }
+        debug!("Ignoring unexpected datagram from {gateway}");
+    }
+}
+
+/// Parse an external address response. Returns `None` for unrelated
+/// messages. A gateway without an external address reports
+/// `0.0.0.0`, which is an error.
+pub(crate) fn parse_response(msg: &[u8]) -> Result<Option<Ipv4Addr>> {
+    if msg.len() < 4 || msg[0] != VERSION || msg[1] != 128 + OP_EXTERNAL_ADDRESS {
+        return Ok(None);
+    }
+    match u16::from_be_bytes([msg[2], msg[3]]) {
+        0 => {}
+        code => return Err(Error::DiscoveryError(format!("NAT-PMP gateway returned result code {code}"))),
+    }
+    let octets: [u8; 4] = msg.get(8..RESPONSE_LEN)
+        .and_then(|ip| ip.try_into().ok())
+        .ok_or_else(|| Error::DiscoveryError("malformed NAT-PMP response".to_string()))?;
+    match Ipv4Addr::from(octets) {
+        Ipv4Addr::UNSPECIFIED => Err(Error::DiscoveryError("NAT-PMP gateway has no external address".to_string())),
+        addr => Ok(Some(addr)),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn response(code: u16, addr: Ipv4Addr) -> Vec<u8> {
+        let mut msg = vec![VERSION, 128 + OP_EXTERNAL_ADDRESS];
+        msg.extend(code.to_be_bytes());
+        msg.extend(1234u32.to_be_bytes());
+        msg.extend(addr.octets());
+        msg
+    }
+
+    fn serve_natpmp(addr: Ipv4Addr) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let local = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; 64];
+            let mut dropped = false;
+            while let Ok((len, from)) = socket.recv_from(&mut buf) {
+                // Drop the first request to exercise retransmission
+                if !dropped {
+                    dropped = true;
+                    continue;
+                }
+                assert_eq!([VERSION, OP_EXTERNAL_ADDRESS], buf[..len]);
+                socket.send_to(&response(0, addr), from).unwrap();
+            }
+        });
+        local
+    }
+
+    #[test]
+    fn test_parse_response() -> Result<()> {
+        let addr = Ipv4Addr::new(198, 51, 100, 30);
+        assert_eq!(Some(addr), parse_response(&response(0, addr))?);
+        assert_eq!(None, parse_response(&[VERSION, 129, 0, 0])?);
+        assert!(parse_response(&response(3, addr)).is_err());
+        assert!(parse_response(&response(0, addr)[..10]).is_err());
+        assert!(parse_response(&response(0, Ipv4Addr::UNSPECIFIED)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_natpmp() -> Result<()> {
+        let addr = Ipv4Addr::new(198, 51, 100, 30);
+        let source = NatPmp { gateway: serve_natpmp(addr), timeout: Duration::from_secs(3), allow_non_global: false };
+        assert_eq!(IpAddr::V4(addr), source.address(Family::V4)?);
+        assert!(matches!(source.address(Family::V6), Err(Error::Unsupported(_))));
+
+        // Behind carrier-grade NAT
+        let cgnat = Ipv4Addr::new(100, 64, 0, 30);
+        let mut source = NatPmp { gateway: serve_natpmp(cgnat), timeout: Duration::from_secs(3), allow_non_global: false };
+        assert!(matches!(source.address(Family::V4), Err(Error::DiscoveryError(_))));
+        source.allow_non_global = true;
+        assert_eq!(IpAddr::V4(cgnat), source.address(Family::V4)?);
+
+        let silent = UdpSocket::bind("127.0.0.1:0")?;
+        let res = external_address(silent.local_addr()?, Duration::from_millis(50));
+        assert!(matches!(res, Err(Error::DiscoveryError(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,273 @@
+//! STUN binding requests (RFC 8489).
+
+use std::{
+    hash::{BuildHasher, RandomState},
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use tracing::debug;
+
+use crate::errors::{Error, Result};
+use super::{AddressSource, Family};
+
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const BINDING_ERROR: u16 = 0x0111;
+const MAGIC_COOKIE: u32 = 0x2112_a442;
+const ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const HEADER_LEN: usize = 20;
+
+/// The initial retransmission timeout.
+const INITIAL_RTO: Duration = Duration::from_millis(500);
+
+
+/// A STUN server, which reports the address and port that a UDP
+/// request came from.
+///
+/// The server name is resolved and the first address of the
+/// requested family is used, so the server must be reachable over
+/// both families to discover both.
+#[derive(Clone, Debug)]
+pub struct Stun {
+    /// The server address, as `host:port`.
+    pub server: String,
+    /// How long to wait for a response, including retransmissions.
+    pub timeout: Duration,
+}
+
+impl Default for Stun {
+    fn default() -> Self {
+        Self {
+            server: "stun.cloudflare.com:3478".to_string(),
+            timeout: Duration::from_secs(3),
+        }
+    }
+}
+
+impl AddressSource for Stun {
+    fn name(&self) -> String {
+        format!("STUN {}", self.server)
+    }
+
+    fn address(&self, family: Family) -> Result<IpAddr> {
+        let server = self.server.to_socket_addrs()?
+            .find(|addr| Family::of(&addr.ip()) == family)
+            .ok_or_else(|| Error::DiscoveryError(format!("{} has no {family} address", self.server)))?;
+        let mapped = binding(server, self.timeout)?;
+        family.check(mapped.ip(), &self.server)
+    }
+}
+
+/// Send a binding request to `server`, returning the mapped address.
+pub fn binding(server: SocketAddr, timeout: Duration) -> Result<SocketAddr> {
+    let bind: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind)?;
+    socket.connect(server)?;
+
+    let tid = transaction_id();
+    let request = binding_request(&tid);
+    let deadline = Instant::now() + timeout;
+    let mut rto = INITIAL_RTO;
+    let mut buf = [0; 1024];
+
+    socket.send(&request)?;
+    let mut resend = Instant::now() + rto;
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(Error::DiscoveryError(format!("no STUN response from {server} after {timeout:?}")));
+        }
+        if now >= resend {
+            debug!("Retransmitting STUN request to {server}");
+            socket.send(&request)?;
+            rto *= 2;
+            resend = now + rto;
+        }
+
+        socket.set_read_timeout(Some(resend.min(deadline) - now))?;
+        let len = match socket.recv(&mut buf) {
+            Ok(len) => len,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(addr) = parse_response(&buf[..len], &tid)? {
+            return Ok(addr);
+        }
+        debug!("Ignoring unexpected datagram from {server}");
+    }
+}
+
+fn transaction_id() -> [u8; 12] {
+    let state = RandomState::new();
+    let mut tid = [0; 12];
+    tid[..8].copy_from_slice(&state.hash_one(Instant::now()).to_be_bytes());
+    let rest = state.hash_one(tid).to_be_bytes();
+    tid[8..].copy_from_slice(&rest[..4]);
+    tid
+}
+
+fn header(msg_type: u16, len: u16, tid: &[u8; 12]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(HEADER_LEN + len as usize);
+    msg.extend(msg_type.to_be_bytes());
+    msg.extend(len.to_be_bytes());
+    msg.extend(MAGIC_COOKIE.to_be_bytes());
+    msg.extend(tid);
+    msg
+}
+
+pub(crate) fn binding_request(tid: &[u8; 12]) -> Vec<u8> {
+    header(BINDING_REQUEST, 0, tid)
+}
+
+/// Parse a binding response to the request `tid`. Returns `None` for
+/// unrelated messages.
+pub(crate) fn parse_response(msg: &[u8], tid: &[u8; 12]) -> Result<Option<SocketAddr>> {
+    if msg.len() < HEADER_LEN
+        || msg[4..8] != MAGIC_COOKIE.to_be_bytes()
+        || msg[8..20] != tid[..]
+    {
+        return Ok(None);
+    }
+    let malformed = || Error::DiscoveryError("malformed STUN response".to_string());
+
+    match u16::from_be_bytes([msg[0], msg[1]]) {
+        BINDING_SUCCESS => {}
+        BINDING_ERROR => return Err(Error::DiscoveryError("STUN server returned an error".to_string())),
+        _ => return Ok(None),
+    }
+    let len = u16::from_be_bytes([msg[2], msg[3]]) as usize;
+    let mut attrs = msg.get(HEADER_LEN..HEADER_LEN + len).ok_or_else(malformed)?;
+
+    let mut mapped = None;
+    while attrs.len() >= 4 {
+        let attr_type = u16::from_be_bytes([attrs[0], attrs[1]]);
+        let attr_len = u16::from_be_bytes([attrs[2], attrs[3]]) as usize;
+        let value = attrs.get(4..4 + attr_len).ok_or_else(malformed)?;
+        match attr_type {
+            ATTR_XOR_MAPPED_ADDRESS => return decode_address(value, Some(tid)).map(Some),
+            ATTR_MAPPED_ADDRESS => mapped = Some(decode_address(value, None)?),
+            _ => {}
+        }
+        // Attributes are padded to a multiple of 4 bytes
+        let padded = (4 + attr_len).div_ceil(4) * 4;
+        attrs = attrs.get(padded..).unwrap_or_default();
+    }
+    mapped.map(Some)
+        .ok_or_else(|| Error::DiscoveryError("STUN response has no mapped address".to_string()))
+}
+
+/// Decode a (XOR-)MAPPED-ADDRESS value. `tid` is given for
+/// XOR-MAPPED-ADDRESS.
+fn decode_address(value: &[u8], tid: Option<&[u8; 12]>) -> Result<SocketAddr> {
+    let mut mask = [0; 16];
+    if let Some(tid) = tid {
+        mask[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        mask[4..].copy_from_slice(tid);
+    }
+    let unmask = |bytes: &[u8]| -> Vec<u8> {
+        bytes.iter().zip(mask).map(|(b, m)| b ^ m).collect()
+    };
+
+    let port = match value.get(2..4) {
+        Some(port) => u16::from_be_bytes([port[0], port[1]]) ^ u16::from_be_bytes([mask[0], mask[1]]),
+        None => return Err(Error::DiscoveryError("malformed STUN address".to_string())),
+    };
+    let ip = match (value[1], value.len()) {
+        (0x01, 8) => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(unmask(&value[4..8])).unwrap())),
+        (0x02, 20) => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(unmask(&value[4..20])).unwrap())),
+        _ => return Err(Error::DiscoveryError("malformed STUN address".to_string())),
+    };
+    Ok(SocketAddr::new(ip, port))
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::thread;
+
+    /// Encode a binding response with an XOR-MAPPED-ADDRESS, or a
+    /// MAPPED-ADDRESS if `xor` is false.
+    pub(crate) fn binding_response(tid: &[u8; 12], addr: SocketAddr, xor: bool) -> Vec<u8> {
+        let (family, ip) = match addr.ip() {
+            IpAddr::V4(ip) => (0x01, ip.octets().to_vec()),
+            IpAddr::V6(ip) => (0x02, ip.octets().to_vec()),
+        };
+        let mut mask = [0; 16];
+        if xor {
+            mask[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+            mask[4..].copy_from_slice(tid);
+        }
+        let mut value = vec![0, family];
+        value.extend((addr.port() ^ u16::from_be_bytes([mask[0], mask[1]])).to_be_bytes());
+        value.extend(ip.iter().zip(mask).map(|(b, m)| b ^ m));
+
+        let attr_type = if xor { ATTR_XOR_MAPPED_ADDRESS } else { ATTR_MAPPED_ADDRESS };
+        // An unknown attribute that needs padding, then the address
+        let mut attrs = vec![0x80, 0x22, 0, 3, b'z', b'u', b'p', 0];
+        attrs.extend(attr_type.to_be_bytes());
+        attrs.extend((value.len() as u16).to_be_bytes());
+        attrs.extend(value);
+
+        let mut msg = header(BINDING_SUCCESS, attrs.len() as u16, tid);
+        msg.extend(attrs);
+        msg
+    }
+
+    /// A STUN server for tests, answering with the sender's address.
+    pub(crate) fn serve_stun() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            let mut dropped = false;
+            while let Ok((len, from)) = socket.recv_from(&mut buf) {
+                // Drop the first request to exercise retransmission
+                if !dropped {
+                    dropped = true;
+                    continue;
+                }
+                let tid: [u8; 12] = buf[8..20].try_into().unwrap();
+                assert_eq!(binding_request(&tid), buf[..len]);
+                socket.send_to(&binding_response(&tid, from, true), from).unwrap();
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_parse_response() -> Result<()> {
+        let tid = transaction_id();
+        let v4: SocketAddr = "192.0.2.1:32853".parse()?;
+        let v6: SocketAddr = "[2001:db8:1234:5678:11:2233:4455:6677]:32853".parse()?;
+
+        assert_eq!(Some(v4), parse_response(&binding_response(&tid, v4, true), &tid)?);
+        assert_eq!(Some(v6), parse_response(&binding_response(&tid, v6, true), &tid)?);
+        assert_eq!(Some(v6), parse_response(&binding_response(&tid, v6, false), &tid)?);
+        assert_eq!(None, parse_response(&binding_response(&transaction_id(), v4, true), &tid)?);
+
+        let mut truncated = binding_response(&tid, v4, true);
+        truncated.truncate(30);
+        assert!(parse_response(&truncated, &tid).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_stun() -> Result<()> {
+        let server = serve_stun();
+        let source = Stun { server: server.to_string(), timeout: Duration::from_secs(3) };
+        assert_eq!(IpAddr::from([127, 0, 0, 1]), source.address(Family::V4)?);
+        assert!(matches!(source.address(Family::V6), Err(Error::DiscoveryError(_))));
+
+        let silent = UdpSocket::bind("127.0.0.1:0")?;
+        let res = binding(silent.local_addr()?, Duration::from_millis(50));
+        assert!(matches!(res, Err(Error::DiscoveryError(_))));
+        Ok(())
+    }
+}
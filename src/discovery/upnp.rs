@@ -0,0 +1,315 @@
+//! UPnP Internet Gateway Device external address queries.
+
+use std::{
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use tracing::debug;
+
+use crate::{
+    errors::{Error, Result},
+    http::{block_on, Blocking, HttpClient, ResponseToOption},
+};
+use super::{check_global, AddressSource, Family};
+
+/// The SSDP multicast address.
+pub const SSDP_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(239, 255, 255, 250)), 1900);
+
+const SEARCH_TARGET: &str = "urn:schemas-upnp-org:device:InternetGatewayDevice:1";
+const SERVICE_TYPES: &[&str] = &[
+    "urn:schemas-upnp-org:service:WANIPConnection:1",
+    "urn:schemas-upnp-org:service:WANIPConnection:2",
+    "urn:schemas-upnp-org:service:WANPPPConnection:1",
+];
+
+
+/// Ask a UPnP Internet Gateway Device (i.e. a home router) for its
+/// external IPv4 address.
+///
+/// The gateway is found with an SSDP search, and queried with the
+/// `GetExternalIPAddress` action. Only IPv4 is supported.
+#[derive(Clone, Debug)]
+pub struct Upnp {
+    /// The address SSDP searches are sent to. Defaults to the
+    /// multicast group; a gateway can also be searched directly.
+    pub ssdp_addr: SocketAddr,
+    /// How long to wait for a search response.
+    pub timeout: Duration,
+    /// Accept an external address that isn't `Scope::Global`, e.g.
+    /// behind carrier-grade NAT.
+    pub allow_non_global: bool,
+}
+
+impl Default for Upnp {
+    fn default() -> Self {
+        Self {
+            ssdp_addr: SSDP_ADDR,
+            timeout: Duration::from_secs(2),
+            allow_non_global: false,
+        }
+    }
+}
+
+impl AddressSource for Upnp {
+    fn name(&self) -> String {
+        format!("UPnP via {}", self.ssdp_addr)
+    }
+
+    fn address(&self, family: Family) -> Result<IpAddr> {
+        if family != Family::V4 {
+            return Err(Error::Unsupported("UPnP only reports IPv4 addresses".to_string()));
+        }
+        let location = self.search()?;
+        debug!("Found gateway at {location}");
+        let (service, control_url) = control_url(&location)?;
+        let addr = family.check(external_address(&control_url, service)?, &control_url)?;
+        match self.allow_non_global {
+            true => Ok(addr),
+            false => check_global(addr, &control_url),
+        }
+    }
+}
+
+impl Upnp {
+    /// Search for a gateway, returning the URL of its description.
+    fn search(&self) -> Result<String> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        let request = format!("M-SEARCH * HTTP/1.1\r\n\
+                               HOST: {SSDP_ADDR}\r\n\
+                               MAN: \"ssdp:discover\"\r\n\
+                               MX: {}\r\n\
+                               ST: {SEARCH_TARGET}\r\n\r\n",
+                              self.timeout.as_secs().max(1));
+        socket.send_to(request.as_bytes(), self.ssdp_addr)?;
+
+        let deadline = Instant::now() + self.timeout;
+        let mut buf = [0; 2048];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(Error::DiscoveryError(format!("no UPnP gateway responded within {:?}", self.timeout)));
+            }
+            socket.set_read_timeout(Some(remaining))?;
+            let len = match socket.recv(&mut buf) {
+                Ok(len) => len,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+                Err(e) => return Err(e.into()),
+            };
+            match header(&String::from_utf8_lossy(&buf[..len]), "location") {
+                Some(location) => return Ok(location.to_string()),
+                None => debug!("Ignoring SSDP response without a location"),
+            }
+        }
+    }
+}
+
+/// Find the value of a header in an SSDP response.
+fn header<'a>(response: &'a str, name: &str) -> Option<&'a str> {
+    response.lines()
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim())
+}
+
+/// The contents of the elements named `name`, ignoring namespace
+/// prefixes. Elements of the same name must not be nested.
+fn elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let is_named = |tag: &str| {
+        let tag = tag.split_whitespace().next().unwrap_or_default();
+        tag.rsplit(':').next() == Some(name)
+    };
+
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(open) = rest.find('<') {
+        let Some(close) = rest[open..].find('>') else { break };
+        let tag = &rest[open + 1..open + close];
+        rest = &rest[open + close + 1..];
+        if tag.starts_with('/') || tag.ends_with('/') || !is_named(tag) {
+            continue;
+        }
+
+        // Find the matching end tag
+        let mut end = 0;
+        while let Some(pos) = rest[end..].find("</") {
+            let start = end + pos;
+            let Some(len) = rest[start..].find('>') else { break };
+            if is_named(&rest[start + 2..start + len]) {
+                found.push(&rest[..start]);
+                break;
+            }
+            end = start + len;
+        }
+    }
+    found
+}
+
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    elements(xml, name).first().map(|text| text.trim())
+}
+
+/// Resolve a URL from a device description against its base.
+fn join_url(base: &str, url: &str) -> String {
+    if url.contains("://") {
+        return url.to_string();
+    }
+    let origin = match base.find("://") {
+        Some(scheme) => match base[scheme + 3..].find('/') {
+            Some(path) => &base[..scheme + 3 + path],
+            None => base,
+        },
+        None => base,
+    };
+    format!("{}/{}", origin.trim_end_matches('/'), url.trim_start_matches('/'))
+}
+
+/// Fetch the device description, returning the connection service
+/// type and its control URL.
+fn control_url(location: &str) -> Result<(&'static str, String)> {
+    let desc = block_on(Blocking.get(location).call())?
+        .check_error()?;
+    let desc = desc.body();
+    let base = element(desc, "URLBase").unwrap_or(location);
+
+    for service in elements(desc, "service") {
+        let Some(stype) = element(service, "serviceType") else { continue };
+        let Some(&known) = SERVICE_TYPES.iter().find(|&&t| t == stype) else { continue };
+        if let Some(control) = element(service, "controlURL") {
+            return Ok((known, join_url(base, control)));
+        }
+    }
+    Err(Error::DiscoveryError(format!("no WAN connection service in {location}")))
+}
+
+fn external_address(control_url: &str, service: &str) -> Result<IpAddr> {
+    let body = format!("<?xml version=\"1.0\"?>\
+                        <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+                        s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+                        <s:Body><u:GetExternalIPAddress xmlns:u=\"{service}\"/></s:Body>\
+                        </s:Envelope>");
+    let resp = block_on(Blocking.post(control_url)
+                        .header("Content-Type", "text/xml; charset=\"utf-8\"")
+                        .header("SOAPAction", format!("\"{service}#GetExternalIPAddress\""))
+                        .send(body))?
+        .check_error()?;
+
+    let value = element(resp.body(), "NewExternalIPAddress")
+        .ok_or_else(|| Error::DiscoveryError(format!("no external address in response from {control_url}")))?;
+    value.parse()
+        .map_err(|_| Error::DiscoveryError(format!("gateway returned {value:?}, not an address")))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::tests::serve_http;
+    use std::thread;
+
+    const DESCRIPTION: &str = r#"<?xml version="1.0"?>
+        <root xmlns="urn:schemas-upnp-org:device-1-0">
+          <device>
+            <deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice:1</deviceType>
+            <serviceList>
+              <service>
+                <serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>
+                <controlURL>/l3f</controlURL>
+              </service>
+            </serviceList>
+            <deviceList><device><deviceList><device>
+              <serviceList>
+                <service>
+                  <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>
+                  <controlURL>/ctl/IPConn</controlURL>
+                </service>
+              </serviceList>
+            </device></deviceList></device></deviceList>
+          </device>
+        </root>"#;
+
+    const RESPONSE: &str = r#"<?xml version="1.0"?>
+        <s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
+          <s:Body>
+            <u:GetExternalIPAddressResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+              <NewExternalIPAddress>198.51.100.20</NewExternalIPAddress>
+            </u:GetExternalIPAddressResponse>
+          </s:Body>
+        </s:Envelope>"#;
+
+    fn serve_ssdp(location: String) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; 1024];
+            while let Ok((len, from)) = socket.recv_from(&mut buf) {
+                let request = String::from_utf8_lossy(&buf[..len]).to_string();
+                assert!(request.starts_with("M-SEARCH * HTTP/1.1\r\n"));
+                let resp = format!("HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=120\r\nST: {SEARCH_TARGET}\r\n\
+                                    Location: {location}\r\n\r\n");
+                socket.send_to(resp.as_bytes(), from).unwrap();
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_elements() {
+        assert_eq!(Some("/ctl/IPConn"), elements(DESCRIPTION, "service").get(1).and_then(|s| element(s, "controlURL")));
+        assert_eq!(Some("198.51.100.20"), element(RESPONSE, "NewExternalIPAddress"));
+        assert_eq!(1, elements(RESPONSE, "GetExternalIPAddressResponse").len());
+        assert_eq!(None, element(RESPONSE, "Missing"));
+    }
+
+    #[test]
+    fn test_join_url() {
+        assert_eq!("http://192.168.1.1:5000/ctl", join_url("http://192.168.1.1:5000/desc.xml", "/ctl"));
+        assert_eq!("http://192.168.1.1:5000/ctl", join_url("http://192.168.1.1:5000", "ctl"));
+        assert_eq!("http://10.0.0.1/ctl", join_url("http://192.168.1.1/", "http://10.0.0.1/ctl"));
+    }
+
+    #[test]
+    fn test_upnp() -> Result<()> {
+        let http = serve_http(|request, body| match request {
+            "GET /desc.xml HTTP/1.1" => (200, DESCRIPTION.to_string()),
+            "POST /ctl/IPConn HTTP/1.1" if body.contains("GetExternalIPAddress") => (200, RESPONSE.to_string()),
+            _ => (404, String::new()),
+        });
+        let source = Upnp {
+            ssdp_addr: serve_ssdp(format!("http://{http}/desc.xml")),
+            timeout: Duration::from_secs(2),
+            ..Default::default()
+        };
+        assert_eq!(IpAddr::from([198, 51, 100, 20]), source.address(Family::V4)?);
+        assert!(matches!(source.address(Family::V6), Err(Error::Unsupported(_))));
+
+        let silent = UdpSocket::bind("127.0.0.1:0")?;
+        let source = Upnp { ssdp_addr: silent.local_addr()?, timeout: Duration::from_millis(50), ..Default::default() };
+        assert!(matches!(source.address(Family::V4), Err(Error::DiscoveryError(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_non_global() -> Result<()> {
+        for external in ["0.0.0.0", "192.168.0.2"] {
+            let response = RESPONSE.replace("198.51.100.20", external);
+            let http = serve_http(move |request, _| match request {
+                "GET /desc.xml HTTP/1.1" => (200, DESCRIPTION.to_string()),
+                "POST /ctl/IPConn HTTP/1.1" => (200, response.clone()),
+                _ => (404, String::new()),
+            });
+            let mut source = Upnp {
+                ssdp_addr: serve_ssdp(format!("http://{http}/desc.xml")),
+                ..Default::default()
+            };
+            let err = source.address(Family::V4).unwrap_err();
+            assert!(err.to_string().contains("not a global address"), "{err}");
+
+            source.allow_non_global = true;
+            assert_eq!(external.parse::<IpAddr>()?, source.address(Family::V4)?);
+        }
+        Ok(())
+    }
+}
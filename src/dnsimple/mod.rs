@@ -3,11 +3,10 @@ mod types;
 use std::{fmt::Display, sync::Mutex};
 
 use serde::de::DeserializeOwned;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tracing::{error, info, warn};
 
-use crate::generate_helpers;
-use crate::http::{self, ResponseToOption, WithHeaders};
+use crate::http::{http_provider_impl, HttpClient, ResponseToOption, WithHeaders};
 
 
 use crate::{
@@ -15,17 +14,23 @@ use crate::{
         Accounts,
         CreateRecord,
         GetRecord,
+        ListRecords,
         Records,
-        UpdateRecord
+        UpdateRecord,
+        Zone,
+        ZoneData,
     },
     errors::{Error, Result},
+    find_zone,
+    verify::same_value,
     Config,
-    DnsProvider,
-    RecordType
+    RecordType,
+    ZoneListing,
 };
 
 
 pub(crate) const API_BASE: &str = "https://api.dnsimple.com/v2";
+const LIST_PAGE_SIZE: usize = 100;
 
 /// Authentication credentials for DNSimple API usage.
 ///
@@ -68,57 +73,89 @@ impl Dnsimple {
         }
     }
 
-    fn get_upstream_id(&self) -> Result<u32> {
+    async fn get_upstream_id<C>(&self, client: &C) -> Result<u32>
+    where
+        C: HttpClient + ?Sized,
+    {
         info!("Fetching account ID from upstream");
         let url = format!("{}/accounts", self.endpoint);
 
-        let accounts_p = http::client().get(url)
+        let accounts = client.get(url)
             .with_auth(self.auth.get_header())
-            .call()?
-            .to_option::<Accounts>()?;
-
-        match accounts_p {
-            Some(accounts) if accounts.accounts.len() == 1 => {
-                Ok(accounts.accounts[0].id)
-            }
-            Some(accounts) if accounts.accounts.len() > 1 => {
-                Err(Error::ApiError("More than one account returned; you must specify the account ID to use".to_string()))
-            }
-            // None or 0 accounts => {
+            .call().await?
+            .to_option::<Accounts>()?
+            .map(|a| a.accounts)
+            .unwrap_or_default();
+
+        match accounts.len() {
+            0 => Err(Error::ApiError("No accounts returned from upstream".to_string())),
+            1 => Ok(accounts[0].id),
             _ => {
-                Err(Error::ApiError("No accounts returned from upstream".to_string()))
+                // Multiple accounts visible to this token; use the
+                // one that holds the zone.
+                let mut holding = Vec::new();
+                for acc in accounts {
+                    if self.get_upstream_zone(client, acc.id).await?.is_some() {
+                        holding.push(acc.id);
+                    }
+                }
+                match holding.len() {
+                    0 => Err(Error::ZoneNotFound(self.config.domain.clone())),
+                    1 => Ok(holding[0]),
+                    _ => Err(Error::AmbiguousZone(format!("{} is present in more than one account; you must specify the account ID to use",
+                                                          self.config.domain))),
+                }
             }
         }
     }
 
-    fn get_id(&self) -> Result<u32> {
-        // This is roughly equivalent to OnceLock.get_or_init(), but
-        // is simpler than dealing with closure->Result and is more
-        // portable.
-        let mut id_p = self.acc_id.lock()
-            .map_err(|e| Error::LockingError(e.to_string()))?;
+    async fn get_upstream_zone<C>(&self, client: &C, acc_id: u32) -> Result<Option<Zone>>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let url = format!("{}/{acc_id}/zones/{}", self.endpoint, self.config.domain);
+
+        let zone = client.get(url)
+            .with_json_headers()
+            .with_auth(self.auth.get_header())
+            .call().await?
+            .to_option::<ZoneData>()?
+            .map(|z| z.zone);
+
+        match zone {
+            Some(zone) => find_zone(vec![zone], &self.config.domain, |z| &z.name).map(Some),
+            None => Ok(None),
+        }
+    }
 
-        if let Some(id) = *id_p {
+    async fn get_id<C>(&self, client: &C) -> Result<u32>
+    where
+        C: HttpClient + ?Sized,
+    {
+        // Check the cache first; the lock is not held across the
+        // upstream lookup, so concurrent callers may both fetch it.
+        if let Some(id) = *self.acc_id.lock().map_err(|e| Error::LockingError(e.to_string()))? {
             return Ok(id);
         }
 
-        let id = self.get_upstream_id()?;
-        *id_p = Some(id);
+        let id = self.get_upstream_id(client).await?;
+        *self.acc_id.lock().map_err(|e| Error::LockingError(e.to_string()))? = Some(id);
 
         Ok(id)
     }
 
-    fn get_upstream_records<T>(&self, rtype: RecordType, host: &str) -> Result<Vec<GetRecord<T>>>
+    async fn get_upstream_records<T, C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<Vec<GetRecord<T>>>
     where
-        T: DeserializeOwned
+        T: DeserializeOwned,
+        C: HttpClient + ?Sized,
     {
-        let acc_id = self.get_id()?;
+        let acc_id = self.get_id(client).await?;
         let url = format!("{}/{acc_id}/zones/{}/records?name={host}&type={rtype}", self.endpoint, self.config.domain);
 
-        let response = http::client().get(url)
+        let response = client.get(url)
             .with_json_headers()
             .with_auth(self.auth.get_header())
-            .call()?
+            .call().await?
             .to_option::<Records<T>>()?;
         let recs: Records<T> = match response {
             Some(rec) => rec,
@@ -128,11 +165,12 @@ impl Dnsimple {
         Ok(recs.records)
     }
 
-    fn get_upstream_record<T>(&self, rtype: RecordType, host: &str) -> Result<Option<GetRecord<T>>>
+    async fn get_upstream_record<T, C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<Option<GetRecord<T>>>
     where
-        T: DeserializeOwned
+        T: DeserializeOwned,
+        C: HttpClient + ?Sized,
     {
-        let mut recs = self.get_upstream_records(rtype, host)?;
+        let mut recs = self.get_upstream_records(client, rtype, host).await?;
 
         // FIXME: Assumes no or single address (which probably makes
         // sense for DDNS and DNS-01, but may cause issues with
@@ -149,8 +187,11 @@ impl Dnsimple {
         Ok(Some(recs.remove(0)))
     }
 
-    fn do_delete(&self, rec: GetRecord<String>) -> Result<()> {
-        let acc_id = self.get_id()?;
+    async fn do_delete<C>(&self, client: &C, rec: GetRecord<String>) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let acc_id = self.get_id(client).await?;
         let url = format!("{}/{acc_id}/zones/{}/records/{}", self.endpoint, self.config.domain, rec.id);
         if self.config.dry_run {
             info!("DRY-RUN: Would have sent DELETE to {url}");
@@ -158,10 +199,10 @@ impl Dnsimple {
         }
 
         info!("Deleting DNS {} record {}", rec.rtype, rec.name);
-        http::client().delete(url)
+        client.delete(url)
             .with_json_headers()
             .with_auth(self.auth.get_header())
-            .call()?;
+            .call().await?;
 
         Ok(())
     }
@@ -169,13 +210,14 @@ impl Dnsimple {
 }
 
 
-impl DnsProvider for Dnsimple {
+impl Dnsimple {
 
-    fn get_record<T>(&self, rtype: RecordType, host: &str) -> Result<Option<T> >
+    async fn get_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<Option<T> >
     where
-        T: DeserializeOwned
+        T: DeserializeOwned,
+        C: HttpClient + ?Sized,
     {
-        let rec: GetRecord<T> = match self.get_upstream_record(rtype, host)? {
+        let rec: GetRecord<T> = match self.get_upstream_record(client, rtype, host).await? {
             Some(recs) => recs,
             None => return Ok(None)
         };
@@ -184,11 +226,12 @@ impl DnsProvider for Dnsimple {
         Ok(Some(rec.content))
     }
 
-    fn create_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+    async fn create_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str, record: &T) -> Result<()>
     where
         T: Display,
+        C: HttpClient + ?Sized,
     {
-        let acc_id = self.get_id()?;
+        let acc_id = self.get_id(client).await?;
 
         let url = format!("{}/{acc_id}/zones/{}/records", self.endpoint, self.config.domain);
 
@@ -205,19 +248,20 @@ impl DnsProvider for Dnsimple {
         }
 
         let body = serde_json::to_string(&rec)?;
-        http::client().post(url)
+        client.post(url)
             .with_json_headers()
             .with_auth(self.auth.get_header())
-            .send(body)?;
+            .send(body).await?;
 
         Ok(())
     }
 
-    fn update_record<T>(&self, rtype: RecordType, host: &str, urec: &T) -> Result<()>
+    async fn update_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str, urec: &T) -> Result<()>
     where
         T: DeserializeOwned + Display,
+        C: HttpClient + ?Sized,
     {
-        let rec: GetRecord<T> = match self.get_upstream_record(rtype, host)? {
+        let rec: GetRecord<T> = match self.get_upstream_record(client, rtype, host).await? {
             Some(rec) => rec,
             None => {
                 warn!("DELETE: Record {host} doesn't exist");
@@ -225,7 +269,7 @@ impl DnsProvider for Dnsimple {
             }
         };
 
-        let acc_id = self.get_id()?;
+        let acc_id = self.get_id(client).await?;
         let rid = rec.id;
 
         let update = UpdateRecord {
@@ -240,16 +284,19 @@ impl DnsProvider for Dnsimple {
 
 
         let body = serde_json::to_string(&update)?;
-        http::client().patch(url)
+        client.patch(url)
             .with_json_headers()
             .with_auth(self.auth.get_header())
-            .send(body)?;
+            .send(body).await?;
 
         Ok(())
     }
 
-    fn delete_record(&self, rtype: RecordType, host: &str) -> Result<()> {
-        let rec: GetRecord<String> = match self.get_upstream_record(rtype, host)? {
+    async fn delete_record_with<C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let rec: GetRecord<String> = match self.get_upstream_record(client, rtype, host).await? {
             Some(rec) => rec,
             None => {
                 warn!("DELETE: Record {host} doesn't exist");
@@ -257,30 +304,72 @@ impl DnsProvider for Dnsimple {
             }
         };
 
-        self.do_delete(rec)?;
+        self.do_delete(client, rec).await?;
 
         Ok(())
     }
 
-    fn delete_all_records(&self, rtype: RecordType, host: &str) -> Result<()>
+    async fn delete_all_records_with<C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
     {
-        let recs: Vec<GetRecord<String>> = self.get_upstream_records(rtype, host)?;
+        let recs: Vec<GetRecord<String>> = self.get_upstream_records(client, rtype, host).await?;
         for rec in recs {
-            self.do_delete(rec)?;
+            self.do_delete(client, rec).await?;
         }
 
         Ok(())
     }
 
-    generate_helpers!();
-}
+    async fn delete_value_with<C>(&self, client: &C, rtype: RecordType, host: &str, value: &str) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let recs: Vec<GetRecord<String>> = self.get_upstream_records(client, rtype, host).await?;
+        for rec in recs.into_iter().filter(|rec| same_value(rtype, &rec.content, value)) {
+            self.do_delete(client, rec).await?;
+        }
+
+        Ok(())
+    }
 
+    async fn list_zone_with<C>(&self, client: &C) -> Result<ZoneListing>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let acc_id = self.get_id(client).await?;
+
+        let mut listed = Vec::new();
+        for page in 1.. {
+            let url = format!("{}/{acc_id}/zones/{}/records?page={page}&per_page={LIST_PAGE_SIZE}",
+                              self.endpoint, self.config.domain);
+            let recs = client.get(url)
+                .with_json_headers()
+                .with_auth(self.auth.get_header())
+                .call().await?
+                .to_option::<ListRecords>()?
+                .ok_or(Error::ZoneNotFound(self.config.domain.clone()))?
+                .records;
+
+            let last = recs.len() < LIST_PAGE_SIZE;
+            listed.extend(recs.into_iter()
+                          .map(|rec| rec.into_zone_record()));
+            if last {
+                break;
+            }
+        }
+
+        Ok(ZoneListing::collect(listed))
+    }
+
+}
 
+http_provider_impl!(Dnsimple);
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{generate_tests, tests::*};
+    use crate::{generate_tests, http::{block_on, Blocking}, tests::*};
     use std::env;
 
     const TEST_API: &str = "https://api.sandbox.dnsimple.com/v2";
@@ -299,7 +388,7 @@ mod tests {
     fn test_id_fetch() -> Result<()> {
         let client = get_client();
 
-        let id = client.get_upstream_id()?;
+        let id = block_on(client.get_upstream_id(&Blocking))?;
         assert_eq!(2602, id);
 
         Ok(())
@@ -1,7 +1,7 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-use crate::RecordType;
+use crate::{parse_rtype, to_rdata, RdataParts, RecordType, UnsupportedRecord, ZoneRecord};
 
 
 // {
@@ -32,6 +32,34 @@ pub struct Accounts {
     pub accounts: Vec<Account>
 }
 
+// {
+//   "data": {
+//     "id": 1,
+//     "account_id": 1010,
+//     "name": "example-alpha.com",
+//     "reverse": false,
+//     "secondary": false,
+//     "last_transferred_at": null,
+//     "active": true,
+//     "created_at": "2015-04-23T07:40:03Z",
+//     "updated_at": "2015-04-23T07:40:03Z"
+//   }
+// }
+/// A zone held by a DNSimple account.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Zone {
+    pub id: u64,
+    pub account_id: u32,
+    pub name: String,
+}
+
+/// Container for single-zone responses from DNSimple.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ZoneData {
+    #[serde(rename = "data")]
+    pub zone: Zone,
+}
+
 // {
 //   "data": [
 //     {
@@ -94,6 +122,47 @@ pub struct Records<T>
 //   "integrated_zones": [1, 2, "dnsimple"]
 // }
 /// Payload used to create a DNS record on DNSimple.
+// As `GetRecord`, but for zone listings, which may contain types we
+// don't support (e.g. SOA).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ListRecord {
+    pub name: String,
+    pub content: String,
+    pub ttl: u32,
+    pub priority: Option<u64>,
+    #[serde(rename = "type")]
+    pub rtype: String,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListRecords {
+    #[serde(rename = "data")]
+    pub records: Vec<ListRecord>,
+}
+
+impl ListRecord {
+    pub fn into_zone_record(self) -> Result<ZoneRecord, UnsupportedRecord> {
+        let Some(rtype) = parse_rtype(&self.rtype) else {
+            return Err(UnsupportedRecord::unknown_type(self.rtype, self.name, self.content));
+        };
+        let parts = RdataParts {
+            priority: self.priority,
+            ..Default::default()
+        };
+
+        Ok(ZoneRecord {
+            rtype,
+            // Names are already relative, with "" for the apex
+            host: self.name,
+            value: to_rdata(rtype, &self.content, parts),
+            ttl: Some(self.ttl),
+            modified: self.updated_at,
+        })
+    }
+}
+
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CreateRecord {
     pub name: String,
@@ -110,3 +179,79 @@ pub struct CreateRecord {
 pub struct UpdateRecord {
     pub content: String,
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::check_malformed_replies;
+
+    #[test]
+    fn test_malformed_accounts() {
+        check_malformed_replies::<Accounts>(r#"{
+            "data": [{
+                "id": 2602,
+                "email": "user@example.com",
+                "plan_identifier": "solo-v2-monthly",
+                "created_at": "2025-09-14T01:53:43Z",
+                "updated_at": "2025-09-14T01:56:14Z"
+            }]
+        }"#);
+    }
+
+    #[test]
+    fn test_malformed_zone() {
+        check_malformed_replies::<ZoneData>(r#"{
+            "data": {"id": 1, "account_id": 1010, "name": "example-alpha.com"}
+        }"#);
+    }
+
+    #[test]
+    fn test_malformed_records() {
+        check_malformed_replies::<Records<String>>(r#"{
+            "data": [{
+                "id": 3422640,
+                "zone_id": "example.com",
+                "name": "test",
+                "content": "1.2.3.4",
+                "ttl": 60,
+                "type": "A",
+                "created_at": "2025-09-20T01:10:32Z",
+                "updated_at": "2025-09-20T01:10:32Z"
+            }]
+        }"#);
+    }
+
+    #[test]
+    fn test_malformed_list() {
+        check_malformed_replies::<ListRecords>(r#"{
+            "data": [{
+                "id": 3422640,
+                "name": "",
+                "content": "mail.example.com",
+                "ttl": 3600,
+                "priority": 10,
+                "type": "MX"
+            }]
+        }"#);
+    }
+
+    #[test]
+    fn test_into_zone_record() {
+        let recs: ListRecords = serde_json::from_str(r#"{
+            "data": [
+                {"name": "", "content": "ns1.dnsimple.com admin.dnsimple.com 1 86400 7200 604800 300",
+                 "ttl": 3600, "priority": null, "type": "SOA"},
+                {"name": "_sip._tcp", "content": "10 5060 sip.example.com", "ttl": 600, "priority": 1, "type": "SRV",
+                 "updated_at": "2025-09-14T01:56:14Z"}
+            ]
+        }"#).unwrap();
+        let recs: Vec<ZoneRecord> = recs.records.into_iter()
+            .filter_map(|r| r.into_zone_record().ok())
+            .collect();
+
+        assert_eq!(1, recs.len());
+        assert_eq!("1 10 5060 sip.example.com", recs[0].value);
+        assert_eq!("2025-09-14T01:56:14+00:00", recs[0].modified.unwrap().to_rfc3339());
+    }
+}
@@ -9,7 +9,7 @@ use sha1::Sha1;
 use tracing::{error, info, warn};
 
 use crate::{
-    dnsmadeeasy::types::{Domain, Record, Records}, errors::{Error, Result}, generate_helpers, http::{self, ResponseToOption, WithHeaders}, Config, DnsProvider, RecordType
+    dnsmadeeasy::types::{Domain, ListRecords, Record, Records}, errors::{Error, Result}, find_zone, http::{http_provider_impl, HttpClient, ResponseToOption, WithHeaders}, verify::same_value, Config, RecordType, ZoneListing
 };
 
 
@@ -78,49 +78,52 @@ impl DnsMadeEasy {
         }
     }
 
-    fn get_domain(&self) -> Result<Domain>
+    async fn get_domain<C>(&self, client: &C) -> Result<Domain>
+    where
+        C: HttpClient + ?Sized,
     {
         let url = format!("{}/dns/managed/name?domainname={}", self.endpoint, self.config.domain);
 
-        let domain = http::client().get(url)
+        let domain = client.get(url)
             .with_headers(self.auth.get_headers()?)?
-            .call()?
+            .call().await?
             .to_option::<Domain>()?
-            .ok_or(Error::ApiError("No domain returned from upstream".to_string()))?;
+            .ok_or(Error::ZoneNotFound(self.config.domain.clone()))?;
 
-        Ok(domain)
+        // Guard against the lookup returning something other than
+        // what we asked for.
+        find_zone(vec![domain], &self.config.domain, |d| &d.name)
     }
 
-    fn get_domain_id(&self) -> Result<u32> {
-        // This is roughly equivalent to OnceLock.get_or_init(), but
-        // is simpler than dealing with closure->Result and is more
-        // portable.
-        let mut id_p = self.domain_id.lock()
-            .map_err(|e| Error::LockingError(e.to_string()))?;
-
-        if let Some(id) = *id_p {
+    async fn get_domain_id<C>(&self, client: &C) -> Result<u32>
+    where
+        C: HttpClient + ?Sized,
+    {
+        // Check the cache first; the lock is not held across the
+        // upstream lookup, so concurrent callers may both fetch it.
+        if let Some(id) = *self.domain_id.lock().map_err(|e| Error::LockingError(e.to_string()))? {
             return Ok(id);
         }
 
-        let domain = self.get_domain()?;
-        let id = domain.id;
-        *id_p = Some(id);
+        let id = self.get_domain(client).await?.id;
+        *self.domain_id.lock().map_err(|e| Error::LockingError(e.to_string()))? = Some(id);
 
         Ok(id)
     }
 
 
-    fn get_upstream_records<T>(&self, rtype: &RecordType, host: &str) -> Result<Vec<Record<T>>>
+    async fn get_upstream_records<T, C>(&self, client: &C, rtype: &RecordType, host: &str) -> Result<Vec<Record<T>>>
     where
-        T: DeserializeOwned
+        T: DeserializeOwned,
+        C: HttpClient + ?Sized,
     {
-        let domain_id = self.get_domain_id()?;
+        let domain_id = self.get_domain_id(client).await?;
         let url = format!("{}/dns/managed/{domain_id}/records?recordName={host}&type={rtype}", self.endpoint);
 
-        let response = http::client().get(url)
+        let response = client.get(url)
             .with_json_headers()
             .with_headers(self.auth.get_headers()?)?
-            .call()?
+            .call().await?
             .to_option::<Records<T>>()?;
 
         // FIXME: Similar to the dnsimple impl, can dedup?
@@ -132,11 +135,12 @@ impl DnsMadeEasy {
         Ok(recs.records)
     }
 
-    fn get_upstream_record<T>(&self, rtype: &RecordType, host: &str) -> Result<Option<Record<T>>>
+    async fn get_upstream_record<T, C>(&self, client: &C, rtype: &RecordType, host: &str) -> Result<Option<Record<T>>>
     where
-        T: DeserializeOwned
+        T: DeserializeOwned,
+        C: HttpClient + ?Sized,
     {
-        let mut recs = self.get_upstream_records(rtype, host)?;
+        let mut recs = self.get_upstream_records(client, rtype, host).await?;
 
         // FIXME: Assumes no or single address (which probably makes
         // sense for DDNS and DNS-01, but may cause issues with
@@ -153,8 +157,11 @@ impl DnsMadeEasy {
         Ok(Some(recs.remove(0)))
     }
 
-    fn do_delete(&self, rec: Record<String>) -> Result<()> {
-        let domain_id = self.get_domain_id()?;
+    async fn do_delete<C>(&self, client: &C, rec: Record<String>) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let domain_id = self.get_domain_id(client).await?;
         let url = format!("{}/dns/managed/{domain_id}/records/{}", self.endpoint, rec.id);
         if self.config.dry_run {
             info!("DRY-RUN: Would have sent DELETE to {url}");
@@ -162,10 +169,10 @@ impl DnsMadeEasy {
         }
 
         info!("Deleting DNS {} record {}", rec.rtype, rec.name);
-        let _response = http::client().delete(url)
+        let _response = client.delete(url)
             .with_json_headers()
             .with_headers(self.auth.get_headers()?)?
-            .call()?
+            .call().await?
             .check_error()?;
 
         Ok(())
@@ -173,14 +180,15 @@ impl DnsMadeEasy {
 }
 
 
-impl DnsProvider for DnsMadeEasy {
+impl DnsMadeEasy {
 
-    fn get_record<T>(&self, rtype: RecordType, host: &str) -> Result<Option<T> >
+    async fn get_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<Option<T> >
     where
-        T: DeserializeOwned
+        T: DeserializeOwned,
+        C: HttpClient + ?Sized,
     {
 
-        let rec: Record<T> = match self.get_upstream_record(&rtype, host)? {
+        let rec: Record<T> = match self.get_upstream_record(client, &rtype, host).await? {
             Some(recs) => recs,
             None => return Ok(None)
         };
@@ -188,11 +196,12 @@ impl DnsProvider for DnsMadeEasy {
         Ok(Some(rec.value))
     }
 
-    fn create_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+    async fn create_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str, record: &T) -> Result<()>
     where
-        T: Serialize + DeserializeOwned + Display + Clone
+        T: Serialize + DeserializeOwned + Display + Clone,
+        C: HttpClient + ?Sized,
     {
-        let domain_id = self.get_domain_id()?;
+        let domain_id = self.get_domain_id(client).await?;
         let url = format!("{}/dns/managed/{domain_id}/records", self.endpoint);
 
         let record = Record {
@@ -209,20 +218,21 @@ impl DnsProvider for DnsMadeEasy {
         }
 
         let body = serde_json::to_string(&record)?;
-        let _response = http::client().post(url)
+        let _response = client.post(url)
             .with_json_headers()
             .with_headers(self.auth.get_headers()?)?
-            .send(body)?
+            .send(body).await?
             .check_error()?;
 
         Ok(())
     }
 
-    fn update_record<T>(&self, rtype: RecordType, host: &str, urec: &T) -> Result<()>
+    async fn update_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str, urec: &T) -> Result<()>
     where
-        T: Serialize + DeserializeOwned + Display + Clone
+        T: Serialize + DeserializeOwned + Display + Clone,
+        C: HttpClient + ?Sized,
     {
-        let rec: Record<String> = match self.get_upstream_record(&rtype, host)? {
+        let rec: Record<String> = match self.get_upstream_record(client, &rtype, host).await? {
             Some(rec) => rec,
             None => {
                 warn!("DELETE: Record {host} doesn't exist");
@@ -231,7 +241,7 @@ impl DnsProvider for DnsMadeEasy {
         };
 
         let rid = rec.id;
-        let domain_id = self.get_domain_id()?;
+        let domain_id = self.get_domain_id(client).await?;
         let url = format!("{}/dns/managed/{domain_id}/records/{rid}", self.endpoint);
 
         let record = Record {
@@ -249,18 +259,21 @@ impl DnsProvider for DnsMadeEasy {
         }
 
         let body = serde_json::to_string(&record)?;
-        let _response = http::client().put(url)
+        let _response = client.put(url)
             .with_json_headers()
             .with_headers(self.auth.get_headers()?)?
-            .send(body)?
+            .send(body).await?
             .check_error()?;
 
         Ok(())
     }
 
-    fn delete_record(&self, rtype: RecordType, host: &str) -> Result<()> {
+    async fn delete_record_with<C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
+    {
 
-        let rec: Record<String> = match self.get_upstream_record(&rtype, host)? {
+        let rec: Record<String> = match self.get_upstream_record(client, &rtype, host).await? {
             Some(rec) => rec,
             None => {
                 warn!("DELETE: Record {host} doesn't exist");
@@ -268,32 +281,76 @@ impl DnsProvider for DnsMadeEasy {
             }
         };
 
-        self.do_delete(rec)?;
+        self.do_delete(client, rec).await?;
 
         Ok(())
     }
 
-    fn delete_all_records(&self, rtype: RecordType, host: &str) -> Result<()>
+    async fn delete_all_records_with<C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
     {
-        let recs: Vec<Record<String>> = self.get_upstream_records(&rtype, host)?;
+        let recs: Vec<Record<String>> = self.get_upstream_records(client, &rtype, host).await?;
         for rec in recs {
-            self.do_delete(rec)?;
+            self.do_delete(client, rec).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn delete_value_with<C>(&self, client: &C, rtype: RecordType, host: &str, value: &str) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let recs: Vec<Record<String>> = self.get_upstream_records(client, &rtype, host).await?;
+        for rec in recs.into_iter().filter(|rec| same_value(rtype, &rec.value, value)) {
+            self.do_delete(client, rec).await?;
         }
 
         Ok(())
     }
 
+    async fn list_zone_with<C>(&self, client: &C) -> Result<ZoneListing>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let domain_id = self.get_domain_id(client).await?;
+        let url = format!("{}/dns/managed/{domain_id}/records", self.endpoint);
 
-    generate_helpers!();
-}
+        let recs = client.get(url)
+            .with_json_headers()
+            .with_headers(self.auth.get_headers()?)?
+            .call().await?
+            .to_option::<ListRecords>()?
+            .ok_or(Error::ZoneNotFound(self.config.domain.clone()))?;
 
+        let listing = ZoneListing::collect(recs.records.into_iter()
+            .map(|rec| rec.into_zone_record()));
 
+        Ok(listing)
+    }
+
+}
 
+// DNS Made Easy has no SVCB or HTTPS records.
+const RECORD_TYPES: &[RecordType] = &[
+    RecordType::A,
+    RecordType::AAAA,
+    RecordType::CAA,
+    RecordType::CNAME,
+    RecordType::MX,
+    RecordType::NS,
+    RecordType::PTR,
+    RecordType::SRV,
+    RecordType::TXT,
+];
+
+http_provider_impl!(DnsMadeEasy, RECORD_TYPES);
 
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
-    use crate::{generate_tests, tests::*};
+    use crate::{generate_tests, http::{block_on, Blocking}, tests::*};
     use std::env;
 
     pub(crate) const TEST_API: &str = "https://api.sandbox.dnsmadeeasy.com/V2.0";
@@ -315,7 +372,7 @@ pub(crate) mod tests {
     fn test_get_domain() -> Result<()> {
         let client = get_client();
 
-        let domain = client.get_domain()?;
+        let domain = block_on(client.get_domain(&Blocking))?;
         assert_eq!("testcondition.net".to_string(), domain.name);
 
         Ok(())
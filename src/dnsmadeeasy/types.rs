@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use crate::RecordType;
+use crate::{parse_rtype, to_rdata, RdataParts, RecordType, UnsupportedRecord, ZoneRecord};
 
 
 /// Representation of a managed domain in DNSMadeEasy.
@@ -51,3 +51,108 @@ pub struct Records<T>
     #[serde(rename = "data")]
     pub records: Vec<Record<T>>
 }
+
+// As `Record`, but for zone listings, which may contain types we
+// don't support (e.g. ANAME, HTTPRED).
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ListRecord {
+    pub name: String,
+    pub value: String,
+    #[serde(rename = "type")]
+    pub rtype: String,
+    pub ttl: u32,
+    pub mx_level: Option<u64>,
+    pub priority: Option<u64>,
+    pub weight: Option<u64>,
+    pub port: Option<u64>,
+    pub issuer_critical: Option<u64>,
+    pub caa_type: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListRecords {
+    #[serde(rename = "data")]
+    pub records: Vec<ListRecord>,
+}
+
+impl ListRecord {
+    pub fn into_zone_record(self) -> Result<ZoneRecord, UnsupportedRecord> {
+        let Some(rtype) = parse_rtype(&self.rtype) else {
+            return Err(UnsupportedRecord::unknown_type(self.rtype, self.name, self.value));
+        };
+        let parts = RdataParts {
+            priority: self.mx_level.or(self.priority),
+            weight: self.weight,
+            port: self.port,
+            flags: self.issuer_critical,
+            tag: self.caa_type.as_deref(),
+        };
+
+        Ok(ZoneRecord {
+            rtype,
+            // Names are already relative, with "" for the apex
+            host: self.name,
+            value: to_rdata(rtype, &self.value, parts),
+            ttl: Some(self.ttl),
+            modified: None,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::check_malformed_replies;
+
+    #[test]
+    fn test_malformed_domain() {
+        check_malformed_replies::<Domain>(r#"{"id": 1119443, "name": "example.com"}"#);
+    }
+
+    #[test]
+    fn test_malformed_records() {
+        check_malformed_replies::<Records<String>>(r#"{
+            "totalRecords": 1,
+            "data": [{
+                "ttl": 1800,
+                "sourceId": 1119443,
+                "name": "ns1",
+                "value": "208.94.148.2",
+                "id": 66813434,
+                "type": "A"
+            }]
+        }"#);
+    }
+
+    #[test]
+    fn test_malformed_list() {
+        check_malformed_replies::<ListRecords>(r#"{
+            "totalRecords": 1,
+            "data": [{
+                "ttl": 1800,
+                "name": "",
+                "value": "mail.example.com.",
+                "mxLevel": 10,
+                "type": "MX"
+            }]
+        }"#);
+    }
+
+    #[test]
+    fn test_into_zone_record() {
+        let recs: ListRecords = serde_json::from_str(r#"{
+            "data": [
+                {"ttl": 1800, "name": "", "value": "mail.example.com.", "mxLevel": 10, "type": "MX"},
+                {"ttl": 1800, "name": "", "value": "example.org.", "type": "ANAME"}
+            ]
+        }"#).unwrap();
+        let recs: Vec<ZoneRecord> = recs.records.into_iter()
+            .filter_map(|r| r.into_zone_record().ok())
+            .collect();
+
+        assert_eq!(1, recs.len());
+        assert_eq!("10 mail.example.com.", recs[0].value);
+    }
+}
@@ -1,7 +1,27 @@
-use std::result;
+use std::{fmt::{self, Display, Formatter}, result};
 use thiserror::Error;
 
 
+/// Whether an interrupted operation may have changed the zone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MutationState {
+    /// No change was sent to the provider.
+    NotApplied,
+    /// A change was sent, but it is unknown whether it was applied.
+    /// The records should be re-read to reconcile.
+    MaybeApplied,
+}
+
+impl Display for MutationState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            MutationState::NotApplied => write!(f, "not applied"),
+            MutationState::MaybeApplied => write!(f, "may have been applied"),
+        }
+    }
+}
+
+
 /// Error type for operations in this crate.
 ///
 /// Represents various failure modes encountered when communicating with
@@ -26,12 +46,54 @@ pub enum Error {
     #[error("Record not found: {0}")]
     RecordNotFound(String),
 
+    #[error("Zone not found: {0}")]
+    ZoneNotFound(String),
+
+    #[error("Ambiguous zone, multiple matches for: {0}")]
+    AmbiguousZone(String),
+
+    #[error("Unsupported operation: {0}")]
+    Unsupported(String),
+
+    #[error("Zone file error: {0}")]
+    ZoneFileError(String),
+
     #[error(transparent)]
     AddrParseError(#[from] std::net::AddrParseError),
 
     #[error(transparent)]
     UreqError(#[from] ureq::Error),
 
+    #[error("Deadline exceeded; change {0}")]
+    DeadlineExceeded(MutationState),
+
+    #[error("Change set {1}: {0}")]
+    ChangeSetFailed(Box<Error>, MutationState),
+
+    #[error("Conflict: {0}; current value is {1:?}")]
+    Conflict(String, Option<String>),
+
+    #[error("Change not visible after {1:?}: {0}")]
+    NotVisible(String, std::time::Duration),
+
+    #[error("Safety policy violation: {0}")]
+    SafetyViolation(String),
+
+    #[error("Record not owned: {0}")]
+    NotOwned(String),
+
+    #[error("DNS error: {0}")]
+    DnsError(String),
+
+    #[error("Change not propagated after {1:?}: {0}")]
+    NotPropagated(String, std::time::Duration),
+
+    #[error("Address discovery failed: {0}")]
+    DiscoveryError(String),
+
+    #[error("Invalid IPv6 prefix: {0}")]
+    PrefixError(String),
+
     #[error("Failed to lock: {0}")]
     LockingError(String),
 
@@ -47,6 +109,10 @@ pub enum Error {
     #[error(transparent)]
     JsonError(#[from] serde_json::Error),
 
+    #[cfg(feature = "instant-acme")]
+    #[error("ACME error: {0}")]
+    AcmeError(#[from] instant_acme::Error),
+
     // #[error(transparent)]
     // RustlsError(#[from] rustls::Error),
 }
@@ -1,12 +1,13 @@
 mod types;
 
-use std::{fmt::Display};
+use std::fmt::Display;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tracing::{error, info, warn};
 
-use types::{Record, RecordUpdate};
+use types::{Record, RecordSet, RecordUpdate, ZoneUpdate};
 use crate::{
-    errors::{Error, Result}, generate_helpers, http::{self, ResponseToOption, WithHeaders}, Config, DnsProvider, RecordType
+    change::{resolve, ChangeSet}, errors::{Error, Result}, http::{http_provider_impl, HttpClient, ResponseToOption, WithHeaders},
+    relative_host, Config, RecordType, ZoneListing
 };
 
 const API_BASE: &str = "https://api.gandi.net/v5/livedns";
@@ -35,31 +36,39 @@ impl Auth {
 /// Holds configuration and authentication for interacting with the Gandi API.
 pub struct Gandi {
     config: Config,
+    endpoint: &'static str,
     auth: Auth,
 }
 
 impl Gandi {
     /// Create a new `Gandi` provider instance.
     pub fn new(config: Config, auth: Auth) -> Self {
+        Self::new_with_endpoint(config, auth, API_BASE)
+    }
+
+    /// Create a new `Gandi` provider with a custom API endpoint.
+    pub fn new_with_endpoint(config: Config, auth: Auth, endpoint: &'static str) -> Self {
         Gandi {
             config,
+            endpoint,
             auth,
         }
     }
 }
 
-impl DnsProvider for Gandi {
+impl Gandi {
 
-    fn get_record<T>(&self, rtype: RecordType, host: &str) -> Result<Option<T>>
+    async fn get_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<Option<T>>
     where
-        T: DeserializeOwned
+        T: DeserializeOwned,
+        C: HttpClient + ?Sized,
     {
 
-        let url = format!("{API_BASE}/domains/{}/records/{host}/{rtype}", self.config.domain);
-        let response = http::client().get(url)
+        let url = format!("{}/domains/{}/records/{host}/{rtype}", self.endpoint, self.config.domain);
+        let response = client.get(url)
             .with_json_headers()
             .with_auth(self.auth.get_header())
-            .call()?
+            .call().await?
             .to_option::<Record<T>>()?;
 
         let mut rec: Record<T> = match response {
@@ -83,19 +92,21 @@ impl DnsProvider for Gandi {
 
     }
 
-    fn create_record<T>(&self, rtype: RecordType, host: &str, rec: &T) -> Result<()>
+    async fn create_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str, rec: &T) -> Result<()>
     where
-        T: Serialize + DeserializeOwned + Display + Clone
+        T: Serialize + DeserializeOwned + Display + Clone,
+        C: HttpClient + ?Sized,
     {
         // PUT works for both operations
-        self.update_record(rtype, host, rec)
+        self.update_record_with(client, rtype, host, rec).await
     }
 
-    fn update_record<T>(&self, rtype: RecordType, host: &str, ip: &T) -> Result<()>
+    async fn update_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str, ip: &T) -> Result<()>
     where
-        T: Serialize + DeserializeOwned + Display + Clone
+        T: Serialize + DeserializeOwned + Display + Clone,
+        C: HttpClient + ?Sized,
     {
-        let url = format!("{API_BASE}/domains/{}/records/{host}/{rtype}", self.config.domain);
+        let url = format!("{}/domains/{}/records/{host}/{rtype}", self.endpoint, self.config.domain);
         if self.config.dry_run {
             info!("DRY-RUN: Would have sent PUT to {url}");
             return Ok(())
@@ -107,42 +118,137 @@ impl DnsProvider for Gandi {
         };
 
         let body = serde_json::to_string(&update)?;
-        let _response = http::client().put(url)
+        let _response = client.put(url)
             .with_json_headers()
             .with_auth(self.auth.get_header())
-            .send(body)?
+            .send(body).await?
             .check_error()?;
 
         Ok(())
     }
 
-     fn delete_record(&self, rtype: RecordType, host: &str) -> Result<()> {
-        let url = format!("{API_BASE}/domains/{}/records/{host}/{rtype}", self.config.domain);
+    async fn delete_record_with<C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let url = format!("{}/domains/{}/records/{host}/{rtype}", self.endpoint, self.config.domain);
 
         if self.config.dry_run {
             info!("DRY-RUN: Would have sent DELETE to {url}");
             return Ok(())
         }
 
-        let _response = http::client().delete(url)
+        let _response = client.delete(url)
             .with_json_headers()
             .with_auth(self.auth.get_header())
-            .call()?
+            .call().await?
             .check_error()?;
 
         Ok(())
     }
 
-    fn delete_all_records(&self, rtype: RecordType, host: &str) -> Result<()>
-    where Self: Sized
+    async fn delete_all_records_with<C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
     {
         // Gandi deletes all members of an RRset by default
-        self.delete_record(rtype, host)
+        self.delete_record_with(client, rtype, host).await
+    }
+
+    async fn list_rrsets<C>(&self, client: &C) -> Result<Vec<Record<String>>>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let url = format!("{}/domains/{}/records", self.endpoint, self.config.domain);
+        client.get(url)
+            .with_json_headers()
+            .with_auth(self.auth.get_header())
+            .call().await?
+            .to_option::<Vec<Record<String>>>()?
+            .ok_or(Error::ZoneNotFound(self.config.domain.clone()))
     }
 
-    generate_helpers!();
+    async fn list_zone_with<C>(&self, client: &C) -> Result<ZoneListing>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let rrsets = self.list_rrsets(client).await?;
+
+        let listing = ZoneListing::collect(rrsets.into_iter()
+            .flat_map(|rrset| rrset.into_zone_records()));
+
+        Ok(listing)
+    }
+
+    async fn apply_changes_with<C>(&self, client: &C, changes: &ChangeSet) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let rrsets = self.list_rrsets(client).await?;
+        // Gandi uses "@" for the apex
+        let matches = |r: &Record<String>, rtype: RecordType, host: &str| {
+            r.rrset_type == rtype.to_string()
+                && relative_host(&r.rrset_name, "").eq_ignore_ascii_case(&relative_host(host, ""))
+        };
+
+        let sets = resolve(&changes.changes, |rtype, host| {
+            rrsets.iter()
+                .find(|r| matches(r, rtype, host))
+                .map(|r| r.rrset_values.clone())
+                .unwrap_or_default()
+        })?;
+        if !sets.iter().any(|set| set.is_changed()) {
+            return Ok(())
+        }
+
+        // The zone is replaced as a whole, so unchanged record sets
+        // (including those of types we don't support) are sent back
+        // as listed.
+        let mut items: Vec<RecordSet> = Vec::with_capacity(rrsets.len());
+        let mut pending = sets;
+        for rrset in rrsets {
+            match pending.iter().position(|set| matches(&rrset, set.rtype, &set.host)) {
+                Some(i) => {
+                    let set = pending.remove(i);
+                    if !set.after.is_empty() {
+                        items.push(RecordSet { rrset_values: set.after, ..RecordSet::from(rrset) });
+                    }
+                }
+                None => items.push(RecordSet::from(rrset)),
+            }
+        }
+        items.extend(pending.into_iter()
+            .filter(|set| !set.after.is_empty())
+            .map(|set| RecordSet {
+                rrset_name: match set.host.as_str() {
+                    "" => "@".to_string(),
+                    host => host.to_string(),
+                },
+                rrset_type: set.rtype.to_string(),
+                rrset_values: set.after,
+                rrset_ttl: Some(300),
+            }));
+
+        let url = format!("{}/domains/{}/records", self.endpoint, self.config.domain);
+        let update = ZoneUpdate { items };
+        if self.config.dry_run {
+            info!("DRY-RUN: Would have sent {update:?} to {url}");
+            return Ok(())
+        }
+
+        let body = serde_json::to_string(&update)?;
+        client.put(url)
+            .with_json_headers()
+            .with_auth(self.auth.get_header())
+            .send(body).await?
+            .check_error()?;
+
+        Ok(())
+    }
 }
 
+http_provider_impl!(Gandi, batch);
+
 #[cfg(test)]
 mod tests {
     use crate::generate_tests;
@@ -165,13 +271,53 @@ mod tests {
             dry_run: false,
         };
 
-        Gandi {
-            config,
-            auth,
-        }
+        Gandi::new(config, auth)
     }
 
 
     generate_tests!("test_gandi");
 
+    #[test]
+    #[cfg(feature = "async")]
+    fn test_apply_changes_zone() -> Result<()> {
+        use crate::async_impl::{http::tests::{json_body, json_ok, serve}, AsyncDnsProvider, Native};
+        use crate::change::Change;
+        use serde_json::json;
+
+        let href = "https://api.test/v5/livedns/domains/example.com/records";
+        let (url, server) = serve(vec![
+            json_ok(&json!([
+                {"rrset_name": "@", "rrset_type": "NS", "rrset_ttl": 10800, "rrset_values": ["ns1.gandi.net."], "rrset_href": href},
+                {"rrset_name": "www", "rrset_type": "A", "rrset_ttl": 600, "rrset_values": ["192.0.2.1"], "rrset_href": href},
+                {"rrset_name": "old", "rrset_type": "TXT", "rrset_ttl": 300, "rrset_values": ["\"a\"", "\"b\""], "rrset_href": href},
+                {"rrset_name": "geo", "rrset_type": "LOC", "rrset_ttl": 300, "rrset_values": ["52 22 23.000 N 4 53 32.000 E -2.00m 0.00m 10000m 10m"], "rrset_href": href},
+            ]).to_string()),
+            json_ok("{}"),
+        ]);
+        let config = Config {
+            domain: "example.com".to_string(),
+            dry_run: false,
+        };
+        let client = Native::wrap(Gandi::new_with_endpoint(config, Auth::PatKey("xyz".to_string()), url.leak()));
+
+        let changes = ChangeSet { changes: vec![
+            Change::Update { rtype: RecordType::A, host: "www".to_string(), value: "192.0.2.2".to_string() },
+            Change::DeleteAll { rtype: RecordType::TXT, host: "old".to_string() },
+            Change::Create { rtype: RecordType::AAAA, host: "".to_string(), value: "2001:db8::1".to_string() },
+        ]};
+        futures::executor::block_on(client.apply_changes(&changes))?;
+
+        // Untouched sets, including unsupported types, are sent back
+        // unchanged; the deleted set is left out.
+        let requests = server.join().unwrap();
+        assert!(requests[1].starts_with("PUT /domains/example.com/records HTTP/1.1\r\n"));
+        assert_eq!(json!({"items": [
+            {"rrset_name": "@", "rrset_type": "NS", "rrset_ttl": 10800, "rrset_values": ["ns1.gandi.net."]},
+            {"rrset_name": "www", "rrset_type": "A", "rrset_ttl": 600, "rrset_values": ["192.0.2.2"]},
+            {"rrset_name": "geo", "rrset_type": "LOC", "rrset_ttl": 300, "rrset_values": ["52 22 23.000 N 4 53 32.000 E -2.00m 0.00m 10000m 10m"]},
+            {"rrset_name": "@", "rrset_type": "AAAA", "rrset_ttl": 300, "rrset_values": ["2001:db8::1"]},
+        ]}), json_body(&requests[1]));
+
+        Ok(())
+    }
 }
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::{parse_rtype, relative_host, UnsupportedRecord, ZoneRecord};
+
 // See https://api.gandi.net/docs/livedns/
 
 // {
@@ -43,6 +45,29 @@ pub struct Record<T>
     pub rrset_ttl: Option<u32>,
 }
 
+impl Record<String> {
+    // Values are already in presentation format.
+    pub fn into_zone_records(self) -> Vec<Result<ZoneRecord, UnsupportedRecord>> {
+        // Gandi uses "@" for the apex
+        let host = relative_host(&self.rrset_name, "");
+        let Some(rtype) = parse_rtype(&self.rrset_type) else {
+            return self.rrset_values.into_iter()
+                .map(|value| Err(UnsupportedRecord::unknown_type(&self.rrset_type, host.clone(), value)))
+                .collect();
+        };
+
+        self.rrset_values.into_iter()
+            .map(|value| Ok(ZoneRecord {
+                rtype,
+                host: host.clone(),
+                value,
+                ttl: self.rrset_ttl,
+                modified: None,
+            }))
+            .collect()
+    }
+}
+
 // {
 //   "rrset_values": [
 //     "www.example.org"
@@ -55,3 +80,77 @@ pub struct RecordUpdate<T> {
     pub rrset_values: Vec<T>,
     pub rrset_ttl: Option<u32>,
 }
+
+// {
+//   "items": [
+//     {
+//       "rrset_name": "@",
+//       "rrset_type": "A",
+//       "rrset_values": ["192.0.2.1"],
+//       "rrset_ttl": 10800
+//     }
+//   ]
+// }
+/// Payload used to replace all the record sets of a zone.
+#[derive(Serialize, Debug)]
+pub struct ZoneUpdate {
+    pub items: Vec<RecordSet>,
+}
+
+/// A record set within a `ZoneUpdate`.
+#[derive(Serialize, Debug)]
+pub struct RecordSet {
+    pub rrset_name: String,
+    pub rrset_type: String,
+    pub rrset_values: Vec<String>,
+    pub rrset_ttl: Option<u32>,
+}
+
+impl From<Record<String>> for RecordSet {
+    fn from(rec: Record<String>) -> Self {
+        Self {
+            rrset_name: rec.rrset_name,
+            rrset_type: rec.rrset_type,
+            rrset_values: rec.rrset_values,
+            rrset_ttl: rec.rrset_ttl,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::check_malformed_replies;
+
+    #[test]
+    fn test_malformed_record() {
+        check_malformed_replies::<Record<String>>(r#"{
+            "rrset_name": "@",
+            "rrset_ttl": 10800,
+            "rrset_type": "A",
+            "rrset_values": ["192.0.2.1"],
+            "rrset_href": "https://api.test/v5/livedns/domains/example.com/records/%40/A"
+        }"#);
+    }
+
+    #[test]
+    fn test_into_zone_records() {
+        let recs: Vec<Record<String>> = serde_json::from_str(r#"[
+            {"rrset_name": "@", "rrset_ttl": 10800, "rrset_type": "MX",
+             "rrset_values": ["10 spool.mail.gandi.net.", "50 fb.mail.gandi.net."],
+             "rrset_href": "https://api.test/v5/livedns/domains/example.com/records/%40/MX"},
+            {"rrset_name": "www", "rrset_ttl": 10800, "rrset_type": "ALIAS",
+             "rrset_values": ["example.org."],
+             "rrset_href": "https://api.test/v5/livedns/domains/example.com/records/www/ALIAS"}
+        ]"#).unwrap();
+        let recs: Vec<ZoneRecord> = recs.into_iter()
+            .flat_map(|r| r.into_zone_records())
+            .filter_map(|r| r.ok())
+            .collect();
+
+        assert_eq!(2, recs.len());
+        assert_eq!("", recs[0].host);
+        assert_eq!("50 fb.mail.gandi.net.", recs[1].value);
+    }
+}
@@ -1,20 +1,116 @@
-use std::str::FromStr;
+use std::{
+    future::{self, Future},
+    pin::pin,
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+};
 
 use serde::{de::DeserializeOwned, Deserialize, Deserializer};
 use tracing::{error, warn};
 use ureq::{
     http::{
         header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
-        HeaderName, HeaderValue, Response, StatusCode
+        request, HeaderName, HeaderValue, Method, Request, Response, StatusCode, Uri
     },
     tls::TlsConfig,
-    Agent, Body, RequestBuilder, ResponseExt
+    Agent
 };
 
 use crate::{errors::{Error, Result}};
 
 
-/// Extension trait for converting ureq HTTP responses to optional
+/// A transport capable of executing a single HTTP request.
+///
+/// Provider operations are written once as `async fn`s against this
+/// trait. The blocking API drives them with the ureq-based
+/// [`Blocking`] client, which completes each request before
+/// returning its future; the async API drives them with a
+/// user-supplied `AsyncHttpClient`.
+///
+/// Requests without a body (e.g. `GET`) have a `None` body. The
+/// response body is fully read before it is returned.
+pub(crate) trait HttpClient: Sync {
+    fn execute(&self, req: Request<Option<String>>) -> impl Future<Output = Result<Response<String>>> + Send;
+
+    fn request(&self, method: Method, url: impl AsRef<str>) -> RequestBuilder<'_, Self> {
+        RequestBuilder {
+            client: self,
+            builder: Request::builder()
+                .method(method)
+                .uri(url.as_ref()),
+        }
+    }
+
+    fn get(&self, url: impl AsRef<str>) -> RequestBuilder<'_, Self> {
+        self.request(Method::GET, url)
+    }
+
+    fn post(&self, url: impl AsRef<str>) -> RequestBuilder<'_, Self> {
+        self.request(Method::POST, url)
+    }
+
+    fn put(&self, url: impl AsRef<str>) -> RequestBuilder<'_, Self> {
+        self.request(Method::PUT, url)
+    }
+
+    fn patch(&self, url: impl AsRef<str>) -> RequestBuilder<'_, Self> {
+        self.request(Method::PATCH, url)
+    }
+
+    fn delete(&self, url: impl AsRef<str>) -> RequestBuilder<'_, Self> {
+        self.request(Method::DELETE, url)
+    }
+}
+
+
+/// The URI a response was fetched from; used for logging.
+#[derive(Clone, Debug)]
+struct RequestUri(Uri);
+
+
+/// A pending request against an `HttpClient`.
+///
+/// This mirrors the subset of the ureq `RequestBuilder` API used by
+/// the providers, but with async `call()` and `send()`.
+pub(crate) struct RequestBuilder<'a, C: ?Sized> {
+    client: &'a C,
+    builder: request::Builder,
+}
+
+impl<C: HttpClient + ?Sized> RequestBuilder<'_, C> {
+
+    /// Add a single header to the request.
+    pub(crate) fn header(mut self, key: impl AsRef<str>, value: impl AsRef<str>) -> Self {
+        self.builder = self.builder.header(key.as_ref(), value.as_ref());
+        self
+    }
+
+    /// Send the request without a body.
+    pub(crate) async fn call(self) -> Result<Response<String>> {
+        self.execute(None).await
+    }
+
+    /// Send the request with the given body.
+    pub(crate) async fn send(self, body: String) -> Result<Response<String>> {
+        self.execute(Some(body)).await
+    }
+
+    async fn execute(self, body: Option<String>) -> Result<Response<String>> {
+        let req = self.builder.body(body)
+            .map_err(|e| Error::HttpError(format!("Invalid request: {e}")))?;
+        let uri = RequestUri(req.uri().clone());
+
+        let mut resp = self.client.execute(req).await?;
+        resp.extensions_mut().insert(uri);
+
+        Ok(resp)
+    }
+}
+
+
+/// Extension trait for converting HTTP responses to optional
 /// values or error information.
 ///
 /// This trait provides methods for handling HTTP responses in a way that:
@@ -39,20 +135,20 @@ pub(crate) trait ResponseToOption: Sized {
     /// - `Ok(Some(T))` if the response status is 200 OK and deserialization succeeds
     /// - `Ok(None)` if the response status is 404 NOT_FOUND
     /// - `Err(Error)` if the response status is not 200 or 404, or if deserialization fails
-    fn to_option<T>(&mut self) -> Result<Option<T>>
+    fn to_option<T>(&self) -> Result<Option<T>>
     where
         T: DeserializeOwned;
 
     /// Checks for HTTP errors in the response.
     ///
-    /// This method provides custom error handling for `ureq` responses. Since the
+    /// This method provides custom error handling for responses. Since the
     /// default `http_status_as_error` feature is disabled to allow for more
     /// granular control (like treating 404 as `None`), this function is used to
     /// explicitly check for other error-level status codes.
     ///
     /// If the response status is successful (2xx), it returns the response as-is.
-    /// Otherwise, it reads the response body, logs the error, and returns an
-    /// `Error::HttpError` with the status code and body content.
+    /// Otherwise, it logs the error body and returns an `Error::HttpError` with
+    /// the status code and body content.
     ///
     /// # Returns
     ///
@@ -64,20 +160,23 @@ pub(crate) trait ResponseToOption: Sized {
 
 
 
-impl ResponseToOption for Response<Body> {
+impl ResponseToOption for Response<String> {
 
-    fn to_option<T>(&mut self) -> Result<Option<T>>
+    fn to_option<T>(&self) -> Result<Option<T>>
     where
         T: DeserializeOwned
     {
-        let body = self.body_mut().read_to_string()?;
+        let body = self.body();
         match self.status() {
             StatusCode::OK => {
-                let obj: T = serde_json::from_str(&body)?;
+                let obj: T = serde_json::from_str(body)?;
                 Ok(Some(obj))
             }
             StatusCode::NOT_FOUND => {
-                warn!("Record doesn't exist: {} -> {body}", self.get_uri());
+                let uri = self.extensions().get::<RequestUri>()
+                    .map(|u| u.0.to_string())
+                    .unwrap_or_default();
+                warn!("Record doesn't exist: {uri} -> {body}");
                 Ok(None)
             }
             _ => {
@@ -86,7 +185,7 @@ impl ResponseToOption for Response<Body> {
         }
     }
 
-    fn check_error(mut self) -> Result<Self> {
+    fn check_error(self) -> Result<Self> {
         // This roughly duplicates the effect of ureq's
         // `http_status_as_error` flag, which we disable to simplify
         // finer-grained status handling above.
@@ -95,8 +194,7 @@ impl ResponseToOption for Response<Body> {
             return Ok(self)
         }
 
-        let err = self.body_mut()
-            .read_to_string()?;
+        let err = self.body();
         error!("REST op failed: {code} {err:?}");
 
         Err(Error::HttpError(format!("REST op failed: {code} {err:?}")))
@@ -105,11 +203,11 @@ impl ResponseToOption for Response<Body> {
 }
 
 
-/// Extension trait for adding headers and authentication to a `ureq` request builder.
+/// Extension trait for adding headers and authentication to a request builder.
 ///
 /// This trait provides convenient methods for adding multiple headers, authentication tokens,
 /// and common JSON headers to a `RequestBuilder`.
-pub(crate) trait WithHeaders<T> {
+pub(crate) trait WithHeaders: Sized {
     /// Adds a collection of headers to the request builder.
     ///
     /// This method takes a vector of key-value pairs and adds them as headers to the request.
@@ -123,7 +221,7 @@ pub(crate) trait WithHeaders<T> {
     ///
     /// Returns a `Result` containing the modified `RequestBuilder` on success, or an `Error`
     /// if header validation fails.
-    fn with_headers(self, headers: Vec<(&str, String)>) -> Result<RequestBuilder<T>>;
+    fn with_headers(self, headers: Vec<(&str, String)>) -> Result<Self>;
 
     /// Adds an `AUTHORIZATION` header to the request builder.
     ///
@@ -137,7 +235,7 @@ pub(crate) trait WithHeaders<T> {
     /// # Returns
     ///
     /// Returns the modified `RequestBuilder`.
-    fn with_auth(self, auth: String) -> RequestBuilder<T>;
+    fn with_auth(self, auth: String) -> Self;
 
     /// Adds `ACCEPT` and `CONTENT_TYPE` headers for JSON content.
     ///
@@ -147,15 +245,15 @@ pub(crate) trait WithHeaders<T> {
     /// # Returns
     ///
     /// Returns the modified `RequestBuilder`.
-    fn with_json_headers(self) -> RequestBuilder<T>;
+    fn with_json_headers(self) -> Self;
 }
 
 /// Implementation of the `WithHeaders` trait for `RequestBuilder`.
-impl<Any> WithHeaders<Any> for RequestBuilder<Any> {
+impl<C: HttpClient + ?Sized> WithHeaders for RequestBuilder<'_, C> {
 
     fn with_headers(mut self, headers: Vec<(&str, String)>) -> Result<Self> {
-        let reqh = self.headers_mut()
-            .ok_or(Error::HttpError("Failed to get headers from ureq".to_string()))?;
+        let reqh = self.builder.headers_mut()
+            .ok_or(Error::HttpError("Failed to get headers from request".to_string()))?;
 
         for (k, v) in headers {
             reqh.insert(HeaderName::from_str(k)?, HeaderValue::from_str(&v)?);
@@ -174,6 +272,35 @@ impl<Any> WithHeaders<Any> for RequestBuilder<Any> {
     }
 }
 
+
+/// The blocking ureq-based client.
+///
+/// The request is performed when `execute()` is called, so the
+/// returned future is always immediately ready.
+pub(crate) struct Blocking;
+
+impl Blocking {
+    pub(crate) fn run(&self, req: Request<Option<String>>) -> Result<Response<String>> {
+        let (parts, body) = req.into_parts();
+        let resp = match body {
+            Some(body) => client().run(Request::from_parts(parts, body))?,
+            None => client().run(Request::from_parts(parts, ()))?,
+        };
+
+        let (parts, mut body) = resp.into_parts();
+        let body = body.read_to_string()?;
+
+        Ok(Response::from_parts(parts, body))
+    }
+}
+
+impl HttpClient for Blocking {
+    fn execute(&self, req: Request<Option<String>>) -> impl Future<Output = Result<Response<String>>> + Send {
+        future::ready(self.run(req))
+    }
+}
+
+
 /// Create and return a configured HTTP ureq agent.
 ///
 /// This function sets up a ureq Agent with specific configuration options
@@ -203,6 +330,251 @@ pub(crate) fn client() -> Agent {
 }
 
 
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Run a future to completion on the current thread.
+///
+/// This is used to drive the provider operations from the blocking
+/// API. With the `Blocking` client the futures never actually
+/// suspend, so this is effectively a single poll.
+pub(crate) fn block_on<F: Future>(fut: F) -> F::Output {
+    let mut fut = pin!(fut);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+        match fut.as_mut().poll(&mut cx) {
+            Poll::Ready(out) => return out,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+
+/// Generate the provider trait implementations from a set of
+/// client-generic operations.
+///
+/// Providers implement their operations once as inherent `async fn`s
+/// taking an `HttpClient`:
+///
+/// - `get_record_with()`
+/// - `create_record_with()`
+/// - `update_record_with()`
+/// - `delete_record_with()`
+/// - `delete_all_records_with()`
+/// - `list_zone_with()`
+///
+/// This macro then implements `DnsProvider` for the provider using
+/// the `Blocking` client, and (with the `async` feature)
+/// `AsyncDnsProvider` for `async_impl::Native<Provider>` using its
+/// `AsyncHttpClient`. Providers that cannot manage all of
+/// `RecordType` pass the types they support as a second argument.
+///
+/// Providers with an atomic batch endpoint also implement
+/// `apply_changes_with()`, and pass `batch` as the second argument
+/// to use it for `apply_changes()`.
+///
+/// Unless the provider can only replace whole record sets, it also
+/// implements `delete_value_with()`, used for `remove_txt_value()`.
+/// Batch providers that do pass `batch, values`.
+macro_rules! http_provider_impl {
+    ($i:ident) => {
+        http_provider_impl!($i, $crate::RecordType::ALL);
+    };
+    ($i:ident, batch) => {
+        http_provider_impl!(@impl $i, $crate::RecordType::ALL, {
+            fn apply_changes(&self, changes: &$crate::change::ChangeSet) -> Result<()> {
+                $crate::http::block_on(self.apply_changes_with(&$crate::http::Blocking, changes))
+            }
+        }, {
+            async fn apply_changes(&self, changes: &$crate::change::ChangeSet) -> Result<()> {
+                self.inner.apply_changes_with(&*self.client, changes).await
+            }
+        });
+    };
+    ($i:ident, batch, values) => {
+        http_provider_impl!(@impl $i, $crate::RecordType::ALL, {
+            fn apply_changes(&self, changes: &$crate::change::ChangeSet) -> Result<()> {
+                $crate::http::block_on(self.apply_changes_with(&$crate::http::Blocking, changes))
+            }
+
+            fn remove_txt_value(&self, host: &str, value: &str) -> Result<()> {
+                $crate::http::block_on(self.delete_value_with(&$crate::http::Blocking, RecordType::TXT, host, value))
+            }
+        }, {
+            async fn apply_changes(&self, changes: &$crate::change::ChangeSet) -> Result<()> {
+                self.inner.apply_changes_with(&*self.client, changes).await
+            }
+
+            async fn remove_txt_value(&self, host: &String, value: &str) -> Result<()> {
+                self.inner.delete_value_with(&*self.client, RecordType::TXT, host, value).await
+            }
+        });
+    };
+    ($i:ident, $types:expr) => {
+        http_provider_impl!(@impl $i, $types, {
+            // Delete just the matching record, rather than rewriting
+            // the set, so concurrent writers are unaffected.
+            fn remove_txt_value(&self, host: &str, value: &str) -> Result<()> {
+                $crate::http::block_on(self.delete_value_with(&$crate::http::Blocking, RecordType::TXT, host, value))
+            }
+        }, {
+            async fn remove_txt_value(&self, host: &String, value: &str) -> Result<()> {
+                self.inner.delete_value_with(&*self.client, RecordType::TXT, host, value).await
+            }
+        });
+    };
+    (@impl $i:ident, $types:expr, { $($sync:tt)* }, { $($async:tt)* }) => {
+        impl $crate::DnsProvider for $i {
+
+            fn get_record<T>(&self, rtype: RecordType, host: &str) -> Result<Option<T>>
+            where
+                T: DeserializeOwned
+            {
+                $crate::http::block_on(self.get_record_with(&$crate::http::Blocking, rtype, host))
+            }
+
+            fn create_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+            where
+                T: Serialize + DeserializeOwned + Display + Clone
+            {
+                $crate::http::block_on(self.create_record_with(&$crate::http::Blocking, rtype, host, record))
+            }
+
+            fn update_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+            where
+                T: Serialize + DeserializeOwned + Display + Clone
+            {
+                $crate::http::block_on(self.update_record_with(&$crate::http::Blocking, rtype, host, record))
+            }
+
+            fn delete_record(&self, rtype: RecordType, host: &str) -> Result<()> {
+                $crate::http::block_on(self.delete_record_with(&$crate::http::Blocking, rtype, host))
+            }
+
+            fn delete_all_records(&self, rtype: RecordType, host: &str) -> Result<()> {
+                $crate::http::block_on(self.delete_all_records_with(&$crate::http::Blocking, rtype, host))
+            }
+
+            fn list_zone(&self) -> Result<$crate::ZoneListing> {
+                $crate::http::block_on(self.list_zone_with(&$crate::http::Blocking))
+            }
+
+            fn record_types(&self) -> &'static [RecordType] {
+                $types
+            }
+
+            $($sync)*
+
+            $crate::generate_helpers!();
+        }
+
+        #[cfg(feature = "async")]
+        #[async_trait::async_trait]
+        impl $crate::async_impl::AsyncDnsProvider for $crate::async_impl::Native<$i> {
+
+            async fn get_record<T>(&self, rtype: RecordType, host: &String) -> Result<Option<T>>
+            where
+                T: DeserializeOwned + Send + Sync + 'static
+            {
+                self.inner.get_record_with(&*self.client, rtype, host).await
+            }
+
+            async fn create_record<T>(&self, rtype: RecordType, host: &String, record: &T) -> Result<()>
+            where
+                T: Serialize + DeserializeOwned + Display + Clone + Send + Sync + 'static
+            {
+                self.inner.create_record_with(&*self.client, rtype, host, record).await
+            }
+
+            async fn update_record<T>(&self, rtype: RecordType, host: &String, record: &T) -> Result<()>
+            where
+                T: Serialize + DeserializeOwned + Display + Clone + Send + Sync + 'static
+            {
+                self.inner.update_record_with(&*self.client, rtype, host, record).await
+            }
+
+            async fn delete_record(&self, rtype: RecordType, host: &String) -> Result<()> {
+                self.inner.delete_record_with(&*self.client, rtype, host).await
+            }
+
+            async fn delete_all_records(&self, rtype: RecordType, host: &String) -> Result<()> {
+                self.inner.delete_all_records_with(&*self.client, rtype, host).await
+            }
+
+            async fn list_zone(&self) -> Result<$crate::ZoneListing> {
+                self.inner.list_zone_with(&*self.client).await
+            }
+
+            fn record_types(&self) -> &'static [RecordType] {
+                $types
+            }
+
+            async fn apply_change(&self, change: &$crate::change::Change) -> Result<()> {
+                $crate::async_impl::bulk::apply_record_change(self, change).await
+            }
+
+            $($async)*
+
+            async fn get_txt_record(&self, host: &String) -> Result<Option<String>> {
+                self.get_record::<String>(RecordType::TXT, host).await
+                    .map(|opt| opt.map(|s| $crate::strip_quotes(&s)))
+            }
+
+            async fn create_txt_record(&self, host: &String, record: &String) -> Result<()> {
+                self.create_record(RecordType::TXT, host, &$crate::ensure_quotes(record)).await
+            }
+
+            async fn update_txt_record(&self, host: &String, record: &String) -> Result<()> {
+                self.update_record(RecordType::TXT, host, &$crate::ensure_quotes(record)).await
+            }
+
+            async fn delete_txt_record(&self, host: &String) -> Result<()> {
+                self.delete_record(RecordType::TXT, host).await
+            }
+
+            async fn get_a_record(&self, host: &String) -> Result<Option<std::net::Ipv4Addr>> {
+                self.get_record(RecordType::A, host).await
+            }
+
+            async fn create_a_record(&self, host: &String, record: &std::net::Ipv4Addr) -> Result<()> {
+                self.create_record(RecordType::A, host, record).await
+            }
+
+            async fn update_a_record(&self, host: &String, record: &std::net::Ipv4Addr) -> Result<()> {
+                self.update_record(RecordType::A, host, record).await
+            }
+
+            async fn delete_a_record(&self, host: &String) -> Result<()> {
+                self.delete_record(RecordType::A, host).await
+            }
+
+            async fn get_aaaa_record(&self, host: &String) -> Result<Option<std::net::Ipv6Addr>> {
+                self.get_record(RecordType::AAAA, host).await
+            }
+
+            async fn create_aaaa_record(&self, host: &String, record: &std::net::Ipv6Addr) -> Result<()> {
+                self.create_record(RecordType::AAAA, host, record).await
+            }
+
+            async fn update_aaaa_record(&self, host: &String, record: &std::net::Ipv6Addr) -> Result<()> {
+                self.update_record(RecordType::AAAA, host, record).await
+            }
+
+            async fn delete_aaaa_record(&self, host: &String) -> Result<()> {
+                self.delete_record(RecordType::AAAA, host).await
+            }
+        }
+    };
+}
+pub(crate) use http_provider_impl;
+
 
 pub(crate) fn de_str<'de, T, D>(destr: D) -> std::result::Result<T, D::Error>
 where
@@ -0,0 +1,523 @@
+//! Mutation hooks and audit journalling.
+//!
+//! Any provider can be wrapped in a [`Journaled`] instance, which
+//! reports every create, update and delete to a [`MutationHook`]
+//! before and after the change is sent upstream. A JSON-lines file
+//! journal is provided in [`JsonLinesJournal`].
+//!
+//! ```no_run
+//! use zone_update::{Config, DnsProvider, porkbun, journal::{Journaled, JsonLinesJournal}};
+//! # fn main() -> zone_update::errors::Result<()> {
+//! let config = Config { domain: "example.com".to_string(), dry_run: false };
+//! let auth = porkbun::Auth { key: "key".to_string(), secret: "secret".to_string() };
+//! let provider = porkbun::Porkbun::new(config, auth);
+//!
+//! let journal = JsonLinesJournal::open("/var/log/dns-changes.jsonl")?;
+//! let client = Journaled::new(provider, "porkbun", "example.com", journal);
+//!
+//! client.update_a_record("www", &"192.0.2.1".parse()?)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::{
+    change::{Change, ChangeSet},
+    errors::{Error, Result},
+    ensure_quotes, generate_helpers, DnsProvider, RecordType, ZoneListing,
+};
+
+
+/// The kind of change being made.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Create,
+    Update,
+    Delete,
+    DeleteAll,
+}
+
+impl Operation {
+    pub(crate) fn of(change: &Change) -> Self {
+        match change {
+            Change::Create { .. } => Operation::Create,
+            Change::Update { .. } => Operation::Update,
+            Change::Delete { .. } => Operation::Delete,
+            Change::DeleteAll { .. } => Operation::DeleteAll,
+        }
+    }
+}
+
+/// Whether an event is emitted before or after the upstream call.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    Before,
+    After,
+}
+
+/// The result of a mutation, as reported to hooks.
+///
+/// `Before` events are always `Pending`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Pending,
+    Success,
+    Failed(String),
+}
+
+/// A single audit event for a DNS change.
+///
+/// The `old` value is read from the provider before the change is
+/// made; it is `None` if the record didn't exist, or if it couldn't be
+/// determined (e.g. the host has more than one record of that type).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MutationEvent {
+    pub timestamp: DateTime<Utc>,
+    pub phase: Phase,
+    pub provider: String,
+    pub zone: String,
+    pub operation: Operation,
+    pub rtype: RecordType,
+    pub host: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+    pub outcome: Outcome,
+}
+
+/// Receiver for mutation events.
+///
+/// An error returned from a `Before` event aborts the operation
+/// before anything is sent upstream; this allows journals that can't
+/// record a change to prevent it. Errors from `After` events are
+/// logged but don't alter the result of the operation, as the change
+/// has already been applied.
+pub trait MutationHook: Send + Sync {
+    fn on_mutation(&self, event: &MutationEvent) -> Result<()>;
+}
+
+impl<F> MutationHook for F
+where
+    F: Fn(&MutationEvent) -> Result<()> + Send + Sync
+{
+    fn on_mutation(&self, event: &MutationEvent) -> Result<()> {
+        self(event)
+    }
+}
+
+
+/// A hook that appends each event as a line of JSON to a file.
+///
+/// Each line is flushed and synced to disk before the hook returns.
+pub struct JsonLinesJournal {
+    file: Mutex<File>,
+}
+
+impl JsonLinesJournal {
+    /// Open the journal file for appending, creating it if necessary.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl MutationHook for JsonLinesJournal {
+    fn on_mutation(&self, event: &MutationEvent) -> Result<()> {
+        let mut line = serde_json::to_string(event)?;
+        line.push('\n');
+
+        let mut file = self.file.lock()
+            .map_err(|e| Error::LockingError(e.to_string()))?;
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+
+        Ok(())
+    }
+}
+
+
+/// Shared event construction and dispatch for the sync and async
+/// journal wrappers.
+pub(crate) struct Journal {
+    provider: String,
+    zone: String,
+    hook: Arc<dyn MutationHook>,
+}
+
+impl Journal {
+    pub(crate) fn new(provider: &str, zone: &str, hook: impl MutationHook + 'static) -> Self {
+        Self {
+            provider: provider.to_string(),
+            zone: zone.to_string(),
+            hook: Arc::new(hook),
+        }
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) fn hook(&self) -> Arc<dyn MutationHook> {
+        self.hook.clone()
+    }
+
+    pub(crate) fn event(&self, operation: Operation, rtype: RecordType, host: &str,
+                        old: Option<String>, new: Option<String>) -> MutationEvent
+    {
+        MutationEvent {
+            timestamp: Utc::now(),
+            phase: Phase::Before,
+            provider: self.provider.clone(),
+            zone: self.zone.clone(),
+            operation,
+            rtype,
+            host: host.to_string(),
+            old,
+            new,
+            outcome: Outcome::Pending,
+        }
+    }
+
+    pub(crate) fn before(&self, operation: Operation, rtype: RecordType, host: &str,
+                         old: Option<String>, new: Option<String>) -> Result<MutationEvent>
+    {
+        let event = self.event(operation, rtype, host, old, new);
+        self.hook.on_mutation(&event)?;
+        Ok(event)
+    }
+
+    pub(crate) fn after(&self, event: MutationEvent, result: &Result<()>) {
+        record_after(&*self.hook, &completed(event, result));
+    }
+}
+
+/// The `After` event for `event`.
+pub(crate) fn completed(mut event: MutationEvent, result: &Result<()>) -> MutationEvent {
+    event.timestamp = Utc::now();
+    event.phase = Phase::After;
+    event.outcome = match result {
+        Ok(()) => Outcome::Success,
+        Err(e) => Outcome::Failed(e.to_string()),
+    };
+    event
+}
+
+/// Report an `After` event; errors are logged only.
+pub(crate) fn record_after(hook: &dyn MutationHook, event: &MutationEvent) {
+    if let Err(e) = hook.on_mutation(event) {
+        error!("Failed to record DNS change for {}: {e}", event.host);
+    }
+}
+
+/// Best-effort lookup of the current value for the journal.
+pub(crate) fn old_value(lookup: Result<Option<String>>, host: &str) -> Option<String> {
+    lookup.unwrap_or_else(|e| {
+        warn!("Unable to fetch previous value of {host} for journal: {e}");
+        None
+    })
+}
+
+
+/// A provider wrapper that reports all changes to a `MutationHook`.
+///
+/// Note that the previous value of a record is fetched from upstream
+/// before each change, so each mutation costs an additional API call.
+pub struct Journaled<P> {
+    inner: P,
+    journal: Journal,
+}
+
+impl<P: DnsProvider> Journaled<P> {
+    /// Wrap `inner`; `provider` and `zone` are recorded in each event.
+    pub fn new(inner: P, provider: &str, zone: &str, hook: impl MutationHook + 'static) -> Self {
+        Self {
+            inner,
+            journal: Journal::new(provider, zone, hook),
+        }
+    }
+
+    /// Return the wrapped provider.
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+
+    fn journaled(&self, operation: Operation, rtype: RecordType, host: &str, new: Option<String>,
+                 op: impl FnOnce(&P) -> Result<()>) -> Result<()>
+    {
+        let old = old_value(self.inner.get_record::<String>(rtype, host), host);
+        let event = self.journal.before(operation, rtype, host, old, new)?;
+        let result = op(&self.inner);
+        self.journal.after(event, &result);
+        result
+    }
+}
+
+impl<P: DnsProvider> DnsProvider for Journaled<P> {
+
+    fn get_record<T>(&self, rtype: RecordType, host: &str) -> Result<Option<T>>
+    where
+        T: DeserializeOwned
+    {
+        self.inner.get_record(rtype, host)
+    }
+
+    fn create_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+    where
+        T: Serialize + DeserializeOwned + Display + Clone
+    {
+        self.journaled(Operation::Create, rtype, host, Some(record.to_string()),
+                       |p| p.create_record(rtype, host, record))
+    }
+
+    fn update_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+    where
+        T: Serialize + DeserializeOwned + Display + Clone
+    {
+        self.journaled(Operation::Update, rtype, host, Some(record.to_string()),
+                       |p| p.update_record(rtype, host, record))
+    }
+
+    fn delete_record(&self, rtype: RecordType, host: &str) -> Result<()> {
+        self.journaled(Operation::Delete, rtype, host, None,
+                       |p| p.delete_record(rtype, host))
+    }
+
+    fn delete_all_records(&self, rtype: RecordType, host: &str) -> Result<()> {
+        self.journaled(Operation::DeleteAll, rtype, host, None,
+                       |p| p.delete_all_records(rtype, host))
+    }
+
+    fn list_zone(&self) -> Result<ZoneListing> {
+        self.inner.list_zone()
+    }
+
+    fn record_types(&self) -> &'static [RecordType] {
+        self.inner.record_types()
+    }
+
+    // Each change is journaled, with the outcome of the set as a
+    // whole.
+    fn apply_changes(&self, changes: &ChangeSet) -> Result<()> {
+        let mut events = Vec::with_capacity(changes.changes.len());
+        for change in &changes.changes {
+            let (rtype, host) = (change.rtype(), change.host());
+            let old = old_value(self.inner.get_record::<String>(rtype, host), host);
+            match self.journal.before(Operation::of(change), rtype, host, old, change.value().map(str::to_string)) {
+                Ok(event) => events.push(event),
+                Err(e) => {
+                    let result = Err(e);
+                    for event in events {
+                        self.journal.after(event, &result);
+                    }
+                    return result;
+                }
+            }
+        }
+        let result = self.inner.apply_changes(changes);
+        for event in events {
+            self.journal.after(event, &result);
+        }
+        result
+    }
+
+    fn check_changes(&self, changes: &[Change]) -> Result<()> {
+        self.inner.check_changes(changes)
+    }
+
+    // Journal the value rather than the whole set, which may have
+    // several values.
+    fn add_txt_value(&self, host: &str, value: &str) -> Result<()> {
+        let event = self.journal.before(Operation::Create, RecordType::TXT, host, None, Some(ensure_quotes(value)))?;
+        let result = self.inner.add_txt_value(host, value);
+        self.journal.after(event, &result);
+        result
+    }
+
+    fn remove_txt_value(&self, host: &str, value: &str) -> Result<()> {
+        let event = self.journal.before(Operation::Delete, RecordType::TXT, host, Some(ensure_quotes(value)), None)?;
+        let result = self.inner.remove_txt_value(host, value);
+        self.journal.after(event, &result);
+        result
+    }
+
+    generate_helpers!();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{Batching, MemoryProvider};
+    use std::net::Ipv4Addr;
+
+    fn collector() -> (Arc<Mutex<Vec<MutationEvent>>>, impl MutationHook + 'static) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let hook = move |ev: &MutationEvent| {
+            sink.lock().unwrap().push(ev.clone());
+            Ok(())
+        };
+        (events, hook)
+    }
+
+    #[test]
+    fn test_events_recorded() -> Result<()> {
+        let (events, hook) = collector();
+        let client = Journaled::new(MemoryProvider::new("example.com"), "memory", "example.com", hook);
+
+        let ip1 = Ipv4Addr::new(10, 0, 0, 1);
+        let ip2 = Ipv4Addr::new(10, 0, 0, 2);
+        client.create_a_record("www", &ip1)?;
+        client.update_a_record("www", &ip2)?;
+        client.delete_a_record("www")?;
+
+        let events = events.lock().unwrap();
+        assert_eq!(6, events.len());
+
+        let ops = events.iter()
+            .map(|e| (e.phase, e.operation))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![
+            (Phase::Before, Operation::Create), (Phase::After, Operation::Create),
+            (Phase::Before, Operation::Update), (Phase::After, Operation::Update),
+            (Phase::Before, Operation::Delete), (Phase::After, Operation::Delete),
+        ], ops);
+
+        assert_eq!(None, events[0].old);
+        assert_eq!(Some("10.0.0.1".to_string()), events[0].new);
+        assert_eq!(Outcome::Pending, events[0].outcome);
+        assert_eq!(Outcome::Success, events[1].outcome);
+
+        assert_eq!(Some("10.0.0.1".to_string()), events[2].old);
+        assert_eq!(Some("10.0.0.2".to_string()), events[2].new);
+
+        assert_eq!(Some("10.0.0.2".to_string()), events[4].old);
+        assert_eq!(None, events[4].new);
+        assert!(events.iter().all(|e| e.provider == "memory" && e.zone == "example.com" && e.host == "www"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_txt_values() -> Result<()> {
+        let (events, hook) = collector();
+        let client = Journaled::new(MemoryProvider::new("example.com"), "memory", "example.com", hook);
+
+        client.add_txt_value("_acme-challenge", "one")?;
+        client.add_txt_value("_acme-challenge", "two")?;
+        client.remove_txt_value("_acme-challenge", "one")?;
+        assert_eq!(vec!["two"], client.get_txt_values("_acme-challenge")?);
+
+        let events = events.lock().unwrap();
+        assert_eq!(6, events.len());
+        assert_eq!((Operation::Create, None, Some("\"two\"".to_string())),
+                   (events[2].operation, events[2].old.clone(), events[2].new.clone()));
+        assert_eq!((Operation::Delete, Some("\"one\"".to_string()), None),
+                   (events[4].operation, events[4].old.clone(), events[4].new.clone()));
+        assert_eq!(Outcome::Success, events[5].outcome);
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_before_hook_aborts() -> Result<()> {
+        let inner = MemoryProvider::new("example.com");
+        let hook = |_: &MutationEvent| Err(Error::ApiError("journal unavailable".to_string()));
+        let client = Journaled::new(inner, "memory", "example.com", hook);
+
+        let res = client.create_a_record("www", &Ipv4Addr::new(10, 0, 0, 1));
+        assert!(res.is_err());
+
+        let client = client.into_inner();
+        assert_eq!(None, client.get_a_record("www")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_failure_outcome() -> Result<()> {
+        let (events, hook) = collector();
+        let inner = MemoryProvider::new("example.com");
+        inner.create_txt_record("multi", "one")?;
+        inner.create_txt_record("multi", "two")?;
+        let client = Journaled::new(inner, "memory", "example.com", hook);
+
+        // Multiple records, so both the old-value lookup and the
+        // update itself fail.
+        let res = client.update_txt_record("multi", "three");
+        assert!(res.is_err());
+
+        let events = events.lock().unwrap();
+        assert_eq!(2, events.len());
+        assert_eq!(None, events[0].old);
+        assert!(matches!(events[1].outcome, Outcome::Failed(_)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_lines_journal() -> Result<()> {
+        let path = std::env::temp_dir()
+            .join(format!("zone-update-journal-{}.jsonl", random_string::generate(8, random_string::charsets::ALPHA_LOWER)));
+        let journal = JsonLinesJournal::open(&path)?;
+        let client = Journaled::new(MemoryProvider::new("example.com"), "memory", "example.com", journal);
+
+        client.create_txt_record("_acme-challenge", "token")?;
+        client.delete_txt_record("_acme-challenge")?;
+        drop(client);
+
+        let contents = std::fs::read_to_string(&path)?;
+        std::fs::remove_file(&path)?;
+
+        let events = contents.lines()
+            .map(serde_json::from_str::<MutationEvent>)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        assert_eq!(4, events.len());
+        assert_eq!(Some("\"token\"".to_string()), events[0].new);
+        assert_eq!(Some("\"token\"".to_string()), events[2].old);
+        assert_eq!(Operation::Delete, events[3].operation);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_changes_batched() -> Result<()> {
+        let (events, hook) = collector();
+        let client = Journaled::new(Batching::new("example.com"), "memory", "example.com", hook);
+
+        let changes: ChangeSet = vec![
+            Change::Create { rtype: RecordType::A, host: "www".to_string(), value: "10.0.0.1".to_string() },
+            Change::Create { rtype: RecordType::TXT, host: "_owner".to_string(), value: "\"managed\"".to_string() },
+        ].into();
+        client.apply_changes(&changes)?;
+
+        let inner = client.into_inner();
+        assert_eq!(vec![changes], *inner.batches.lock().unwrap());
+
+        let events = events.lock().unwrap();
+        let ops = events.iter()
+            .map(|e| (e.phase, e.host.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![
+            (Phase::Before, "www"), (Phase::Before, "_owner"),
+            (Phase::After, "www"), (Phase::After, "_owner"),
+        ], ops);
+        assert!(events[2..].iter().all(|e| e.outcome == Outcome::Success));
+
+        Ok(())
+    }
+}
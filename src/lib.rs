@@ -1,7 +1,21 @@
 #![doc = include_str!("../README.md")]
 
+#[cfg(feature = "acme")]
+pub mod acme;
+pub mod change;
+pub mod ddns;
+pub mod discovery;
 pub mod errors;
 mod http;
+pub mod journal;
+pub mod migrate;
+pub mod ownership;
+pub mod plan;
+pub mod prefix;
+pub mod propagation;
+pub mod safety;
+pub mod verify;
+pub mod zonefile;
 
 #[cfg(feature = "async")]
 pub mod async_impl;
@@ -25,12 +39,16 @@ pub mod linode;
 #[cfg(feature = "porkbun")]
 pub mod porkbun;
 
-use std::{fmt::{self, Debug, Display, Formatter}, net::Ipv4Addr};
+use std::{fmt::{self, Debug, Display, Formatter}, net::{IpAddr, Ipv4Addr, Ipv6Addr}};
 
+use chrono::{DateTime, Utc};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use tracing::warn;
+use tracing::{debug, warn};
 
-use crate::errors::Result;
+use crate::{
+    change::{Change, ChangeSet},
+    errors::{Error, Result},
+};
 
 
 /// Configuration for DNS operations.
@@ -74,65 +92,90 @@ pub enum Provider {
     PorkBun(porkbun::Auth),
 }
 
-impl Provider {
-
-    /// Return a blocking (synchronous) implementation of the selected provider.
-    ///
-    /// The returned boxed trait object implements `DnsProvider`.
-    pub fn blocking_impl(&self, dns_conf: Config) -> Box<dyn DnsProvider> {
-        match self {
+/// Construct the selected provider as `$p` and evaluate `$wrap`
+/// with it.
+///
+/// This is the only place the provider variants are mapped to their
+/// implementations; the blocking and async constructors below are
+/// generated from it. It can also be used where a concrete provider
+/// type is needed, e.g. for the generic `DnsProvider` methods.
+macro_rules! with_provider {
+    ($provider:expr, $conf:expr, |$p:ident| $wrap:expr) => {
+        match $provider {
             #[cfg(feature = "bunny")]
-            Provider::Bunny(auth) => Box::new(bunny::Bunny::new(dns_conf, auth.clone())),
+            $crate::Provider::Bunny(auth) => { let $p = $crate::bunny::Bunny::new($conf, auth.clone()); $wrap }
             #[cfg(feature = "cloudflare")]
-            Provider::Cloudflare(auth) => Box::new(cloudflare::Cloudflare::new(dns_conf, auth.clone())),
+            $crate::Provider::Cloudflare(auth) => { let $p = $crate::cloudflare::Cloudflare::new($conf, auth.clone()); $wrap }
             #[cfg(feature = "desec")]
-            Provider::DeSec(auth) => Box::new(desec::DeSec::new(dns_conf, auth.clone())),
+            $crate::Provider::DeSec(auth) => { let $p = $crate::desec::DeSec::new($conf, auth.clone()); $wrap }
             #[cfg(feature = "digitalocean")]
-            Provider::DigitalOcean(auth) => Box::new(digitalocean::DigitalOcean::new(dns_conf, auth.clone())),
+            $crate::Provider::DigitalOcean(auth) => { let $p = $crate::digitalocean::DigitalOcean::new($conf, auth.clone()); $wrap }
             #[cfg(feature = "gandi")]
-            Provider::Gandi(auth) => Box::new(gandi::Gandi::new(dns_conf, auth.clone())),
+            $crate::Provider::Gandi(auth) => { let $p = $crate::gandi::Gandi::new($conf, auth.clone()); $wrap }
             #[cfg(feature = "dnsimple")]
-            Provider::Dnsimple(auth) => Box::new(dnsimple::Dnsimple::new(dns_conf, auth.clone(), None)),
+            $crate::Provider::Dnsimple(auth) => { let $p = $crate::dnsimple::Dnsimple::new($conf, auth.clone(), None); $wrap }
             #[cfg(feature = "dnsmadeeasy")]
-            Provider::DnsMadeEasy(auth) => Box::new(dnsmadeeasy::DnsMadeEasy::new(dns_conf, auth.clone())),
+            $crate::Provider::DnsMadeEasy(auth) => { let $p = $crate::dnsmadeeasy::DnsMadeEasy::new($conf, auth.clone()); $wrap }
             #[cfg(feature = "porkbun")]
-            Provider::PorkBun(auth) => Box::new(porkbun::Porkbun::new(dns_conf, auth.clone())),
+            $crate::Provider::PorkBun(auth) => { let $p = $crate::porkbun::Porkbun::new($conf, auth.clone()); $wrap }
             #[cfg(feature = "linode")]
-            Provider::Linode(auth) => Box::new(linode::Linode::new(dns_conf, auth.clone())),
+            $crate::Provider::Linode(auth) => { let $p = $crate::linode::Linode::new($conf, auth.clone()); $wrap }
         }
+    };
+}
+pub(crate) use with_provider;
+
+impl Provider {
+
+    /// Return a blocking (synchronous) implementation of the selected provider.
+    ///
+    /// The returned boxed trait object implements `DnsProvider`.
+    pub fn blocking_impl(&self, dns_conf: Config) -> Box<dyn DnsProvider> {
+        with_provider!(self, dns_conf, |p| Box::new(p))
+    }
+
+    /// Return a blocking implementation of the selected provider that
+    /// enforces `policy`.
+    ///
+    /// See `safety::Guarded`.
+    pub fn guarded_impl(&self, dns_conf: Config, policy: safety::SafetyPolicy) -> Box<dyn DnsProvider> {
+        let domain = dns_conf.domain.clone();
+        with_provider!(self, dns_conf, |p| Box::new(safety::Guarded::new(p, &domain, policy)))
     }
 
     /// Return an async implementation of the selected provider.
     ///
-    /// The returned boxed trait object implements `async_impl::AsyncDnsProvider`.
+    /// The returned boxed trait object implements
+    /// `async_impl::AsyncDnsProvider`, using the native async
+    /// implementation of the provider.
     #[cfg(feature = "async")]
     pub fn async_impl(&self, dns_conf: Config) -> Box<dyn async_impl::AsyncDnsProvider> {
-        match self {
-            #[cfg(feature = "bunny")]
-            Provider::Bunny(auth) => Box::new(async_impl::bunny::Bunny::new(dns_conf, auth.clone())),
-            #[cfg(feature = "cloudflare")]
-            Provider::Cloudflare(auth) => Box::new(async_impl::cloudflare::Cloudflare::new(dns_conf, auth.clone())),
-            #[cfg(feature = "desec")]
-            Provider::DeSec(auth) => Box::new(async_impl::desec::DeSec::new(dns_conf, auth.clone())),
-            #[cfg(feature = "digitalocean")]
-            Provider::DigitalOcean(auth) => Box::new(async_impl::digitalocean::DigitalOcean::new(dns_conf, auth.clone())),
-            #[cfg(feature = "gandi")]
-            Provider::Gandi(auth) => Box::new(async_impl::gandi::Gandi::new(dns_conf, auth.clone())),
-            #[cfg(feature = "dnsimple")]
-            Provider::Dnsimple(auth) => Box::new(async_impl::dnsimple::Dnsimple::new(dns_conf, auth.clone(), None)),
-            #[cfg(feature = "dnsmadeeasy")]
-            Provider::DnsMadeEasy(auth) => Box::new(async_impl::dnsmadeeasy::DnsMadeEasy::new(dns_conf, auth.clone())),
-            #[cfg(feature = "porkbun")]
-            Provider::PorkBun(auth) => Box::new(async_impl::porkbun::Porkbun::new(dns_conf, auth.clone())),
-            #[cfg(feature = "linode")]
-            Provider::Linode(auth) => Box::new(async_impl::linode::Linode::new(dns_conf, auth.clone())),
-        }
+        with_provider!(self, dns_conf, |p| Box::new(async_impl::Native::wrap(p)))
+    }
+
+    /// Return an async implementation of the selected provider that
+    /// runs the blocking implementation on a thread pool.
+    ///
+    /// See `async_impl::Unblocked`.
+    #[cfg(feature = "async")]
+    pub fn unblocked_impl(&self, dns_conf: Config) -> Box<dyn async_impl::AsyncDnsProvider> {
+        with_provider!(self, dns_conf, |p| Box::new(async_impl::Unblocked::new(p)))
+    }
+
+    /// Return a native async implementation of the selected provider
+    /// that enforces `policy`.
+    ///
+    /// See `async_impl::safety::Guarded`.
+    #[cfg(feature = "async")]
+    pub fn guarded_async_impl(&self, dns_conf: Config, policy: safety::SafetyPolicy) -> Box<dyn async_impl::AsyncDnsProvider> {
+        let domain = dns_conf.domain.clone();
+        with_provider!(self, dns_conf, |p| Box::new(async_impl::safety::Guarded::new(async_impl::Native::wrap(p), &domain, policy)))
     }
 }
 
 
 
-#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
 #[non_exhaustive]
 pub enum RecordType {
     A,
@@ -148,19 +191,118 @@ pub enum RecordType {
     HTTPS,
 }
 
+impl RecordType {
+    /// All supported record types.
+    pub const ALL: &'static [RecordType] = &[
+        RecordType::A,
+        RecordType::AAAA,
+        RecordType::CAA,
+        RecordType::CNAME,
+        RecordType::MX,
+        RecordType::NS,
+        RecordType::PTR,
+        RecordType::SRV,
+        RecordType::TXT,
+        RecordType::SVCB,
+        RecordType::HTTPS,
+    ];
+}
+
 impl Display for RecordType {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "{:?}", self)
     }
 }
 
+/// A record as listed from a provider's zone.
+///
+/// This is provider-independent; `value` is the record data in
+/// zone-file presentation format, e.g. `10 mail.example.com` for MX
+/// records. Fields the provider stores separately (MX priority, SRV
+/// weight and port, etc.) are included in it. TXT values are always
+/// quoted.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ZoneRecord {
+    pub rtype: RecordType,
+    /// The name relative to the zone; empty for the apex.
+    pub host: String,
+    pub value: String,
+    /// The TTL in seconds, if the provider reports one.
+    pub ttl: Option<u32>,
+    /// When the record was last changed, if the provider reports it
+    /// (currently Cloudflare, deSEC, DNSimple and Linode). deSEC
+    /// reports when the whole record set was last changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<DateTime<Utc>>,
+}
+
+/// A listed record that cannot be fully represented as a
+/// `ZoneRecord`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct UnsupportedRecord {
+    /// The provider's name for the record type.
+    pub rtype: String,
+    /// The name relative to the zone; empty for the apex.
+    pub host: String,
+    pub value: String,
+    /// Why the record cannot be represented.
+    pub reason: String,
+}
+
+impl UnsupportedRecord {
+    /// A record with a type not covered by `RecordType`.
+    pub(crate) fn unknown_type(rtype: impl Into<String>, host: String, value: String) -> Self {
+        Self {
+            rtype: rtype.into(),
+            host,
+            value,
+            reason: "record type is not supported".to_string(),
+        }
+    }
+}
+
+/// The contents of a zone, as listed from a provider.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ZoneListing {
+    pub records: Vec<ZoneRecord>,
+    /// Records that are not in `records`, as their types are not
+    /// covered by `RecordType` (e.g. provider-specific redirects).
+    /// SOA records are managed by the provider and are omitted.
+    pub skipped: Vec<UnsupportedRecord>,
+    /// Records that are in `records`, but which use
+    /// provider-specific settings that are not represented there
+    /// (e.g. Cloudflare proxying).
+    pub lossy: Vec<UnsupportedRecord>,
+}
+
+impl ZoneListing {
+    /// Collect the converted records of a provider listing.
+    pub(crate) fn collect<I>(listed: I) -> Self
+    where
+        I: IntoIterator<Item = std::result::Result<ZoneRecord, UnsupportedRecord>>
+    {
+        let mut listing = ZoneListing::default();
+        for rec in listed {
+            match rec {
+                Ok(rec) => listing.records.push(rec),
+                Err(rec) if rec.rtype.eq_ignore_ascii_case("SOA") => {}
+                Err(rec) => {
+                    debug!("Skipping unsupported {} record for {}", rec.rtype, rec.host);
+                    listing.skipped.push(rec);
+                }
+            }
+        }
+        listing
+    }
+}
+
 /// A trait for a DNS provider.
 ///
 /// This trait defines the basic operations that a DNS provider must support.
 ///
 /// The trait provides methods for creating, reading, updating, and
 /// deleting DNS records. It also provides default implementations for
-/// TXT and A records.
+/// TXT, A and AAAA records.
 pub trait DnsProvider {
     /// Get a DNS record by host and record type.
     fn get_record<T>(&self, rtype: RecordType, host: &str) -> Result<Option<T>>
@@ -183,6 +325,84 @@ pub trait DnsProvider {
     /// Delete all DNS records matching host and record type.
     fn delete_all_records(&self, rtype: RecordType, host: &str) -> Result<()>;
 
+    /// Update a DNS record only if its current value is `expected`.
+    ///
+    /// Otherwise returns `Error::Conflict` with the current value.
+    /// Values are compared as by `verify::Verified`, so e.g. quoting
+    /// and address formatting are ignored.
+    ///
+    /// None of the supported provider APIs offer conditional writes,
+    /// so by default the record is re-read and compared before the
+    /// update. This narrows, but does not close, the window for
+    /// concurrent writers. Providers with native preconditions may
+    /// override this.
+    fn update_record_if<T>(&self, rtype: RecordType, host: &str, expected: &T, record: &T) -> Result<()>
+    where T: Serialize + DeserializeOwned + Display + Clone,
+          Self: Sized
+    {
+        check_current(rtype, host, &expected.to_string(), self.get_record::<String>(rtype, host)?)?;
+        self.update_record(rtype, host, record)
+    }
+
+    /// Delete a DNS record only if its current value is `expected`.
+    /// See `update_record_if()`.
+    fn delete_record_if<T>(&self, rtype: RecordType, host: &str, expected: &T) -> Result<()>
+    where T: Serialize + DeserializeOwned + Display + Clone,
+          Self: Sized
+    {
+        check_current(rtype, host, &expected.to_string(), self.get_record::<String>(rtype, host)?)?;
+        self.delete_record(rtype, host)
+    }
+
+    /// List all records in the zone, including those that cannot be
+    /// represented as a `ZoneRecord`.
+    ///
+    /// The default implementation returns `Error::Unsupported`.
+    fn list_zone(&self) -> Result<ZoneListing> {
+        Err(Error::Unsupported("listing records".to_string()))
+    }
+
+    /// List all records in the zone.
+    ///
+    /// Records of types not covered by `RecordType` (e.g. SOA, or
+    /// provider-specific types) are skipped; see `list_zone()`.
+    fn list_records(&self) -> Result<Vec<ZoneRecord>> {
+        self.list_zone()
+            .map(|zone| zone.records)
+    }
+
+    /// The record types this provider can manage. Defaults to all of
+    /// `RecordType`.
+    fn record_types(&self) -> &'static [RecordType] {
+        RecordType::ALL
+    }
+
+    /// Apply a set of changes as a unit.
+    ///
+    /// Providers with an atomic batch endpoint (currently deSEC,
+    /// Gandi and Cloudflare) apply all of the changes or none of
+    /// them. Otherwise the changes are applied in
+    /// order, with best-effort rollback on failure; see
+    /// `change::apply_sequential()`.
+    fn apply_changes(&self, changes: &ChangeSet) -> Result<()> {
+        change::apply_sequential(self, changes)
+    }
+
+    /// Check a set of changes against any policy enforced by the
+    /// provider, without applying them. See `safety::Guarded`.
+    ///
+    /// Callers such as `plan::Plan::apply()` use this to refuse the
+    /// whole set before anything is changed. The default accepts all changes; wrappers
+    /// forward to the wrapped provider.
+    fn check_changes(&self, _changes: &[Change]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Apply a single change.
+    ///
+    /// This is a helper method that calls the matching record method.
+    fn apply_change(&self, change: &Change) -> Result<()>;
+
 
     /// Get a TXT record.
     ///
@@ -204,6 +424,52 @@ pub trait DnsProvider {
     /// This is a helper method that calls `delete_record` with the `TXT` record type.
     fn delete_txt_record(&self, host: &str) -> Result<()>;
 
+    /// Get all values of the TXT record set at `host`, without
+    /// quotes. Unlike `get_txt_record()` this doesn't fail if there is
+    /// more than one value, but may need to list the zone.
+    fn get_txt_values(&self, host: &str) -> Result<Vec<String>> {
+        match self.get_txt_record(host) {
+            Ok(value) => Ok(value.into_iter().collect()),
+            Err(Error::UnexpectedRecord(_)) => {
+                let values = verify::set_values(self.list_records()?, RecordType::TXT, host);
+                Ok(values.iter().map(|v| strip_quotes(v)).collect())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Add a value to the TXT record set at `host`, leaving any other
+    /// values in place. Adding a value that is already present is a
+    /// no-op.
+    ///
+    /// Unlike `create_txt_record()` this is safe with several values
+    /// on one name on all providers, e.g. concurrent DNS-01
+    /// challenges for `example.com` and `*.example.com`.
+    fn add_txt_value(&self, host: &str, value: &str) -> Result<()> {
+        if has_txt_value(&self.get_txt_values(host)?, value) {
+            return Ok(())
+        }
+        self.apply_changes(&vec![Change::Create {
+            rtype: RecordType::TXT,
+            host: host.to_string(),
+            value: ensure_quotes(value),
+        }].into())
+    }
+
+    /// Remove a value from the TXT record set at `host`, leaving any
+    /// other values in place. Removing a missing value is a no-op.
+    ///
+    /// Most providers, including Cloudflare, delete just the
+    /// matching record. deSEC and Gandi can only replace whole record
+    /// sets, so write back the set without the value; a value added
+    /// concurrently by another client may be lost.
+    fn remove_txt_value(&self, host: &str, value: &str) -> Result<()> {
+        match txt_removal(host, value, self.get_txt_values(host)?) {
+            Some(changes) => self.apply_changes(&changes),
+            None => Ok(()),
+        }
+    }
+
     /// Get an A record.
     ///
     /// This is a helper method that calls `get_record` with the `A` record type.
@@ -223,6 +489,44 @@ pub trait DnsProvider {
     ///
     /// This is a helper method that calls `delete_record` with the `A` record type.
     fn delete_a_record(&self, host: &str) -> Result<()>;
+
+    /// Get an AAAA record.
+    ///
+    /// This is a helper method that calls `get_record` with the `AAAA` record type.
+    fn get_aaaa_record(&self, host: &str) -> Result<Option<Ipv6Addr>>;
+
+    /// Create a new AAAA record.
+    ///
+    /// This is a helper method that calls `create_record` with the `AAAA` record type.
+    fn create_aaaa_record(&self, host: &str, record: &Ipv6Addr) -> Result<()>;
+
+    /// Update an AAAA record.
+    ///
+    /// This is a helper method that calls `update_record` with the `AAAA` record type.
+    fn update_aaaa_record(&self, host: &str, record: &Ipv6Addr) -> Result<()>;
+
+    /// Delete an AAAA record.
+    ///
+    /// This is a helper method that calls `delete_record` with the `AAAA` record type.
+    fn delete_aaaa_record(&self, host: &str) -> Result<()>;
+
+    /// Set the A and AAAA records of a dual-stack host.
+    ///
+    /// Each family is created, updated or deleted as needed to match
+    /// `v4` and `v6`; `None` removes the record. Families that are
+    /// already correct are left alone, and the remaining changes are
+    /// applied together with `apply_changes()`.
+    fn set_host_addresses(&self, host: &str, v4: Option<Ipv4Addr>, v6: Option<Ipv6Addr>) -> Result<()> {
+        let current = [
+            self.get_a_record(host)?.map(IpAddr::V4),
+            self.get_aaaa_record(host)?.map(IpAddr::V6),
+        ];
+        let changes = address_changes(host, current, [v4.map(IpAddr::V4), v6.map(IpAddr::V6)]);
+        if changes.is_empty() {
+            return Ok(());
+        }
+        self.apply_changes(&changes)
+    }
 }
 
 /// A macro to generate default helper implementations for provider impls.
@@ -270,6 +574,26 @@ macro_rules! generate_helpers {
         fn delete_a_record(&self, host: &str) -> Result<()> {
             self.delete_record(RecordType::A, host)
         }
+
+        fn get_aaaa_record(&self, host: &str) -> Result<Option<std::net::Ipv6Addr>> {
+            self.get_record(RecordType::AAAA, host)
+        }
+
+        fn create_aaaa_record(&self, host: &str, record: &std::net::Ipv6Addr) -> Result<()> {
+            self.create_record(RecordType::AAAA, host, record)
+        }
+
+        fn update_aaaa_record(&self, host: &str, record: &std::net::Ipv6Addr) -> Result<()> {
+            self.update_record(RecordType::AAAA, host, record)
+        }
+
+        fn delete_aaaa_record(&self, host: &str) -> Result<()> {
+            self.delete_record(RecordType::AAAA, host)
+        }
+
+        fn apply_change(&self, change: &$crate::change::Change) -> Result<()> {
+            change.apply(self)
+        }
     }
 }
 
@@ -303,6 +627,135 @@ fn strip_quotes(record: &str) -> String {
     }
 }
 
+/// Select the zone whose name exactly matches the configured domain.
+///
+/// Provider zone lookups are frequently search or prefix queries, so
+/// the upstream list may contain unrelated zones (or none at
+/// all). Names are compared case-insensitively, ignoring any trailing
+/// root dot.
+pub(crate) fn find_zone<Z>(zones: Vec<Z>, domain: &str, name: impl Fn(&Z) -> &str) -> Result<Z> {
+    let domain = domain.trim_end_matches('.');
+    let mut matches = zones.into_iter()
+        .filter(|z| name(z).trim_end_matches('.').eq_ignore_ascii_case(domain))
+        .collect::<Vec<Z>>();
+
+    match matches.len() {
+        0 => Err(Error::ZoneNotFound(domain.to_string())),
+        1 => Ok(matches.remove(0)),
+        n => {
+            warn!("Found {n} zones matching {domain}");
+            Err(Error::AmbiguousZone(domain.to_string()))
+        }
+    }
+}
+
+/// Check the current value of a record for a conditional change.
+pub(crate) fn check_current(rtype: RecordType, host: &str, expected: &str, current: Option<String>) -> Result<()> {
+    match current {
+        Some(cur) if verify::same_value(rtype, expected, &cur) => Ok(()),
+        current => {
+            warn!("Conditional change to {rtype} {host} failed; expected {expected}, found {current:?}");
+            Err(Error::Conflict(format!("{rtype} {host} is not {expected}"), current))
+        }
+    }
+}
+
+pub(crate) fn has_txt_value(current: &[String], value: &str) -> bool {
+    current.iter().any(|v| verify::same_value(RecordType::TXT, v, value))
+}
+
+/// The changes that remove `value` from a TXT record set with the
+/// `current` values, or `None` if it isn't present.
+pub(crate) fn txt_removal(host: &str, value: &str, current: Vec<String>) -> Option<ChangeSet> {
+    if !has_txt_value(&current, value) {
+        return None;
+    }
+    let delete = Change::DeleteAll { rtype: RecordType::TXT, host: host.to_string() };
+    let keep = current.iter()
+        .filter(|v| !verify::same_value(RecordType::TXT, v, value))
+        .map(|v| Change::Create { rtype: RecordType::TXT, host: host.to_string(), value: ensure_quotes(v) });
+    Some(std::iter::once(delete).chain(keep).collect())
+}
+
+/// The changes that bring the A and AAAA records of `host` from
+/// `current` to `target`, both given as `[v4, v6]`.
+pub(crate) fn address_changes(host: &str, current: [Option<IpAddr>; 2], target: [Option<IpAddr>; 2]) -> ChangeSet {
+    [RecordType::A, RecordType::AAAA].into_iter()
+        .zip(current.into_iter().zip(target))
+        .filter_map(|(rtype, (cur, new))| {
+            let host = host.to_string();
+            match (cur, new) {
+                (None, Some(addr)) => Some(Change::Create { rtype, host, value: addr.to_string() }),
+                (Some(cur), Some(addr)) if cur != addr => Some(Change::Update { rtype, host, value: addr.to_string() }),
+                (Some(_), None) => Some(Change::Delete { rtype, host }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Convert a record name as returned by a provider to one relative
+/// to the zone. Providers variously return the FQDN, `@`, or an
+/// empty string for the apex.
+pub(crate) fn relative_host(name: &str, domain: &str) -> String {
+    let name = name.trim_end_matches('.');
+    let domain = domain.trim_end_matches('.');
+
+    if name == "@" || name.eq_ignore_ascii_case(domain) {
+        return String::new();
+    }
+    let split = name.len().checked_sub(domain.len() + 1);
+    match split {
+        Some(i) if name.is_char_boundary(i)
+            && name[i..].starts_with('.')
+            && name[i + 1..].eq_ignore_ascii_case(domain) => name[..i].to_string(),
+        _ => name.to_string(),
+    }
+}
+
+/// Parse a provider's record type name, returning `None` for types
+/// not covered by `RecordType`.
+pub(crate) fn parse_rtype(name: &str) -> Option<RecordType> {
+    serde_json::from_value(serde_json::Value::String(name.to_string())).ok()
+}
+
+/// Record fields that some providers store separately from the
+/// main value.
+#[derive(Default)]
+pub(crate) struct RdataParts<'a> {
+    pub priority: Option<u64>,
+    pub weight: Option<u64>,
+    pub port: Option<u64>,
+    pub flags: Option<u64>,
+    pub tag: Option<&'a str>,
+}
+
+/// Build presentation-format record data from a provider value and
+/// any separately stored fields. Fields are only added where the
+/// type uses them.
+pub(crate) fn to_rdata(rtype: RecordType, value: &str, parts: RdataParts) -> String {
+    match (rtype, parts) {
+        (RecordType::MX, RdataParts { priority: Some(p), .. }) =>
+            format!("{p} {value}"),
+        (RecordType::SRV, RdataParts { priority: Some(p), weight: Some(w), port: Some(port), .. }) =>
+            format!("{p} {w} {port} {value}"),
+        // Some providers only store the priority separately
+        (RecordType::SRV, RdataParts { priority: Some(p), .. }) =>
+            format!("{p} {value}"),
+        (RecordType::CAA, RdataParts { tag: Some(tag), flags, .. }) if !tag.is_empty() =>
+            format!("{} {tag} {}", flags.unwrap_or(0), quote_string(value)),
+        (RecordType::TXT, _) if !value.starts_with('"') =>
+            quote_string(value),
+        _ => value.to_string(),
+    }
+}
+
+/// Quote a string value, escaping embedded quotes and backslashes.
+pub(crate) fn quote_string(value: &str) -> String {
+    let escaped = value.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("\"{escaped}\"")
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -366,6 +819,366 @@ mod tests {
         assert_eq!(ensure_quotes(&"123!@#$%^&*()".to_string()), "\"123!@#$%^&*()\"");
     }
 
+    #[test]
+    fn test_find_zone_exact() {
+        let zones = vec!["sub.example.com", "example.com", "example.com.au"];
+        let zone = find_zone(zones, "example.com", |z| z).unwrap();
+        assert_eq!("example.com", zone);
+    }
+
+    #[test]
+    fn test_find_zone_normalised() {
+        let zones = vec!["Example.COM."];
+        let zone = find_zone(zones, "example.com", |z| z).unwrap();
+        assert_eq!("Example.COM.", zone);
+    }
+
+    #[test]
+    fn test_find_zone_missing() {
+        let zones = vec!["example.net", "myexample.com"];
+        let err = find_zone(zones, "example.com", |z| z).unwrap_err();
+        assert!(matches!(err, Error::ZoneNotFound(_)));
+
+        let err = find_zone(Vec::<&str>::new(), "example.com", |z| z).unwrap_err();
+        assert!(matches!(err, Error::ZoneNotFound(_)));
+    }
+
+    #[test]
+    fn test_find_zone_ambiguous() {
+        let zones = vec!["example.com", "example.com."];
+        let err = find_zone(zones, "example.com", |z| z).unwrap_err();
+        assert!(matches!(err, Error::AmbiguousZone(_)));
+    }
+
+    #[test]
+    fn test_relative_host() {
+        assert_eq!("", relative_host("example.com", "example.com"));
+        assert_eq!("", relative_host("example.com.", "example.com"));
+        assert_eq!("", relative_host("@", "example.com"));
+        assert_eq!("", relative_host("", "example.com"));
+        assert_eq!("www", relative_host("www.Example.com", "example.com"));
+        assert_eq!("www", relative_host("www", "example.com"));
+        assert_eq!("a.b", relative_host("a.b.example.com.", "example.com"));
+        assert_eq!("myexample.com", relative_host("myexample.com", "example.com"));
+    }
+
+    #[test]
+    fn test_conditional_changes() -> Result<()> {
+        let client = MemoryProvider::new("example.com");
+        let (ip1, ip2, ip3) = (Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2), Ipv4Addr::new(10, 0, 0, 3));
+        client.create_a_record("www", &ip1)?;
+
+        client.update_record_if(RecordType::A, "www", &ip1, &ip2)?;
+        assert_eq!(Some(ip2), client.get_a_record("www")?);
+
+        // Another agent got there first
+        let err = client.update_record_if(RecordType::A, "www", &ip1, &ip3).unwrap_err();
+        let Error::Conflict(_, actual) = err else {
+            panic!("Unexpected error {err}");
+        };
+        assert_eq!(Some("10.0.0.2".to_string()), actual);
+        assert!(matches!(client.delete_record_if(RecordType::A, "www", &ip1), Err(Error::Conflict(_, _))));
+        assert_eq!(Some(ip2), client.get_a_record("www")?);
+
+        client.delete_record_if(RecordType::A, "www", &ip2)?;
+        assert!(matches!(client.update_record_if(RecordType::A, "www", &ip2, &ip3), Err(Error::Conflict(_, None))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_txt_value_changes() -> Result<()> {
+        test_txt_values(MemoryProvider::new("example.com"))?;
+
+        let client = MemoryProvider::new("example.com");
+
+        // Values from a listing are unquoted and compared as such
+        client.create_txt_record("_acme", "one")?;
+        client.add_txt_value("_acme", "two")?;
+        client.add_txt_value("_acme", "\"one\"")?;
+        assert_eq!(vec!["one", "two"], client.get_txt_values("_acme")?);
+        client.remove_txt_value("_acme", "three")?;
+        client.remove_txt_value("_acme", "one")?;
+        assert_eq!(Some("two".to_string()), client.get_txt_record("_acme")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_txt_removal() {
+        let values = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(None, txt_removal("_acme", "abc", values(&["def"])));
+
+        let changes = txt_removal("_acme", "abc", values(&["\"abc\"", "def"])).unwrap();
+        assert_eq!(vec![
+            Change::DeleteAll { rtype: RecordType::TXT, host: "_acme".to_string() },
+            Change::Create { rtype: RecordType::TXT, host: "_acme".to_string(), value: "\"def\"".to_string() },
+        ], changes.changes);
+    }
+
+    #[test]
+    fn test_set_host_addresses() -> Result<()> {
+        let client = MemoryProvider::new("example.com");
+        let (v4a, v4b) = (Ipv4Addr::new(192, 0, 2, 1), Ipv4Addr::new(192, 0, 2, 2));
+        let v6: Ipv6Addr = "2001:db8::1".parse()?;
+
+        client.set_host_addresses("www", Some(v4a), Some(v6))?;
+        assert_eq!(Some(v4a), client.get_a_record("www")?);
+        assert_eq!(Some(v6), client.get_aaaa_record("www")?);
+
+        client.set_host_addresses("www", Some(v4b), None)?;
+        assert_eq!(Some(v4b), client.get_a_record("www")?);
+        assert_eq!(None, client.get_aaaa_record("www")?);
+
+        client.set_host_addresses("www", None, None)?;
+        assert!(client.records.lock().unwrap().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_address_changes() {
+        let (v4, v6): (IpAddr, IpAddr) = ("192.0.2.1".parse().unwrap(), "2001:db8::1".parse().unwrap());
+        assert!(address_changes("www", [Some(v4), Some(v6)], [Some(v4), Some(v6)]).is_empty());
+
+        let changes = address_changes("www", [Some(v4), None], [Some("192.0.2.2".parse().unwrap()), Some(v6)]);
+        assert_eq!(vec![
+            Change::Update { rtype: RecordType::A, host: "www".to_string(), value: "192.0.2.2".to_string() },
+            Change::Create { rtype: RecordType::AAAA, host: "www".to_string(), value: "2001:db8::1".to_string() },
+        ], changes.changes);
+
+        let changes = address_changes("www", [Some(v4), Some(v6)], [Some(v4), None]);
+        assert_eq!(vec![Change::Delete { rtype: RecordType::AAAA, host: "www".to_string() }], changes.changes);
+    }
+
+    #[test]
+    fn test_to_rdata() {
+        let mx = RdataParts { priority: Some(10), ..Default::default() };
+        assert_eq!("10 mail.example.com", to_rdata(RecordType::MX, "mail.example.com", mx));
+        let srv = RdataParts { priority: Some(1), weight: Some(5), port: Some(443), ..Default::default() };
+        assert_eq!("1 5 443 host.example.com", to_rdata(RecordType::SRV, "host.example.com", srv));
+        let srv = RdataParts { priority: Some(1), ..Default::default() };
+        assert_eq!("1 5 443 host.example.com", to_rdata(RecordType::SRV, "5 443 host.example.com", srv));
+        let caa = RdataParts { tag: Some("issue"), ..Default::default() };
+        assert_eq!("0 issue \"letsencrypt.org\"", to_rdata(RecordType::CAA, "letsencrypt.org", caa));
+        // Priority is ignored for types that don't use it
+        let prio = RdataParts { priority: Some(10), ..Default::default() };
+        assert_eq!("10.0.0.1", to_rdata(RecordType::A, "10.0.0.1", prio));
+
+        assert_eq!("\"a \\\"b\\\" c\"", to_rdata(RecordType::TXT, "a \"b\" c", RdataParts::default()));
+        assert_eq!("\"quoted\"", to_rdata(RecordType::TXT, "\"quoted\"", RdataParts::default()));
+    }
+
+    /// Check that a deserialiser survives malformed upstream replies.
+    ///
+    /// Starting from a known-good payload, every truncation and every
+    /// single-byte substitution from a set of JSON-significant bytes
+    /// is fed to `serde_json`. The result is discarded; the property
+    /// being tested is that parsing returns rather than panics.
+    pub(crate) fn check_malformed_replies<T>(sample: &str)
+    where
+        T: DeserializeOwned
+    {
+        const SUBSTITUTES: &[u8] = b"\"{}[],:0-9.enatfu\\ \xff";
+
+        let bytes = sample.as_bytes();
+        assert!(serde_json::from_slice::<T>(bytes).is_ok(), "Sample payload should parse");
+
+        for i in 0..bytes.len() {
+            let _ = serde_json::from_slice::<T>(&bytes[..i]);
+
+            let mut mutated = bytes.to_vec();
+            for b in SUBSTITUTES {
+                mutated[i] = *b;
+                let _ = serde_json::from_slice::<T>(&mutated);
+            }
+        }
+
+        // Wrong top-level shapes
+        for junk in ["", "null", "[]", "{}", "0", "\"\"", "[{}]", "{\"data\": null}"] {
+            let _ = serde_json::from_str::<T>(junk);
+        }
+    }
+
+    /// An in-memory provider for testing the provider-agnostic
+    /// layers without network access.
+    ///
+    /// Semantics follow the majority of the upstream providers;
+    /// e.g. `get_record` fails if a host has more than one record of
+    /// the requested type, and updating a missing record is a no-op.
+    pub(crate) struct MemoryProvider {
+        pub(crate) config: Config,
+        pub(crate) records: std::sync::Mutex<Vec<(RecordType, String, String)>>,
+        pub(crate) types: &'static [RecordType],
+    }
+
+    impl MemoryProvider {
+        pub(crate) fn new(domain: &str) -> Self {
+            Self {
+                config: Config {
+                    domain: domain.to_string(),
+                    dry_run: false,
+                },
+                records: std::sync::Mutex::new(Vec::new()),
+                types: RecordType::ALL,
+            }
+        }
+
+        fn matching(&self, rtype: RecordType, host: &str) -> Vec<String> {
+            self.records.lock().unwrap().iter()
+                .filter(|(t, h, _)| *t == rtype && h == host)
+                .map(|(_, _, v)| v.clone())
+                .collect()
+        }
+    }
+
+    impl DnsProvider for MemoryProvider {
+        fn get_record<T>(&self, rtype: RecordType, host: &str) -> Result<Option<T>>
+        where
+            T: DeserializeOwned
+        {
+            let mut recs = self.matching(rtype, host);
+            if recs.len() > 1 {
+                return Err(Error::UnexpectedRecord(format!("Returned number of records is {}, should be 1", recs.len())));
+            }
+            recs.pop()
+                .map(|v| serde_json::from_value(serde_json::Value::String(v)))
+                .transpose()
+                .map_err(Error::from)
+        }
+
+        fn create_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+        where
+            T: Serialize + DeserializeOwned + Display + Clone
+        {
+            if !self.types.contains(&rtype) {
+                return Err(Error::Unsupported(format!("{rtype} records")));
+            }
+            if self.config.dry_run {
+                return Ok(())
+            }
+            self.records.lock().unwrap()
+                .push((rtype, host.to_string(), record.to_string()));
+            Ok(())
+        }
+
+        fn update_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+        where
+            T: Serialize + DeserializeOwned + Display + Clone
+        {
+            let nr = self.matching(rtype, host).len();
+            if nr > 1 {
+                return Err(Error::UnexpectedRecord(format!("Returned number of records is {nr}, should be 1")));
+            }
+            if self.config.dry_run {
+                return Ok(())
+            }
+            let mut recs = self.records.lock().unwrap();
+            if let Some(rec) = recs.iter_mut().find(|(t, h, _)| *t == rtype && h == host) {
+                rec.2 = record.to_string();
+            }
+            Ok(())
+        }
+
+        fn delete_record(&self, rtype: RecordType, host: &str) -> Result<()> {
+            let nr = self.matching(rtype, host).len();
+            if nr > 1 {
+                return Err(Error::UnexpectedRecord(format!("Returned number of records is {nr}, should be 1")));
+            }
+            self.delete_all_records(rtype, host)
+        }
+
+        fn delete_all_records(&self, rtype: RecordType, host: &str) -> Result<()> {
+            if self.config.dry_run {
+                return Ok(())
+            }
+            self.records.lock().unwrap()
+                .retain(|(t, h, _)| !(*t == rtype && h == host));
+            Ok(())
+        }
+
+        fn list_zone(&self) -> Result<ZoneListing> {
+            let recs = self.records.lock().unwrap().iter()
+                .map(|(rtype, host, value)| ZoneRecord {
+                    rtype: *rtype,
+                    host: host.clone(),
+                    value: to_rdata(*rtype, value, RdataParts::default()),
+                    ttl: None,
+                    modified: None,
+                })
+                .collect();
+            Ok(ZoneListing { records: recs, ..Default::default() })
+        }
+
+        fn record_types(&self) -> &'static [RecordType] {
+            self.types
+        }
+
+        generate_helpers!();
+    }
+
+    /// Memory provider with a batch endpoint that records each change
+    /// set submitted to it.
+    pub(crate) struct Batching {
+        pub(crate) inner: MemoryProvider,
+        pub(crate) batches: std::sync::Mutex<Vec<ChangeSet>>,
+    }
+
+    impl Batching {
+        pub(crate) fn new(domain: &str) -> Self {
+            Self {
+                inner: MemoryProvider::new(domain),
+                batches: std::sync::Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    impl DnsProvider for Batching {
+        fn get_record<T>(&self, rtype: RecordType, host: &str) -> Result<Option<T>>
+        where
+            T: DeserializeOwned
+        {
+            self.inner.get_record(rtype, host)
+        }
+
+        fn create_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+        where
+            T: Serialize + DeserializeOwned + Display + Clone
+        {
+            self.inner.create_record(rtype, host, record)
+        }
+
+        fn update_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+        where
+            T: Serialize + DeserializeOwned + Display + Clone
+        {
+            self.inner.update_record(rtype, host, record)
+        }
+
+        fn delete_record(&self, rtype: RecordType, host: &str) -> Result<()> {
+            self.inner.delete_record(rtype, host)
+        }
+
+        fn delete_all_records(&self, rtype: RecordType, host: &str) -> Result<()> {
+            self.inner.delete_all_records(rtype, host)
+        }
+
+        fn list_zone(&self) -> Result<ZoneListing> {
+            self.inner.list_zone()
+        }
+
+        fn apply_changes(&self, changes: &ChangeSet) -> Result<()> {
+            self.batches.lock().unwrap().push(changes.clone());
+            change::apply_sequential(&self.inner, changes)
+        }
+
+        generate_helpers!();
+    }
+
+    /// Wrap an API test client so that changes are visible before
+    /// they are checked; some provider APIs are eventually consistent.
+    pub(crate) fn verified<P: DnsProvider>(client: P) -> verify::Verified<P> {
+        verify::Verified::new(client, verify::VerifyOptions::default())
+    }
+
     pub(crate) fn test_create_update_delete_ipv4(client: impl DnsProvider) -> Result<()> {
 
         let host = random_string::generate(16, ALPHA_LOWER);
@@ -468,13 +1281,52 @@ mod tests {
         Ok(())
     }
 
+    pub(crate) fn test_txt_values(client: impl DnsProvider) -> Result<()> {
+
+        let host = random_string::generate(16, ALPHA_LOWER);
+
+        // Two challenges on the same name
+        client.add_txt_value(&host, "first challenge")?;
+        client.add_txt_value(&host, "second challenge")?;
+        client.add_txt_value(&host, "first challenge")?;
+        let mut values = client.get_txt_values(&host)?;
+        values.sort();
+        assert_eq!(vec!["first challenge", "second challenge"], values);
+
+        client.remove_txt_value(&host, "first challenge")?;
+        assert_eq!(vec!["second challenge"], client.get_txt_values(&host)?);
+
+        client.remove_txt_value(&host, "second challenge")?;
+        assert!(client.get_txt_values(&host)?.is_empty());
+
+        Ok(())
+    }
+
+    pub(crate) fn test_list_records(client: impl DnsProvider) -> Result<()> {
+
+        let host = random_string::generate(16, ALPHA_LOWER);
+
+        let ip: Ipv4Addr = "10.9.8.7".parse()?;
+        client.create_a_record(&host, &ip)?;
+
+        let recs = client.list_records()?;
+        let found = recs.iter()
+            .any(|r| r.rtype == RecordType::A && r.host == host && r.value == "10.9.8.7");
+
+        client.delete_a_record(&host)?;
+        assert!(found, "Created record not found in zone listing");
+
+        Ok(())
+    }
+
     /// A macro to generate a standard set of tests for a DNS provider.
     ///
-    /// This macro generates four tests:
+    /// This macro generates five tests:
     /// - `create_update_v4`: tests creating, updating, and deleting an A record.
     /// - `create_update_txt`: tests creating, updating, and deleting a TXT record.
     /// - `create_update_default`: tests creating, updating, and deleting a TXT record using the default provider methods.
     /// - `delete_all_records`: tests deleting all TXT records for a host.
+    /// - `list_records`: tests that a created record appears in the zone listing.
     ///
     /// The tests are conditionally compiled based on the feature flag passed as an argument.
     ///
@@ -510,7 +1362,7 @@ mod tests {
             #[serial]
             #[cfg_attr(not(feature = $feat), ignore = "API test")]
             fn create_update_v4() -> Result<()> {
-                test_create_update_delete_ipv4(get_client())?;
+                test_create_update_delete_ipv4(verified(get_client()))?;
                 Ok(())
             }
 
@@ -518,7 +1370,7 @@ mod tests {
             #[serial]
             #[cfg_attr(not(feature = $feat), ignore = "API test")]
             fn create_update_txt() -> Result<()> {
-                test_create_update_delete_txt(get_client())?;
+                test_create_update_delete_txt(verified(get_client()))?;
                 Ok(())
             }
 
@@ -526,7 +1378,7 @@ mod tests {
             #[serial]
             #[cfg_attr(not(feature = $feat), ignore = "API test")]
             fn create_update_default() -> Result<()> {
-                test_create_update_delete_txt_default(get_client())?;
+                test_create_update_delete_txt_default(verified(get_client()))?;
                 Ok(())
             }
 
@@ -534,7 +1386,23 @@ mod tests {
             #[serial]
             #[cfg_attr(not(feature = $feat), ignore = "API test")]
             fn delete_all_records() -> Result<()> {
-                test_delete_all_records(get_client())?;
+                test_delete_all_records(verified(get_client()))?;
+                Ok(())
+            }
+
+            #[test_log::test]
+            #[serial]
+            #[cfg_attr(not(feature = $feat), ignore = "API test")]
+            fn txt_values() -> Result<()> {
+                test_txt_values(verified(get_client()))?;
+                Ok(())
+            }
+
+            #[test_log::test]
+            #[serial]
+            #[cfg_attr(not(feature = $feat), ignore = "API test")]
+            fn list_records() -> Result<()> {
+                test_list_records(verified(get_client()))?;
                 Ok(())
             }
         }
@@ -6,10 +6,11 @@ use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tracing::{error, info, warn};
 
 use crate::{
-    errors::{Error, Result}, generate_helpers, http::{self, ResponseToOption, WithHeaders}, linode::types::{CreateUpdate, Domain, List, Record}, Config, DnsProvider, RecordType
+    errors::{Error, Result}, find_zone, http::{http_provider_impl, HttpClient, ResponseToOption, WithHeaders}, linode::types::{CreateUpdate, Domain, List, ListRecord, Record}, verify::same_value, Config, RecordType, ZoneListing
 };
 
 const API_BASE: &str = "https://api.linode.com/v4/domains";
+const LIST_PAGE_SIZE: usize = 500;
 
 /// Authentication credentials for the Linode API.
 ///
@@ -45,57 +46,57 @@ impl Linode {
         }
     }
 
-    fn get_domain(&self) -> Result<Domain> {
-        let list = http::client().get(API_BASE)
+    async fn get_domain<C>(&self, client: &C) -> Result<Domain>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let list = client.get(API_BASE)
             .with_auth(self.auth.get_header())
             .with_json_headers()
-            .call()?
+            .call().await?
             .to_option::<List<Domain>>()?
-            .ok_or(Error::ApiError("No domains returned from upstream".to_string()))?;
+            .ok_or(Error::ZoneNotFound(self.config.domain.clone()))?;
 
-        let domain = list.data.into_iter()
-            .find(|d| d.domain == self.config.domain)
-            .ok_or(Error::RecordNotFound(self.config.domain.clone()))?;
-
-        Ok(domain)
+        find_zone(list.data, &self.config.domain, |d| &d.domain)
     }
 
-    fn get_domain_id(&self) -> Result<u64> {
-        // This is roughly equivalent to OnceLock.get_or_init(), but
-        // is simpler than dealing with closure->Result and is more
-        // portable.
-        let mut id_p = self.domain_id.lock()
-            .map_err(|e| Error::LockingError(e.to_string()))?;
-
-        if let Some(id) = *id_p {
+    async fn get_domain_id<C>(&self, client: &C) -> Result<u64>
+    where
+        C: HttpClient + ?Sized,
+    {
+        // Check the cache first; the lock is not held across the
+        // upstream lookup, so concurrent callers may both fetch it.
+        if let Some(id) = *self.domain_id.lock().map_err(|e| Error::LockingError(e.to_string()))? {
             return Ok(id);
         }
 
-        let id = self.get_domain()?.id;
-        *id_p = Some(id);
+        let id = self.get_domain(client).await?.id;
+        *self.domain_id.lock().map_err(|e| Error::LockingError(e.to_string()))? = Some(id);
 
         Ok(id)
     }
 
-    fn get_upstream_records<T>(&self, rtype: &RecordType, host: &str) -> Result<Vec<Record<T>>>
-        where T: DeserializeOwned
+    async fn get_upstream_records<T, C>(&self, client: &C, rtype: &RecordType, host: &str) -> Result<Vec<Record<T>>>
+    where
+        T: DeserializeOwned,
+        C: HttpClient + ?Sized,
     {
-        let did = self.get_domain_id()?;
+        let did = self.get_domain_id(client).await?;
         let url = format!("{API_BASE}/{did}/records");
 
-        let mut response = http::client().get(url)
+        let response = client.get(url)
             .with_auth(self.auth.get_header())
             .with_json_headers()
-            .call()?;
+            .call().await?;
 
         // Linode returns *all* records, with no ability to filter by
         // type, resulting in a mixed-type array. To work around this
         // we filter on the raw json values before deserialising
         // properly.
-        let body = response.body_mut().read_to_string()?;
+        let body = response.body();
         let srtype = rtype.to_string();
 
-        let values: serde_json::Value = serde_json::from_str(&body)?;
+        let values: serde_json::Value = serde_json::from_str(body)?;
         let data = values["data"].as_array()
             .ok_or(Error::ApiError("Data field not found".to_string()))?;
         let records = data.iter()
@@ -110,10 +111,12 @@ impl Linode {
         Ok(records)
     }
 
-    fn get_upstream_record<T>(&self, rtype: &RecordType, host: &str) -> Result<Option<Record<T>>>
-        where T: DeserializeOwned
+    async fn get_upstream_record<T, C>(&self, client: &C, rtype: &RecordType, host: &str) -> Result<Option<Record<T>>>
+    where
+        T: DeserializeOwned,
+        C: HttpClient + ?Sized,
     {
-        let mut recs = self.get_upstream_records(rtype, host)?;
+        let mut recs = self.get_upstream_records(client, rtype, host).await?;
 
         // FIXME: Assumes no or single address (which probably makes
         // sense for DDNS and DNS-01, but may cause issues with
@@ -130,8 +133,11 @@ impl Linode {
         Ok(Some(recs.remove(0)))
     }
 
-    fn do_delete(&self, rec: Record<String>) -> Result<()> {
-        let did = self.get_domain_id()?;
+    async fn do_delete<C>(&self, client: &C, rec: Record<String>) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let did = self.get_domain_id(client).await?;
         let url = format!("{API_BASE}/{did}/records/{}", rec.id);
         if self.config.dry_run {
             info!("DRY-RUN: Would have sent DELETE to {url}");
@@ -139,23 +145,24 @@ impl Linode {
         }
 
         info!("Deleting DNS {} record {}", rec.rtype, rec.name);
-        http::client().delete(url)
+        client.delete(url)
             .with_auth(self.auth.get_header())
             .with_json_headers()
-            .call()?;
+            .call().await?;
 
         Ok(())
     }
 }
 
 
-impl DnsProvider for Linode {
+impl Linode {
 
-    fn get_record<T>(&self, rtype: RecordType, host: &str) -> Result<Option<T>>
+    async fn get_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<Option<T>>
     where
-        T: DeserializeOwned
+        T: DeserializeOwned,
+        C: HttpClient + ?Sized,
     {
-         let rec = match self.get_upstream_record(&rtype, host)? {
+         let rec = match self.get_upstream_record(client, &rtype, host).await? {
             Some(rec) => rec,
             None => return Ok(None)
         };
@@ -163,11 +170,12 @@ impl DnsProvider for Linode {
         Ok(Some(rec.target))
     }
 
-    fn create_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+    async fn create_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str, record: &T) -> Result<()>
     where
-        T: Serialize + DeserializeOwned + Display + Clone
+        T: Serialize + DeserializeOwned + Display + Clone,
+        C: HttpClient + ?Sized,
     {
-        let did = self.get_domain_id()?;
+        let did = self.get_domain_id(client).await?;
         let url = format!("{API_BASE}/{did}/records");
 
         let create = CreateUpdate {
@@ -182,21 +190,22 @@ impl DnsProvider for Linode {
         }
 
         let body = serde_json::to_string(&create)?;
-        let _response = http::client().post(url)
+        let _response = client.post(url)
             .with_auth(self.auth.get_header())
             .with_json_headers()
-            .send(body)?
+            .send(body).await?
             .check_error()?;
 
         Ok(())
     }
 
-    fn update_record<T>(&self, rtype: RecordType, host: &str, urec: &T) -> Result<()>
+    async fn update_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str, urec: &T) -> Result<()>
     where
-        T: Serialize + DeserializeOwned + Display + Clone
+        T: Serialize + DeserializeOwned + Display + Clone,
+        C: HttpClient + ?Sized,
     {
-        let did = self.get_domain_id()?;
-        let rec: Record<T> = self.get_upstream_record(&rtype, host)?
+        let did = self.get_domain_id(client).await?;
+        let rec: Record<T> = self.get_upstream_record(client, &rtype, host).await?
             .ok_or(Error::RecordNotFound(host.to_string()))?;
         let url = format!("{API_BASE}/{did}/records/{}", rec.id);
 
@@ -213,18 +222,20 @@ impl DnsProvider for Linode {
         }
 
         let body = serde_json::to_string(&update)?;
-        let _response = http::client().put(url)
+        let _response = client.put(url)
             .with_auth(self.auth.get_header())
             .with_json_headers()
-            .send(body)?
+            .send(body).await?
             .check_error()?;
 
         Ok(())
     }
 
-    fn delete_record(&self, rtype: RecordType, host: &str) -> Result<()>
+    async fn delete_record_with<C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
     {
-        let rec = match self.get_upstream_record(&rtype, host)? {
+        let rec = match self.get_upstream_record(client, &rtype, host).await? {
             Some(rec) => rec,
             None => {
                 warn!("No {rtype} record to delete for {host}");
@@ -232,23 +243,77 @@ impl DnsProvider for Linode {
             }
         };
 
-        self.do_delete(rec)
+        self.do_delete(client, rec).await
     }
 
-    fn delete_all_records(&self, rtype: RecordType, host: &str) -> Result<()>
+    async fn delete_all_records_with<C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
     {
-        let recs: Vec<Record<String>> = self.get_upstream_records(&rtype, host)?;
+        let recs: Vec<Record<String>> = self.get_upstream_records(client, &rtype, host).await?;
         for rec in recs {
-            self.do_delete(rec)?;
+            self.do_delete(client, rec).await?;
         }
 
         Ok(())
     }
 
-    generate_helpers!();
+    async fn delete_value_with<C>(&self, client: &C, rtype: RecordType, host: &str, value: &str) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let recs: Vec<Record<String>> = self.get_upstream_records(client, &rtype, host).await?;
+        for rec in recs.into_iter().filter(|rec| same_value(rtype, &rec.target, value)) {
+            self.do_delete(client, rec).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn list_zone_with<C>(&self, client: &C) -> Result<ZoneListing>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let did = self.get_domain_id(client).await?;
+
+        let mut listed = Vec::new();
+        for page in 1.. {
+            let url = format!("{API_BASE}/{did}/records?page={page}&page_size={LIST_PAGE_SIZE}");
+            let recs = client.get(url)
+                .with_auth(self.auth.get_header())
+                .with_json_headers()
+                .call().await?
+                .to_option::<List<ListRecord>>()?
+                .ok_or(Error::ZoneNotFound(self.config.domain.clone()))?
+                .data;
+
+            let last = recs.len() < LIST_PAGE_SIZE;
+            listed.extend(recs.into_iter()
+                          .map(|rec| rec.into_zone_record()));
+            if last {
+                break;
+            }
+        }
+
+        Ok(ZoneListing::collect(listed))
+    }
 
 }
 
+// Linode has no SVCB or HTTPS records.
+const RECORD_TYPES: &[RecordType] = &[
+    RecordType::A,
+    RecordType::AAAA,
+    RecordType::CAA,
+    RecordType::CNAME,
+    RecordType::MX,
+    RecordType::NS,
+    RecordType::PTR,
+    RecordType::SRV,
+    RecordType::TXT,
+];
+
+http_provider_impl!(Linode, RECORD_TYPES);
 
 #[cfg(test)]
 pub(crate) mod tests {
@@ -1,7 +1,8 @@
 
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 
-use crate::RecordType;
+use crate::{parse_rtype, to_rdata, RdataParts, RecordType, UnsupportedRecord, ZoneRecord};
 
 #[derive(Deserialize, Debug, Clone)]
 pub(crate) struct List<T> {
@@ -80,6 +81,49 @@ pub(crate) struct Record<T> {
 //   "updated": "2018-01-01T00:01:01",
 //   "weight": 50
 // }
+// As `Record`, but for zone listings, which may contain types we
+// don't support.
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct ListRecord {
+    pub(crate) name: String,
+    pub(crate) target: String,
+    #[serde(rename = "type")]
+    pub(crate) rtype: String,
+    pub(crate) ttl_sec: u32,
+    pub(crate) priority: Option<u64>,
+    pub(crate) weight: Option<u64>,
+    pub(crate) port: Option<u64>,
+    pub(crate) tag: Option<String>,
+    // UTC, without a timezone
+    #[serde(default)]
+    pub(crate) updated: Option<NaiveDateTime>,
+}
+
+impl ListRecord {
+    pub(crate) fn into_zone_record(self) -> Result<ZoneRecord, UnsupportedRecord> {
+        let Some(rtype) = parse_rtype(&self.rtype) else {
+            return Err(UnsupportedRecord::unknown_type(self.rtype, self.name, self.target));
+        };
+        let parts = RdataParts {
+            priority: self.priority,
+            weight: self.weight,
+            port: self.port,
+            tag: self.tag.as_deref(),
+            ..Default::default()
+        };
+
+        Ok(ZoneRecord {
+            rtype,
+            // Names are already relative, with "" for the apex
+            host: self.name,
+            value: to_rdata(rtype, &self.target, parts),
+            // A TTL of 0 is the zone default
+            ttl: (self.ttl_sec != 0).then_some(self.ttl_sec),
+            modified: self.updated.map(|t| t.and_utc()),
+        })
+    }
+}
+
 #[derive(Serialize, Debug, Clone)]
 pub(crate) struct CreateUpdate<T> {
     pub(crate) name: String,
@@ -88,3 +132,67 @@ pub(crate) struct CreateUpdate<T> {
     #[serde(rename = "type")]
     pub(crate) rtype: RecordType,
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::check_malformed_replies;
+
+    #[test]
+    fn test_malformed_domains() {
+        check_malformed_replies::<List<Domain>>(r#"{
+            "data": [{"domain": "example.org", "id": 1234, "type": "master"}],
+            "page": 1
+        }"#);
+    }
+
+    #[test]
+    fn test_malformed_records() {
+        check_malformed_replies::<List<Record<String>>>(r#"{
+            "data": [{
+                "created": "2018-01-01T00:01:01",
+                "id": 123456,
+                "name": "test",
+                "target": "192.0.2.0",
+                "ttl_sec": 604800,
+                "type": "A"
+            }]
+        }"#);
+    }
+
+    #[test]
+    fn test_malformed_list() {
+        check_malformed_replies::<List<ListRecord>>(r#"{
+            "data": [{
+                "id": 123456,
+                "name": "",
+                "target": "mail.example.com",
+                "priority": 10,
+                "ttl_sec": 0,
+                "type": "MX"
+            }]
+        }"#);
+    }
+
+    #[test]
+    fn test_into_zone_record() {
+        let recs: List<ListRecord> = serde_json::from_str(r#"{
+            "data": [
+                {"name": "", "target": "mail.example.com", "priority": 10, "ttl_sec": 0, "type": "MX"},
+                {"name": "", "target": "letsencrypt.org", "tag": "issue", "ttl_sec": 300, "type": "CAA",
+                 "updated": "2018-01-01T00:01:01"}
+            ]
+        }"#).unwrap();
+        let recs: Vec<ZoneRecord> = recs.data.into_iter()
+            .filter_map(|r| r.into_zone_record().ok())
+            .collect();
+
+        assert_eq!("10 mail.example.com", recs[0].value);
+        assert_eq!(None, recs[0].ttl);
+        assert_eq!("0 issue \"letsencrypt.org\"", recs[1].value);
+        assert_eq!(Some(300), recs[1].ttl);
+        assert_eq!(None, recs[0].modified);
+        assert_eq!("2018-01-01T00:01:01+00:00", recs[1].modified.unwrap().to_rfc3339());
+    }
+}
@@ -0,0 +1,340 @@
+//! Cross-provider zone migration.
+//!
+//! Copy the records of a zone from one provider to another, using
+//! only the `Provider` configuration of each side:
+//!
+//! ```no_run
+//! # use zone_update::{migrate::{self, MigrateOptions}, Provider};
+//! # fn main() -> zone_update::errors::Result<()> {
+//! # let (source, dest): (Provider, Provider) = todo!();
+//! let report = migrate::migrate(&source, &dest, "example.com", &MigrateOptions::default())?;
+//! print!("{report}");
+//! if !report.is_complete() {
+//!     eprintln!("Migration needs manual attention");
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Records that cannot be copied are reported rather than failing
+//! the migration; see `MigrationReport`.
+
+use std::fmt::{self, Display, Formatter};
+
+use tracing::{debug, info, warn};
+
+use crate::{
+    change::{Change, ChangeSet},
+    errors::Result,
+    plan::{self, DesiredState, Plan},
+    with_provider, Config, DnsProvider, Provider, RecordType, UnsupportedRecord,
+};
+
+
+/// Options for `migrate()`.
+#[derive(Clone, Debug, Default)]
+pub struct MigrateOptions {
+    /// Copy NS records at the zone apex. These are normally managed
+    /// by each provider, so are skipped by default.
+    pub apex_ns: bool,
+    /// Delete destination records that are not in the source. NS
+    /// records at the zone apex are never deleted.
+    pub prune: bool,
+    /// Log the changes without making them. Verification is skipped.
+    pub dry_run: bool,
+}
+
+/// The outcome of a migration.
+#[derive(Clone, Debug, Default)]
+pub struct MigrationReport {
+    /// The changes made to the destination, and the record sets that
+    /// were already present.
+    pub plan: Plan,
+    /// Source records that were not copied, as they cannot be
+    /// represented at the destination.
+    pub skipped: Vec<UnsupportedRecord>,
+    /// Source records that were copied, but whose provider-specific
+    /// settings were not.
+    pub lossy: Vec<UnsupportedRecord>,
+    /// Changes that failed, with the error.
+    pub failed: Vec<(Change, String)>,
+    /// Changes that are still needed after re-reading the
+    /// destination. Always empty for dry runs.
+    pub unverified: Vec<Change>,
+}
+
+impl MigrationReport {
+    /// Whether every source record is present at the destination.
+    /// Lossy records are not considered.
+    pub fn is_complete(&self) -> bool {
+        self.skipped.is_empty() && self.failed.is_empty() && self.unverified.is_empty()
+    }
+}
+
+impl Display for MigrationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.plan)?;
+        for rec in &self.skipped {
+            writeln!(f, "! skipped {} {} {}: {}", rec.rtype, rec.host, rec.value, rec.reason)?;
+        }
+        for rec in &self.lossy {
+            writeln!(f, "! lossy {} {} {}: {}", rec.rtype, rec.host, rec.value, rec.reason)?;
+        }
+        for (change, err) in &self.failed {
+            writeln!(f, "! failed to {change}: {err}")?;
+        }
+        for change in &self.unverified {
+            writeln!(f, "! unverified {change}")?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Migrate `domain` from the `source` provider to `dest`.
+///
+/// The source is only read. See `migrate_zone()` for details.
+pub fn migrate(source: &Provider, dest: &Provider, domain: &str, opts: &MigrateOptions) -> Result<MigrationReport> {
+    let from = source.blocking_impl(Config {
+        domain: domain.to_string(),
+        dry_run: true,
+    });
+    let conf = Config {
+        domain: domain.to_string(),
+        dry_run: opts.dry_run,
+    };
+
+    with_provider!(dest, conf, |to| migrate_zone(&*from, &to, opts))
+}
+
+/// Copy the records of `source` to `dest`.
+///
+/// The source zone is listed with `list_zone()`, and records of
+/// types that `dest` supports are brought into the destination as a
+/// `plan::DesiredState`; existing destination records are updated or
+/// kept as appropriate. The plan is checked as a whole with
+/// `DnsProvider::check_changes()` and submitted with
+/// `DnsProvider::apply_changes()`, so batch providers apply it
+/// atomically. If that fails the changes still needed are applied
+/// one at a time, so that each failure can be reported. Afterwards
+/// the destination is re-read to verify the result.
+///
+/// TTLs are not copied; the destination's defaults are used.
+/// Support for types with structured data (e.g. MX and SRV) varies
+/// between providers; failures appear in the report.
+pub fn migrate_zone<S, D>(source: &S, dest: &D, opts: &MigrateOptions) -> Result<MigrationReport>
+where
+    S: DnsProvider + ?Sized,
+    D: DnsProvider + ?Sized,
+{
+    let listing = source.list_zone()?;
+    let types = dest.record_types();
+
+    let mut report = MigrationReport {
+        skipped: listing.skipped,
+        lossy: listing.lossy,
+        ..Default::default()
+    };
+
+    let mut records = Vec::with_capacity(listing.records.len());
+    for rec in listing.records {
+        if rec.rtype == RecordType::NS && rec.host.is_empty() && !opts.apex_ns {
+            debug!("Skipping apex NS record {}", rec.value);
+        } else if !types.contains(&rec.rtype) {
+            report.skipped.push(UnsupportedRecord {
+                rtype: rec.rtype.to_string(),
+                host: rec.host,
+                value: rec.value,
+                reason: "record type is not supported by the destination".to_string(),
+            });
+        } else {
+            records.push(rec);
+        }
+    }
+
+    let desired = DesiredState {
+        prune: opts.prune,
+        records,
+    };
+    let plan = plan::plan(&desired, &dest.list_records()?);
+    dest.check_changes(&plan.changes)?;
+
+    if !plan.is_empty() {
+        info!("Applying {} changes", plan.changes.len());
+        if let Err(e) = dest.apply_changes(&ChangeSet { changes: plan.changes.clone() }) {
+            warn!("Failed to apply changes as a set, applying individually: {e}");
+            // The set may have been partly applied
+            for change in plan::plan(&desired, &dest.list_records()?).changes {
+                info!("Applying {change}");
+                if let Err(e) = dest.apply_change(&change) {
+                    warn!("Failed to {change}: {e}");
+                    report.failed.push((change, e.to_string()));
+                }
+            }
+        }
+    }
+
+    if !opts.dry_run {
+        report.unverified = plan::plan(&desired, &dest.list_records()?).changes;
+    }
+    report.plan = plan;
+
+    Ok(report)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{errors::Error, tests::MemoryProvider};
+    use serde::{de::DeserializeOwned, Serialize};
+    use std::{fmt::Display, sync::atomic::{AtomicUsize, Ordering}};
+
+    /// Memory provider that can't create MX records, and counts the
+    /// change sets submitted.
+    struct NoMx {
+        inner: MemoryProvider,
+        sets: AtomicUsize,
+    }
+
+    impl DnsProvider for NoMx {
+        fn get_record<T>(&self, rtype: RecordType, host: &str) -> Result<Option<T>>
+        where
+            T: DeserializeOwned
+        {
+            self.inner.get_record(rtype, host)
+        }
+
+        fn create_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+        where
+            T: Serialize + DeserializeOwned + Display + Clone
+        {
+            if rtype == RecordType::MX {
+                return Err(Error::ApiError("MX not allowed".to_string()));
+            }
+            self.inner.create_record(rtype, host, record)
+        }
+
+        fn update_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+        where
+            T: Serialize + DeserializeOwned + Display + Clone
+        {
+            self.inner.update_record(rtype, host, record)
+        }
+
+        fn delete_record(&self, rtype: RecordType, host: &str) -> Result<()> {
+            self.inner.delete_record(rtype, host)
+        }
+
+        fn delete_all_records(&self, rtype: RecordType, host: &str) -> Result<()> {
+            self.inner.delete_all_records(rtype, host)
+        }
+
+        fn list_zone(&self) -> Result<crate::ZoneListing> {
+            self.inner.list_zone()
+        }
+
+        fn apply_changes(&self, changes: &ChangeSet) -> Result<()> {
+            self.sets.fetch_add(1, Ordering::SeqCst);
+            crate::change::apply_sequential(self, changes)
+        }
+
+        crate::generate_helpers!();
+    }
+
+    fn source() -> MemoryProvider {
+        let source = MemoryProvider::new("example.com");
+        let mut recs = source.records.lock().unwrap();
+        for (rtype, host, value) in [
+            (RecordType::NS, "", "ns1.source.net."),
+            (RecordType::A, "www", "192.0.2.1"),
+            (RecordType::MX, "", "10 mail.example.com."),
+            (RecordType::TXT, "", "\"v=spf1 -all\""),
+            (RecordType::PTR, "1", "www.example.com."),
+        ] {
+            recs.push((rtype, host.to_string(), value.to_string()));
+        }
+        drop(recs);
+        source
+    }
+
+    #[test]
+    fn test_migrate() -> Result<()> {
+        let mut dest = MemoryProvider::new("example.com");
+        dest.create_a_record("www", &"192.0.2.9".parse()?)?;
+        dest.types = &[RecordType::A, RecordType::MX, RecordType::TXT];
+
+        let report = migrate_zone(&source(), &dest, &MigrateOptions::default())?;
+
+        // Apex NS is skipped silently, PTR is reported
+        assert_eq!(1, report.skipped.len());
+        assert_eq!("PTR", report.skipped[0].rtype);
+        assert!(report.failed.is_empty());
+        assert!(report.unverified.is_empty());
+        assert!(!report.is_complete());
+
+        assert_eq!(Some("192.0.2.1".parse()?), dest.get_a_record("www")?);
+        let recs = dest.list_records()?;
+        assert_eq!(3, recs.len());
+        assert!(!recs.iter().any(|r| r.rtype == RecordType::NS));
+
+        // Running again changes nothing
+        let report = migrate_zone(&source(), &dest, &MigrateOptions::default())?;
+        assert!(report.plan.is_empty());
+        assert_eq!(3, report.plan.unchanged.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_prune() -> Result<()> {
+        let dest = MemoryProvider::new("example.com");
+        dest.create_a_record("old", &"192.0.2.2".parse()?)?;
+        let opts = MigrateOptions { apex_ns: true, prune: true, ..Default::default() };
+
+        let report = migrate_zone(&source(), &dest, &opts)?;
+        assert!(report.is_complete());
+        assert_eq!(None, dest.get_a_record("old")?);
+        assert_eq!(5, dest.list_records()?.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_batch_failure() -> Result<()> {
+        let dest = NoMx {
+            inner: MemoryProvider::new("example.com"),
+            sets: AtomicUsize::new(0),
+        };
+
+        let report = migrate_zone(&source(), &dest as &dyn DnsProvider, &MigrateOptions::default())?;
+
+        // The set was rolled back, then all but the MX record copied
+        assert_eq!(1, dest.sets.load(Ordering::SeqCst));
+        assert_eq!(1, report.failed.len());
+        assert_eq!(RecordType::MX, report.failed[0].0.rtype());
+        assert_eq!(3, dest.list_records()?.len());
+        assert_eq!(Some("192.0.2.1".parse()?), dest.get_a_record("www")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_unverified() -> Result<()> {
+        let mut dest = MemoryProvider::new("example.com");
+        // The changes will not be made, but we still verify
+        dest.config.dry_run = true;
+
+        let report = migrate_zone(&source(), &dest, &MigrateOptions::default())?;
+        assert!(report.failed.is_empty());
+        assert_eq!(report.plan.changes, report.unverified);
+        assert!(!report.is_complete());
+        assert!(report.to_string().contains("! unverified create A www -> 192.0.2.1"));
+
+        let opts = MigrateOptions { dry_run: true, ..Default::default() };
+        let report = migrate_zone(&source(), &dest, &opts)?;
+        assert!(report.unverified.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,561 @@
+//! Record ownership registry.
+//!
+//! Zones are often shared between teams and tools, and operations
+//! such as `delete_all_records()` will remove records regardless of
+//! who created them. Wrapping a provider in [`Owned`] records the
+//! owner of each record set it creates in a companion TXT record, and
+//! refuses to modify record sets that belong to someone else:
+//!
+//! ```no_run
+//! use zone_update::{Config, DnsProvider, porkbun, ownership::{Owned, Registry}};
+//! # fn main() -> zone_update::errors::Result<()> {
+//! let config = Config { domain: "example.com".to_string(), dry_run: false };
+//! let auth = porkbun::Auth { key: "key".to_string(), secret: "secret".to_string() };
+//! let provider = porkbun::Porkbun::new(config, auth);
+//!
+//! let client = Owned::new(provider, Registry::new("team-a"));
+//!
+//! // Creates `www` and `_owner-a.www`
+//! client.create_a_record("www", &"192.0.2.1".parse()?)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The registry record for an `A` record set on `www` is a TXT record
+//! on `_owner-a.www` with the value
+//! `heritage=zone-update,zone-update/owner=team-a,zone-update/created=2026-01-01T00:00:00Z`.
+//! The registry uses plain TXT records, so works with all providers.
+
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fmt::Display,
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    change::{Change, ChangeSet},
+    errors::{Error, Result},
+    ensure_quotes, generate_helpers, DnsProvider, RecordType, ZoneListing,
+};
+
+/// The default prefix for registry record hosts.
+pub const DEFAULT_PREFIX: &str = "_owner-";
+
+const HERITAGE: &str = "heritage=zone-update";
+const OWNER_KEY: &str = "zone-update/owner=";
+const CREATED_KEY: &str = "zone-update/created=";
+
+
+/// Ownership settings for `Owned`.
+#[derive(Clone, Debug)]
+pub struct Registry {
+    /// The ID recorded as the owner of record sets created through
+    /// this registry.
+    pub owner: String,
+    /// Prefix of registry record hosts. This should not collide with
+    /// any hosts in the zone.
+    pub prefix: String,
+    /// Refuse to modify record sets that are not owned by `owner`. If
+    /// unset such changes are logged and allowed.
+    pub enforce: bool,
+}
+
+/// The ownership of a record set.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Claim {
+    /// There are no records and no registry record.
+    Free,
+    /// The registry record names us as the owner.
+    Owned,
+    /// The registry record names another owner, or there are records
+    /// but no registry record.
+    Foreign(Option<String>),
+}
+
+impl Registry {
+    /// A registry for `owner`, with the default prefix, that enforces
+    /// ownership.
+    pub fn new(owner: &str) -> Self {
+        Self {
+            owner: owner.to_string(),
+            prefix: DEFAULT_PREFIX.to_string(),
+            enforce: true,
+        }
+    }
+
+    /// The host of the registry record for a record set.
+    pub fn registry_host(&self, rtype: RecordType, host: &str) -> String {
+        registry_host(&self.prefix, rtype, host)
+    }
+
+    /// The TXT value identifying our registry records, without
+    /// quotes.
+    pub fn marker(&self) -> String {
+        format!("{HERITAGE},{OWNER_KEY}{}", self.owner)
+    }
+
+    /// The TXT value of a new registry record; `marker()` with the
+    /// time the record set was claimed.
+    pub fn marker_at(&self, created: DateTime<Utc>) -> String {
+        format!("{},{CREATED_KEY}{}", self.marker(), created.to_rfc3339_opts(SecondsFormat::Secs, true))
+    }
+
+    pub(crate) fn claim(&self, marker: Option<String>, exists: bool) -> Claim {
+        match marker.as_deref().and_then(parse_owner) {
+            Some(owner) if owner == self.owner => Claim::Owned,
+            Some(owner) => Claim::Foreign(Some(owner.to_string())),
+            None if exists => Claim::Foreign(None),
+            None => Claim::Free,
+        }
+    }
+
+    /// Check whether a change to a record set is allowed.
+    pub(crate) fn allow(&self, claim: &Claim, rtype: RecordType, host: &str) -> Result<()> {
+        let Claim::Foreign(owner) = claim else {
+            return Ok(())
+        };
+        let msg = match owner {
+            Some(owner) => format!("{rtype} {host} is owned by {owner}"),
+            None => format!("{rtype} {host} is not managed by {}", self.owner),
+        };
+        if self.enforce {
+            return Err(Error::NotOwned(msg));
+        }
+        warn!("Modifying unowned record: {msg}");
+        Ok(())
+    }
+
+    /// Check a change set against the claims on the record sets it
+    /// touches, as they were before it is applied, and add the
+    /// registry changes to claim and release them.
+    ///
+    /// Returns the new change set, and the record sets to release if
+    /// a `Delete` leaves them empty.
+    pub(crate) fn claimed_changes(&self, changes: &[Change], mut claims: HashMap<(RecordType, String), Claim>,
+                                  now: DateTime<Utc>) -> Result<(ChangeSet, Vec<(RecordType, String)>)>
+    {
+        let mut claimed = Vec::with_capacity(changes.len());
+        let mut deleted = Vec::new();
+        for change in changes {
+            let (rtype, host) = (change.rtype(), change.host());
+            let key = (rtype, host.to_string());
+            let claim = claims.get(&key).cloned().unwrap_or(Claim::Free);
+            self.allow(&claim, rtype, host)?;
+            claimed.push(change.clone());
+
+            match (change, &claim) {
+                (Change::Create { .. }, Claim::Free) => {
+                    info!("Claiming ownership of {rtype} {host}");
+                    claimed.push(Change::Create {
+                        rtype: RecordType::TXT,
+                        host: self.registry_host(rtype, host),
+                        value: ensure_quotes(&self.marker_at(now)),
+                    });
+                    claims.insert(key, Claim::Owned);
+                }
+                (Change::DeleteAll { .. }, Claim::Owned) => {
+                    info!("Releasing ownership of {rtype} {host}");
+                    claimed.push(Change::DeleteAll { rtype: RecordType::TXT, host: self.registry_host(rtype, host) });
+                    claims.insert(key, Claim::Free);
+                }
+                (Change::DeleteAll { .. }, Claim::Foreign(None)) => {
+                    claims.insert(key, Claim::Free);
+                }
+                (Change::Delete { .. }, Claim::Owned) if !deleted.contains(&key) => {
+                    deleted.push(key);
+                }
+                _ => {}
+            }
+        }
+        deleted.retain(|key| claims.get(key) == Some(&Claim::Owned));
+        Ok((claimed.into(), deleted))
+    }
+
+    /// Remove registry records from a listing. If ownership is
+    /// enforced, records that we don't own are also removed, so that
+    /// e.g. `plan::sync()` will not prune them.
+    pub fn owned_listing(&self, mut listing: ZoneListing) -> ZoneListing {
+        let is_registry = |rec: &crate::ZoneRecord| rec.rtype == RecordType::TXT
+            && rec.host.to_lowercase().starts_with(&self.prefix.to_lowercase())
+            && parse_owner(&rec.value).is_some();
+
+        let owned: HashSet<String> = listing.records.iter()
+            .filter(|rec| is_registry(rec) && parse_owner(&rec.value) == Some(self.owner.as_str()))
+            .map(|rec| rec.host.to_lowercase())
+            .collect();
+
+        listing.records.retain(|rec| {
+            !is_registry(rec)
+                && (!self.enforce || owned.contains(&self.registry_host(rec.rtype, &rec.host).to_lowercase()))
+        });
+        listing
+    }
+}
+
+pub(crate) fn registry_host(prefix: &str, rtype: RecordType, host: &str) -> String {
+    let rtype = rtype.to_string().to_lowercase();
+    match host {
+        "" => format!("{prefix}{rtype}"),
+        host => format!("{prefix}{rtype}.{host}"),
+    }
+}
+
+/// The owner named in a registry record value, if it is one.
+fn parse_owner(value: &str) -> Option<&str> {
+    let mut fields = value.trim_matches('"').split(',');
+    if fields.next()? != HERITAGE {
+        return None;
+    }
+    fields.find_map(|f| f.strip_prefix(OWNER_KEY))
+}
+
+/// The time a record set was claimed, from a registry record value.
+/// Registry records written before this was recorded have none.
+pub fn parse_created(value: &str) -> Option<DateTime<Utc>> {
+    let mut fields = value.trim_matches('"').split(',');
+    if fields.next()? != HERITAGE {
+        return None;
+    }
+    let created = fields.find_map(|f| f.strip_prefix(CREATED_KEY))?;
+    DateTime::parse_from_rfc3339(created).ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/// Whether a record lookup found anything. Lookups of multi-valued
+/// record sets fail, but still show that records exist.
+pub(crate) fn exists(lookup: Result<Option<String>>) -> Result<bool> {
+    match lookup {
+        Ok(value) => Ok(value.is_some()),
+        Err(Error::UnexpectedRecord(_)) => Ok(true),
+        Err(e) => Err(e),
+    }
+}
+
+
+/// A provider wrapper that tracks record ownership in a TXT registry.
+///
+/// * Creating records in an empty record set claims it.
+/// * Changes to record sets owned by others, or with records but no
+///   registry entry (e.g. created by hand), are refused with
+///   `Error::NotOwned` if the registry enforces ownership.
+/// * Deleting all records of an owned set releases it.
+/// * Listings omit registry records and, if enforced, unowned records.
+///
+/// Each mutation costs one or two additional lookups. The ownership
+/// check and the change are not atomic, so concurrent writers to the
+/// same record set may race.
+pub struct Owned<P> {
+    inner: P,
+    registry: Registry,
+}
+
+impl<P: DnsProvider> Owned<P> {
+    /// Wrap `inner`, tracking ownership in `registry`.
+    pub fn new(inner: P, registry: Registry) -> Self {
+        Self {
+            inner,
+            registry,
+        }
+    }
+
+    /// The ownership settings.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Return the wrapped provider.
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+
+    fn claim(&self, rtype: RecordType, host: &str) -> Result<Claim> {
+        let marker = self.inner.get_record::<String>(RecordType::TXT, &self.registry.registry_host(rtype, host))?;
+        let exists = marker.is_none() && exists(self.inner.get_record::<String>(rtype, host))?;
+        Ok(self.registry.claim(marker, exists))
+    }
+
+    /// The claims on the record sets touched by `changes`.
+    fn claims(&self, changes: &[Change]) -> Result<HashMap<(RecordType, String), Claim>> {
+        let mut claims = HashMap::new();
+        for change in changes {
+            if let Entry::Vacant(entry) = claims.entry((change.rtype(), change.host().to_string())) {
+                entry.insert(self.claim(change.rtype(), change.host())?);
+            }
+        }
+        Ok(claims)
+    }
+
+    fn checked(&self, rtype: RecordType, host: &str) -> Result<Claim> {
+        let claim = self.claim(rtype, host)?;
+        self.registry.allow(&claim, rtype, host)?;
+        Ok(claim)
+    }
+
+    fn release(&self, rtype: RecordType, host: &str) -> Result<()> {
+        info!("Releasing ownership of {rtype} {host}");
+        self.inner.delete_all_records(RecordType::TXT, &self.registry.registry_host(rtype, host))
+    }
+}
+
+impl<P: DnsProvider> DnsProvider for Owned<P> {
+
+    fn get_record<T>(&self, rtype: RecordType, host: &str) -> Result<Option<T>>
+    where
+        T: DeserializeOwned
+    {
+        self.inner.get_record(rtype, host)
+    }
+
+    fn create_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+    where
+        T: Serialize + DeserializeOwned + Display + Clone
+    {
+        let claim = self.checked(rtype, host)?;
+        self.inner.create_record(rtype, host, record)?;
+        if claim == Claim::Free {
+            info!("Claiming ownership of {rtype} {host}");
+            self.inner.create_txt_record(&self.registry.registry_host(rtype, host), &self.registry.marker_at(Utc::now()))?;
+        }
+        Ok(())
+    }
+
+    fn update_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+    where
+        T: Serialize + DeserializeOwned + Display + Clone
+    {
+        self.checked(rtype, host)?;
+        self.inner.update_record(rtype, host, record)
+    }
+
+    fn delete_record(&self, rtype: RecordType, host: &str) -> Result<()> {
+        let claim = self.checked(rtype, host)?;
+        self.inner.delete_record(rtype, host)?;
+        if claim == Claim::Owned && !exists(self.inner.get_record::<String>(rtype, host))? {
+            self.release(rtype, host)?;
+        }
+        Ok(())
+    }
+
+    fn delete_all_records(&self, rtype: RecordType, host: &str) -> Result<()> {
+        let claim = self.checked(rtype, host)?;
+        self.inner.delete_all_records(rtype, host)?;
+        if claim == Claim::Owned {
+            self.release(rtype, host)?;
+        }
+        Ok(())
+    }
+
+    fn list_zone(&self) -> Result<ZoneListing> {
+        self.inner.list_zone()
+            .map(|listing| self.registry.owned_listing(listing))
+    }
+
+    fn record_types(&self) -> &'static [RecordType] {
+        self.inner.record_types()
+    }
+
+    // Registry changes are submitted in the same set, so batch
+    // providers apply them atomically with the records.
+    fn apply_changes(&self, changes: &ChangeSet) -> Result<()> {
+        let claims = self.claims(&changes.changes)?;
+        let (changes, deleted) = self.registry.claimed_changes(&changes.changes, claims, Utc::now())?;
+        self.inner.apply_changes(&changes)?;
+        for (rtype, host) in deleted {
+            if !exists(self.inner.get_record::<String>(rtype, &host))? {
+                self.release(rtype, &host)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_changes(&self, changes: &[Change]) -> Result<()> {
+        self.inner.check_changes(changes)
+    }
+
+    fn add_txt_value(&self, host: &str, value: &str) -> Result<()> {
+        let claim = self.checked(RecordType::TXT, host)?;
+        self.inner.add_txt_value(host, value)?;
+        if claim == Claim::Free {
+            info!("Claiming ownership of TXT {host}");
+            self.inner.create_txt_record(&self.registry.registry_host(RecordType::TXT, host), &self.registry.marker_at(Utc::now()))?;
+        }
+        Ok(())
+    }
+
+    fn remove_txt_value(&self, host: &str, value: &str) -> Result<()> {
+        let claim = self.checked(RecordType::TXT, host)?;
+        self.inner.remove_txt_value(host, value)?;
+        if claim == Claim::Owned && self.inner.get_txt_values(host)?.is_empty() {
+            self.release(RecordType::TXT, host)?;
+        }
+        Ok(())
+    }
+
+    generate_helpers!();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{plan::{self, DesiredState}, tests::{Batching, MemoryProvider}, ZoneRecord};
+    use std::net::Ipv4Addr;
+
+    fn shared() -> MemoryProvider {
+        let inner = MemoryProvider::new("example.com");
+        inner.create_a_record("manual", &Ipv4Addr::new(10, 0, 0, 9)).unwrap();
+        inner
+    }
+
+    #[test]
+    fn test_parse_owner() {
+        assert_eq!(Some("team-a"), parse_owner("\"heritage=zone-update,zone-update/owner=team-a\""));
+        assert_eq!(None, parse_owner("heritage=external-dns,external-dns/owner=team-a"));
+        assert_eq!(None, parse_owner("v=spf1 -all"));
+    }
+
+    #[test]
+    fn test_parse_created() {
+        let registry = Registry::new("team-a");
+        let created = DateTime::parse_from_rfc3339("2026-01-01T00:00:00Z").unwrap().with_timezone(&Utc);
+        let marker = registry.marker_at(created);
+        assert_eq!("heritage=zone-update,zone-update/owner=team-a,zone-update/created=2026-01-01T00:00:00Z", marker);
+        assert_eq!(Some("team-a"), parse_owner(&marker));
+        assert_eq!(Some(created), parse_created(&format!("\"{marker}\"")));
+        assert_eq!(None, parse_created(&registry.marker()));
+    }
+
+    #[test]
+    fn test_claim_and_release() -> Result<()> {
+        let client = Owned::new(shared(), Registry::new("team-a"));
+
+        client.create_a_record("www", &Ipv4Addr::new(10, 0, 0, 1))?;
+        let marker = client.inner.get_txt_record("_owner-a.www")?.unwrap();
+        assert!(marker.starts_with(&client.registry().marker()));
+        assert!(parse_created(&marker).is_some());
+
+        client.update_a_record("www", &Ipv4Addr::new(10, 0, 0, 2))?;
+        assert_eq!(Some(Ipv4Addr::new(10, 0, 0, 2)), client.get_a_record("www")?);
+
+        client.delete_a_record("www")?;
+        assert_eq!(None, client.inner.get_txt_record("_owner-a.www")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_txt_values() -> Result<()> {
+        let client = Owned::new(shared(), Registry::new("team-a"));
+
+        client.add_txt_value("_acme-challenge", "one")?;
+        client.add_txt_value("_acme-challenge", "two")?;
+        assert!(client.inner.get_txt_record("_owner-txt._acme-challenge")?.is_some());
+
+        client.remove_txt_value("_acme-challenge", "one")?;
+        assert!(client.inner.get_txt_record("_owner-txt._acme-challenge")?.is_some());
+        client.remove_txt_value("_acme-challenge", "two")?;
+        assert_eq!(None, client.inner.get_txt_record("_owner-txt._acme-challenge")?);
+
+        let other = Owned::new(client.into_inner(), Registry::new("team-b"));
+        other.add_txt_value("_dmarc", "v=DMARC1")?;
+        let client = Owned::new(other.into_inner(), Registry::new("team-a"));
+        assert!(matches!(client.remove_txt_value("_dmarc", "v=DMARC1"), Err(Error::NotOwned(_))));
+        assert!(matches!(client.add_txt_value("_dmarc", "v=spf1"), Err(Error::NotOwned(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_refuse_unowned() -> Result<()> {
+        let client = Owned::new(shared(), Registry::new("team-a"));
+        let other = Owned::new(client.into_inner(), Registry::new("team-b"));
+        other.create_a_record("api", &Ipv4Addr::new(10, 0, 0, 3))?;
+        let client = Owned::new(other.into_inner(), Registry::new("team-a"));
+
+        let err = client.delete_all_records(RecordType::A, "api").unwrap_err();
+        assert!(matches!(&err, Error::NotOwned(msg) if msg.contains("owned by team-b")));
+        let err = client.update_a_record("manual", &Ipv4Addr::new(10, 0, 0, 4)).unwrap_err();
+        assert!(matches!(&err, Error::NotOwned(msg) if msg.contains("not managed by team-a")));
+        assert!(client.create_a_record("manual", &Ipv4Addr::new(10, 0, 0, 4)).is_err());
+
+        assert_eq!(Some(Ipv4Addr::new(10, 0, 0, 3)), client.get_a_record("api")?);
+        assert_eq!(Some(Ipv4Addr::new(10, 0, 0, 9)), client.get_a_record("manual")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unenforced() -> Result<()> {
+        let registry = Registry { enforce: false, ..Registry::new("team-a") };
+        let client = Owned::new(shared(), registry);
+
+        client.update_a_record("manual", &Ipv4Addr::new(10, 0, 0, 4))?;
+        assert_eq!(Some(Ipv4Addr::new(10, 0, 0, 4)), client.get_a_record("manual")?);
+        // Not claimed
+        assert_eq!(None, client.inner.get_txt_record("_owner-a.manual")?);
+        assert_eq!(1, client.list_records()?.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_owned_only() -> Result<()> {
+        let client = Owned::new(shared(), Registry::new("team-a"));
+        client.create_a_record("old", &Ipv4Addr::new(10, 0, 0, 1))?;
+
+        let listed = client.list_records()?;
+        assert_eq!(1, listed.len());
+        assert_eq!("old", listed[0].host);
+
+        let desired = DesiredState {
+            prune: true,
+            records: vec![ZoneRecord {
+                rtype: RecordType::A,
+                host: "new".to_string(),
+                value: "10.0.0.2".to_string(),
+                ttl: None,
+                modified: None,
+            }],
+        };
+        plan::sync(&client, &desired)?;
+
+        let inner = client.into_inner();
+        assert_eq!(None, inner.get_a_record("old")?);
+        assert_eq!(Some(Ipv4Addr::new(10, 0, 0, 2)), inner.get_a_record("new")?);
+        assert_eq!(Some(Ipv4Addr::new(10, 0, 0, 9)), inner.get_a_record("manual")?);
+        assert_eq!(None, inner.get_txt_record("_owner-a.old")?);
+        assert!(inner.get_txt_record("_owner-a.new")?.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_changes_batched() -> Result<()> {
+        let client = Owned::new(Batching::new("example.com"), Registry::new("team-a"));
+
+        let www = |value: &str| Change::Create { rtype: RecordType::A, host: "www".to_string(), value: value.to_string() };
+        client.apply_changes(&vec![www("10.0.0.1")].into())?;
+        assert!(client.inner.get_txt_record("_owner-a.www")?.is_some());
+
+        // Pruning and recreating the set keeps the claim
+        client.apply_changes(&vec![
+            Change::DeleteAll { rtype: RecordType::A, host: "www".to_string() },
+            www("10.0.0.2"),
+        ].into())?;
+        assert!(client.inner.get_txt_record("_owner-a.www")?.is_some());
+
+        client.apply_changes(&vec![Change::DeleteAll { rtype: RecordType::A, host: "www".to_string() }].into())?;
+        assert_eq!(None, client.inner.get_txt_record("_owner-a.www")?);
+
+        // Registry changes are submitted with the records
+        let batches = client.inner.batches.lock().unwrap();
+        assert_eq!(3, batches.len());
+        assert_eq!("_owner-a.www", batches[0].changes[1].host());
+        assert_eq!(4, batches[1].changes.len());
+        assert_eq!(Change::DeleteAll { rtype: RecordType::TXT, host: "_owner-a.www".to_string() }, batches[2].changes[1]);
+
+        Ok(())
+    }
+}
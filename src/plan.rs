@@ -0,0 +1,426 @@
+//! Declarative zone management.
+//!
+//! Describe the records you own for a zone as a `DesiredState`, then
+//! compute a `Plan` against the live records and apply it. Applying
+//! the same state again is a no-op.
+//!
+//! ```no_run
+//! # use zone_update::{plan::{self, DesiredState}, DnsProvider, RecordType, ZoneRecord};
+//! # fn main() -> zone_update::errors::Result<()> {
+//! # let client: zone_update::desec::DeSec = todo!();
+//! let desired = DesiredState {
+//!     prune: false,
+//!     records: vec![
+//!         ZoneRecord { rtype: RecordType::A, host: "www".to_string(), value: "192.0.2.1".to_string(), ttl: None, modified: None },
+//!     ],
+//! };
+//! let plan = plan::plan(&desired, &client.list_records()?);
+//! println!("{plan}");
+//! plan.apply(&client)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! `DesiredState` can also be deserialised from configuration files.
+//! For async providers compute the plan from
+//! `AsyncDnsProvider::list_records()` and apply the changes with
+//! `async_impl::bulk::apply_bulk()`.
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    net::IpAddr,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    change::{Change, ChangeSet},
+    errors::Result,
+    to_rdata, zonefile::parse_txt, DnsProvider, RdataParts, RecordType, ZoneRecord,
+};
+
+
+/// The records to maintain in a zone.
+///
+/// Records with the same host and type form a record set, which is
+/// managed as a whole. Values are in presentation format, as for
+/// `ZoneRecord`; domain names should be fully-qualified. TTLs are
+/// currently ignored.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DesiredState {
+    /// Delete live records that are not in the desired state. NS
+    /// records at the zone apex are never deleted.
+    #[serde(default)]
+    pub prune: bool,
+    #[serde(default)]
+    pub records: Vec<ZoneRecord>,
+}
+
+/// The changes needed to reach a desired state.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Plan {
+    /// Changes to apply, in order.
+    pub changes: Vec<Change>,
+    /// Record sets that are already in the desired state.
+    pub unchanged: Vec<(RecordType, String)>,
+}
+
+impl Plan {
+    /// Whether the zone is already in the desired state.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Apply the changes as a unit with
+    /// `DnsProvider::apply_changes()`.
+    ///
+    /// The whole plan is first checked with
+    /// `DnsProvider::check_changes()`, so e.g. a deletion limit
+    /// refuses it before anything is changed. Batch providers then
+    /// apply it atomically; others apply it in order, rolling back on
+    /// failure. If the provider is configured with `dry_run` the
+    /// changes are logged but not made.
+    pub fn apply(&self, provider: &(impl DnsProvider + ?Sized)) -> Result<()> {
+        provider.check_changes(&self.changes)?;
+        if self.changes.is_empty() {
+            return Ok(())
+        }
+        info!("Applying {} changes", self.changes.len());
+        provider.apply_changes(&ChangeSet { changes: self.changes.clone() })
+    }
+}
+
+impl Display for Plan {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for change in &self.changes {
+            let sign = match change {
+                Change::Create { .. } => '+',
+                Change::Update { .. } => '~',
+                Change::Delete { .. } | Change::DeleteAll { .. } => '-',
+            };
+            writeln!(f, "{sign} {change}")?;
+        }
+        for (rtype, host) in &self.unchanged {
+            writeln!(f, "= unchanged {rtype} {host}")?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Compute the changes needed to bring the `live` records to the
+/// `desired` state.
+///
+/// For each desired record set:
+///
+/// * If the live set has the same values it is unchanged.
+/// * If both have a single value the record is updated.
+/// * Otherwise missing values are created. If `prune` is set and the
+///   live set has values that are not desired, the set is deleted and
+///   recreated.
+///
+/// If `prune` is set, live record sets that are not in the desired
+/// state are deleted.
+///
+/// Hosts are compared case-insensitively, and values are compared
+/// after normalisation (e.g. ignoring trailing dots and TXT string
+/// splitting).
+pub fn plan(desired: &DesiredState, live: &[ZoneRecord]) -> Plan {
+    let desired_sets = record_sets(&desired.records);
+    let live_sets = record_sets(live);
+    let live_index: HashMap<Key, usize> = live_sets.iter()
+        .enumerate()
+        .map(|(i, set)| (set.key.clone(), i))
+        .collect();
+
+    let mut plan = Plan::default();
+
+    for want in &desired_sets {
+        let have = live_index.get(&want.key)
+            .map(|i| &live_sets[*i]);
+        let rtype = want.key.0;
+        let host = want.host.clone();
+
+        let Some(have) = have else {
+            for value in &want.values {
+                plan.changes.push(Change::Create { rtype, host: host.clone(), value: desired_value(rtype, value) });
+            }
+            continue;
+        };
+
+        let missing: Vec<&String> = want.values.iter()
+            .filter(|v| !have.contains(rtype, v))
+            .collect();
+        let extra = have.values.iter()
+            .any(|v| !want.contains(rtype, v));
+
+        if missing.is_empty() && !extra {
+            plan.unchanged.push((rtype, host));
+        } else if want.values.len() == 1 && have.values.len() == 1 {
+            plan.changes.push(Change::Update { rtype, host, value: desired_value(rtype, &want.values[0]) });
+        } else if extra && desired.prune {
+            plan.changes.push(Change::DeleteAll { rtype, host: host.clone() });
+            for value in &want.values {
+                plan.changes.push(Change::Create { rtype, host: host.clone(), value: desired_value(rtype, value) });
+            }
+        } else {
+            for value in missing {
+                plan.changes.push(Change::Create { rtype, host: host.clone(), value: desired_value(rtype, value) });
+            }
+        }
+    }
+
+    if desired.prune {
+        let wanted: Vec<&Key> = desired_sets.iter().map(|s| &s.key).collect();
+        for have in &live_sets {
+            let (rtype, host) = &have.key;
+            let apex_ns = *rtype == RecordType::NS && host.is_empty();
+            if !apex_ns && !wanted.contains(&&have.key) {
+                plan.changes.push(Change::DeleteAll { rtype: *rtype, host: have.host.clone() });
+            }
+        }
+    }
+
+    plan
+}
+
+/// Compute and apply the plan for a provider, returning the applied
+/// plan. See `plan()` and `Plan::apply()`.
+pub fn sync(provider: &(impl DnsProvider + ?Sized), desired: &DesiredState) -> Result<Plan> {
+    let live = provider.list_records()?;
+    let plan = plan(desired, &live);
+    plan.apply(provider)?;
+    Ok(plan)
+}
+
+
+/// Record sets are keyed by type and lower-cased host.
+type Key = (RecordType, String);
+
+struct RecordSet {
+    key: Key,
+    /// The host as first given.
+    host: String,
+    values: Vec<String>,
+}
+
+impl RecordSet {
+    fn contains(&self, rtype: RecordType, value: &str) -> bool {
+        let value = normalise(rtype, value);
+        self.values.iter()
+            .any(|v| normalise(rtype, v) == value)
+    }
+}
+
+/// Group records into sets, in order of first appearance.
+fn record_sets(records: &[ZoneRecord]) -> Vec<RecordSet> {
+    let mut index: HashMap<Key, usize> = HashMap::new();
+    let mut sets: Vec<RecordSet> = Vec::new();
+
+    for rec in records {
+        let key = (rec.rtype, rec.host.to_lowercase());
+        let i = *index.entry(key.clone())
+            .or_insert_with(|| {
+                sets.push(RecordSet { key, host: rec.host.clone(), values: Vec::new() });
+                sets.len() - 1
+            });
+        if !sets[i].contains(rec.rtype, &rec.value) {
+            sets[i].values.push(rec.value.clone());
+        }
+    }
+
+    sets
+}
+
+/// The value to send to the provider; TXT values are quoted if
+/// necessary.
+fn desired_value(rtype: RecordType, value: &str) -> String {
+    to_rdata(rtype, value, RdataParts::default())
+}
+
+/// Normalise a value for comparison.
+fn normalise(rtype: RecordType, value: &str) -> String {
+    match rtype {
+        RecordType::A | RecordType::AAAA => value.trim().parse::<IpAddr>()
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|_| value.trim().to_string()),
+        RecordType::TXT => String::from_utf8_lossy(&parse_txt(value).concat()).to_string(),
+        RecordType::CAA => value.split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" "),
+        _ => value.split_whitespace()
+            .map(|field| field.trim_end_matches('.').to_lowercase())
+            .collect::<Vec<String>>()
+            .join(" "),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{errors::{Error, MutationState}, tests::MemoryProvider};
+
+    fn rec(rtype: RecordType, host: &str, value: &str) -> ZoneRecord {
+        ZoneRecord {
+            rtype,
+            host: host.to_string(),
+            value: value.to_string(),
+            ttl: None,
+            modified: None,
+        }
+    }
+
+    fn create(rtype: RecordType, host: &str, value: &str) -> Change {
+        Change::Create { rtype, host: host.to_string(), value: value.to_string() }
+    }
+
+    #[test]
+    fn test_plan() {
+        let live = vec![
+            rec(RecordType::NS, "", "ns1.example.net."),
+            rec(RecordType::A, "www", "192.0.2.1"),
+            rec(RecordType::CNAME, "ftp", "www.example.com."),
+            rec(RecordType::TXT, "", "\"v=spf1 \" \"-all\""),
+            rec(RecordType::MX, "", "10 mail.example.com."),
+            rec(RecordType::A, "old", "192.0.2.9"),
+        ];
+        let desired = DesiredState {
+            prune: false,
+            records: vec![
+                rec(RecordType::A, "WWW", "192.0.2.2"),
+                rec(RecordType::CNAME, "ftp", "WWW.example.com"),
+                rec(RecordType::TXT, "", "v=spf1 -all"),
+                rec(RecordType::MX, "", "10 mail.example.com"),
+                rec(RecordType::MX, "", "20 backup.example.com"),
+                rec(RecordType::AAAA, "www", "2001:db8::1"),
+            ],
+        };
+
+        let plan = plan(&desired, &live);
+        assert_eq!(vec![
+            Change::Update { rtype: RecordType::A, host: "WWW".to_string(), value: "192.0.2.2".to_string() },
+            create(RecordType::MX, "", "20 backup.example.com"),
+            create(RecordType::AAAA, "www", "2001:db8::1"),
+        ], plan.changes);
+        assert_eq!(vec![
+            (RecordType::CNAME, "ftp".to_string()),
+            (RecordType::TXT, "".to_string()),
+        ], plan.unchanged);
+    }
+
+    #[test]
+    fn test_plan_prune() {
+        let live = vec![
+            rec(RecordType::NS, "", "ns1.example.net."),
+            rec(RecordType::MX, "", "10 mail.example.com."),
+            rec(RecordType::MX, "", "20 old.example.com."),
+            rec(RecordType::A, "old", "192.0.2.9"),
+        ];
+        let desired = DesiredState {
+            prune: true,
+            records: vec![
+                rec(RecordType::MX, "", "10 mail.example.com."),
+                rec(RecordType::MX, "", "30 new.example.com."),
+            ],
+        };
+
+        let plan = plan(&desired, &live);
+        assert_eq!(vec![
+            Change::DeleteAll { rtype: RecordType::MX, host: "".to_string() },
+            create(RecordType::MX, "", "10 mail.example.com."),
+            create(RecordType::MX, "", "30 new.example.com."),
+            // The apex NS is kept
+            Change::DeleteAll { rtype: RecordType::A, host: "old".to_string() },
+        ], plan.changes);
+    }
+
+    #[test]
+    fn test_normalise() {
+        assert_eq!(normalise(RecordType::AAAA, "2001:0db8:0:0::1"), normalise(RecordType::AAAA, "2001:db8::1"));
+        assert_eq!(normalise(RecordType::TXT, "\"a\" \"b\""), normalise(RecordType::TXT, "ab"));
+        assert_eq!(normalise(RecordType::MX, "10 Mail.example.com."), normalise(RecordType::MX, "10  mail.example.com"));
+        assert_ne!(normalise(RecordType::MX, "10 mail.example.com"), normalise(RecordType::MX, "20 mail.example.com"));
+    }
+
+    #[test]
+    fn test_sync_idempotent() -> Result<()> {
+        let provider = MemoryProvider::new("example.com");
+        provider.create_a_record("www", &"192.0.2.1".parse()?)?;
+        provider.create_a_record("stale", &"192.0.2.9".parse()?)?;
+
+        let desired: DesiredState = serde_json::from_str(r#"{
+            "prune": true,
+            "records": [
+                {"rtype": "A", "host": "www", "value": "192.0.2.2"},
+                {"rtype": "TXT", "host": "_owner", "value": "managed"}
+            ]
+        }"#)?;
+
+        let first = sync(&provider, &desired)?;
+        assert_eq!(3, first.changes.len());
+        assert_eq!(Some("192.0.2.2".parse()?), provider.get_a_record("www")?);
+        assert_eq!(Some("managed".to_string()), provider.get_txt_record("_owner")?);
+        assert_eq!(None, provider.get_a_record("stale")?);
+
+        let second = sync(&provider, &desired)?;
+        assert!(second.is_empty(), "{second}");
+        assert_eq!(2, second.unchanged.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_rolled_back() -> Result<()> {
+        let mut memory = MemoryProvider::new("example.com");
+        memory.types = &[RecordType::A];
+        memory.create_a_record("www", &"192.0.2.1".parse()?)?;
+        memory.create_a_record("www", &"192.0.2.5".parse()?)?;
+        let provider: Box<dyn DnsProvider> = Box::new(memory);
+
+        // The TXT record can't be created, so the prune of `www` is
+        // undone
+        let desired = DesiredState {
+            prune: true,
+            records: vec![
+                rec(RecordType::A, "www", "192.0.2.2"),
+                rec(RecordType::TXT, "_owner", "managed"),
+            ],
+        };
+        let result = sync(&*provider, &desired);
+        assert!(matches!(result, Err(Error::ChangeSetFailed(_, MutationState::NotApplied))));
+        assert_eq!(2, provider.list_records()?.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dry_run() -> Result<()> {
+        let mut provider = MemoryProvider::new("example.com");
+        provider.config.dry_run = true;
+        let desired = DesiredState {
+            prune: false,
+            records: vec![rec(RecordType::A, "www", "192.0.2.1")],
+        };
+
+        let plan = sync(&provider, &desired)?;
+        assert_eq!(1, plan.changes.len());
+        assert!(provider.records.lock().unwrap().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_display() {
+        let plan = Plan {
+            changes: vec![
+                create(RecordType::A, "www", "192.0.2.1"),
+                Change::DeleteAll { rtype: RecordType::A, host: "old".to_string() },
+            ],
+            unchanged: vec![(RecordType::MX, "".to_string())],
+        };
+        assert_eq!("+ create A www -> 192.0.2.1\n- delete all A old\n= unchanged MX \n", plan.to_string());
+    }
+}
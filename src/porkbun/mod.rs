@@ -6,12 +6,13 @@ use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tracing::{error, info, warn};
 
 use crate::{
-    errors::{Error, Result}, generate_helpers, http::{self, ResponseToOption, WithHeaders}, porkbun::types::{
+    errors::{Error, Result}, http::{http_provider_impl, HttpClient, ResponseToOption, WithHeaders}, porkbun::types::{
         AuthOnly,
         CreateUpdate,
+        ListRecords,
         Record,
         Records
-    }, Config, DnsProvider, RecordType
+    }, verify::same_value, Config, RecordType, ZoneListing
 };
 
 
@@ -43,17 +44,18 @@ impl Porkbun {
         }
     }
 
-    fn get_upstream_records<T>(&self, rtype: &RecordType, host: &str) -> Result<Vec<Record<T>>>
+    async fn get_upstream_records<T, C>(&self, client: &C, rtype: &RecordType, host: &str) -> Result<Vec<Record<T>>>
     where
-        T: DeserializeOwned
+        T: DeserializeOwned,
+        C: HttpClient + ?Sized,
     {
         let url = format!("{API_BASE}/retrieveByNameType/{}/{rtype}/{host}", self.config.domain);
         let auth = AuthOnly::from(self.auth.clone());
 
         let body = serde_json::to_string(&auth)?;
-        let response = http::client().post(url)
+        let response = client.post(url)
             .with_json_headers()
-            .send(body)?
+            .send(body).await?
             .to_option()?;
 
         // FIXME: Similar to other impls, can dedup?
@@ -65,11 +67,12 @@ impl Porkbun {
         Ok(recs.records)
     }
 
-    fn get_upstream_record<T>(&self, rtype: &RecordType, host: &str) -> Result<Option<Record<T>>>
+    async fn get_upstream_record<T, C>(&self, client: &C, rtype: &RecordType, host: &str) -> Result<Option<Record<T>>>
     where
-        T: DeserializeOwned
+        T: DeserializeOwned,
+        C: HttpClient + ?Sized,
     {
-        let mut recs = self.get_upstream_records(rtype, host)?;
+        let mut recs = self.get_upstream_records(client, rtype, host).await?;
 
         // FIXME (?): Assumes no or single address (which probably makes
         // sense for DDNS and DNS-01, but may cause issues with
@@ -86,7 +89,10 @@ impl Porkbun {
         Ok(Some(recs.remove(0)))
     }
 
-    fn do_delete(&self, rec: &Record<String>) -> Result<()> {
+    async fn do_delete<C>(&self, client: &C, rec: &Record<String>) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
+    {
         let url = format!("{API_BASE}/delete/{}/{}", self.config.domain, rec.id);
         if self.config.dry_run {
             info!("DRY-RUN: Would have sent DELETE to {url}");
@@ -96,22 +102,23 @@ impl Porkbun {
         info!("Deleting DNS {} record {}", rec.rtype, rec.name);
         let auth = AuthOnly::from(self.auth.clone());
         let body = serde_json::to_string(&auth)?;
-        http::client().post(url)
+        client.post(url)
             .with_json_headers()
-            .send(body)?;
+            .send(body).await?;
 
         Ok(())
     }
 }
 
 
-impl DnsProvider for Porkbun {
+impl Porkbun {
 
-    fn get_record<T>(&self, rtype: RecordType, host: &str) -> Result<Option<T> >
+    async fn get_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<Option<T> >
     where
-        T: DeserializeOwned
+        T: DeserializeOwned,
+        C: HttpClient + ?Sized,
     {
-         let rec: Record<T> = match self.get_upstream_record(&rtype, host)? {
+         let rec: Record<T> = match self.get_upstream_record(client, &rtype, host).await? {
             Some(rec) => rec,
             None => return Ok(None)
         };
@@ -119,9 +126,10 @@ impl DnsProvider for Porkbun {
         Ok(Some(rec.content))
     }
 
-    fn create_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+    async fn create_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str, record: &T) -> Result<()>
     where
-        T: Serialize + DeserializeOwned + Display + Clone
+        T: Serialize + DeserializeOwned + Display + Clone,
+        C: HttpClient + ?Sized,
     {
         let url = format!("{API_BASE}/create/{}", self.config.domain);
 
@@ -139,23 +147,24 @@ impl DnsProvider for Porkbun {
         }
 
         let body = serde_json::to_string(&record)?;
-        let _response = http::client().post(url)
+        let _response = client.post(url)
             .with_json_headers()
-            .send(body)?
+            .send(body).await?
             .check_error()?;
 
         Ok(())
     }
 
-    fn update_record<T>(&self, rtype: RecordType, host: &str, urec: &T) -> Result<()>
+    async fn update_record_with<T, C>(&self, client: &C, rtype: RecordType, host: &str, urec: &T) -> Result<()>
     where
-        T: Serialize + DeserializeOwned + Display + Clone
+        T: Serialize + DeserializeOwned + Display + Clone,
+        C: HttpClient + ?Sized,
     {
-        let existing = match self.get_upstream_record::<T>(&rtype, host)? {
+        let existing = match self.get_upstream_record::<T, C>(client, &rtype, host).await? {
             Some(record) => record,
             None => {
                 // Assume we want to create it
-                return self.create_record(rtype, host, urec);
+                return self.create_record_with(client, rtype, host, urec).await;
             }
         };
 
@@ -176,17 +185,19 @@ impl DnsProvider for Porkbun {
         }
 
         let body = serde_json::to_string(&record)?;
-        let _response = http::client().post(url)
+        let _response = client.post(url)
             .with_json_headers()
-            .send(body)?
+            .send(body).await?
             .check_error()?;
 
         Ok(())
     }
 
-    fn delete_record(&self, rtype: RecordType, host: &str) -> Result<()>
+    async fn delete_record_with<C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
     {
-        let recs: Vec<Record<String>> = self.get_upstream_records(&rtype, host)?;
+        let recs: Vec<Record<String>> = self.get_upstream_records(client, &rtype, host).await?;
         if recs.len() > 1 {
             error!("Returned number of records is {}, should be 1", recs.len());
             return Err(Error::UnexpectedRecord(format!("Returned number of records is {}, should be 1", recs.len())));
@@ -195,25 +206,72 @@ impl DnsProvider for Porkbun {
              return Ok(());
         }
 
-        self.do_delete(&recs[0])?;
+        self.do_delete(client, &recs[0]).await?;
 
         Ok(())
     }
 
-    fn delete_all_records(&self, rtype: RecordType, host: &str) -> Result<()>
+    async fn delete_all_records_with<C>(&self, client: &C, rtype: RecordType, host: &str) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
     {
-        let recs: Vec<Record<String>> = self.get_upstream_records(&rtype, host)?;
+        let recs: Vec<Record<String>> = self.get_upstream_records(client, &rtype, host).await?;
         for rec in recs {
-            self.do_delete(&rec)?;
+            self.do_delete(client, &rec).await?;
         }
 
         Ok(())
     }
 
-    generate_helpers!();
+    async fn delete_value_with<C>(&self, client: &C, rtype: RecordType, host: &str, value: &str) -> Result<()>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let recs: Vec<Record<String>> = self.get_upstream_records(client, &rtype, host).await?;
+        for rec in recs.into_iter().filter(|rec| same_value(rtype, &rec.content, value)) {
+            self.do_delete(client, &rec).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn list_zone_with<C>(&self, client: &C) -> Result<ZoneListing>
+    where
+        C: HttpClient + ?Sized,
+    {
+        let url = format!("{API_BASE}/retrieve/{}", self.config.domain);
+        let auth = AuthOnly::from(self.auth.clone());
+
+        let body = serde_json::to_string(&auth)?;
+        let recs = client.post(url)
+            .with_json_headers()
+            .send(body).await?
+            .to_option::<ListRecords>()?
+            .ok_or(Error::ZoneNotFound(self.config.domain.clone()))?;
+
+        let listing = ZoneListing::collect(recs.records.into_iter()
+            .map(|rec| rec.into_zone_record(&self.config.domain)));
+
+        Ok(listing)
+    }
 
 }
 
+// Porkbun has no PTR records.
+const RECORD_TYPES: &[RecordType] = &[
+    RecordType::A,
+    RecordType::AAAA,
+    RecordType::CAA,
+    RecordType::CNAME,
+    RecordType::MX,
+    RecordType::NS,
+    RecordType::SRV,
+    RecordType::TXT,
+    RecordType::SVCB,
+    RecordType::HTTPS,
+];
+
+http_provider_impl!(Porkbun, RECORD_TYPES);
 
 #[cfg(test)]
 pub(crate) mod tests {
@@ -2,8 +2,8 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     http::de_str,
-    porkbun::Auth,
-    RecordType
+    parse_rtype, porkbun::Auth, relative_host, to_rdata,
+    RdataParts, RecordType, UnsupportedRecord, ZoneRecord,
 };
 
 // This could be folded into the records below with #[serde(flatten)],
@@ -75,3 +75,101 @@ pub struct Record<T> {
 pub struct Records<T> {
     pub records: Vec<Record<T>>
 }
+
+// As `Record`, but for zone listings, which may contain types we
+// don't support (e.g. ALIAS). Numbers are returned as strings.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ListRecord {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub rtype: String,
+    pub content: String,
+    #[serde(deserialize_with = "de_str")]
+    pub ttl: u32,
+    pub prio: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ListRecords {
+    pub records: Vec<ListRecord>
+}
+
+impl ListRecord {
+    pub fn into_zone_record(self, domain: &str) -> Result<ZoneRecord, UnsupportedRecord> {
+        let Some(rtype) = parse_rtype(&self.rtype) else {
+            return Err(UnsupportedRecord::unknown_type(self.rtype, relative_host(&self.name, domain), self.content));
+        };
+        let parts = RdataParts {
+            priority: self.prio.and_then(|p| p.parse().ok()),
+            ..Default::default()
+        };
+
+        Ok(ZoneRecord {
+            rtype,
+            host: relative_host(&self.name, domain),
+            value: to_rdata(rtype, &self.content, parts),
+            ttl: Some(self.ttl),
+            modified: None,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::check_malformed_replies;
+
+    #[test]
+    fn test_malformed_records() {
+        check_malformed_replies::<Records<String>>(r#"{
+            "status": "SUCCESS",
+            "records": [{
+                "id": "106926659",
+                "name": "www.example.com",
+                "type": "A",
+                "content": "1.1.1.1",
+                "ttl": "600"
+            }]
+        }"#);
+    }
+
+    #[test]
+    fn test_malformed_list() {
+        check_malformed_replies::<ListRecords>(r#"{
+            "status": "SUCCESS",
+            "records": [{
+                "id": "106926659",
+                "name": "example.com",
+                "type": "MX",
+                "content": "mail.example.com",
+                "ttl": "600",
+                "prio": "10"
+            }]
+        }"#);
+    }
+
+    #[test]
+    fn test_into_zone_record() {
+        let recs: ListRecords = serde_json::from_str(r#"{
+            "records": [
+                {"name": "example.com", "type": "MX", "content": "mail.example.com", "ttl": "600", "prio": "10"},
+                {"name": "www.example.com", "type": "A", "content": "1.1.1.1", "ttl": "600", "prio": null},
+                {"name": "example.com", "type": "ALIAS", "content": "example.org", "ttl": "600", "prio": null}
+            ]
+        }"#).unwrap();
+        let recs: Vec<ZoneRecord> = recs.records.into_iter()
+            .filter_map(|r| r.into_zone_record("example.com").ok())
+            .collect();
+
+        assert_eq!(2, recs.len());
+        assert_eq!(ZoneRecord {
+            rtype: RecordType::MX,
+            host: "".to_string(),
+            value: "10 mail.example.com".to_string(),
+            ttl: Some(600),
+            modified: None,
+        }, recs[0]);
+        assert_eq!("www", recs[1].host);
+    }
+}
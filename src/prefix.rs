@@ -0,0 +1,251 @@
+//! AAAA records derived from a delegated IPv6 prefix.
+//!
+//! Where an ISP delegates a prefix that changes from time to time,
+//! each host keeps a fixed interface identifier (suffix) and its
+//! address is the current prefix plus that suffix. [`detect()`] finds
+//! the prefix from any `AddressSource`, usually an interface, and
+//! [`update_hosts()`] publishes the derived AAAA records:
+//!
+//! ```no_run
+//! use zone_update::{Config, porkbun, discovery::interfaces::Interfaces, prefix::{self, HostSuffix}};
+//! # fn main() -> zone_update::errors::Result<()> {
+//! let config = Config { domain: "example.com".to_string(), dry_run: false };
+//! let auth = porkbun::Auth { key: "key".to_string(), secret: "secret".to_string() };
+//! let client = porkbun::Porkbun::new(config, auth);
+//!
+//! let prefix = prefix::detect(&Interfaces::default(), 56)?;
+//! let hosts = [
+//!     HostSuffix { host: "nas".to_string(), suffix: "::10:0:0:2".parse()? },
+//!     HostSuffix { host: "printer".to_string(), suffix: "::20:0:0:5".parse()? },
+//! ];
+//! let report = prefix::update_hosts(&client, &prefix, &hosts)?;
+//! println!("Updated {:?}", report.updated);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    fmt::{self, Display, Formatter},
+    net::{IpAddr, Ipv6Addr},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    discovery::{AddressSource, Family},
+    errors::{Error, Result},
+    DnsProvider,
+};
+
+
+/// An IPv6 prefix, e.g. `2001:db8:1200::/56`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Prefix {
+    addr: Ipv6Addr,
+    len: u8,
+}
+
+impl Prefix {
+    /// The prefix of length `len` containing `addr`. Bits of `addr`
+    /// past the prefix are ignored.
+    pub fn new(addr: Ipv6Addr, len: u8) -> Result<Self> {
+        if len > 128 {
+            return Err(Error::PrefixError(format!("prefix length {len} is longer than 128")));
+        }
+        let addr = Ipv6Addr::from_bits(addr.to_bits() & mask(len));
+        Ok(Self { addr, len })
+    }
+
+    /// The network address.
+    pub fn addr(&self) -> Ipv6Addr {
+        self.addr
+    }
+
+    /// The prefix length in bits.
+    pub fn prefix_len(&self) -> u8 {
+        self.len
+    }
+
+    /// Whether `addr` is within the prefix.
+    pub fn contains(&self, addr: &Ipv6Addr) -> bool {
+        addr.to_bits() & mask(self.len) == self.addr.to_bits()
+    }
+
+    /// The address with this prefix and the interface identifier
+    /// `suffix`. The suffix must fit in the bits after the prefix.
+    pub fn host(&self, suffix: &Ipv6Addr) -> Result<Ipv6Addr> {
+        let suffix = suffix.to_bits();
+        if suffix & mask(self.len) != 0 {
+            return Err(Error::PrefixError(format!("suffix {} overlaps prefix {self}", Ipv6Addr::from_bits(suffix))));
+        }
+        Ok(Ipv6Addr::from_bits(self.addr.to_bits() | suffix))
+    }
+}
+
+/// The network mask of a prefix of length `len`.
+fn mask(len: u8) -> u128 {
+    u128::MAX.checked_shl(128 - len as u32).unwrap_or(0)
+}
+
+impl Display for Prefix {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.len)
+    }
+}
+
+impl FromStr for Prefix {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, len) = s.split_once('/')
+            .ok_or_else(|| Error::PrefixError(format!("{s} has no prefix length")))?;
+        let len = len.parse()
+            .map_err(|_| Error::PrefixError(format!("invalid prefix length in {s}")))?;
+        Prefix::new(addr.parse()?, len)
+    }
+}
+
+/// A host and its fixed interface identifier.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostSuffix {
+    /// The record host, relative to the zone.
+    pub host: String,
+    /// The interface identifier, e.g. `::1` or `::211:22ff:fe33:4455`.
+    pub suffix: Ipv6Addr,
+}
+
+/// The outcome of `update_hosts()`.
+#[derive(Clone, Debug, Default)]
+pub struct PrefixReport {
+    /// Hosts whose AAAA record was created or changed, with the new
+    /// address.
+    pub updated: Vec<(String, Ipv6Addr)>,
+    /// Hosts that already had the derived address.
+    pub unchanged: Vec<String>,
+    /// Hosts that failed, with the error.
+    pub failed: Vec<(String, String)>,
+}
+
+
+/// Detect the current prefix of length `len` from the IPv6 address
+/// reported by `source`.
+pub fn detect(source: &dyn AddressSource, len: u8) -> Result<Prefix> {
+    match source.address(Family::V6)? {
+        IpAddr::V6(addr) => {
+            let prefix = Prefix::new(addr, len)?;
+            info!("Detected prefix {prefix} from {}", source.name());
+            Ok(prefix)
+        }
+        IpAddr::V4(addr) => Err(Error::DiscoveryError(format!("{} returned {addr}, expected an IPv6 address",
+                                                             source.name()))),
+    }
+}
+
+/// The AAAA address of each host under `prefix`.
+pub fn derive(prefix: &Prefix, hosts: &[HostSuffix]) -> Result<Vec<(String, Ipv6Addr)>> {
+    hosts.iter()
+        .map(|h| Ok((h.host.clone(), prefix.host(&h.suffix)?)))
+        .collect()
+}
+
+/// Set the AAAA record of each host to its address under `prefix`.
+///
+/// All addresses are derived before any change is made, so an
+/// invalid suffix fails the whole update. Records that are already
+/// correct are left alone, and missing records are created. A
+/// failure for one host doesn't stop the others; see
+/// `PrefixReport::failed`.
+pub fn update_hosts<P>(client: &P, prefix: &Prefix, hosts: &[HostSuffix]) -> Result<PrefixReport>
+where
+    P: DnsProvider + ?Sized
+{
+    let mut report = PrefixReport::default();
+    for (host, addr) in derive(prefix, hosts)? {
+        let result = client.get_aaaa_record(&host)
+            .and_then(|current| match current {
+                Some(cur) if cur == addr => Ok(false),
+                Some(_) => client.update_aaaa_record(&host, &addr).map(|_| true),
+                None => client.create_aaaa_record(&host, &addr).map(|_| true),
+            });
+        record(&mut report, host, addr, result);
+    }
+    Ok(report)
+}
+
+pub(crate) fn record(report: &mut PrefixReport, host: String, addr: Ipv6Addr, result: Result<bool>) {
+    match result {
+        Ok(true) => {
+            info!("Set {host} AAAA to {addr}");
+            report.updated.push((host, addr));
+        }
+        Ok(false) => report.unchanged.push(host),
+        Err(e) => {
+            warn!("Failed to set {host} AAAA to {addr}: {e}");
+            report.failed.push((host, e.to_string()));
+        }
+    }
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::tests::MemoryProvider;
+
+    pub(crate) fn hosts() -> Vec<HostSuffix> {
+        vec![
+            HostSuffix { host: "nas".to_string(), suffix: "::10:0:0:2".parse().unwrap() },
+            HostSuffix { host: "printer".to_string(), suffix: "::20:0:0:5".parse().unwrap() },
+        ]
+    }
+
+    #[test]
+    fn test_prefix() -> Result<()> {
+        let prefix: Prefix = "2001:db8:1234:5678::1/56".parse()?;
+        assert_eq!("2001:db8:1234:5600::/56", prefix.to_string());
+        assert!(prefix.contains(&"2001:db8:1234:56ff::1".parse()?));
+        assert!(!prefix.contains(&"2001:db8:1234:5700::1".parse()?));
+
+        assert_eq!("2001:db8:1234:5610::1".parse::<Ipv6Addr>()?, prefix.host(&"0:0:0:10::1".parse()?)?);
+        assert!(matches!(prefix.host(&"0:0:0:100::1".parse()?), Err(Error::PrefixError(_))));
+
+        assert_eq!(Ipv6Addr::UNSPECIFIED, "2001:db8::/0".parse::<Prefix>()?.addr());
+        assert!("2001:db8::1".parse::<Prefix>().is_err());
+        assert!("2001:db8::/129".parse::<Prefix>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_hosts() -> Result<()> {
+        let client = MemoryProvider::new("example.com");
+        client.create_aaaa_record("nas", &"2001:db8:aaaa::10:0:0:2".parse()?)?;
+
+        let prefix = "2001:db8:bbbb::/48".parse()?;
+        let report = update_hosts(&client, &prefix, &hosts())?;
+        assert_eq!(2, report.updated.len());
+        assert_eq!(Some("2001:db8:bbbb:0:10::2".parse()?), client.get_aaaa_record("nas")?);
+        assert_eq!(Some("2001:db8:bbbb:0:20::5".parse()?), client.get_aaaa_record("printer")?);
+
+        let report = update_hosts(&client, &prefix, &hosts())?;
+        assert!(report.updated.is_empty());
+        assert_eq!(vec!["nas", "printer"], report.unchanged);
+
+        // Suffixes are checked before any change is made
+        let bad = [HostSuffix { host: "bad".to_string(), suffix: "1::1".parse()? }];
+        let hosts = [hosts(), bad.to_vec()].concat();
+        assert!(update_hosts(&client, &"2001:db8:cccc::/48".parse()?, &hosts).is_err());
+        assert_eq!(Some("2001:db8:bbbb:0:10::2".parse()?), client.get_aaaa_record("nas")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_hosts_dyn() -> Result<()> {
+        let client: Box<dyn DnsProvider> = Box::new(MemoryProvider::new("example.com"));
+        let report = update_hosts(&*client, &"2001:db8:bbbb::/48".parse()?, &hosts())?;
+        assert_eq!(2, report.updated.len());
+        assert_eq!(Some("2001:db8:bbbb:0:20::5".parse()?), client.get_aaaa_record("printer")?);
+        Ok(())
+    }
+}
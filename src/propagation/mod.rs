@@ -0,0 +1,598 @@
+//! DNS propagation checks.
+//!
+//! A change accepted by a provider's API can take some time to reach
+//! all of the zone's authoritative nameservers. Before asking an ACME
+//! server to validate a DNS-01 challenge, for example, it is worth
+//! waiting until every nameserver returns the new record:
+//!
+//! ```no_run
+//! use zone_update::{propagation::{wait_for_propagation, PropagationOptions}, RecordType};
+//! # fn main() -> zone_update::errors::Result<()> {
+//! let report = wait_for_propagation("example.com", "_acme-challenge.www", RecordType::TXT,
+//!                                   Some("token"), &PropagationOptions::default())?;
+//! println!("Propagated to {:?} after {:?}", report.nameservers, report.elapsed);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The nameservers are found by looking up the zone's NS records with
+//! the system resolver, and are then queried directly without
+//! recursion. Queries use UDP, falling back to TCP for truncated
+//! responses. Only A, AAAA, CNAME, NS and TXT records can be checked.
+//!
+//! `resolve_cname()` follows a CNAME chain with the resolver, e.g. to
+//! find the zone a challenge name is delegated to.
+//!
+//! Async versions are in `async_impl::propagation`.
+
+pub(crate) mod wire;
+
+use std::{
+    fs,
+    io::{ErrorKind, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket},
+    thread,
+    time::{Duration, Instant},
+};
+
+use tracing::{debug, info, warn};
+
+use crate::{
+    errors::{Error, Result},
+    relative_host,
+    verify::is_visible,
+    RecordType,
+};
+use wire::{Query, Response};
+
+
+/// Options for propagation checks.
+#[derive(Clone, Debug)]
+pub struct PropagationOptions {
+    /// How long to wait for all nameservers to agree.
+    pub timeout: Duration,
+    /// The delay between rounds of queries.
+    pub interval: Duration,
+    /// The timeout for a single query.
+    pub query_timeout: Duration,
+    /// The recursive resolver used to find the nameservers. Defaults
+    /// to the first nameserver in `/etc/resolv.conf`.
+    pub resolver: SocketAddr,
+    /// The nameservers to check; if empty they are looked up.
+    pub nameservers: Vec<SocketAddr>,
+    /// The port to query discovered nameservers on.
+    pub port: u16,
+    /// Also query nameservers over IPv6.
+    pub ipv6: bool,
+}
+
+impl Default for PropagationOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(120),
+            interval: Duration::from_secs(2),
+            query_timeout: Duration::from_secs(3),
+            resolver: system_resolver(),
+            nameservers: Vec::new(),
+            port: 53,
+            ipv6: false,
+        }
+    }
+}
+
+/// The result of a successful propagation check.
+#[derive(Clone, Debug)]
+pub struct PropagationReport {
+    /// The time taken for all nameservers to agree.
+    pub elapsed: Duration,
+    /// The nameservers that were checked.
+    pub nameservers: Vec<SocketAddr>,
+}
+
+
+/// The first nameserver in `/etc/resolv.conf`, or Cloudflare's
+/// public resolver if there is none.
+pub fn system_resolver() -> SocketAddr {
+    fs::read_to_string("/etc/resolv.conf").ok()
+        .and_then(|conf| parse_resolv_conf(&conf))
+        .unwrap_or(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 53))
+}
+
+fn parse_resolv_conf(conf: &str) -> Option<SocketAddr> {
+    conf.lines()
+        .filter_map(|line| line.strip_prefix("nameserver"))
+        .filter_map(|addr| addr.trim().parse::<IpAddr>().ok())
+        .map(|ip| SocketAddr::new(ip, 53))
+        .next()
+}
+
+/// The fully-qualified name of `host`, which may be relative to
+/// `domain`.
+pub(crate) fn fqdn(domain: &str, host: &str) -> String {
+    let domain = domain.trim_end_matches('.');
+    match relative_host(host, domain).as_str() {
+        "" => domain.to_string(),
+        rel => format!("{rel}.{domain}"),
+    }
+}
+
+pub(crate) fn answer_values(resp: &Response, qtype: u16) -> Result<Vec<String>> {
+    match resp.rcode {
+        0 => Ok(resp.values(qtype)),
+        wire::RCODE_NXDOMAIN => Ok(Vec::new()),
+        rcode => Err(Error::DnsError(format!("Server returned error code {rcode}"))),
+    }
+}
+
+/// The address record types to look up nameservers with.
+pub(crate) fn address_types(opts: &PropagationOptions) -> &'static [u16] {
+    if opts.ipv6 {
+        &[wire::TYPE_A, wire::TYPE_AAAA]
+    } else {
+        &[wire::TYPE_A]
+    }
+}
+
+pub(crate) fn nameserver_addrs(domain: &str, mut addrs: Vec<SocketAddr>) -> Result<Vec<SocketAddr>> {
+    addrs.sort();
+    addrs.dedup();
+    if addrs.is_empty() {
+        return Err(Error::DnsError(format!("No authoritative nameservers found for {domain}")));
+    }
+    Ok(addrs)
+}
+
+pub(crate) fn query_timeout(server: SocketAddr) -> Error {
+    Error::DnsError(format!("Query to {server} timed out"))
+}
+
+pub(crate) fn mismatched_id(server: SocketAddr) -> Error {
+    Error::DnsError(format!("Mismatched response ID from {server}"))
+}
+
+pub(crate) fn not_propagated(rtype: RecordType, fqdn: &str, expected: Option<&str>,
+                             pending: &[SocketAddr], elapsed: Duration) -> Error
+{
+    let change = match expected {
+        Some(value) => format!("{rtype} {fqdn} -> {value}"),
+        None => format!("deletion of {rtype} {fqdn}"),
+    };
+    let servers = pending.iter()
+        .map(SocketAddr::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+    Error::NotPropagated(format!("{change} (pending on {servers})"), elapsed)
+}
+
+fn unspecified(server: SocketAddr) -> SocketAddr {
+    let ip = match server {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    SocketAddr::new(ip, 0)
+}
+
+
+fn udp_exchange(server: SocketAddr, query: &Query, timeout: Duration) -> Result<Response> {
+    let socket = UdpSocket::bind(unspecified(server))?;
+    socket.connect(server)?;
+    socket.send(&query.encode()?)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0; 4096];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(query_timeout(server));
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let len = match socket.recv(&mut buf) {
+            Ok(len) => len,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Err(query_timeout(server));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        // Ignore stray or malformed datagrams
+        match wire::parse(&buf[..len]) {
+            Ok(resp) if resp.id == query.id => return Ok(resp),
+            _ => debug!("Ignoring unexpected datagram from {server}"),
+        }
+    }
+}
+
+fn tcp_exchange(server: SocketAddr, query: &Query, timeout: Duration) -> Result<Response> {
+    let mut stream = TcpStream::connect_timeout(&server, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let msg = query.encode()?;
+    let mut out = (msg.len() as u16).to_be_bytes().to_vec();
+    out.extend(msg);
+    stream.write_all(&out)?;
+
+    let mut len = [0; 2];
+    stream.read_exact(&mut len)?;
+    let mut buf = vec![0; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut buf)?;
+
+    let resp = wire::parse(&buf)?;
+    if resp.id != query.id {
+        return Err(mismatched_id(server));
+    }
+    Ok(resp)
+}
+
+fn exchange(server: SocketAddr, query: &Query, timeout: Duration) -> Result<Response> {
+    let resp = udp_exchange(server, query, timeout)?;
+    if resp.truncated {
+        debug!("Truncated response from {server}, retrying over TCP");
+        return tcp_exchange(server, query, timeout);
+    }
+    Ok(resp)
+}
+
+
+/// Look up the addresses of the authoritative nameservers for
+/// `domain`, which should be the zone apex. Returns
+/// `opts.nameservers` if set.
+pub fn authoritative_nameservers(domain: &str, opts: &PropagationOptions) -> Result<Vec<SocketAddr>> {
+    if !opts.nameservers.is_empty() {
+        return Ok(opts.nameservers.clone());
+    }
+
+    let query = Query::new(domain, wire::TYPE_NS, true);
+    let names = answer_values(&exchange(opts.resolver, &query, opts.query_timeout)?, wire::TYPE_NS)?;
+    debug!("Nameservers for {domain}: {names:?}");
+
+    let mut addrs = Vec::new();
+    for name in names {
+        for &qtype in address_types(opts) {
+            let query = Query::new(&name, qtype, true);
+            let ips = answer_values(&exchange(opts.resolver, &query, opts.query_timeout)?, qtype)?;
+            addrs.extend(ips.iter()
+                         .filter_map(|ip| ip.parse::<IpAddr>().ok())
+                         .map(|ip| SocketAddr::new(ip, opts.port)));
+        }
+    }
+
+    nameserver_addrs(domain, addrs)
+}
+
+/// Follow the CNAME chain from `name` with the resolver, returning
+/// the final name; this is `name` itself if it isn't an alias.
+pub fn resolve_cname(name: &str, opts: &PropagationOptions) -> Result<String> {
+    let mut chain = vec![name.trim_end_matches('.').to_string()];
+    loop {
+        let current = chain.last().unwrap();
+        let query = Query::new(current, wire::TYPE_CNAME, true);
+        let resp = exchange(opts.resolver, &query, opts.query_timeout)?;
+        match answer_values(&resp, wire::TYPE_CNAME)?.pop() {
+            Some(target) => follow_cname(&mut chain, target)?,
+            None => return Ok(chain.pop().unwrap()),
+        }
+    }
+}
+
+/// The maximum length of a CNAME chain.
+const MAX_CNAME_CHAIN: usize = 8;
+
+pub(crate) fn follow_cname(chain: &mut Vec<String>, target: String) -> Result<()> {
+    debug!("{} is an alias for {target}", chain.last().unwrap());
+    if chain.iter().any(|name| name.eq_ignore_ascii_case(&target)) {
+        return Err(Error::DnsError(format!("CNAME loop at {target}")));
+    }
+    if chain.len() >= MAX_CNAME_CHAIN {
+        return Err(Error::DnsError(format!("CNAME chain from {} is too long", chain[0])));
+    }
+    chain.push(target);
+    Ok(())
+}
+
+/// Query a nameserver directly, without recursion, for the values of
+/// a record set. A missing name returns no values.
+pub fn lookup(server: SocketAddr, fqdn: &str, rtype: RecordType, timeout: Duration) -> Result<Vec<String>> {
+    let qtype = wire::type_code(rtype)?;
+    let query = Query::new(fqdn, qtype, false);
+    answer_values(&exchange(server, &query, timeout)?, qtype)
+}
+
+/// Wait until every authoritative nameserver for `domain` returns
+/// `expected` for the record, or no value if `expected` is `None`.
+/// Other values in the set are ignored. Fails with
+/// `Error::NotPropagated` if they don't agree within the timeout.
+pub fn wait_for_propagation(domain: &str, host: &str, rtype: RecordType, expected: Option<&str>,
+                            opts: &PropagationOptions) -> Result<PropagationReport>
+{
+    wire::type_code(rtype)?;
+    let fqdn = fqdn(domain, host);
+    let nameservers = authoritative_nameservers(domain, opts)?;
+    let start = Instant::now();
+
+    loop {
+        let pending = nameservers.iter()
+            .copied()
+            .filter(|&ns| match lookup(ns, &fqdn, rtype, opts.query_timeout) {
+                Ok(values) => !is_visible(rtype, expected, &values),
+                Err(e) => {
+                    warn!("Failed to query {ns} for {fqdn}: {e}");
+                    true
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let elapsed = start.elapsed();
+        if pending.is_empty() {
+            info!("{rtype} {fqdn} propagated to {} nameservers after {elapsed:?}", nameservers.len());
+            return Ok(PropagationReport { elapsed, nameservers });
+        }
+        if elapsed >= opts.timeout {
+            return Err(not_propagated(rtype, &fqdn, expected, &pending, elapsed));
+        }
+        debug!("{rtype} {fqdn} pending on {pending:?}");
+        thread::sleep(opts.interval.min(opts.timeout - elapsed));
+    }
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::{
+        net::TcpListener,
+        sync::{Arc, Mutex},
+    };
+
+    type Records = Arc<Mutex<Vec<(String, u16, Vec<u8>)>>>;
+
+    /// An in-process authoritative server, listening on UDP and TCP
+    /// on the same local port.
+    pub(crate) struct Responder {
+        pub(crate) addr: SocketAddr,
+        records: Records,
+    }
+
+    impl Responder {
+        /// Start the server; if `truncate` is set all UDP responses
+        /// are truncated, forcing clients to use TCP.
+        pub(crate) fn start(truncate: bool) -> Self {
+            let (udp, tcp) = loop {
+                let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+                if let Ok(tcp) = TcpListener::bind(udp.local_addr().unwrap()) {
+                    break (udp, tcp);
+                }
+            };
+            let addr = udp.local_addr().unwrap();
+            let records = Records::default();
+
+            let recs = records.clone();
+            thread::spawn(move || {
+                let mut buf = [0; 4096];
+                while let Ok((len, peer)) = udp.recv_from(&mut buf) {
+                    let _ = udp.send_to(&respond(&recs, &buf[..len], truncate), peer);
+                }
+            });
+
+            let recs = records.clone();
+            thread::spawn(move || {
+                for mut stream in tcp.incoming().flatten() {
+                    let mut len = [0; 2];
+                    while stream.read_exact(&mut len).is_ok() {
+                        let mut msg = vec![0; u16::from_be_bytes(len) as usize];
+                        if stream.read_exact(&mut msg).is_err() {
+                            break;
+                        }
+                        let resp = respond(&recs, &msg, false);
+                        let mut out = (resp.len() as u16).to_be_bytes().to_vec();
+                        out.extend(resp);
+                        let _ = stream.write_all(&out);
+                    }
+                }
+            });
+
+            Self { addr, records }
+        }
+
+        pub(crate) fn add(&self, name: &str, qtype: u16, rdata: Vec<u8>) {
+            self.records.lock().unwrap().push((name.to_string(), qtype, rdata));
+        }
+
+        pub(crate) fn add_txt(&self, name: &str, txt: &str) {
+            let mut rdata = vec![txt.len() as u8];
+            rdata.extend(txt.as_bytes());
+            self.add(name, wire::TYPE_TXT, rdata);
+        }
+
+        pub(crate) fn add_a(&self, name: &str, ip: Ipv4Addr) {
+            self.add(name, wire::TYPE_A, ip.octets().to_vec());
+        }
+
+        pub(crate) fn add_ns(&self, name: &str, target: &str) {
+            let mut rdata = Vec::new();
+            wire::encode_name(target, &mut rdata).unwrap();
+            self.add(name, wire::TYPE_NS, rdata);
+        }
+
+        pub(crate) fn add_cname(&self, name: &str, target: &str) {
+            let mut rdata = Vec::new();
+            wire::encode_name(target, &mut rdata).unwrap();
+            self.add(name, wire::TYPE_CNAME, rdata);
+        }
+
+        pub(crate) fn remove(&self, name: &str, qtype: u16) {
+            self.records.lock().unwrap()
+                .retain(|(n, t, _)| !(n.eq_ignore_ascii_case(name) && *t == qtype));
+        }
+
+        /// Options that discover this server as the nameserver for
+        /// `domain`.
+        pub(crate) fn options(&self, domain: &str) -> PropagationOptions {
+            let ns = format!("ns1.{domain}");
+            self.add_ns(domain, &ns);
+            self.add_a(&ns, Ipv4Addr::LOCALHOST);
+            PropagationOptions {
+                timeout: Duration::from_secs(5),
+                interval: Duration::from_millis(10),
+                query_timeout: Duration::from_secs(1),
+                resolver: self.addr,
+                nameservers: Vec::new(),
+                port: self.addr.port(),
+                ipv6: false,
+            }
+        }
+    }
+
+    fn respond(records: &Records, query: &[u8], truncate: bool) -> Vec<u8> {
+        let (name, end) = wire::read_name(query, 12).unwrap();
+        let qtype = u16::from_be_bytes([query[end], query[end + 1]]);
+        let records = records.lock().unwrap();
+
+        let exists = records.iter().any(|(n, _, _)| n.eq_ignore_ascii_case(&name));
+        let answers = records.iter()
+            .filter(|(n, t, _)| n.eq_ignore_ascii_case(&name) && *t == qtype && !truncate)
+            .collect::<Vec<_>>();
+
+        let mut flags: u16 = 0x8400;
+        if truncate {
+            flags |= 0x0200;
+        }
+        if !exists {
+            flags |= wire::RCODE_NXDOMAIN as u16;
+        }
+
+        let mut msg = query[..2].to_vec();
+        for field in [flags, 1, answers.len() as u16, 0, 0] {
+            msg.extend(field.to_be_bytes());
+        }
+        msg.extend(&query[12..end + 4]);
+        for (_, rtype, rdata) in answers {
+            msg.extend([0xc0, 12]);
+            msg.extend(rtype.to_be_bytes());
+            msg.extend([0, 1, 0, 0, 0, 60]);
+            msg.extend((rdata.len() as u16).to_be_bytes());
+            msg.extend(rdata);
+        }
+        msg
+    }
+
+    #[test]
+    fn test_fqdn() {
+        assert_eq!("example.com", fqdn("example.com", "@"));
+        assert_eq!("example.com", fqdn("example.com.", ""));
+        assert_eq!("www.example.com", fqdn("example.com", "www"));
+        assert_eq!("www.example.com", fqdn("example.com", "www.example.com."));
+    }
+
+    #[test]
+    fn test_parse_resolv_conf() {
+        let conf = "# comment\nsearch lan\nnameserver fe80::1%eth0\nnameserver 192.168.1.1\nnameserver 10.0.0.1\n";
+        assert_eq!(Some("192.168.1.1:53".parse().unwrap()), parse_resolv_conf(conf));
+        assert_eq!(None, parse_resolv_conf("search lan\n"));
+    }
+
+    #[test]
+    fn test_discovery() -> Result<()> {
+        let server = Responder::start(false);
+        let opts = server.options("example.com");
+        assert_eq!(vec![SocketAddr::new(Ipv4Addr::LOCALHOST.into(), server.addr.port())],
+                   authoritative_nameservers("example.com", &opts)?);
+
+        let err = authoritative_nameservers("example.org", &opts).unwrap_err();
+        assert!(matches!(err, Error::DnsError(_)));
+
+        let fixed = PropagationOptions { nameservers: vec![server.addr], ..opts };
+        assert_eq!(vec![server.addr], authoritative_nameservers("example.org", &fixed)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lookup() -> Result<()> {
+        let server = Responder::start(false);
+        server.add_txt("_acme-challenge.example.com", "token");
+        server.add_a("www.example.com", Ipv4Addr::new(192, 0, 2, 1));
+        let timeout = Duration::from_secs(1);
+
+        assert_eq!(vec!["token"], lookup(server.addr, "_acme-challenge.example.com", RecordType::TXT, timeout)?);
+        assert_eq!(vec!["192.0.2.1"], lookup(server.addr, "WWW.example.com", RecordType::A, timeout)?);
+        assert!(lookup(server.addr, "www.example.com", RecordType::TXT, timeout)?.is_empty());
+        assert!(lookup(server.addr, "missing.example.com", RecordType::A, timeout)?.is_empty());
+        assert!(matches!(lookup(server.addr, "www.example.com", RecordType::MX, timeout),
+                         Err(Error::Unsupported(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_cname() -> Result<()> {
+        let server = Responder::start(false);
+        let opts = server.options("example.com");
+        server.add_cname("_acme-challenge.example.com", "_acme-challenge.example.net.");
+        server.add_cname("_acme-challenge.example.net", "abc123.auth.example.org");
+        server.add_cname("loop1.example.com", "loop2.example.com");
+        server.add_cname("loop2.example.com", "LOOP1.example.com");
+
+        assert_eq!("abc123.auth.example.org", resolve_cname("_acme-challenge.example.com.", &opts)?);
+        assert_eq!("www.example.com", resolve_cname("www.example.com", &opts)?);
+        let err = resolve_cname("loop1.example.com", &opts).unwrap_err();
+        assert_eq!("DNS error: CNAME loop at LOOP1.example.com", err.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tcp_fallback() -> Result<()> {
+        let server = Responder::start(true);
+        server.add_txt("_acme-challenge.example.com", "token");
+        let values = lookup(server.addr, "_acme-challenge.example.com", RecordType::TXT, Duration::from_secs(1))?;
+        assert_eq!(vec!["token"], values);
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_timeout() {
+        // Bound but never answers
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let res = lookup(silent.local_addr().unwrap(), "example.com", RecordType::A, Duration::from_millis(50));
+        assert!(matches!(res, Err(Error::DnsError(_))));
+    }
+
+    #[test]
+    fn test_wait_for_propagation() -> Result<()> {
+        let server = Arc::new(Responder::start(false));
+        let opts = server.options("example.com");
+
+        let delayed = server.clone();
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            delayed.add_txt("_acme-challenge.www.example.com", "token");
+        });
+        let report = wait_for_propagation("example.com", "_acme-challenge.www", RecordType::TXT,
+                                          Some("\"token\""), &opts)?;
+        writer.join().unwrap();
+        assert_eq!(1, report.nameservers.len());
+        assert!(report.elapsed >= Duration::from_millis(40));
+
+        server.remove("_acme-challenge.www.example.com", wire::TYPE_TXT);
+        wait_for_propagation("example.com", "_acme-challenge.www", RecordType::TXT, None, &opts)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_not_propagated() {
+        let updated = Responder::start(false);
+        let stale = Responder::start(false);
+        updated.add_txt("_acme-challenge.example.com", "token");
+        stale.add_txt("_acme-challenge.example.com", "old");
+
+        let opts = PropagationOptions {
+            timeout: Duration::from_millis(50),
+            nameservers: vec![updated.addr, stale.addr],
+            ..updated.options("example.com")
+        };
+        let err = wait_for_propagation("example.com", "_acme-challenge", RecordType::TXT, Some("token"), &opts)
+            .unwrap_err();
+        let Error::NotPropagated(msg, elapsed) = err else {
+            panic!("Unexpected error {err}");
+        };
+        assert_eq!(format!("TXT _acme-challenge.example.com -> token (pending on {})", stale.addr), msg);
+        assert!(elapsed >= Duration::from_millis(50));
+    }
+}
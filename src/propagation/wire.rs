@@ -0,0 +1,323 @@
+// Minimal DNS message encoding and decoding; just enough to send a
+// single question and read the answers. See RFC 1035 section 4.
+
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    sync::atomic::{AtomicU16, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{errors::{Error, Result}, RecordType};
+
+pub(crate) const TYPE_A: u16 = 1;
+pub(crate) const TYPE_NS: u16 = 2;
+pub(crate) const TYPE_CNAME: u16 = 5;
+pub(crate) const TYPE_TXT: u16 = 16;
+pub(crate) const TYPE_AAAA: u16 = 28;
+const TYPE_OPT: u16 = 41;
+const CLASS_IN: u16 = 1;
+
+pub(crate) const RCODE_NXDOMAIN: u8 = 3;
+
+/// The UDP payload size advertised with EDNS(0).
+const UDP_PAYLOAD: u16 = 1232;
+
+/// The query type for the record types we can check.
+pub(crate) fn type_code(rtype: RecordType) -> Result<u16> {
+    match rtype {
+        RecordType::A => Ok(TYPE_A),
+        RecordType::AAAA => Ok(TYPE_AAAA),
+        RecordType::CNAME => Ok(TYPE_CNAME),
+        RecordType::NS => Ok(TYPE_NS),
+        RecordType::TXT => Ok(TYPE_TXT),
+        _ => Err(Error::Unsupported(format!("propagation checks for {rtype} records"))),
+    }
+}
+
+fn next_id() -> u16 {
+    static NEXT: AtomicU16 = AtomicU16::new(0);
+    let seed = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u16)
+        .unwrap_or_default();
+    NEXT.fetch_add(1, Ordering::Relaxed).wrapping_add(seed)
+}
+
+fn dns_err(msg: &str) -> Error {
+    Error::DnsError(msg.to_string())
+}
+
+
+/// A single question.
+#[derive(Clone, Debug)]
+pub(crate) struct Query {
+    pub id: u16,
+    pub name: String,
+    pub qtype: u16,
+    /// Whether to request recursion; off for authoritative queries.
+    pub recurse: bool,
+}
+
+impl Query {
+    pub fn new(name: &str, qtype: u16, recurse: bool) -> Self {
+        Self {
+            id: next_id(),
+            name: name.to_string(),
+            qtype,
+            recurse,
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let flags: u16 = if self.recurse { 0x0100 } else { 0 };
+        let mut msg = Vec::with_capacity(64);
+        for field in [self.id, flags, 1, 0, 0, 1] {
+            msg.extend(field.to_be_bytes());
+        }
+        encode_name(&self.name, &mut msg)?;
+        msg.extend(self.qtype.to_be_bytes());
+        msg.extend(CLASS_IN.to_be_bytes());
+
+        // EDNS(0) OPT record, so larger answers fit in UDP
+        msg.push(0);
+        msg.extend(TYPE_OPT.to_be_bytes());
+        msg.extend(UDP_PAYLOAD.to_be_bytes());
+        msg.extend([0; 6]);
+
+        Ok(msg)
+    }
+}
+
+pub(crate) fn encode_name(name: &str, msg: &mut Vec<u8>) -> Result<()> {
+    let name = name.trim_end_matches('.');
+    if name.len() > 253 {
+        return Err(Error::DnsError(format!("Name too long: {name}")));
+    }
+    for label in name.split('.').filter(|l| !l.is_empty()) {
+        if label.len() > 63 {
+            return Err(Error::DnsError(format!("Label too long in {name}")));
+        }
+        msg.push(label.len() as u8);
+        msg.extend(label.as_bytes());
+    }
+    msg.push(0);
+    Ok(())
+}
+
+
+/// Decoded record data; types we don't check are skipped.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Rdata {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Txt(String),
+    /// NS or CNAME target, without the trailing dot.
+    Name(String),
+    Other,
+}
+
+impl Rdata {
+    /// The value in presentation format, as used by the providers.
+    pub fn value(&self) -> Option<String> {
+        match self {
+            Rdata::A(ip) => Some(ip.to_string()),
+            Rdata::Aaaa(ip) => Some(ip.to_string()),
+            Rdata::Txt(txt) => Some(txt.clone()),
+            Rdata::Name(name) => Some(name.clone()),
+            Rdata::Other => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Answer {
+    pub rtype: u16,
+    pub data: Rdata,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Response {
+    pub id: u16,
+    pub truncated: bool,
+    pub rcode: u8,
+    pub answers: Vec<Answer>,
+}
+
+impl Response {
+    /// The values of answers of the given type.
+    pub fn values(&self, qtype: u16) -> Vec<String> {
+        self.answers.iter()
+            .filter(|a| a.rtype == qtype)
+            .filter_map(|a| a.data.value())
+            .collect()
+    }
+}
+
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        let b = self.buf.get(self.pos..self.pos + n)
+            .ok_or_else(|| dns_err("Truncated message"))?;
+        self.pos += n;
+        Ok(b)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn name(&mut self) -> Result<String> {
+        let (name, next) = read_name(self.buf, self.pos)?;
+        self.pos = next;
+        Ok(name)
+    }
+}
+
+/// Read a possibly-compressed name at `pos`, returning it and the
+/// position after it.
+pub(crate) fn read_name(buf: &[u8], mut pos: usize) -> Result<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    let mut jumps = 0;
+
+    loop {
+        let len = *buf.get(pos).ok_or_else(|| dns_err("Truncated name"))? as usize;
+        if len & 0xc0 == 0xc0 {
+            let low = *buf.get(pos + 1).ok_or_else(|| dns_err("Truncated name"))? as usize;
+            end.get_or_insert(pos + 2);
+            jumps += 1;
+            if jumps > 32 {
+                return Err(dns_err("Name compression loop"));
+            }
+            pos = ((len & 0x3f) << 8) | low;
+        } else if len == 0 {
+            end.get_or_insert(pos + 1);
+            break;
+        } else {
+            let label = buf.get(pos + 1..pos + 1 + len)
+                .ok_or_else(|| dns_err("Truncated name"))?;
+            labels.push(String::from_utf8_lossy(label).into_owned());
+            pos += 1 + len;
+        }
+    }
+
+    Ok((labels.join("."), end.unwrap_or(pos)))
+}
+
+pub(crate) fn parse(buf: &[u8]) -> Result<Response> {
+    let mut r = Reader { buf, pos: 0 };
+    let id = r.u16()?;
+    let flags = r.u16()?;
+    let qdcount = r.u16()?;
+    let ancount = r.u16()?;
+    r.bytes(4)?;
+
+    if flags & 0x8000 == 0 {
+        return Err(dns_err("Not a response"));
+    }
+
+    for _ in 0..qdcount {
+        r.name()?;
+        r.bytes(4)?;
+    }
+
+    let mut answers = Vec::with_capacity(ancount as usize);
+    for _ in 0..ancount {
+        r.name()?;
+        let rtype = r.u16()?;
+        r.bytes(6)?;
+        let len = r.u16()? as usize;
+        let start = r.pos;
+        let rdata = r.bytes(len)?;
+
+        let data = match (rtype, len) {
+            (TYPE_A, 4) => Rdata::A(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3])),
+            (TYPE_AAAA, 16) => {
+                let octets: [u8; 16] = rdata.try_into()
+                    .map_err(|_| dns_err("Bad AAAA record"))?;
+                Rdata::Aaaa(Ipv6Addr::from(octets))
+            }
+            (TYPE_TXT, _) => {
+                let mut txt = Reader { buf: rdata, pos: 0 };
+                let mut text = Vec::new();
+                while txt.pos < rdata.len() {
+                    let n = txt.u8()? as usize;
+                    text.extend(txt.bytes(n)?);
+                }
+                Rdata::Txt(String::from_utf8_lossy(&text).into_owned())
+            }
+            (TYPE_NS | TYPE_CNAME, _) => Rdata::Name(read_name(buf, start)?.0),
+            _ => Rdata::Other,
+        };
+        answers.push(Answer { rtype, data });
+    }
+
+    Ok(Response {
+        id,
+        truncated: flags & 0x0200 != 0,
+        rcode: (flags & 0x000f) as u8,
+        answers,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_query() -> Result<()> {
+        let query = Query { id: 0x1234, name: "www.example.com.".to_string(), qtype: TYPE_A, recurse: true };
+        let msg = query.encode()?;
+        assert_eq!(&[0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 1], &msg[..12]);
+        assert_eq!(b"\x03www\x07example\x03com\x00\x00\x01\x00\x01", &msg[12..33]);
+        // OPT record
+        assert_eq!(11, msg.len() - 33);
+
+        let long = Query::new(&"a".repeat(64), TYPE_A, false);
+        assert!(long.encode().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_response() -> Result<()> {
+        let mut msg = vec![0xab, 0xcd, 0x84, 0x00, 0, 1, 0, 3, 0, 0, 0, 0];
+        msg.extend(b"\x05_acme\x07example\x03com\x00\x00\x10\x00\x01");
+        // TXT split over two strings, compressed name
+        msg.extend([0xc0, 12, 0, 16, 0, 1, 0, 0, 0, 60, 0, 8, 3]);
+        msg.extend(b"abc\x03def");
+        // A record
+        msg.extend([0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 1]);
+        // CNAME pointing into the question
+        msg.extend([0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 2, 0xc0, 18]);
+
+        let resp = parse(&msg)?;
+        assert_eq!(0xabcd, resp.id);
+        assert!(!resp.truncated);
+        assert_eq!(0, resp.rcode);
+        assert_eq!(vec!["abcdef"], resp.values(TYPE_TXT));
+        assert_eq!(vec!["192.0.2.1"], resp.values(TYPE_A));
+        assert_eq!(vec!["example.com"], resp.values(TYPE_CNAME));
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert!(parse(&[0, 1, 0x80]).is_err());
+        // Not a response
+        assert!(parse(&[0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]).is_err());
+        // Answer count with no answers
+        assert!(parse(&[0, 1, 0x80, 0, 0, 0, 0, 1, 0, 0, 0, 0]).is_err());
+        // Compression loop
+        assert!(read_name(&[0xc0, 0], 0).is_err());
+    }
+}
@@ -0,0 +1,438 @@
+//! Guardrails against destructive changes.
+//!
+//! A provider wrapped in [`Guarded`] checks updates and deletes
+//! against a [`SafetyPolicy`] before they are sent upstream. Changes
+//! to protected records, or change sets with more deletions than the
+//! limit, fail with `Error::SafetyViolation`, so e.g. a mistaken
+//! `delete_all_records(RecordType::NS, "")` doesn't take the domain
+//! offline:
+//!
+//! ```no_run
+//! use zone_update::{Config, DnsProvider, RecordType, porkbun, safety::{Guarded, SafetyPolicy}};
+//! # fn main() -> zone_update::errors::Result<()> {
+//! let config = Config { domain: "example.com".to_string(), dry_run: false };
+//! let auth = porkbun::Auth { key: "key".to_string(), secret: "secret".to_string() };
+//! let policy = SafetyPolicy::default()
+//!     .protect(Some(RecordType::CNAME), "*.prod")
+//!     .max_deletions(10);
+//! let client = Guarded::new(porkbun::Porkbun::new(config, auth), "example.com", policy);
+//!
+//! assert!(client.delete_all_records(RecordType::NS, "").is_err());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The default policy protects the NS and MX records at the zone
+//! apex, and has no deletion limit; `SafetyPolicy::unrestricted()`
+//! allows everything. Creating records is always allowed.
+//!
+//! The policy is enforced by the wrapper rather than being part of
+//! `Config`, so that existing `Config` literals still build. Providers
+//! that are not wrapped, including those from
+//! `Provider::blocking_impl()`, are not protected; use
+//! `Provider::guarded_impl()` to get a guarded provider from the
+//! configuration.
+
+use std::fmt::Display;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    change::{Change, ChangeSet},
+    errors::{Error, Result},
+    generate_helpers, relative_host, DnsProvider, RecordType, ZoneListing, ZoneRecord,
+};
+
+
+/// A protected record pattern.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProtectedRecord {
+    /// The record type; `None` matches all types.
+    #[serde(default)]
+    pub rtype: Option<RecordType>,
+    /// The host, relative to the zone. `@` or an empty string is the
+    /// apex, and `*` matches any run of characters (including dots).
+    /// Matching is case-insensitive.
+    pub host: String,
+}
+
+impl ProtectedRecord {
+    pub fn new(rtype: Option<RecordType>, host: &str) -> Self {
+        Self {
+            rtype,
+            host: host.to_string(),
+        }
+    }
+
+    fn matches(&self, rtype: RecordType, host: &str) -> bool {
+        let pattern = relative_host(&self.host, "").to_lowercase();
+        self.rtype.is_none_or(|t| t == rtype)
+            && glob_match(pattern.as_bytes(), host.to_lowercase().as_bytes())
+    }
+}
+
+/// Limits on the changes a `Guarded` provider will make.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SafetyPolicy {
+    /// Records that may not be updated or deleted. Defaults to the
+    /// NS and MX records at the zone apex; setting this replaces
+    /// them.
+    pub protected: Vec<ProtectedRecord>,
+    /// The maximum number of records deleted by a single call or
+    /// change set. See `DnsProvider::apply_changes()`.
+    pub max_deletions: Option<usize>,
+    /// Allow changes to protected records. The deletion limit still
+    /// applies.
+    pub allow_protected: bool,
+}
+
+impl Default for SafetyPolicy {
+    fn default() -> Self {
+        Self::unrestricted().protect_apex()
+    }
+}
+
+impl SafetyPolicy {
+    /// A policy that allows all changes.
+    pub fn unrestricted() -> Self {
+        Self {
+            protected: Vec::new(),
+            max_deletions: None,
+            allow_protected: false,
+        }
+    }
+
+    /// Protect the NS and MX records at the zone apex. These are
+    /// protected by default.
+    pub fn protect_apex(self) -> Self {
+        self.protect(Some(RecordType::NS), "@")
+            .protect(Some(RecordType::MX), "@")
+    }
+
+    /// Add a protected record pattern.
+    pub fn protect(mut self, rtype: Option<RecordType>, host: &str) -> Self {
+        let record = ProtectedRecord::new(rtype, host);
+        if !self.protected.contains(&record) {
+            self.protected.push(record);
+        }
+        self
+    }
+
+    /// Limit the number of records deleted at once.
+    pub fn max_deletions(mut self, max: usize) -> Self {
+        self.max_deletions = Some(max);
+        self
+    }
+
+    /// Whether a record is protected, ignoring `allow_protected`.
+    /// `host` may be relative or fully-qualified.
+    pub fn is_protected(&self, domain: &str, rtype: RecordType, host: &str) -> bool {
+        let host = relative_host(host, domain);
+        self.protected.iter()
+            .any(|p| p.matches(rtype, &host))
+    }
+
+    /// Check that a record may be updated or deleted.
+    pub fn check_record(&self, domain: &str, rtype: RecordType, host: &str) -> Result<()> {
+        if !self.allow_protected && self.is_protected(domain, rtype, host) {
+            let host = match relative_host(host, domain).as_str() {
+                "" => "@".to_string(),
+                host => host.to_string(),
+            };
+            return Err(Error::SafetyViolation(format!("{rtype} {host} is protected")));
+        }
+        Ok(())
+    }
+
+    /// Check a number of deletions against the limit.
+    pub fn check_deletions(&self, deletions: usize) -> Result<()> {
+        if let Some(max) = self.max_deletions && deletions > max {
+            return Err(Error::SafetyViolation(format!("{deletions} deletions exceeds the limit of {max}")));
+        }
+        Ok(())
+    }
+
+    /// Check a change set against the policy, counting each change
+    /// as one deletion. `Guarded` counts the records removed by
+    /// `DeleteAll` changes instead.
+    pub fn check_changes(&self, domain: &str, changes: &[Change]) -> Result<()> {
+        self.check_deletions(changes.iter().filter(|c| c.is_delete()).count())?;
+        self.check_protected(domain, changes)
+    }
+
+    fn check_protected(&self, domain: &str, changes: &[Change]) -> Result<()> {
+        changes.iter()
+            .filter(|c| !matches!(c, Change::Create { .. }))
+            .try_for_each(|c| self.check_record(domain, c.rtype(), c.host()))
+    }
+
+    /// Whether counting the deletions in `changes` needs the zone
+    /// listing; see `deletions()`.
+    pub(crate) fn needs_listing(&self, changes: &[Change]) -> bool {
+        self.max_deletions.is_some()
+            && changes.iter().any(|c| matches!(c, Change::DeleteAll { .. }))
+    }
+
+    /// Check `changes`, counting `DeleteAll` changes as the number of
+    /// matching `records`.
+    pub(crate) fn check_listed(&self, domain: &str, changes: &[Change], records: &[ZoneRecord]) -> Result<()> {
+        self.check_protected(domain, changes)?;
+        self.check_deletions(deletions(domain, changes, records))
+    }
+}
+
+/// The number of records deleted by `changes`.
+fn deletions(domain: &str, changes: &[Change], records: &[ZoneRecord]) -> usize {
+    changes.iter()
+        .map(|c| match c {
+            Change::Delete { .. } => 1,
+            Change::DeleteAll { rtype, host } => {
+                let host = relative_host(host, domain);
+                records.iter()
+                    .filter(|r| r.rtype == *rtype && r.host.eq_ignore_ascii_case(&host))
+                    .count()
+            }
+            _ => 0,
+        })
+        .sum()
+}
+
+
+
+/// Provider wrapper that refuses changes not allowed by a
+/// `SafetyPolicy`.
+///
+/// Single-record updates and deletes are checked against the
+/// protected records. `delete_all_records()` and `apply_changes()`
+/// are also checked against the deletion limit; `DeleteAll` changes
+/// count each matching record, so when a limit is set the zone is
+/// listed first. Callers applying changes one at a time should check
+/// the whole set with `check_changes()` first, as `plan::Plan::apply()`
+/// does.
+pub struct Guarded<P> {
+    inner: P,
+    domain: String,
+    policy: SafetyPolicy,
+}
+
+impl<P: DnsProvider> Guarded<P> {
+    /// Wrap `inner`, which manages `domain`, enforcing `policy`.
+    pub fn new(inner: P, domain: &str, policy: SafetyPolicy) -> Self {
+        Self {
+            inner,
+            domain: domain.to_string(),
+            policy,
+        }
+    }
+
+    pub fn policy(&self) -> &SafetyPolicy {
+        &self.policy
+    }
+
+    /// Return the wrapped provider.
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+
+    fn check(&self, changes: &[Change]) -> Result<()> {
+        let records = if self.policy.needs_listing(changes) {
+            self.inner.list_records()?
+        } else {
+            Vec::new()
+        };
+        self.policy.check_listed(&self.domain, changes, &records)
+    }
+}
+
+impl<P: DnsProvider> DnsProvider for Guarded<P> {
+
+    fn get_record<T>(&self, rtype: RecordType, host: &str) -> Result<Option<T>>
+    where
+        T: DeserializeOwned
+    {
+        self.inner.get_record(rtype, host)
+    }
+
+    fn create_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+    where
+        T: Serialize + DeserializeOwned + Display + Clone
+    {
+        self.inner.create_record(rtype, host, record)
+    }
+
+    fn update_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+    where
+        T: Serialize + DeserializeOwned + Display + Clone
+    {
+        self.policy.check_record(&self.domain, rtype, host)?;
+        self.inner.update_record(rtype, host, record)
+    }
+
+    fn delete_record(&self, rtype: RecordType, host: &str) -> Result<()> {
+        self.check(&[Change::Delete { rtype, host: host.to_string() }])?;
+        self.inner.delete_record(rtype, host)
+    }
+
+    fn delete_all_records(&self, rtype: RecordType, host: &str) -> Result<()> {
+        self.check(&[Change::DeleteAll { rtype, host: host.to_string() }])?;
+        self.inner.delete_all_records(rtype, host)
+    }
+
+    fn list_zone(&self) -> Result<ZoneListing> {
+        self.inner.list_zone()
+    }
+
+    fn record_types(&self) -> &'static [RecordType] {
+        self.inner.record_types()
+    }
+
+    fn apply_changes(&self, changes: &ChangeSet) -> Result<()> {
+        self.check(&changes.changes)?;
+        self.inner.apply_changes(changes)
+    }
+
+    fn check_changes(&self, changes: &[Change]) -> Result<()> {
+        self.check(changes)?;
+        self.inner.check_changes(changes)
+    }
+
+    fn add_txt_value(&self, host: &str, value: &str) -> Result<()> {
+        self.inner.add_txt_value(host, value)
+    }
+
+    fn remove_txt_value(&self, host: &str, value: &str) -> Result<()> {
+        self.check(&[Change::Delete { rtype: RecordType::TXT, host: host.to_string() }])?;
+        self.inner.remove_txt_value(host, value)
+    }
+
+    generate_helpers!();
+}
+
+/// Match `text` against a pattern where `*` matches any sequence.
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|i| glob_match(rest, &text[i..])),
+        Some((c, rest)) => text.first() == Some(c) && glob_match(rest, &text[1..]),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::MemoryProvider;
+    use std::net::Ipv4Addr;
+
+    const DOMAIN: &str = "example.com";
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"*", b""));
+        assert!(glob_match(b"*.prod", b"api.prod"));
+        assert!(glob_match(b"*.prod", b"a.b.prod"));
+        assert!(!glob_match(b"*.prod", b"prod"));
+        assert!(glob_match(b"_acme*", b"_acme-challenge.www"));
+        assert!(!glob_match(b"www", b"www2"));
+    }
+
+    #[test]
+    fn test_protect_apex() {
+        assert!(!SafetyPolicy::unrestricted().is_protected(DOMAIN, RecordType::NS, ""));
+        assert_eq!(SafetyPolicy::default(), SafetyPolicy::unrestricted().protect_apex());
+
+        let policy = SafetyPolicy::default();
+        for host in ["", "@", "example.com", "example.com.", "EXAMPLE.COM"] {
+            assert!(policy.is_protected(DOMAIN, RecordType::NS, host), "{host}");
+        }
+        assert!(policy.is_protected(DOMAIN, RecordType::MX, ""));
+        assert!(!policy.is_protected(DOMAIN, RecordType::NS, "sub"));
+        assert!(!policy.is_protected(DOMAIN, RecordType::A, ""));
+
+        let err = policy.check_record(DOMAIN, RecordType::NS, "").unwrap_err();
+        assert_eq!("Safety policy violation: NS @ is protected", err.to_string());
+
+        let allowed = SafetyPolicy { allow_protected: true, ..policy };
+        assert!(allowed.check_record(DOMAIN, RecordType::NS, "").is_ok());
+    }
+
+    #[test]
+    fn test_check_changes() {
+        let delete = |host: &str| Change::DeleteAll { rtype: RecordType::A, host: host.to_string() };
+        let policy = SafetyPolicy::unrestricted()
+            .protect(None, "keep")
+            .max_deletions(2);
+
+        assert!(policy.check_changes(DOMAIN, &[delete("a"), delete("b")]).is_ok());
+        assert!(matches!(policy.check_changes(DOMAIN, &[delete("a"), delete("b"), delete("c")]),
+                         Err(Error::SafetyViolation(_))));
+        assert!(policy.check_changes(DOMAIN, &[delete("keep")]).is_err());
+
+        let create = Change::Create { rtype: RecordType::A, host: "keep".to_string(), value: "192.0.2.1".to_string() };
+        assert!(policy.check_changes(DOMAIN, &[create]).is_ok());
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let policy: SafetyPolicy = toml::from_str(r#"
+            max_deletions = 5
+            protected = [
+                { rtype = "TXT", host = "_dmarc" },
+                { host = "*.internal" },
+            ]
+        "#).unwrap();
+        assert_eq!(Some(5), policy.max_deletions);
+        assert!(policy.is_protected(DOMAIN, RecordType::TXT, "_dmarc"));
+        assert!(policy.is_protected(DOMAIN, RecordType::A, "db.internal"));
+        assert!(!policy.is_protected(DOMAIN, RecordType::NS, ""));
+    }
+
+    #[test]
+    fn test_guarded() -> Result<()> {
+        let inner = MemoryProvider::new(DOMAIN);
+        for i in 1..=3 {
+            inner.create_a_record("www", &Ipv4Addr::new(192, 0, 2, i))?;
+        }
+        inner.create_record(RecordType::NS, "", &"ns1.example.net.".to_string())?;
+        let policy = SafetyPolicy::default().max_deletions(2);
+        let client = Guarded::new(inner, DOMAIN, policy);
+
+        // Refused before anything is changed
+        let err = client.delete_all_records(RecordType::NS, "").unwrap_err();
+        assert!(matches!(err, Error::SafetyViolation(_)));
+        let err = client.delete_all_records(RecordType::A, "www").unwrap_err();
+        assert_eq!("Safety policy violation: 3 deletions exceeds the limit of 2", err.to_string());
+        let changes = vec![Change::Delete { rtype: RecordType::NS, host: DOMAIN.to_string() }].into();
+        assert!(matches!(client.apply_changes(&changes), Err(Error::SafetyViolation(_))));
+        assert_eq!(4, client.list_records()?.len());
+
+        client.create_a_record("mail", &Ipv4Addr::new(192, 0, 2, 10))?;
+        client.delete_all_records(RecordType::A, "mail")?;
+        assert_eq!(None, client.get_a_record("mail")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_checked() -> Result<()> {
+        use crate::plan::Plan;
+
+        let inner = MemoryProvider::new(DOMAIN);
+        for host in ["a", "b", "c"] {
+            inner.create_a_record(host, &Ipv4Addr::new(192, 0, 2, 1))?;
+        }
+        let client = Guarded::new(inner, DOMAIN, SafetyPolicy::default().max_deletions(2));
+
+        // Individually each change is within the limit, but the plan
+        // as a whole is not
+        let plan = Plan {
+            changes: ["a", "b", "c"].iter()
+                .map(|host| Change::DeleteAll { rtype: RecordType::A, host: host.to_string() })
+                .collect(),
+            unchanged: Vec::new(),
+        };
+        assert!(matches!(plan.apply(&client), Err(Error::SafetyViolation(_))));
+        assert_eq!(3, client.list_records()?.len());
+        Ok(())
+    }
+}
@@ -0,0 +1,435 @@
+//! Read-after-write verification.
+//!
+//! Some provider APIs are eventually consistent, so a record may not
+//! be returned by `get_record()` immediately after it is created. A
+//! provider wrapped in [`Verified`] polls after each change until the
+//! new state is visible, failing with `Error::NotVisible` if it isn't
+//! within the timeout:
+//!
+//! ```no_run
+//! use zone_update::{Config, DnsProvider, porkbun, verify::{Verified, VerifyOptions}};
+//! # fn main() -> zone_update::errors::Result<()> {
+//! let config = Config { domain: "example.com".to_string(), dry_run: false };
+//! let auth = porkbun::Auth { key: "key".to_string(), secret: "secret".to_string() };
+//! let client = Verified::new(porkbun::Porkbun::new(config, auth), VerifyOptions::default());
+//!
+//! client.create_a_record("www", &"192.0.2.1".parse()?)?;
+//! println!("Visible after {:?}", client.last_elapsed());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Verification reads the provider's API; it does not check that the
+//! change has reached the authoritative nameservers. Don't verify
+//! dry-run providers, as their changes never become visible.
+
+use std::{
+    fmt::Display,
+    net::IpAddr,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::{debug, info};
+
+use crate::{
+    change::{Change, ChangeSet},
+    errors::{Error, Result},
+    generate_helpers, has_txt_value, DnsProvider, RecordType, ZoneListing, ZoneRecord,
+};
+
+
+/// Options for `Verified`.
+#[derive(Clone, Debug)]
+pub struct VerifyOptions {
+    /// How long to wait for a change to become visible.
+    pub timeout: Duration,
+    /// The delay between lookups.
+    pub interval: Duration,
+}
+
+impl Default for VerifyOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(60),
+            interval: Duration::from_secs(1),
+        }
+    }
+}
+
+
+/// Whether two record values are equivalent. Quoting, trailing dots
+/// and (except for TXT) case are ignored, and addresses are compared
+/// numerically.
+pub(crate) fn same_value(rtype: RecordType, a: &str, b: &str) -> bool {
+    if let (Ok(a), Ok(b)) = (a.parse::<IpAddr>(), b.parse::<IpAddr>()) {
+        return a == b;
+    }
+    let norm = |v: &str| v.trim_matches('"').trim_end_matches('.').to_string();
+    let (a, b) = (norm(a), norm(b));
+    match rtype {
+        RecordType::TXT => a == b,
+        _ => a.eq_ignore_ascii_case(&b),
+    }
+}
+
+/// Whether the current values of a record set reflect a change;
+/// `expected` is the new value, or `None` for deletions.
+pub(crate) fn is_visible(rtype: RecordType, expected: Option<&str>, current: &[String]) -> bool {
+    match expected {
+        Some(exp) => current.iter().any(|v| same_value(rtype, exp, v)),
+        None => current.is_empty(),
+    }
+}
+
+/// The states to verify after applying a change set: each value
+/// expected in the record sets it touches, or `None` if a set ends
+/// up deleted.
+pub(crate) fn expected_states(changes: &[Change]) -> Vec<(RecordType, String, Option<String>)> {
+    let mut sets: Vec<(RecordType, &str)> = Vec::new();
+    for change in changes {
+        if !sets.contains(&(change.rtype(), change.host())) {
+            sets.push((change.rtype(), change.host()));
+        }
+    }
+
+    let mut states = Vec::new();
+    for (rtype, host) in sets {
+        let set: Vec<&Change> = changes.iter()
+            .filter(|c| c.rtype() == rtype && c.host() == host)
+            .collect();
+        // Only changes after the last deletion are visible
+        match set.iter().rposition(|c| c.is_delete()) {
+            Some(i) if i == set.len() - 1 => states.push((rtype, host.to_string(), None)),
+            last => states.extend(set[last.map_or(0, |i| i + 1)..].iter()
+                .filter_map(|c| c.value())
+                .map(|value| (rtype, host.to_string(), Some(value.to_string())))),
+        }
+    }
+    states
+}
+
+/// The values of a record set from a listing.
+pub(crate) fn set_values(records: Vec<ZoneRecord>, rtype: RecordType, host: &str) -> Vec<String> {
+    records.into_iter()
+        .filter(|r| r.rtype == rtype && r.host.eq_ignore_ascii_case(host))
+        .map(|r| r.value)
+        .collect()
+}
+
+pub(crate) fn not_visible(rtype: RecordType, host: &str, expected: Option<&str>, elapsed: Duration) -> Error {
+    let change = match expected {
+        Some(value) => format!("{rtype} {host} -> {value}"),
+        None => format!("deletion of {rtype} {host}"),
+    };
+    Error::NotVisible(change, elapsed)
+}
+
+
+/// A provider wrapper that waits for each change to be visible in
+/// the provider's API before returning.
+///
+/// Record sets with more than one value are read with
+/// `list_records()`, so verifying changes to them requires listing
+/// support.
+pub struct Verified<P> {
+    inner: P,
+    opts: VerifyOptions,
+    last: Mutex<Option<Duration>>,
+}
+
+impl<P: DnsProvider> Verified<P> {
+    /// Wrap `inner`, verifying with `opts`.
+    pub fn new(inner: P, opts: VerifyOptions) -> Self {
+        Self {
+            inner,
+            opts,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// The time the last verified change took to become visible.
+    pub fn last_elapsed(&self) -> Option<Duration> {
+        *self.last.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Return the wrapped provider.
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+
+    fn current(&self, rtype: RecordType, host: &str) -> Result<Vec<String>> {
+        match self.inner.get_record::<String>(rtype, host) {
+            Ok(value) => Ok(value.into_iter().collect()),
+            Err(Error::UnexpectedRecord(_)) => Ok(set_values(self.inner.list_records()?, rtype, host)),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn verify(&self, rtype: RecordType, host: &str, expected: Option<&str>) -> Result<()> {
+        self.verify_with(rtype, host, expected, |current| is_visible(rtype, expected, current))
+    }
+
+    fn verify_with<F>(&self, rtype: RecordType, host: &str, expected: Option<&str>, visible: F) -> Result<()>
+    where
+        F: Fn(&[String]) -> bool
+    {
+        let start = Instant::now();
+        loop {
+            if visible(&self.current(rtype, host)?) {
+                let elapsed = start.elapsed();
+                info!("Change to {rtype} {host} visible after {elapsed:?}");
+                *self.last.lock().unwrap_or_else(|e| e.into_inner()) = Some(elapsed);
+                return Ok(())
+            }
+
+            let elapsed = start.elapsed();
+            if elapsed >= self.opts.timeout {
+                return Err(not_visible(rtype, host, expected, elapsed));
+            }
+            debug!("Change to {rtype} {host} not yet visible");
+            thread::sleep(self.opts.interval.min(self.opts.timeout - elapsed));
+        }
+    }
+}
+
+impl<P: DnsProvider> DnsProvider for Verified<P> {
+
+    fn get_record<T>(&self, rtype: RecordType, host: &str) -> Result<Option<T>>
+    where
+        T: DeserializeOwned
+    {
+        self.inner.get_record(rtype, host)
+    }
+
+    fn create_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+    where
+        T: Serialize + DeserializeOwned + Display + Clone
+    {
+        self.inner.create_record(rtype, host, record)?;
+        self.verify(rtype, host, Some(&record.to_string()))
+    }
+
+    fn update_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+    where
+        T: Serialize + DeserializeOwned + Display + Clone
+    {
+        self.inner.update_record(rtype, host, record)?;
+        self.verify(rtype, host, Some(&record.to_string()))
+    }
+
+    fn delete_record(&self, rtype: RecordType, host: &str) -> Result<()> {
+        self.inner.delete_record(rtype, host)?;
+        self.verify(rtype, host, None)
+    }
+
+    fn delete_all_records(&self, rtype: RecordType, host: &str) -> Result<()> {
+        self.inner.delete_all_records(rtype, host)?;
+        self.verify(rtype, host, None)
+    }
+
+    fn list_zone(&self) -> Result<ZoneListing> {
+        self.inner.list_zone()
+    }
+
+    fn record_types(&self) -> &'static [RecordType] {
+        self.inner.record_types()
+    }
+
+    fn apply_changes(&self, changes: &ChangeSet) -> Result<()> {
+        self.inner.apply_changes(changes)?;
+        for (rtype, host, expected) in expected_states(&changes.changes) {
+            self.verify(rtype, &host, expected.as_deref())?;
+        }
+        Ok(())
+    }
+
+    fn check_changes(&self, changes: &[Change]) -> Result<()> {
+        self.inner.check_changes(changes)
+    }
+
+    fn add_txt_value(&self, host: &str, value: &str) -> Result<()> {
+        self.inner.add_txt_value(host, value)?;
+        self.verify(RecordType::TXT, host, Some(value))
+    }
+
+    fn remove_txt_value(&self, host: &str, value: &str) -> Result<()> {
+        self.inner.remove_txt_value(host, value)?;
+        self.verify_with(RecordType::TXT, host, None, |current| !has_txt_value(current, value))
+    }
+
+    generate_helpers!();
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::tests::{Batching, MemoryProvider};
+    use std::net::Ipv4Addr;
+
+    /// A provider whose writes only become visible after a number of
+    /// reads.
+    pub(crate) struct Lagging {
+        pub(crate) inner: MemoryProvider,
+        lag: usize,
+        pending: Mutex<Vec<(usize, Change)>>,
+    }
+
+    impl Lagging {
+        pub(crate) fn new(lag: usize) -> Self {
+            Self {
+                inner: MemoryProvider::new("example.com"),
+                lag,
+                pending: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn queue(&self, change: Change) -> Result<()> {
+            self.pending.lock().unwrap().push((self.lag, change));
+            Ok(())
+        }
+    }
+
+    impl DnsProvider for Lagging {
+        fn get_record<T>(&self, rtype: RecordType, host: &str) -> Result<Option<T>>
+        where
+            T: DeserializeOwned
+        {
+            let mut pending = self.pending.lock().unwrap();
+            for (reads, change) in pending.iter_mut() {
+                *reads = reads.saturating_sub(1);
+                if *reads == 0 {
+                    change.apply(&self.inner)?;
+                }
+            }
+            pending.retain(|(reads, _)| *reads > 0);
+            drop(pending);
+            self.inner.get_record(rtype, host)
+        }
+
+        fn create_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+        where
+            T: Serialize + DeserializeOwned + Display + Clone
+        {
+            self.queue(Change::Create { rtype, host: host.to_string(), value: record.to_string() })
+        }
+
+        fn update_record<T>(&self, rtype: RecordType, host: &str, record: &T) -> Result<()>
+        where
+            T: Serialize + DeserializeOwned + Display + Clone
+        {
+            self.queue(Change::Update { rtype, host: host.to_string(), value: record.to_string() })
+        }
+
+        fn delete_record(&self, rtype: RecordType, host: &str) -> Result<()> {
+            self.queue(Change::Delete { rtype, host: host.to_string() })
+        }
+
+        fn delete_all_records(&self, rtype: RecordType, host: &str) -> Result<()> {
+            self.queue(Change::DeleteAll { rtype, host: host.to_string() })
+        }
+
+        fn list_zone(&self) -> Result<ZoneListing> {
+            self.inner.list_zone()
+        }
+
+        generate_helpers!();
+    }
+
+    fn opts(timeout: u64) -> VerifyOptions {
+        VerifyOptions {
+            timeout: Duration::from_millis(timeout),
+            interval: Duration::from_millis(1),
+        }
+    }
+
+    #[test]
+    fn test_same_value() {
+        assert!(same_value(RecordType::AAAA, "2001:db8::1", "2001:0db8:0:0::1"));
+        assert!(same_value(RecordType::CNAME, "Target.example.com.", "target.example.com"));
+        assert!(same_value(RecordType::TXT, "\"abc\"", "abc"));
+        assert!(!same_value(RecordType::TXT, "ABC", "abc"));
+    }
+
+    #[test]
+    fn test_verify_waits() -> Result<()> {
+        let client = Verified::new(Lagging::new(3), opts(5000));
+        let ip = Ipv4Addr::new(10, 0, 0, 1);
+
+        client.create_a_record("www", &ip)?;
+        assert!(client.last_elapsed().is_some());
+        assert_eq!(Some(ip), client.get_a_record("www")?);
+
+        client.update_a_record("www", &Ipv4Addr::new(10, 0, 0, 2))?;
+        client.delete_a_record("www")?;
+        assert_eq!(None, client.into_inner().inner.get_a_record("www")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_multiple() -> Result<()> {
+        let client = Verified::new(Lagging::new(2), opts(5000));
+        client.create_txt_record("_acme", "one")?;
+        client.create_txt_record("_acme", "two")?;
+        client.delete_all_records(RecordType::TXT, "_acme")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_txt_values() -> Result<()> {
+        let client = Verified::new(Lagging::new(2), opts(5000));
+        client.add_txt_value("_acme", "one")?;
+        client.add_txt_value("_acme", "two")?;
+        client.remove_txt_value("_acme", "one")?;
+        assert_eq!(vec!["two"], client.get_txt_values("_acme")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_not_visible() {
+        let client = Verified::new(Lagging::new(usize::MAX), opts(20));
+
+        let err = client.create_a_record("www", &Ipv4Addr::new(10, 0, 0, 1)).unwrap_err();
+        let Error::NotVisible(change, elapsed) = err else {
+            panic!("Unexpected error {err}");
+        };
+        assert_eq!("A www -> 10.0.0.1", change);
+        assert!(elapsed >= Duration::from_millis(20));
+        assert_eq!(None, client.last_elapsed());
+    }
+
+    #[test]
+    fn test_apply_changes_batched() -> Result<()> {
+        let client = Verified::new(Batching::new("example.com"), opts(5000));
+        let txt = |value: &str| Change::Create { rtype: RecordType::TXT, host: "_acme".to_string(), value: value.to_string() };
+
+        client.apply_changes(&vec![txt("\"one\""), txt("\"two\"")].into())?;
+        client.apply_changes(&vec![
+            Change::DeleteAll { rtype: RecordType::TXT, host: "_acme".to_string() },
+            txt("\"three\""),
+        ].into())?;
+        assert!(client.last_elapsed().is_some());
+        assert_eq!(2, client.into_inner().batches.lock().unwrap().len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_expected_states() {
+        let a = |value: &str| Change::Create { rtype: RecordType::A, host: "www".to_string(), value: value.to_string() };
+        let changes = vec![
+            a("10.0.0.1"),
+            Change::DeleteAll { rtype: RecordType::A, host: "www".to_string() },
+            a("10.0.0.2"),
+            Change::Delete { rtype: RecordType::A, host: "old".to_string() },
+        ];
+        assert_eq!(vec![
+            (RecordType::A, "www".to_string(), Some("10.0.0.2".to_string())),
+            (RecordType::A, "old".to_string(), None),
+        ], expected_states(&changes));
+    }
+}
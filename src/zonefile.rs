@@ -0,0 +1,981 @@
+//! Zone files in the RFC 1035 master file format.
+//!
+//! A zone can be exported from any provider that supports
+//! `DnsProvider::list_records()`:
+//!
+//! ```no_run
+//! # use zone_update::{Config, Provider, zonefile};
+//! # fn main() -> zone_update::errors::Result<()> {
+//! # let provider: Provider = todo!();
+//! let config = Config { domain: "example.com".to_string(), dry_run: false };
+//! let client = provider.blocking_impl(config);
+//! let zone = zonefile::export(client.as_ref(), "example.com")?;
+//! std::fs::write("example.com.zone", zone)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! For async providers use `to_zone_file()` with the result of
+//! `AsyncDnsProvider::list_records()`.
+//!
+//! Master files can also be parsed and the records pushed into a
+//! provider, e.g. to migrate a zone from another DNS host:
+//!
+//! ```no_run
+//! # use zone_update::{zonefile, DnsProvider};
+//! # fn main() -> zone_update::errors::Result<()> {
+//! # let client: zone_update::desec::DeSec = todo!();
+//! let records = zonefile::parse_file("example.com.zone", "example.com", &Default::default())?;
+//! for result in zonefile::import(&client, &records) {
+//!     result?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{collections::HashMap, fmt::Write, fs, path::Path};
+
+use tracing::{debug, info, warn};
+
+use crate::{
+    change::Change,
+    errors::{Error, Result},
+    parse_rtype, relative_host, DnsProvider, RecordType, ZoneRecord,
+};
+
+
+/// The `$TTL` used when no records report a TTL.
+pub const DEFAULT_TTL: u32 = 3600;
+
+/// The maximum length of a single TXT character-string.
+const MAX_STRING_LEN: usize = 255;
+
+/// The maximum `$INCLUDE` nesting, to catch include loops.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+
+/// Export all records in a provider's zone as a master file.
+///
+/// `origin` is the zone's domain, e.g. `example.com`. Records of
+/// types not supported by this crate are not included.
+pub fn export(provider: &(impl DnsProvider + ?Sized), origin: &str) -> Result<String> {
+    let records = provider.list_records()?;
+    Ok(to_zone_file(origin, &records))
+}
+
+/// Format records as a master file.
+///
+/// The output starts with `$ORIGIN` and `$TTL` directives; `$TTL`
+/// is the most common TTL of the records, and only records that
+/// differ from it have an explicit TTL. Owner names are relative to
+/// the origin, with `@` for the apex. Domain names in record data
+/// are made absolute where the provider returned a FQDN. TXT values
+/// are escaped, and split into multiple strings where longer than
+/// 255 bytes.
+pub fn to_zone_file(origin: &str, records: &[ZoneRecord]) -> String {
+    let origin = origin.trim_end_matches('.');
+    let default_ttl = default_ttl(records);
+
+    let mut out = String::new();
+    // Writing to a String cannot fail.
+    let _ = writeln!(out, "$ORIGIN {origin}.");
+    let _ = writeln!(out, "$TTL {default_ttl}");
+
+    for rec in records {
+        let owner = if rec.host.is_empty() {
+            "@".to_string()
+        } else {
+            escape_name(&rec.host)
+        };
+        let ttl = match rec.ttl {
+            Some(ttl) if ttl != default_ttl => format!("{ttl}"),
+            _ => String::new(),
+        };
+        let _ = writeln!(out, "{owner}\t{ttl}\tIN\t{}\t{}", rec.rtype, format_rdata(rec.rtype, &rec.value));
+    }
+
+    out
+}
+
+fn default_ttl(records: &[ZoneRecord]) -> u32 {
+    let mut counts: HashMap<u32, usize> = HashMap::new();
+    for ttl in records.iter().filter_map(|r| r.ttl) {
+        *counts.entry(ttl).or_default() += 1;
+    }
+    // Prefer the lower TTL on a tie, for stable output.
+    counts.into_iter()
+        .max_by_key(|(ttl, count)| (*count, std::cmp::Reverse(*ttl)))
+        .map(|(ttl, _)| ttl)
+        .unwrap_or(DEFAULT_TTL)
+}
+
+fn format_rdata(rtype: RecordType, value: &str) -> String {
+    match rtype {
+        RecordType::TXT => format_txt(value),
+        RecordType::CNAME | RecordType::NS | RecordType::PTR => qualify(value.trim()).to_string(),
+        // Target is the last field
+        RecordType::MX => qualify_field(value, 1),
+        RecordType::SRV => qualify_field(value, 3),
+        // Target follows the priority
+        RecordType::SVCB | RecordType::HTTPS => qualify_field(value, 1),
+        _ => value.to_string(),
+    }
+}
+
+/// Make a domain name absolute if it appears to be fully-qualified.
+/// Providers generally return FQDNs without the trailing dot, which
+/// would otherwise be read as relative to the origin.
+fn qualify(name: &str) -> String {
+    if name.ends_with('.') || name == "@" || !name.contains('.') {
+        name.to_string()
+    } else {
+        format!("{name}.")
+    }
+}
+
+fn qualify_field(value: &str, field: usize) -> String {
+    let mut fields: Vec<String> = value.split_whitespace()
+        .map(str::to_string)
+        .collect();
+    if let Some(name) = fields.get_mut(field) {
+        *name = qualify(name);
+    }
+    fields.join(" ")
+}
+
+fn escape_name(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for c in name.chars() {
+        if matches!(c, ' ' | ';' | '"' | '(' | ')' | '\\' | '$' | '@') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Format a TXT value as one or more quoted character-strings.
+fn format_txt(value: &str) -> String {
+    let mut strings = Vec::new();
+    for s in parse_txt(value) {
+        if s.is_empty() {
+            strings.push("\"\"".to_string());
+        }
+        for chunk in s.chunks(MAX_STRING_LEN) {
+            strings.push(quote_bytes(chunk));
+        }
+    }
+    strings.join(" ")
+}
+
+fn quote_bytes(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() + 2);
+    out.push('"');
+    for b in bytes {
+        match b {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            0x20..=0x7e => out.push(*b as char),
+            _ => {
+                let _ = write!(out, "\\{b:03}");
+            }
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Split a TXT value into its character-strings.
+///
+/// Values may be a sequence of quoted strings in presentation format
+/// (e.g. `"v=spf1 " "-all"`), or a single unquoted string. Quoted
+/// values that are not valid presentation format (e.g. with
+/// unescaped inner quotes) are treated as a single string.
+pub(crate) fn parse_txt(value: &str) -> Vec<Vec<u8>> {
+    let trimmed = value.trim();
+    if !trimmed.starts_with('"') {
+        return vec![value.as_bytes().to_vec()];
+    }
+
+    parse_quoted(trimmed).unwrap_or_else(|| {
+        let inner = trimmed.strip_prefix('"').unwrap_or(trimmed);
+        let inner = inner.strip_suffix('"').unwrap_or(inner);
+        vec![inner.as_bytes().to_vec()]
+    })
+}
+
+fn parse_quoted(value: &str) -> Option<Vec<Vec<u8>>> {
+    let bytes = value.as_bytes();
+    let mut strings = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i].is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+        if bytes[i] != b'"' {
+            return None;
+        }
+        i += 1;
+
+        let mut s = Vec::new();
+        loop {
+            match *bytes.get(i)? {
+                b'"' => {
+                    i += 1;
+                    break;
+                }
+                b'\\' => {
+                    let (b, len) = unescape(&bytes[i + 1..])?;
+                    s.push(b);
+                    i += 1 + len;
+                }
+                b => {
+                    s.push(b);
+                    i += 1;
+                }
+            }
+        }
+        strings.push(s);
+    }
+
+    Some(strings)
+}
+
+/// Decode an escape sequence, following the backslash. Returns the
+/// byte and the number of input bytes consumed.
+pub(crate) fn unescape(bytes: &[u8]) -> Option<(u8, usize)> {
+    match bytes {
+        [a, b, c, ..] if a.is_ascii_digit() && b.is_ascii_digit() && c.is_ascii_digit() => {
+            let n = (a - b'0') as u32 * 100 + (b - b'0') as u32 * 10 + (c - b'0') as u32;
+            u8::try_from(n).ok().map(|b| (b, 3))
+        }
+        [b, ..] => Some((*b, 1)),
+        [] => None,
+    }
+}
+
+
+
+/// Options for parsing master files.
+#[derive(Clone, Debug, Default)]
+pub struct ParseOptions {
+    /// Include NS records at the zone apex. These are normally
+    /// managed by the provider, so are skipped by default.
+    pub apex_ns: bool,
+}
+
+/// Parse a master file for the zone `origin`.
+///
+/// Supports the `$ORIGIN`, `$TTL` and `$INCLUDE` directives, relative
+/// names and `@`, blank owners (repeating the previous owner),
+/// optional TTLs and classes, and parenthesised multi-line records.
+/// `$INCLUDE` paths are relative to the current directory; see
+/// `parse_file()`.
+///
+/// SOA records, apex NS records (see `ParseOptions`), types not
+/// supported by `RecordType`, and records outside the zone are
+/// skipped. Hosts are returned relative to `origin`, and domain names
+/// in record data are made absolute.
+pub fn parse(text: &str, origin: &str, opts: &ParseOptions) -> Result<Vec<ZoneRecord>> {
+    let mut parser = Parser::new(origin, opts);
+    let mut state = State::new(&parser.zone);
+    parser.parse(text, &mut state, Path::new("."), 0)?;
+    Ok(parser.records)
+}
+
+/// Parse a master file from disk. See `parse()`; `$INCLUDE` paths
+/// are relative to the including file.
+pub fn parse_file(path: impl AsRef<Path>, origin: &str, opts: &ParseOptions) -> Result<Vec<ZoneRecord>> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+
+    let mut parser = Parser::new(origin, opts);
+    let mut state = State::new(&parser.zone);
+    parser.parse(&text, &mut state, dir, 0)?;
+    Ok(parser.records)
+}
+
+/// Convert records to the changes that would create them, e.g. for
+/// `async_impl::bulk::apply_bulk()`.
+pub fn to_changes(records: &[ZoneRecord]) -> Vec<Change> {
+    records.iter()
+        .map(|rec| Change::Create {
+            rtype: rec.rtype,
+            host: rec.host.clone(),
+            value: rec.value.clone(),
+        })
+        .collect()
+}
+
+/// Create records in a provider, e.g. those returned by `parse()`.
+///
+/// Records are created in order with `apply_change()`; a failure
+/// does not stop the remaining records. Returns the result for each
+/// record.
+///
+/// Values are passed to the provider in presentation format and the
+/// provider's default TTL is used. Support for types with structured
+/// data (e.g. MX and SRV) varies between providers.
+pub fn import(provider: &(impl DnsProvider + ?Sized), records: &[ZoneRecord]) -> Vec<Result<()>> {
+    to_changes(records).iter()
+        .map(|change| {
+            info!("Importing: {change}");
+            let result = provider.apply_change(change);
+            if let Err(e) = &result {
+                warn!("Failed to {change}: {e}");
+            }
+            result
+        })
+        .collect()
+}
+
+
+struct Parser<'a> {
+    /// The zone being parsed, without the trailing dot.
+    zone: String,
+    opts: &'a ParseOptions,
+    records: Vec<ZoneRecord>,
+}
+
+/// Parsing state; this is per-file, as `$INCLUDE` does not change the
+/// including file's state.
+#[derive(Clone)]
+struct State {
+    origin: String,
+    default_ttl: Option<u32>,
+    last_ttl: Option<u32>,
+    last_owner: Option<String>,
+}
+
+impl State {
+    fn new(origin: &str) -> Self {
+        Self {
+            origin: origin.to_string(),
+            default_ttl: None,
+            last_ttl: None,
+            last_owner: None,
+        }
+    }
+}
+
+impl<'a> Parser<'a> {
+    fn new(zone: &str, opts: &'a ParseOptions) -> Self {
+        Self {
+            zone: zone.trim_end_matches('.').to_string(),
+            opts,
+            records: Vec::new(),
+        }
+    }
+
+    fn parse(&mut self, text: &str, state: &mut State, dir: &Path, depth: usize) -> Result<()> {
+        for line in tokenise(text)? {
+            let err = |msg: &str| Error::ZoneFileError(format!("line {}: {msg}", line.number));
+            let first = &line.tokens[0];
+
+            if !line.indented && !first.quoted && first.text.starts_with('$') {
+                let arg = line.tokens.get(1)
+                    .ok_or_else(|| err(&format!("missing argument to {}", first.text)))?;
+
+                match first.text.to_ascii_uppercase().as_str() {
+                    "$ORIGIN" => {
+                        state.origin = absolute_name(&arg.text, &state.origin);
+                    }
+                    "$TTL" => {
+                        state.default_ttl = Some(parse_ttl(&arg.text)
+                                                 .ok_or_else(|| err(&format!("invalid TTL {}", arg.text)))?);
+                    }
+                    "$INCLUDE" => {
+                        if depth >= MAX_INCLUDE_DEPTH {
+                            return Err(err("too many nested includes"));
+                        }
+                        let path = dir.join(&arg.text);
+                        let text = fs::read_to_string(&path)
+                            .map_err(|e| err(&format!("failed to include {}: {e}", path.display())))?;
+
+                        let mut included = state.clone();
+                        if let Some(origin) = line.tokens.get(2) {
+                            included.origin = absolute_name(&origin.text, &state.origin);
+                        }
+                        let dir = path.parent().unwrap_or(Path::new("."));
+                        self.parse(&text, &mut included, dir, depth + 1)?;
+                    }
+                    other => return Err(err(&format!("unknown directive {other}"))),
+                }
+                continue;
+            }
+
+            self.record(&line, state)
+                .map_err(|e| err(&e))?;
+        }
+
+        Ok(())
+    }
+
+    fn record(&mut self, line: &Line, state: &mut State) -> std::result::Result<(), String> {
+        let mut tokens = line.tokens.iter();
+
+        let owner = if line.indented {
+            state.last_owner.clone()
+                .ok_or("no previous owner name")?
+        } else {
+            let name = tokens.next().ok_or("missing owner name")?;
+            owner_name(&name.text, &state.origin)
+        };
+        state.last_owner = Some(owner.clone());
+
+        // TTL and class may appear in either order
+        let mut ttl = None;
+        let rtype = loop {
+            let tok = tokens.next().ok_or("missing record type")?;
+            if let Some(t) = parse_ttl(&tok.text) {
+                ttl = Some(t);
+            } else if is_class(&tok.text) {
+                if !tok.text.eq_ignore_ascii_case("IN") {
+                    return Err(format!("unsupported class {}", tok.text));
+                }
+            } else {
+                break tok.text.to_ascii_uppercase();
+            }
+        };
+        let rdata: Vec<&Token> = tokens.collect();
+
+        if ttl.is_some() {
+            state.last_ttl = ttl;
+        }
+        let ttl = ttl.or(state.default_ttl).or(state.last_ttl);
+
+        if rtype == "SOA" {
+            debug!("Skipping SOA record for {owner}");
+            return Ok(());
+        }
+        let Some(rt) = parse_rtype(&rtype) else {
+            warn!("Skipping unsupported record type {rtype} for {owner}");
+            return Ok(());
+        };
+        if !in_zone(&owner, &self.zone) {
+            warn!("Skipping {rtype} record for {owner}, which is outside {}", self.zone);
+            return Ok(());
+        }
+        let host = relative_host(&owner, &self.zone);
+        if rt == RecordType::NS && host.is_empty() && !self.opts.apex_ns {
+            debug!("Skipping apex NS record");
+            return Ok(());
+        }
+        if rdata.is_empty() {
+            return Err(format!("missing data for {rtype} record"));
+        }
+
+        self.records.push(ZoneRecord {
+            rtype: rt,
+            host,
+            value: parsed_rdata(rt, &rdata, &state.origin),
+            ttl,
+            modified: None,
+        });
+
+        Ok(())
+    }
+}
+
+fn in_zone(name: &str, zone: &str) -> bool {
+    zone.is_empty()
+        || name.eq_ignore_ascii_case(zone)
+        || name.len() > zone.len()
+            && name.is_char_boundary(name.len() - zone.len() - 1)
+            && name[name.len() - zone.len() - 1..].starts_with('.')
+            && name[name.len() - zone.len()..].eq_ignore_ascii_case(zone)
+}
+
+fn is_class(tok: &str) -> bool {
+    ["IN", "CH", "HS", "CS"].iter()
+        .any(|c| tok.eq_ignore_ascii_case(c))
+}
+
+/// Parse a TTL, either in seconds or with BIND-style units
+/// (e.g. `1h30m`).
+fn parse_ttl(tok: &str) -> Option<u32> {
+    if !tok.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    if let Ok(secs) = tok.parse() {
+        return Some(secs);
+    }
+
+    let mut total: u32 = 0;
+    let mut num: Option<u32> = None;
+    for c in tok.chars() {
+        if let Some(d) = c.to_digit(10) {
+            num = Some(num.unwrap_or(0).checked_mul(10)?.checked_add(d)?);
+            continue;
+        }
+        let mult = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 604800,
+            _ => return None,
+        };
+        total = total.checked_add(num.take()?.checked_mul(mult)?)?;
+    }
+    match num {
+        Some(n) => total.checked_add(n),
+        None => Some(total),
+    }
+}
+
+/// Resolve a name relative to `origin`, returning it with the
+/// trailing dot.
+fn absolute_name(name: &str, origin: &str) -> String {
+    let origin = origin.trim_end_matches('.');
+    if name == "@" {
+        format!("{origin}.")
+    } else if name.ends_with('.') {
+        name.to_string()
+    } else if origin.is_empty() {
+        format!("{name}.")
+    } else {
+        format!("{name}.{origin}.")
+    }
+}
+
+fn owner_name(name: &str, origin: &str) -> String {
+    let abs = absolute_name(name, origin);
+    let bytes = unescape_str(abs.trim_end_matches('.'));
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+fn unescape_str(text: &str) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && let Some((b, len)) = unescape(&bytes[i + 1..]) {
+            out.push(b);
+            i += 1 + len;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    out
+}
+
+fn parsed_rdata(rtype: RecordType, rdata: &[&Token], origin: &str) -> String {
+    let name_field = match rtype {
+        RecordType::TXT => {
+            return rdata.iter()
+                .map(|t| quote_bytes(&unescape_str(&t.text)))
+                .collect::<Vec<String>>()
+                .join(" ");
+        }
+        RecordType::CNAME | RecordType::NS | RecordType::PTR => Some(0),
+        RecordType::MX | RecordType::SVCB | RecordType::HTTPS => Some(1),
+        RecordType::SRV => Some(3),
+        _ => None,
+    };
+
+    rdata.iter()
+        .enumerate()
+        .map(|(i, t)| match t {
+            Token { quoted: true, text } => format!("\"{text}\""),
+            // The root name is used as a placeholder by SVCB
+            Token { text, .. } if Some(i) == name_field && text != "." => absolute_name(text, origin),
+            Token { text, .. } => text.clone(),
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+
+/// A logical line, after joining parenthesised continuations and
+/// removing comments.
+struct Line {
+    number: usize,
+    /// Whether the line started with whitespace, i.e. has a blank
+    /// owner name.
+    indented: bool,
+    tokens: Vec<Token>,
+}
+
+struct Token {
+    /// The token text; escape sequences are preserved, and quoted
+    /// strings do not include the quotes.
+    text: String,
+    quoted: bool,
+}
+
+fn tokenise(text: &str) -> Result<Vec<Line>> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut lines = Vec::new();
+    let mut number = 1;
+    let mut parens = 0;
+    let mut current: Option<Line> = None;
+    let mut i = 0;
+
+    let err = |number: usize, msg: &str| Error::ZoneFileError(format!("line {number}: {msg}"));
+
+    while i < chars.len() {
+        let line = current.get_or_insert_with(|| Line {
+            number,
+            indented: chars[i] == ' ' || chars[i] == '\t',
+            tokens: Vec::new(),
+        });
+
+        match chars[i] {
+            '\n' => {
+                number += 1;
+                i += 1;
+                if parens == 0 && let Some(line) = current.take() && !line.tokens.is_empty() {
+                    lines.push(line);
+                }
+            }
+            ';' => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            }
+            c if c.is_whitespace() => i += 1,
+            '(' => {
+                parens += 1;
+                i += 1;
+            }
+            ')' => {
+                if parens == 0 {
+                    return Err(err(number, "unbalanced parentheses"));
+                }
+                parens -= 1;
+                i += 1;
+            }
+            '"' => {
+                let mut s = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None | Some('\n') => return Err(err(number, "unterminated string")),
+                        Some('"') => break,
+                        Some('\\') => {
+                            s.push('\\');
+                            if let Some(c) = chars.get(i + 1) {
+                                s.push(*c);
+                            }
+                            i += 2;
+                            continue;
+                        }
+                        Some(c) => s.push(*c),
+                    }
+                    i += 1;
+                }
+                i += 1;
+                line.tokens.push(Token { text: s, quoted: true });
+            }
+            _ => {
+                let mut s = String::new();
+                while let Some(c) = chars.get(i) {
+                    if c.is_whitespace() || matches!(c, ';' | '(' | ')' | '"') {
+                        break;
+                    }
+                    s.push(*c);
+                    if *c == '\\' && let Some(next) = chars.get(i + 1) {
+                        s.push(*next);
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                line.tokens.push(Token { text: s, quoted: false });
+            }
+        }
+    }
+
+    if parens != 0 {
+        return Err(err(number, "unbalanced parentheses"));
+    }
+    if let Some(line) = current && !line.tokens.is_empty() {
+        lines.push(line);
+    }
+
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::MemoryProvider;
+
+    fn rec(rtype: RecordType, host: &str, value: &str, ttl: Option<u32>) -> ZoneRecord {
+        ZoneRecord {
+            rtype,
+            host: host.to_string(),
+            value: value.to_string(),
+            ttl,
+            modified: None,
+        }
+    }
+
+    #[test]
+    fn test_zone_file() {
+        let records = vec![
+            rec(RecordType::NS, "", "ns1.example.net", Some(3600)),
+            rec(RecordType::MX, "", "10 mail.example.com", Some(3600)),
+            rec(RecordType::A, "www", "10.0.0.1", Some(300)),
+            rec(RecordType::CNAME, "ftp", "www", None),
+            rec(RecordType::SRV, "_sip._tcp", "1 10 5060 sip.example.com.", Some(3600)),
+            rec(RecordType::TXT, "", "\"v=spf1 -all\"", Some(3600)),
+        ];
+        let zone = to_zone_file("example.com.", &records);
+
+        let expected = "\
+$ORIGIN example.com.
+$TTL 3600
+@\t\tIN\tNS\tns1.example.net.
+@\t\tIN\tMX\t10 mail.example.com.
+www\t300\tIN\tA\t10.0.0.1
+ftp\t\tIN\tCNAME\twww
+_sip._tcp\t\tIN\tSRV\t1 10 5060 sip.example.com.
+@\t\tIN\tTXT\t\"v=spf1 -all\"
+";
+        assert_eq!(expected, zone);
+    }
+
+    #[test]
+    fn test_default_ttl() {
+        assert_eq!(DEFAULT_TTL, default_ttl(&[]));
+        let records = vec![
+            rec(RecordType::A, "a", "10.0.0.1", Some(300)),
+            rec(RecordType::A, "b", "10.0.0.2", Some(60)),
+            rec(RecordType::A, "c", "10.0.0.3", Some(60)),
+            rec(RecordType::A, "d", "10.0.0.4", Some(300)),
+        ];
+        assert_eq!(60, default_ttl(&records));
+    }
+
+    #[test]
+    fn test_txt_escaping() {
+        assert_eq!("\"plain\"", format_txt("plain"));
+        assert_eq!("\"say \\\"hi\\\" \\\\o/\"", format_txt("say \"hi\" \\o/"));
+        // Already in presentation format
+        assert_eq!("\"say \\\"hi\\\"\"", format_txt("\"say \\\"hi\\\"\""));
+        assert_eq!("\"one\" \"two\"", format_txt("\"one\" \"two\""));
+        // Unescaped inner quotes, as stored by `ensure_quotes()`
+        assert_eq!("\"quoted \\\" string\"", format_txt("\"quoted \" string\""));
+        assert_eq!("\"\"", format_txt("\"\""));
+        assert_eq!("\"caf\\195\\169\"", format_txt("café"));
+        assert_eq!("\"tab\\009\"", format_txt("\"tab\\009\""));
+    }
+
+    #[test]
+    fn test_txt_splitting() {
+        let long = "a".repeat(600);
+        let out = format_txt(&long);
+        let strings: Vec<&str> = out.split(' ').collect();
+        assert_eq!(3, strings.len());
+        assert_eq!(257, strings[0].len());
+        assert_eq!(257, strings[1].len());
+        assert_eq!(92, strings[2].len());
+        assert_eq!(vec![long.into_bytes()], vec![parse_txt(&out).concat()]);
+    }
+
+    #[test]
+    fn test_parse_txt() {
+        assert_eq!(vec![b"v=spf1 ".to_vec(), b"-all".to_vec()], parse_txt("\"v=spf1 \" \"-all\""));
+        assert_eq!(vec![b"unquoted value".to_vec()], parse_txt("unquoted value"));
+        assert_eq!(vec![b"a\"b\\c".to_vec()], parse_txt("\"a\\\"b\\\\c\""));
+        assert_eq!(vec![vec![b'x', 0xff]], parse_txt("\"x\\255\""));
+    }
+
+    #[test]
+    fn test_escape_name() {
+        assert_eq!("www", escape_name("www"));
+        assert_eq!("*.dev", escape_name("*.dev"));
+        assert_eq!("a\\;b", escape_name("a;b"));
+    }
+
+    #[test]
+    fn test_export() -> Result<()> {
+        let provider = MemoryProvider::new("example.com");
+        provider.create_a_record("www", &"10.0.0.1".parse()?)?;
+        provider.create_txt_record("_acme-challenge", "token")?;
+
+        let zone = export(&provider, "example.com")?;
+        assert_eq!("$ORIGIN example.com.\n$TTL 3600\n\
+                    www\t\tIN\tA\t10.0.0.1\n\
+                    _acme-challenge\t\tIN\tTXT\t\"token\"\n", zone);
+
+        Ok(())
+    }
+
+    const ZONE: &str = r#"
+$ORIGIN example.com.
+$TTL 1h
+; The SOA is always skipped
+@   IN  SOA ns1.example.net. hostmaster.example.com. (
+            2024010101 ; serial
+            7200       ; refresh
+            3600 1209600 300 )
+    IN  NS  ns1.example.net.
+    IN  MX  10 mail          ; relative to the origin
+        MX  20 mail.example.org.
+www 300 IN  A   192.0.2.1
+        IN 600 AAAA 2001:db8::1
+ftp     CNAME www
+mail.example.com.  A  192.0.2.2
+@       TXT ( "v=spf1 mx "
+              "-all" )
+quote   TXT "say \"hi\"" unquoted
+_sip._tcp  SRV 0 5 5060 sip
+caa     CAA 0 issue "letsencrypt.org"
+other.example.org. A 192.0.2.3
+$ORIGIN dev.example.com.
+api     A   192.0.2.4
+svc     HTTPS 1 . alpn=h2
+"#;
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let recs = parse(ZONE, "example.com", &ParseOptions::default())?;
+
+        let expected = vec![
+            rec(RecordType::MX, "", "10 mail.example.com.", Some(3600)),
+            rec(RecordType::MX, "", "20 mail.example.org.", Some(3600)),
+            rec(RecordType::A, "www", "192.0.2.1", Some(300)),
+            rec(RecordType::AAAA, "www", "2001:db8::1", Some(600)),
+            rec(RecordType::CNAME, "ftp", "www.example.com.", Some(3600)),
+            rec(RecordType::A, "mail", "192.0.2.2", Some(3600)),
+            rec(RecordType::TXT, "", "\"v=spf1 mx \" \"-all\"", Some(3600)),
+            rec(RecordType::TXT, "quote", "\"say \\\"hi\\\"\" \"unquoted\"", Some(3600)),
+            rec(RecordType::SRV, "_sip._tcp", "0 5 5060 sip.example.com.", Some(3600)),
+            rec(RecordType::CAA, "caa", "0 issue \"letsencrypt.org\"", Some(3600)),
+            rec(RecordType::A, "api.dev", "192.0.2.4", Some(3600)),
+            rec(RecordType::HTTPS, "svc.dev", "1 . alpn=h2", Some(3600)),
+        ];
+        assert_eq!(expected, recs);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_apex_ns() -> Result<()> {
+        let opts = ParseOptions { apex_ns: true };
+        let recs = parse(ZONE, "example.com", &opts)?;
+        assert_eq!(rec(RecordType::NS, "", "ns1.example.net.", Some(3600)), recs[0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_ttl() {
+        assert_eq!(Some(300), parse_ttl("300"));
+        assert_eq!(Some(3600), parse_ttl("1h"));
+        assert_eq!(Some(5400), parse_ttl("1h30m"));
+        assert_eq!(Some(604800 + 86400 + 10), parse_ttl("1W1d10"));
+        assert_eq!(None, parse_ttl("IN"));
+        assert_eq!(None, parse_ttl("1x"));
+        assert_eq!(None, parse_ttl("99999999999"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = parse("www A 192.0.2.1\n( www A\n", "example.com", &ParseOptions::default()).unwrap_err();
+        assert!(matches!(&err, Error::ZoneFileError(msg) if msg.contains("unbalanced")), "{err}");
+
+        let err = parse("\n$FOO bar\n", "example.com", &ParseOptions::default()).unwrap_err();
+        assert!(matches!(&err, Error::ZoneFileError(msg) if msg == "line 2: unknown directive $FOO"), "{err}");
+
+        let err = parse("  A 192.0.2.1\n", "example.com", &ParseOptions::default()).unwrap_err();
+        assert!(matches!(&err, Error::ZoneFileError(msg) if msg.contains("no previous owner")), "{err}");
+
+        let err = parse("www TXT \"open\n", "example.com", &ParseOptions::default()).unwrap_err();
+        assert!(matches!(&err, Error::ZoneFileError(msg) if msg.contains("unterminated")), "{err}");
+    }
+
+    #[test]
+    fn test_parse_include() -> Result<()> {
+        let dir = std::env::temp_dir()
+            .join(format!("zone-update-include-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub"))?;
+        fs::write(dir.join("main.zone"), "$TTL 600\nwww A 192.0.2.1\n$INCLUDE sub/hosts.zone lab\nafter A 192.0.2.3\n")?;
+        fs::write(dir.join("sub/hosts.zone"), "host1 A 192.0.2.2\n")?;
+
+        let recs = parse_file(dir.join("main.zone"), "example.com", &ParseOptions::default());
+        fs::remove_dir_all(&dir)?;
+
+        let expected = vec![
+            rec(RecordType::A, "www", "192.0.2.1", Some(600)),
+            rec(RecordType::A, "host1.lab", "192.0.2.2", Some(600)),
+            // The origin is restored after the include
+            rec(RecordType::A, "after", "192.0.2.3", Some(600)),
+        ];
+        assert_eq!(expected, recs?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        let records = vec![
+            rec(RecordType::NS, "", "ns1.example.net.", Some(3600)),
+            rec(RecordType::MX, "", "10 mail.example.com.", Some(3600)),
+            rec(RecordType::A, "www", "10.0.0.1", Some(300)),
+            rec(RecordType::CNAME, "ftp", "www.example.com.", Some(3600)),
+            rec(RecordType::SRV, "_sip._tcp", "1 10 5060 sip.example.com.", Some(3600)),
+            rec(RecordType::TXT, "", "\"v=spf1 -all\"", Some(3600)),
+            rec(RecordType::TXT, "esc", "\"a \\\"b\\\" \\\\c\" \"\\200\"", Some(60)),
+            rec(RecordType::CAA, "", "0 issue \"letsencrypt.org\"", Some(3600)),
+        ];
+        let zone = to_zone_file("example.com", &records);
+        let parsed = parse(&zone, "example.com", &ParseOptions { apex_ns: true })?;
+        assert_eq!(records, parsed);
+
+        // Long TXT values are split on export, but the data is the same
+        let long = vec![rec(RecordType::TXT, "dkim", &format!("\"{}\"", "k".repeat(300)), Some(3600))];
+        let parsed = parse(&to_zone_file("example.com", &long), "example.com", &ParseOptions::default())?;
+        assert_eq!(parse_txt(&long[0].value).concat(), parse_txt(&parsed[0].value).concat());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import() -> Result<()> {
+        let provider = MemoryProvider::new("example.com");
+        let recs = parse("www A 192.0.2.1\n_acme TXT \"token\"\n", "example.com", &ParseOptions::default())?;
+
+        let results = import(&provider, &recs);
+        assert!(results.iter().all(|r| r.is_ok()));
+        assert_eq!(Some("192.0.2.1".parse()?), provider.get_a_record("www")?);
+        assert_eq!(Some("token".to_string()), provider.get_txt_record("_acme")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_boxed() -> Result<()> {
+        let source = MemoryProvider::new("example.com");
+        source.create_a_record("www", &"192.0.2.1".parse()?)?;
+        let dest: Box<dyn DnsProvider> = Box::new(MemoryProvider::new("example.com"));
+
+        let recs = parse(&export(&source, "example.com")?, "example.com", &ParseOptions::default())?;
+        let results = import(&*dest, &recs);
+        assert!(results.iter().all(|r| r.is_ok()));
+        assert_eq!(Some("192.0.2.1".parse()?), dest.get_a_record("www")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_unsupported() {
+        struct NoList;
+        impl DnsProvider for NoList {
+            fn get_record<T>(&self, _: RecordType, _: &str) -> Result<Option<T>> { Ok(None) }
+            fn create_record<T>(&self, _: RecordType, _: &str, _: &T) -> Result<()> { Ok(()) }
+            fn update_record<T>(&self, _: RecordType, _: &str, _: &T) -> Result<()> { Ok(()) }
+            fn delete_record(&self, _: RecordType, _: &str) -> Result<()> { Ok(()) }
+            fn delete_all_records(&self, _: RecordType, _: &str) -> Result<()> { Ok(()) }
+            crate::generate_helpers!();
+        }
+
+        let err = export(&NoList, "example.com").unwrap_err();
+        assert!(matches!(err, Error::Unsupported(_)));
+    }
+}